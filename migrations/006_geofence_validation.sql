@@ -0,0 +1,16 @@
+-- Migration: Server-side geofence validation for synced check-ins
+-- Stores the geofence result of every punch and a per-department policy for out-of-range punches
+
+ALTER TABLE checkins
+    ADD COLUMN IF NOT EXISTS within_geofence BOOLEAN,
+    ADD COLUMN IF NOT EXISTS distance_meters DOUBLE PRECISION,
+    ADD COLUMN IF NOT EXISTS matched_point_id INTEGER;
+
+-- 'flag' stores out-of-range punches and marks them, 'reject' refuses them
+CREATE TABLE IF NOT EXISTS department_settings (
+    department INTEGER PRIMARY KEY,
+    geofence_policy VARCHAR(20) NOT NULL DEFAULT 'flag' CHECK (geofence_policy IN ('flag', 'reject')),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_checkins_within_geofence ON checkins(within_geofence);
@@ -0,0 +1,7 @@
+-- Migration: Idempotent check-in sync with client-generated punch IDs
+-- The app sends a UUID with every punch; replayed batches hit the unique index instead of inserting duplicates
+
+ALTER TABLE checkins ADD COLUMN IF NOT EXISTS client_id UUID;
+
+-- NULL client ids (older app versions, admin-created punches) never conflict
+CREATE UNIQUE INDEX IF NOT EXISTS idx_checkins_user_client_id ON checkins(user_id, client_id);
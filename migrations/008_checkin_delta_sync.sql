@@ -0,0 +1,61 @@
+-- Migration: Cursor-based delta sync for check-ins
+-- Every insert or edit of a checkin takes a new value from checkins_sync_seq.
+-- Deletes (and moves to another user) leave a tombstone with its own sequence value,
+-- so a device can ask for "everything after cursor N" instead of comparing row counts.
+
+CREATE SEQUENCE IF NOT EXISTS checkins_sync_seq;
+
+ALTER TABLE checkins
+    ADD COLUMN IF NOT EXISTS sync_seq BIGINT,
+    ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
+
+UPDATE checkins SET sync_seq = nextval('checkins_sync_seq') WHERE sync_seq IS NULL;
+
+ALTER TABLE checkins
+    ALTER COLUMN sync_seq SET DEFAULT nextval('checkins_sync_seq'),
+    ALTER COLUMN sync_seq SET NOT NULL;
+
+CREATE TABLE IF NOT EXISTS checkin_tombstones (
+    id SERIAL PRIMARY KEY,
+    checkin_id INTEGER NOT NULL,
+    user_id VARCHAR(255) NOT NULL,
+    client_id UUID,
+    sync_seq BIGINT NOT NULL DEFAULT nextval('checkins_sync_seq'),
+    deleted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE OR REPLACE FUNCTION bump_checkin_sync_seq()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.sync_seq := nextval('checkins_sync_seq');
+    NEW.updated_at := NOW();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS bump_checkin_sync_seq_trigger ON checkins;
+CREATE TRIGGER bump_checkin_sync_seq_trigger
+    BEFORE UPDATE ON checkins
+    FOR EACH ROW
+    WHEN (OLD.* IS DISTINCT FROM NEW.*)
+    EXECUTE FUNCTION bump_checkin_sync_seq();
+
+CREATE OR REPLACE FUNCTION record_checkin_tombstone()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'DELETE' OR OLD.user_id IS DISTINCT FROM NEW.user_id THEN
+        INSERT INTO checkin_tombstones (checkin_id, user_id, client_id)
+        VALUES (OLD.id, OLD.user_id, OLD.client_id);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS record_checkin_tombstone_trigger ON checkins;
+CREATE TRIGGER record_checkin_tombstone_trigger
+    AFTER UPDATE OR DELETE ON checkins
+    FOR EACH ROW
+    EXECUTE FUNCTION record_checkin_tombstone();
+
+CREATE INDEX IF NOT EXISTS idx_checkins_user_sync_seq ON checkins(user_id, sync_seq);
+CREATE INDEX IF NOT EXISTS idx_checkin_tombstones_user_sync_seq ON checkin_tombstones(user_id, sync_seq);
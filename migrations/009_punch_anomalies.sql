@@ -0,0 +1,23 @@
+-- Migration: Punch anomaly review queue
+-- Records irregular punches found while pairing check-ins so admins can resolve or dismiss them
+
+CREATE TABLE IF NOT EXISTS punch_anomalies (
+    id SERIAL PRIMARY KEY,
+    user_id VARCHAR(255) NOT NULL,
+    anomaly_type VARCHAR(40) NOT NULL CHECK (anomaly_type IN
+        ('duplicate_in', 'orphan_out', 'future_timestamp', 'overlong_session', 'short_session')),
+    checkin_id INTEGER NOT NULL REFERENCES checkins(id) ON DELETE CASCADE,
+    related_checkin_id INTEGER REFERENCES checkins(id) ON DELETE SET NULL,
+    occurred_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    details TEXT,
+    -- 'open' until an admin resolves or dismisses it
+    status VARCHAR(20) NOT NULL DEFAULT 'open' CHECK (status IN ('open', 'resolved', 'dismissed')),
+    review_note TEXT,
+    reviewed_by VARCHAR(255),
+    reviewed_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    CONSTRAINT unique_anomaly_per_punch UNIQUE(anomaly_type, checkin_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_punch_anomalies_status ON punch_anomalies(status, occurred_at);
+CREATE INDEX IF NOT EXISTS idx_punch_anomalies_user ON punch_anomalies(user_id, occurred_at);
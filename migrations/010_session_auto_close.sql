@@ -0,0 +1,76 @@
+-- Migration: Auto-close forgotten sessions
+-- Sessions left open past the cutoff are closed by the background job and marked as auto-closed
+
+ALTER TABLE attendance_sessions
+    ADD COLUMN IF NOT EXISTS auto_closed BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN IF NOT EXISTS auto_closed_at TIMESTAMP WITH TIME ZONE;
+
+ALTER TABLE attendance_summary
+    ADD COLUMN IF NOT EXISTS auto_closed_sessions INTEGER NOT NULL DEFAULT 0;
+
+-- 'off_duty' closes at the user's off-duty time, 'zero' closes at the checkin time
+ALTER TABLE department_settings
+    ADD COLUMN IF NOT EXISTS auto_close_policy VARCHAR(20) NOT NULL DEFAULT 'off_duty'
+        CHECK (auto_close_policy IN ('off_duty', 'zero'));
+
+CREATE INDEX IF NOT EXISTS idx_attendance_sessions_open ON attendance_sessions(checkin_time) WHERE checkout_time IS NULL;
+
+-- Count auto-closed sessions in the daily summary
+CREATE OR REPLACE FUNCTION update_attendance_summary()
+RETURNS TRIGGER AS $$
+DECLARE
+    v_first_checkin TIMESTAMP WITH TIME ZONE;
+    v_last_checkout TIMESTAMP WITH TIME ZONE;
+    v_total_minutes INTEGER;
+    v_total_sessions INTEGER;
+    v_is_complete BOOLEAN;
+    v_auto_closed INTEGER;
+BEGIN
+    SELECT 
+        MIN(checkin_time),
+        MAX(checkout_time),
+        COALESCE(SUM(duration_minutes), 0),
+        COUNT(*),
+        BOOL_AND(is_complete),
+        COUNT(*) FILTER (WHERE auto_closed)
+    INTO 
+        v_first_checkin,
+        v_last_checkout,
+        v_total_minutes,
+        v_total_sessions,
+        v_is_complete,
+        v_auto_closed
+    FROM attendance_sessions
+    WHERE user_id = COALESCE(NEW.user_id, OLD.user_id)
+        AND date = COALESCE(NEW.date, OLD.date);
+
+    INSERT INTO attendance_summary (
+        user_id, date, first_checkin_time, last_checkout_time, checkin_time, checkout_time,
+        total_work_minutes, total_sessions, is_complete, auto_closed_sessions, updated_at
+    ) VALUES (
+        COALESCE(NEW.user_id, OLD.user_id),
+        COALESCE(NEW.date, OLD.date),
+        v_first_checkin,
+        v_last_checkout,
+        v_first_checkin,
+        v_last_checkout,
+        v_total_minutes,
+        v_total_sessions,
+        v_is_complete,
+        v_auto_closed,
+        NOW()
+    )
+    ON CONFLICT (user_id, date) DO UPDATE SET
+        first_checkin_time = EXCLUDED.first_checkin_time,
+        last_checkout_time = EXCLUDED.last_checkout_time,
+        checkin_time = EXCLUDED.checkin_time,
+        checkout_time = EXCLUDED.checkout_time,
+        total_work_minutes = EXCLUDED.total_work_minutes,
+        total_sessions = EXCLUDED.total_sessions,
+        is_complete = EXCLUDED.is_complete,
+        auto_closed_sessions = EXCLUDED.auto_closed_sessions,
+        updated_at = NOW();
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,32 @@
+-- Migration: Trusted server receive time and device clock skew handling
+-- Every synced punch records when the server received it; punches from devices whose clock
+-- is off by more than the allowed skew are either corrected or flagged per department
+
+ALTER TABLE checkins
+    ADD COLUMN IF NOT EXISTS received_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN IF NOT EXISTS device_created_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN IF NOT EXISTS clock_skew_seconds BIGINT,
+    ADD COLUMN IF NOT EXISTS clock_skew_flagged BOOLEAN NOT NULL DEFAULT false;
+
+-- Existing rows keep NULL, their receive time is unknown
+ALTER TABLE checkins ALTER COLUMN received_at SET DEFAULT NOW();
+
+-- 'flag' keeps the device timestamp and raises an anomaly, 'correct' shifts it by the skew
+ALTER TABLE department_settings
+    ADD COLUMN IF NOT EXISTS clock_skew_policy VARCHAR(20) NOT NULL DEFAULT 'flag'
+        CHECK (clock_skew_policy IN ('flag', 'correct'));
+
+-- Latest skew estimate (server time minus device time) per device
+CREATE TABLE IF NOT EXISTS device_clocks (
+    user_id VARCHAR(255) NOT NULL,
+    device_id VARCHAR(255) NOT NULL DEFAULT '',
+    skew_seconds BIGINT NOT NULL,
+    last_device_time TIMESTAMP WITH TIME ZONE NOT NULL,
+    last_received_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (user_id, device_id)
+);
+
+ALTER TABLE punch_anomalies
+    DROP CONSTRAINT IF EXISTS punch_anomalies_anomaly_type_check,
+    ADD CONSTRAINT punch_anomalies_anomaly_type_check CHECK (anomaly_type IN
+        ('duplicate_in', 'orphan_out', 'future_timestamp', 'overlong_session', 'short_session', 'clock_skew'));
@@ -0,0 +1,19 @@
+-- Migration: Resumable chunked uploads for large offline sync backlogs
+-- A device over the sync batch limit starts an upload and sends numbered chunks;
+-- each chunk is committed on its own and recorded so an interrupted upload can resume
+
+CREATE TABLE IF NOT EXISTS sync_uploads (
+    id UUID PRIMARY KEY,
+    user_id VARCHAR(255) NOT NULL,
+    device_id VARCHAR(255),
+    total_chunks INTEGER NOT NULL CHECK (total_chunks > 0),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS sync_upload_chunks (
+    upload_id UUID NOT NULL REFERENCES sync_uploads(id) ON DELETE CASCADE,
+    chunk_index INTEGER NOT NULL,
+    punches INTEGER NOT NULL,
+    received_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (upload_id, chunk_index)
+);
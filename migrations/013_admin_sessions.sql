@@ -0,0 +1,15 @@
+-- Migration: Persistent admin sessions with idle and absolute expiry
+-- Replaces the in-memory token map so sessions survive restarts and are shared
+-- between server processes. Only a SHA-256 digest of each token is stored.
+
+CREATE TABLE IF NOT EXISTS admin_sessions (
+    token_hash VARCHAR(64) PRIMARY KEY,
+    admin_user_id INTEGER NOT NULL REFERENCES admin_user(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    revoked_at TIMESTAMP WITH TIME ZONE,
+    ip_address VARCHAR(255)
+);
+
+CREATE INDEX IF NOT EXISTS idx_admin_sessions_user ON admin_sessions(admin_user_id);
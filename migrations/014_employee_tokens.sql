@@ -0,0 +1,14 @@
+-- Migration: Token-based employee authentication
+-- /api/auth/verify exchanges the passkey for a short-lived signed access token and a
+-- refresh token. Access tokens are verified by signature alone; refresh tokens are
+-- stored here (as SHA-256 digests) so they can be rotated and revoked.
+
+CREATE TABLE IF NOT EXISTS employee_refresh_tokens (
+    token_hash VARCHAR(64) PRIMARY KEY,
+    user_id VARCHAR(255) NOT NULL REFERENCES user_info(user_id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    revoked_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS idx_employee_refresh_tokens_user ON employee_refresh_tokens(user_id);
@@ -0,0 +1,16 @@
+-- Migration: Hashed, rotatable employee passkeys
+-- Passkeys are stored as HMAC-SHA256 hashes keyed with PASSKEY_HASH_KEY and looked up
+-- by hash. The key is not available to SQL, so existing plaintext passkeys are hashed
+-- (and the plaintext column emptied) by the server on its next startup.
+-- passkey_version is embedded in employee access tokens; bumping it on rotation
+-- invalidates every token issued under the old passkey.
+
+ALTER TABLE user_info
+    ALTER COLUMN passkey DROP NOT NULL,
+    ADD COLUMN IF NOT EXISTS passkey_hash VARCHAR(64),
+    ADD COLUMN IF NOT EXISTS passkey_version INTEGER NOT NULL DEFAULT 1,
+    ADD COLUMN IF NOT EXISTS passkey_rotated_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN IF NOT EXISTS passkey_expires_at TIMESTAMP WITH TIME ZONE;
+
+DROP INDEX IF EXISTS idx_user_info_passkey;
+CREATE INDEX IF NOT EXISTS idx_user_info_passkey_hash ON user_info(passkey_hash);
@@ -0,0 +1,30 @@
+-- Migration: Device binding for employee accounts
+-- The app sends a device identifier to /api/auth/verify. The first device of an employee
+-- is approved on registration, later ones wait for an admin. Punches from a device that
+-- is not approved are held or rejected per department ('off' keeps the old behaviour).
+
+CREATE TABLE IF NOT EXISTS user_devices (
+    id SERIAL PRIMARY KEY,
+    user_id VARCHAR(255) NOT NULL REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    device_id VARCHAR(255) NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('approved', 'pending', 'revoked')),
+    registered_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    reviewed_by VARCHAR(255),
+    reviewed_at TIMESTAMP WITH TIME ZONE,
+    CONSTRAINT unique_user_device UNIQUE(user_id, device_id)
+);
+
+ALTER TABLE department_settings
+    ADD COLUMN IF NOT EXISTS device_policy VARCHAR(20) NOT NULL DEFAULT 'off'
+        CHECK (device_policy IN ('off', 'hold', 'reject'));
+
+-- Held punches are stored but left out of sessions until their device is approved
+ALTER TABLE checkins
+    ADD COLUMN IF NOT EXISTS device_id VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS held BOOLEAN NOT NULL DEFAULT false;
+
+-- Revoking a device deletes the refresh tokens issued to it
+ALTER TABLE employee_refresh_tokens ADD COLUMN IF NOT EXISTS device_id VARCHAR(255);
+
+CREATE INDEX IF NOT EXISTS idx_checkins_held ON checkins(user_id, device_id) WHERE held;
@@ -0,0 +1,14 @@
+-- Migration: Capability-based admin roles
+-- Handlers check capabilities granted by the role instead of comparing role names.
+-- Auditors get read-only access, payroll users attendance figures and exports. Non-admin
+-- users can be limited to several departments; department is kept as the first of them
+-- for older clients.
+
+ALTER TABLE admin_user
+    DROP CONSTRAINT IF EXISTS admin_user_role_check,
+    ADD CONSTRAINT admin_user_role_check CHECK (role IN ('admin', 'department', 'auditor', 'payroll'));
+
+ALTER TABLE admin_user ADD COLUMN IF NOT EXISTS departments INTEGER[] NOT NULL DEFAULT '{}';
+
+UPDATE admin_user SET departments = ARRAY[department]
+WHERE department IS NOT NULL AND cardinality(departments) = 0;
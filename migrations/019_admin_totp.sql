@@ -0,0 +1,29 @@
+-- Migration: TOTP two-factor authentication for admin accounts
+-- Admins enrol an authenticator app and get single-use recovery codes. Once enrolled,
+-- or when their role requires it (ADMIN_TOTP_REQUIRED_ROLES), the password step of
+-- /admin/login returns a short-lived challenge that /admin/login/verify trades for a
+-- session.
+
+ALTER TABLE admin_user
+    ADD COLUMN IF NOT EXISTS totp_secret VARCHAR(64),
+    ADD COLUMN IF NOT EXISTS totp_enabled_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN IF NOT EXISTS totp_last_step BIGINT;
+
+CREATE TABLE IF NOT EXISTS admin_recovery_codes (
+    id SERIAL PRIMARY KEY,
+    admin_user_id INTEGER NOT NULL REFERENCES admin_user(id) ON DELETE CASCADE,
+    code_hash VARCHAR(64) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    used_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE TABLE IF NOT EXISTS admin_login_challenges (
+    token_hash VARCHAR(64) PRIMARY KEY,
+    admin_user_id INTEGER NOT NULL REFERENCES admin_user(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS idx_admin_recovery_codes_user ON admin_recovery_codes(admin_user_id);
+CREATE INDEX IF NOT EXISTS idx_admin_login_challenges_expires ON admin_login_challenges(expires_at);
@@ -0,0 +1,32 @@
+-- Migration: audit admin data changes
+-- Entries for admin mutations of points, users, checkins, admin users and time settings
+-- record the entity changed, the record before and after as JSON, and the client IP.
+-- Entries are append-only: updates, deletes and truncation are rejected.
+
+ALTER TABLE audit_log
+    ADD COLUMN IF NOT EXISTS entity VARCHAR(64),
+    ADD COLUMN IF NOT EXISTS before_data JSONB,
+    ADD COLUMN IF NOT EXISTS after_data JSONB,
+    ADD COLUMN IF NOT EXISTS ip_address VARCHAR(255);
+
+CREATE OR REPLACE FUNCTION reject_audit_log_change()
+RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS audit_log_append_only ON audit_log;
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW
+    EXECUTE FUNCTION reject_audit_log_change();
+
+DROP TRIGGER IF EXISTS audit_log_no_truncate ON audit_log;
+CREATE TRIGGER audit_log_no_truncate
+    BEFORE TRUNCATE ON audit_log
+    FOR EACH STATEMENT
+    EXECUTE FUNCTION reject_audit_log_change();
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_entity ON audit_log(entity, target);
+CREATE INDEX IF NOT EXISTS idx_audit_log_actor ON audit_log(actor, occurred_at);
@@ -0,0 +1,18 @@
+-- Migration: API keys for service accounts
+-- Integrations such as the HR system send a key in the X-API-Key header instead of
+-- logging in through /admin/login. Keys carry read-only capability scopes, an optional
+-- department limit and expiry; only a SHA-256 digest of each key is stored.
+
+CREATE TABLE IF NOT EXISTS admin_api_keys (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(100) NOT NULL,
+    key_prefix VARCHAR(16) NOT NULL,
+    key_hash VARCHAR(64) NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL DEFAULT '{}',
+    departments INTEGER[] NOT NULL DEFAULT '{}',
+    created_by VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE,
+    last_used_at TIMESTAMP WITH TIME ZONE,
+    revoked_at TIMESTAMP WITH TIME ZONE
+);
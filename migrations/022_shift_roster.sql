@@ -0,0 +1,38 @@
+-- Migration: shift templates and roster
+-- Named shifts (start, end, break allowance; a shift ending before it starts runs past
+-- midnight) are assigned to employees for single dates or through a repeating rotation.
+-- Lateness and early leave are judged against the shift scheduled for each day; days
+-- without a roster entry fall back to user_time_settings and then to 07:30-17:00.
+
+CREATE TABLE IF NOT EXISTS shift_templates (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(100) NOT NULL UNIQUE,
+    start_time TIME NOT NULL,
+    end_time TIME NOT NULL CHECK (end_time <> start_time),
+    crosses_midnight BOOLEAN GENERATED ALWAYS AS (end_time < start_time) STORED,
+    break_minutes INTEGER NOT NULL DEFAULT 0 CHECK (break_minutes >= 0),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- A NULL template_id marks the date as a day off
+CREATE TABLE IF NOT EXISTS shift_assignments (
+    id SERIAL PRIMARY KEY,
+    user_id VARCHAR(255) NOT NULL REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    date DATE NOT NULL,
+    template_id INTEGER REFERENCES shift_templates(id),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    CONSTRAINT unique_shift_assignment UNIQUE(user_id, date)
+);
+
+-- template_ids repeats from starts_on, one entry per day; NULL entries are days off
+CREATE TABLE IF NOT EXISTS shift_rotations (
+    id SERIAL PRIMARY KEY,
+    user_id VARCHAR(255) NOT NULL REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    starts_on DATE NOT NULL,
+    ends_on DATE CHECK (ends_on >= starts_on),
+    template_ids INTEGER[] NOT NULL CHECK (cardinality(template_ids) > 0),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_shift_rotations_user ON shift_rotations(user_id, starts_on);
@@ -0,0 +1,14 @@
+-- Migration: working hours by day of the week
+-- Replaces a user's on/off duty times from user_time_settings on the weekdays listed.
+-- A row without times marks a day the user does not work, so part-time staff are
+-- neither late nor absent on it. ISO weekdays: 1 is Monday, 7 is Sunday.
+
+CREATE TABLE IF NOT EXISTS user_weekday_hours (
+    user_id VARCHAR(255) NOT NULL REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    weekday SMALLINT NOT NULL CHECK (weekday BETWEEN 1 AND 7),
+    on_duty_time TIME,
+    off_duty_time TIME,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, weekday),
+    CHECK ((on_duty_time IS NULL) = (off_duty_time IS NULL))
+);
@@ -0,0 +1,31 @@
+-- Migration: effective-dated time settings
+-- user_time_settings keeps one row per change instead of overwriting the user's duty
+-- times, so past months keep being judged against the hours that applied then. A row
+-- applies from effective_from until the next row of the same user; rows dated in the
+-- future are scheduled changes. Existing settings apply to all history.
+
+ALTER TABLE user_time_settings ADD COLUMN IF NOT EXISTS effective_from DATE NOT NULL DEFAULT '1970-01-01';
+ALTER TABLE user_time_settings DROP CONSTRAINT IF EXISTS unique_user_time_settings;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_user_time_settings_effective ON user_time_settings(user_id, effective_from);
+
+-- Settings follow a user whose user_id is changed
+ALTER TABLE user_time_settings
+    DROP CONSTRAINT IF EXISTS fk_user_time_settings_user_id,
+    DROP CONSTRAINT IF EXISTS user_time_settings_user_id_fkey,
+    ADD CONSTRAINT user_time_settings_user_id_fkey FOREIGN KEY (user_id)
+        REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE;
+
+-- Weekday hours belong to one dated setting and change with it
+ALTER TABLE user_weekday_hours ADD COLUMN IF NOT EXISTS effective_from DATE NOT NULL DEFAULT '1970-01-01';
+
+DELETE FROM user_weekday_hours uwh
+WHERE NOT EXISTS (
+    SELECT 1 FROM user_time_settings uts
+    WHERE uts.user_id = uwh.user_id AND uts.effective_from = uwh.effective_from
+);
+
+ALTER TABLE user_weekday_hours
+    DROP CONSTRAINT IF EXISTS user_weekday_hours_pkey,
+    ADD PRIMARY KEY (user_id, effective_from, weekday),
+    ADD CONSTRAINT user_weekday_hours_setting_fkey FOREIGN KEY (user_id, effective_from)
+        REFERENCES user_time_settings(user_id, effective_from) ON DELETE CASCADE ON UPDATE CASCADE;
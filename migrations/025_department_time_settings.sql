@@ -0,0 +1,26 @@
+-- Migration: department default schedules
+-- Departments own dated default duty times that their users inherit on days without a
+-- setting of their own in user_time_settings. Users no longer get a 07:30-17:00 row on
+-- creation; the rows created that way are removed so those users follow their department.
+-- A user explicitly set to exactly 07:30-17:00 from the start is indistinguishable from
+-- such a row and inherits as well.
+
+DELETE FROM user_time_settings uts
+WHERE effective_from = '1970-01-01'
+  AND on_duty_time = '07:30:00'
+  AND off_duty_time = '17:00:00'
+  AND NOT EXISTS (
+      SELECT 1 FROM user_weekday_hours uwh
+      WHERE uwh.user_id = uts.user_id AND uwh.effective_from = uts.effective_from
+  );
+
+CREATE TABLE IF NOT EXISTS department_time_settings (
+    id SERIAL PRIMARY KEY,
+    department INTEGER NOT NULL,
+    effective_from DATE NOT NULL DEFAULT '1970-01-01',
+    on_duty_time TIME NOT NULL,
+    off_duty_time TIME NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    CONSTRAINT unique_department_time_setting UNIQUE (department, effective_from)
+);
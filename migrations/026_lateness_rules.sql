@@ -0,0 +1,23 @@
+-- Migration: grace periods and late tiers
+-- A checkin only counts as late once it is more than late_grace_minutes after the shift
+-- start, and a checkout as leaving early once it is more than early_leave_grace_minutes
+-- before the shift end. Lateness of major_late_minutes or more is major, less is minor.
+-- Departments set the thresholds for their users; user_lateness_rules overrides them per
+-- user, with empty columns following the department. The defaults keep the previous
+-- behaviour of counting any lateness at all.
+
+ALTER TABLE department_settings
+    ADD COLUMN IF NOT EXISTS late_grace_minutes INTEGER NOT NULL DEFAULT 0
+        CHECK (late_grace_minutes >= 0),
+    ADD COLUMN IF NOT EXISTS early_leave_grace_minutes INTEGER NOT NULL DEFAULT 0
+        CHECK (early_leave_grace_minutes >= 0),
+    ADD COLUMN IF NOT EXISTS major_late_minutes INTEGER NOT NULL DEFAULT 30
+        CHECK (major_late_minutes > 0);
+
+CREATE TABLE IF NOT EXISTS user_lateness_rules (
+    user_id VARCHAR(255) PRIMARY KEY REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+    late_grace_minutes INTEGER CHECK (late_grace_minutes >= 0),
+    early_leave_grace_minutes INTEGER CHECK (early_leave_grace_minutes >= 0),
+    major_late_minutes INTEGER CHECK (major_late_minutes > 0),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
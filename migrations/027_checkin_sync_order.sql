@@ -0,0 +1,40 @@
+-- Migration: commit each user's check-in changes in sequence order
+-- Sequence values are taken when a change is written but become visible on commit, so a
+-- long transaction could commit sync_seq N after a device had moved its cursor past N.
+-- Every sequence value is now taken under the user's transaction-level advisory lock (the
+-- one session rebuilds already use). It is held until commit, so a change with a higher
+-- value cannot commit before a lower one of the same user.
+
+ALTER TABLE checkins DROP COLUMN IF EXISTS sync_xid;
+ALTER TABLE checkin_tombstones DROP COLUMN IF EXISTS sync_xid;
+
+CREATE OR REPLACE FUNCTION take_checkin_sync_seq()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_advisory_xact_lock(hashtext(NEW.user_id));
+    NEW.sync_seq := nextval('checkins_sync_seq');
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS take_sync_seq_trigger ON checkins;
+CREATE TRIGGER take_sync_seq_trigger
+    BEFORE INSERT ON checkins
+    FOR EACH ROW
+    EXECUTE FUNCTION take_checkin_sync_seq();
+
+DROP TRIGGER IF EXISTS take_sync_seq_trigger ON checkin_tombstones;
+CREATE TRIGGER take_sync_seq_trigger
+    BEFORE INSERT ON checkin_tombstones
+    FOR EACH ROW
+    EXECUTE FUNCTION take_checkin_sync_seq();
+
+CREATE OR REPLACE FUNCTION bump_checkin_sync_seq()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_advisory_xact_lock(hashtext(NEW.user_id));
+    NEW.sync_seq := nextval('checkins_sync_seq');
+    NEW.updated_at := NOW();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,9 @@
+-- Migration: no two employees may share a passkey
+-- A passkey identifies the employee at login, so a shared one would let either sign in
+-- as the other. Rotate duplicates first; this lists them:
+--   SELECT passkey_hash, string_agg(user_id, ', ') FROM user_info
+--   WHERE passkey_hash IS NOT NULL GROUP BY passkey_hash HAVING COUNT(*) > 1;
+
+DROP INDEX IF EXISTS idx_user_info_passkey_hash;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_user_info_passkey_hash_unique ON user_info(passkey_hash);
@@ -1,22 +1,19 @@
 use actix_web::{web, HttpResponse, HttpRequest};
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 
-use crate::admin::auth::require_admin_auth;
-use crate::admin::models::{AdminUser, AdminUserResponse, CreateAdminUserRequest, UpdateAdminUserRequest, ResetPasswordRequest};
+use crate::admin::auth::{bearer_token, require_capability, revoke_user_sessions};
+use crate::admin::models::{AdminUser, AdminUserResponse, CreateAdminUserRequest, UpdateAdminUserRequest, ResetPasswordRequest, RevokeSessionsResponse};
+use crate::audit::{self, Change};
 use crate::models::ApiResponse;
+use crate::password::hash_password;
+use crate::permissions::{Capability, Role};
 
 pub async fn get_admin_users(
     pool: web::Data<PgPool>,
     req: HttpRequest,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
-        Ok(session) => session,
-        Err(response) => return response,
-    };
-
-    // Only admin role can manage admin users
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Insufficient permissions"));
+    if let Err(response) = require_capability(&req, Capability::AdminUsersManage).await {
+        return response;
     }
 
     match sqlx::query_as::<_, AdminUser>(
@@ -27,13 +24,7 @@ pub async fn get_admin_users(
     {
         Ok(admin_users) => {
             // Convert to response format (without passwords)
-            let response: Vec<AdminUserResponse> = admin_users.into_iter().map(|user| AdminUserResponse {
-                id: user.id,
-                username: user.username,
-                role: user.role,
-                department: user.department,
-                created_at: user.created_at,
-            }).collect();
+            let response: Vec<AdminUserResponse> = admin_users.into_iter().map(AdminUserResponse::from).collect();
             
             HttpResponse::Ok().json(ApiResponse::success(response, "Admin users retrieved"))
         }
@@ -44,52 +35,95 @@ pub async fn get_admin_users(
     }
 }
 
+/// Departments an admin user is limited to. `departments` takes precedence over the
+/// single `department` older clients send; admins are never limited.
+#[allow(clippy::result_large_err)]
+fn role_departments(role: &str, department: Option<i32>, departments: &Option<Vec<i32>>) -> Result<Vec<i32>, HttpResponse> {
+    let role = match Role::parse(role) {
+        Some(role) => role,
+        None => {
+            return Err(HttpResponse::BadRequest().json(ApiResponse::<()>::error(
+                "role must be 'admin', 'department', 'auditor' or 'payroll'"
+            )));
+        }
+    };
+
+    if role == Role::Admin {
+        return Ok(Vec::new());
+    }
+
+    let mut departments = departments.clone().unwrap_or_else(|| department.into_iter().collect());
+    departments.sort_unstable();
+    departments.dedup();
+
+    if role.requires_departments() && departments.is_empty() {
+        return Err(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Department is required for department role")));
+    }
+
+    Ok(departments)
+}
+
 pub async fn create_admin_user(
     pool: web::Data<PgPool>,
     req: HttpRequest,
     user_req: web::Json<CreateAdminUserRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::AdminUsersManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    // Only admin role can create admin users
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Insufficient permissions"));
-    }
+    let departments = match role_departments(&user_req.role, user_req.department, &user_req.departments) {
+        Ok(departments) => departments,
+        Err(response) => return response,
+    };
 
-    // Validate department requirement for department role
-    if user_req.role == "department" && user_req.department.is_none() {
-        return HttpResponse::BadRequest().json(ApiResponse::<()>::error("Department is required for department role"));
-    }
+    let password_hash = match hash_password(&user_req.password) {
+        Ok(hash) => hash,
+        Err(e) => {
+            log::error!("Failed to hash admin password: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to hash password"));
+        }
+    };
 
-    match sqlx::query_as::<_, AdminUser>(
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create admin user"));
+        }
+    };
+
+    let result = sqlx::query_as::<_, AdminUser>(
         r#"
-        INSERT INTO admin_user (username, password, role, department)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO admin_user (username, password, role, department, departments)
+        VALUES ($1, $2, $3, $4, $5)
         RETURNING *
         "#
     )
     .bind(&user_req.username)
-    .bind(&user_req.password)
+    .bind(&password_hash)
     .bind(&user_req.role)
-    .bind(&user_req.department)
-    .fetch_one(pool.as_ref())
-    .await
-    {
+    .bind(departments.first())
+    .bind(&departments)
+    .fetch_one(&mut *tx)
+    .await;
+
+    match result {
         Ok(admin_user) => {
-            let response = AdminUserResponse {
-                id: admin_user.id,
-                username: admin_user.username,
-                role: admin_user.role,
-                department: admin_user.department,
-                created_at: admin_user.created_at,
-            };
-            HttpResponse::Created().json(ApiResponse::success(response, "Admin user created"))
+            let response = AdminUserResponse::from(admin_user);
+            let change = Change::new("admin_user_create", "admin_user", response.id).after(&response);
+            match audit::commit_change(tx, &session.actor(&req), &change).await {
+                Ok(_) => HttpResponse::Created().json(ApiResponse::success(response, "Admin user created")),
+                Err(e) => {
+                    log::error!("Failed to commit admin user: {:?}", e);
+                    HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create admin user"))
+                }
+            }
         }
         Err(e) => {
             log::error!("Failed to create admin user: {:?}", e);
+            let _ = tx.rollback().await;
             if e.to_string().contains("duplicate") || e.to_string().contains("unique") {
                 HttpResponse::Conflict().json(ApiResponse::<()>::error("Username already exists"))
             } else {
@@ -105,75 +139,56 @@ pub async fn update_admin_user(
     path: web::Path<i32>,
     user_req: web::Json<UpdateAdminUserRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::AdminUsersManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
     let id = path.into_inner();
 
-    // Only admin role can update admin users
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Insufficient permissions"));
-    }
-
-    // Validate department requirement for department role
-    if user_req.role == "department" && user_req.department.is_none() {
-        return HttpResponse::BadRequest().json(ApiResponse::<()>::error("Department is required for department role"));
-    }
+    let departments = match role_departments(&user_req.role, user_req.department, &user_req.departments) {
+        Ok(departments) => departments,
+        Err(response) => return response,
+    };
 
-    // Build update query dynamically based on whether password is provided
-    let query = if user_req.password.is_some() {
-        r#"
-        UPDATE admin_user 
-        SET username = $1, password = $2, role = $3, department = $4
-        WHERE id = $5
-        RETURNING *
-        "#
-    } else {
-        r#"
-        UPDATE admin_user 
-        SET username = $1, role = $2, department = $3
-        WHERE id = $4
-        RETURNING *
-        "#
+    let password_hash = match user_req.password.as_deref().map(hash_password).transpose() {
+        Ok(hash) => hash,
+        Err(e) => {
+            log::error!("Failed to hash admin password: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to hash password"));
+        }
     };
 
-    let result = if let Some(ref password) = user_req.password {
-        sqlx::query_as::<_, AdminUser>(query)
-            .bind(&user_req.username)
-            .bind(password)
-            .bind(&user_req.role)
-            .bind(&user_req.department)
-            .bind(id)
-            .fetch_optional(pool.as_ref())
-            .await
-    } else {
-        sqlx::query_as::<_, AdminUser>(query)
-            .bind(&user_req.username)
-            .bind(&user_req.role)
-            .bind(&user_req.department)
-            .bind(id)
-            .fetch_optional(pool.as_ref())
-            .await
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update admin user"));
+        }
     };
 
+    let result = update_admin_user_in(&mut tx, id, &user_req, &departments, password_hash.as_deref(), bearer_token(&req)).await;
+
     match result {
-        Ok(Some(admin_user)) => {
-            let response = AdminUserResponse {
-                id: admin_user.id,
-                username: admin_user.username,
-                role: admin_user.role,
-                department: admin_user.department,
-                created_at: admin_user.created_at,
-            };
-            HttpResponse::Ok().json(ApiResponse::success(response, "Admin user updated"))
+        Ok(Some((previous, admin_user))) => {
+            let previous = AdminUserResponse::from(previous);
+            let response = AdminUserResponse::from(admin_user);
+            let change = Change::new("admin_user_update", "admin_user", id).before(&previous).after(&response);
+            match audit::commit_change(tx, &session.actor(&req), &change).await {
+                Ok(_) => HttpResponse::Ok().json(ApiResponse::success(response, "Admin user updated")),
+                Err(e) => {
+                    log::error!("Failed to commit admin user update: {:?}", e);
+                    HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update admin user"))
+                }
+            }
         }
         Ok(None) => {
+            let _ = tx.rollback().await;
             HttpResponse::NotFound().json(ApiResponse::<()>::error("Admin user not found"))
         }
         Err(e) => {
             log::error!("Failed to update admin user: {:?}", e);
+            let _ = tx.rollback().await;
             if e.to_string().contains("duplicate") || e.to_string().contains("unique") {
                 HttpResponse::Conflict().json(ApiResponse::<()>::error("Username already exists"))
             } else {
@@ -183,42 +198,93 @@ pub async fn update_admin_user(
     }
 }
 
+/// Update an admin user, returning them before and after the update. A new password
+/// ends the user's other sessions.
+async fn update_admin_user_in(
+    conn: &mut PgConnection,
+    id: i32,
+    user_req: &UpdateAdminUserRequest,
+    departments: &[i32],
+    password_hash: Option<&str>,
+    keep_token: Option<&str>,
+) -> Result<Option<(AdminUser, AdminUser)>, sqlx::Error> {
+    let previous = match sqlx::query_as::<_, AdminUser>("SELECT * FROM admin_user WHERE id = $1 FOR UPDATE")
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await?
+    {
+        Some(previous) => previous,
+        None => return Ok(None),
+    };
+
+    let admin_user = sqlx::query_as::<_, AdminUser>(
+        r#"
+        UPDATE admin_user 
+        SET username = $1, password = COALESCE($2, password), role = $3, department = $4, departments = $5
+        WHERE id = $6
+        RETURNING *
+        "#
+    )
+    .bind(&user_req.username)
+    .bind(password_hash)
+    .bind(&user_req.role)
+    .bind(departments.first())
+    .bind(departments)
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if password_hash.is_some() {
+        revoke_user_sessions(&mut *conn, id, keep_token).await?;
+    }
+
+    Ok(Some((previous, admin_user)))
+}
+
 pub async fn delete_admin_user(
     pool: web::Data<PgPool>,
     req: HttpRequest,
     path: web::Path<i32>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::AdminUsersManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
     let id = path.into_inner();
 
-    // Only admin role can delete admin users
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Insufficient permissions"));
-    }
-
     // Prevent deleting your own account
     if session.user_id == id {
         return HttpResponse::BadRequest().json(ApiResponse::<()>::error("Cannot delete your own account"));
     }
 
-    match sqlx::query("DELETE FROM admin_user WHERE id = $1")
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete admin user"));
+        }
+    };
+
+    // The user's sessions are removed with it by the admin_sessions foreign key
+    let deleted = match sqlx::query_as::<_, AdminUser>("DELETE FROM admin_user WHERE id = $1 RETURNING *")
         .bind(id)
-        .execute(pool.as_ref())
+        .fetch_optional(&mut *tx)
         .await
     {
-        Ok(result) => {
-            if result.rows_affected() > 0 {
-                HttpResponse::Ok().json(ApiResponse::<()>::success((), "Admin user deleted"))
-            } else {
-                HttpResponse::NotFound().json(ApiResponse::<()>::error("Admin user not found"))
-            }
-        }
+        Ok(Some(deleted)) => AdminUserResponse::from(deleted),
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Admin user not found")),
         Err(e) => {
             log::error!("Failed to delete admin user: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete admin user"));
+        }
+    };
+
+    let change = Change::new("admin_user_delete", "admin_user", id).before(&deleted);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::<()>::success((), "Admin user deleted")),
+        Err(e) => {
+            log::error!("Failed to commit admin user deletion: {:?}", e);
             HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete admin user"))
         }
     }
@@ -230,34 +296,115 @@ pub async fn reset_admin_password(
     path: web::Path<i32>,
     password_req: web::Json<ResetPasswordRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::AdminUsersManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
     let id = path.into_inner();
 
-    // Only admin role can reset passwords
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Insufficient permissions"));
-    }
+    let password_hash = match hash_password(&password_req.new_password) {
+        Ok(hash) => hash,
+        Err(e) => {
+            log::error!("Failed to hash admin password: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to hash password"));
+        }
+    };
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to reset password"));
+        }
+    };
 
-    match sqlx::query("UPDATE admin_user SET password = $1 WHERE id = $2")
-        .bind(&password_req.new_password)
+    let updated = match sqlx::query("UPDATE admin_user SET password = $1 WHERE id = $2")
+        .bind(&password_hash)
         .bind(id)
-        .execute(pool.as_ref())
+        .execute(&mut *tx)
         .await
     {
-        Ok(result) => {
-            if result.rows_affected() > 0 {
-                HttpResponse::Ok().json(ApiResponse::<()>::success((), "Password reset successfully"))
-            } else {
-                HttpResponse::NotFound().json(ApiResponse::<()>::error("Admin user not found"))
-            }
-        }
+        Ok(result) => result.rows_affected() > 0,
         Err(e) => {
             log::error!("Failed to reset admin password: {:?}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to reset password"));
+        }
+    };
+
+    if !updated {
+        let _ = tx.rollback().await;
+        return HttpResponse::NotFound().json(ApiResponse::<()>::error("Admin user not found"));
+    }
+
+    // Everyone signed in with the old password is signed out; an admin resetting
+    // their own password keeps the session they are using
+    if let Err(e) = revoke_user_sessions(&mut *tx, id, bearer_token(&req)).await {
+        log::error!("Failed to revoke admin sessions: {:?}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to reset password"));
+    }
+
+    // The password itself is never recorded, only that it changed
+    let change = Change::new("admin_user_password_reset", "admin_user", id);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::<()>::success((), "Password reset successfully")),
+        Err(e) => {
+            log::error!("Failed to commit password reset: {:?}", e);
             HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to reset password"))
         }
     }
-}
\ No newline at end of file
+}
+
+pub async fn revoke_admin_sessions(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::AdminUsersManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke sessions"));
+        }
+    };
+
+    match sqlx::query_scalar::<_, i32>("SELECT id FROM admin_user WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Admin user not found")),
+        Err(e) => {
+            log::error!("Failed to look up admin user: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke sessions"));
+        }
+    }
+
+    let revoked = match revoke_user_sessions(&mut *tx, id, None).await {
+        Ok(revoked) => RevokeSessionsResponse { revoked },
+        Err(e) => {
+            log::error!("Failed to revoke admin sessions: {:?}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke sessions"));
+        }
+    };
+
+    let change = Change::new("admin_user_sessions_revoke", "admin_user", id).after(&revoked);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success(revoked, "Sessions revoked")),
+        Err(e) => {
+            log::error!("Failed to commit session revocation: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke sessions"))
+        }
+    }
+}
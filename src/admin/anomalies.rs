@@ -0,0 +1,126 @@
+use actix_web::{web, HttpResponse, HttpRequest};
+use sqlx::PgPool;
+
+use crate::admin::auth::require_capability;
+use crate::admin::models::{AnomalyQuery, PunchAnomaly, ReviewAnomalyRequest};
+use crate::anomalies::AnomalyKind;
+use crate::models::ApiResponse;
+use crate::permissions::Capability;
+
+pub async fn get_anomalies(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    query: web::Query<AnomalyQuery>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::AnomaliesRead).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    // The queue shows open anomalies unless asked otherwise; "all" lifts the filter
+    let status = match query.status.as_deref() {
+        None => Some("open"),
+        Some("all") => None,
+        Some(status @ ("open" | "resolved" | "dismissed")) => Some(status),
+        Some(_) => return HttpResponse::BadRequest().json(ApiResponse::<()>::error("status must be 'open', 'resolved', 'dismissed' or 'all'")),
+    };
+
+    if let Some(anomaly_type) = &query.anomaly_type {
+        if AnomalyKind::parse(anomaly_type).is_none() {
+            return HttpResponse::BadRequest().json(ApiResponse::<()>::error("Unknown anomaly_type"));
+        }
+    }
+
+    match sqlx::query_as::<_, PunchAnomaly>(
+        r#"
+        SELECT * FROM punch_anomalies
+        WHERE ($1::text IS NULL OR status = $1)
+          AND ($2::text IS NULL OR user_id = $2)
+          AND ($3::text IS NULL OR anomaly_type = $3)
+          AND ($5::int[] IS NULL OR user_id IN (SELECT user_id FROM user_info WHERE department = ANY($5)))
+        ORDER BY occurred_at DESC
+        LIMIT $4
+        "#
+    )
+    .bind(status)
+    .bind(&query.user_id)
+    .bind(&query.anomaly_type)
+    .bind(query.limit.unwrap_or(100))
+    .bind(session.scope().departments())
+    .fetch_all(pool.as_ref())
+    .await
+    {
+        Ok(anomalies) => HttpResponse::Ok().json(ApiResponse::success(anomalies, "Anomalies retrieved")),
+        Err(e) => {
+            log::error!("Failed to retrieve anomalies: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve anomalies"))
+        }
+    }
+}
+
+pub async fn resolve_anomaly(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    review_req: web::Json<ReviewAnomalyRequest>,
+) -> HttpResponse {
+    review_anomaly(pool, req, path.into_inner(), review_req.into_inner(), "resolved").await
+}
+
+pub async fn dismiss_anomaly(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    review_req: web::Json<ReviewAnomalyRequest>,
+) -> HttpResponse {
+    review_anomaly(pool, req, path.into_inner(), review_req.into_inner(), "dismissed").await
+}
+
+/// Close an open anomaly with the given status, recording who reviewed it
+async fn review_anomaly(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    id: i32,
+    review_req: ReviewAnomalyRequest,
+    status: &str,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::AnomaliesReview).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let result = sqlx::query_as::<_, PunchAnomaly>(
+        r#"
+        UPDATE punch_anomalies
+        SET status = $1, review_note = $2, reviewed_by = $3, reviewed_at = NOW()
+        WHERE id = $4 AND status = 'open'
+        RETURNING *
+        "#
+    )
+    .bind(status)
+    .bind(&review_req.note)
+    .bind(&session.username)
+    .bind(id)
+    .fetch_optional(pool.as_ref())
+    .await;
+
+    match result {
+        Ok(Some(anomaly)) => HttpResponse::Ok().json(ApiResponse::success(anomaly, &format!("Anomaly {}", status))),
+        Ok(None) => {
+            // Tell apart a missing anomaly from one that was already reviewed
+            match sqlx::query_scalar::<_, String>("SELECT status FROM punch_anomalies WHERE id = $1")
+                .bind(id)
+                .fetch_optional(pool.as_ref())
+                .await
+            {
+                Ok(Some(current)) => HttpResponse::Conflict().json(ApiResponse::<()>::error(&format!("Anomaly is already {}", current))),
+                Ok(None) => HttpResponse::NotFound().json(ApiResponse::<()>::error("Anomaly not found")),
+                Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to review anomaly")),
+            }
+        }
+        Err(e) => {
+            log::error!("Failed to review anomaly {}: {:?}", id, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to review anomaly"))
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use actix_web::{web, HttpResponse, HttpRequest};
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::admin::auth::require_capability;
+use crate::admin::models::{CreateApiKeyRequest, CreatedApiKeyResponse};
+use crate::api_keys;
+use crate::audit::{self, Change};
+use crate::models::ApiResponse;
+use crate::permissions::Capability;
+
+/// Longest service account name accepted
+const MAX_NAME_LENGTH: usize = 100;
+
+pub async fn get_api_keys(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Err(response) = require_capability(&req, Capability::AdminUsersManage).await {
+        return response;
+    }
+
+    match api_keys::list(pool.as_ref()).await {
+        Ok(keys) => HttpResponse::Ok().json(ApiResponse::success(keys, "API keys retrieved")),
+        Err(e) => {
+            log::error!("Failed to retrieve API keys: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve API keys"))
+        }
+    }
+}
+
+pub async fn create_api_key(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    key_req: web::Json<CreateApiKeyRequest>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::AdminUsersManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let name = key_req.name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error(&format!(
+            "name must be 1 to {} characters", MAX_NAME_LENGTH
+        )));
+    }
+
+    let scopes = match api_keys::parse_scopes(&key_req.scopes) {
+        Ok(scopes) if !scopes.is_empty() => scopes,
+        Ok(_) => return HttpResponse::BadRequest().json(ApiResponse::<()>::error("At least one scope is required")),
+        Err(scope) => {
+            return HttpResponse::BadRequest().json(ApiResponse::<()>::error(&format!(
+                "Scope {} cannot be granted to API keys", scope
+            )));
+        }
+    };
+
+    if key_req.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error("expires_at must be in the future"));
+    }
+
+    let mut departments = key_req.departments.clone().unwrap_or_default();
+    departments.sort_unstable();
+    departments.dedup();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create API key"));
+        }
+    };
+
+    let key = api_keys::generate_key();
+    let api_key = match api_keys::create(&mut *tx, name, &key, &scopes, &departments, key_req.expires_at, &session.username).await {
+        Ok(api_key) => api_key,
+        Err(e) => {
+            log::error!("Failed to create API key: {:?}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create API key"));
+        }
+    };
+
+    let change = Change::new("api_key_create", "api_key", api_key.id).after(&api_key);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => {
+            log::info!("Admin {} created API key {} for {}", session.username, api_key.key_prefix, api_key.name);
+            HttpResponse::Created().json(ApiResponse::success(
+                CreatedApiKeyResponse { key, api_key },
+                "API key created; store it now, it will not be shown again"
+            ))
+        }
+        Err(e) => {
+            log::error!("Failed to commit API key: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create API key"))
+        }
+    }
+}
+
+pub async fn revoke_api_key(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::AdminUsersManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke API key"));
+        }
+    };
+
+    let api_key = match api_keys::revoke(&mut *tx, id).await {
+        Ok(Some(api_key)) => api_key,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Active API key not found")),
+        Err(e) => {
+            log::error!("Failed to revoke API key {}: {:?}", id, e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke API key"));
+        }
+    };
+
+    let change = Change::new("api_key_revoke", "api_key", id).after(&api_key);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => {
+            log::info!("Admin {} revoked API key {} of {}", session.username, api_key.key_prefix, api_key.name);
+            HttpResponse::Ok().json(ApiResponse::success(api_key, "API key revoked"))
+        }
+        Err(e) => {
+            log::error!("Failed to commit API key revocation: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke API key"))
+        }
+    }
+}
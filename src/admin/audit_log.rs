@@ -0,0 +1,25 @@
+use actix_web::{web, HttpResponse, HttpRequest};
+use sqlx::PgPool;
+
+use crate::admin::auth::require_capability;
+use crate::audit::{self, AuditQuery};
+use crate::models::ApiResponse;
+use crate::permissions::Capability;
+
+pub async fn get_audit_log(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    query: web::Query<AuditQuery>,
+) -> HttpResponse {
+    if let Err(response) = require_capability(&req, Capability::AuditRead).await {
+        return response;
+    }
+
+    match audit::search(pool.as_ref(), &query).await {
+        Ok(page) => HttpResponse::Ok().json(ApiResponse::success(page, "Audit log retrieved")),
+        Err(e) => {
+            log::error!("Failed to retrieve audit log: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve audit log"))
+        }
+    }
+}
@@ -1,56 +1,210 @@
 use actix_web::{web, HttpResponse, HttpRequest};
-use sqlx::PgPool;
-use std::collections::HashMap;
-use std::sync::Mutex;
-use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
 use lazy_static::lazy_static;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
 
 use crate::admin::models::*;
+use crate::admin::two_factor;
+use crate::api_keys;
+use crate::lockout::{self, LockScope};
 use crate::models::ApiResponse;
+use crate::password::{hash_password, token_hash, verify_dummy_password, verify_password, PasswordCheck};
+use crate::permissions::Capability;
+
+/// Default time an admin session survives without requests
+pub const DEFAULT_SESSION_IDLE_MINUTES: i64 = 60;
+/// Default time after login at which an admin session ends regardless of activity
+pub const DEFAULT_SESSION_MAX_HOURS: i64 = 12;
 
 lazy_static! {
-    static ref ADMIN_SESSIONS: Mutex<HashMap<String, AdminSession>> = Mutex::new(HashMap::new());
+    static ref ADMIN_SESSION_CONFIG: AdminSessionConfig = AdminSessionConfig::from_env();
+}
+
+/// Expiry of admin sessions
+#[derive(Debug, Clone)]
+pub struct AdminSessionConfig {
+    pub idle_timeout: Duration,
+    pub max_lifetime: Duration,
+}
+
+impl Default for AdminSessionConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::minutes(DEFAULT_SESSION_IDLE_MINUTES),
+            max_lifetime: Duration::hours(DEFAULT_SESSION_MAX_HOURS),
+        }
+    }
+}
+
+impl AdminSessionConfig {
+    /// Read ADMIN_SESSION_IDLE_MINUTES and ADMIN_SESSION_MAX_HOURS, falling back to the defaults
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+
+        if let Ok(value) = std::env::var("ADMIN_SESSION_IDLE_MINUTES") {
+            match value.parse::<i64>() {
+                Ok(minutes) if minutes > 0 => config.idle_timeout = Duration::minutes(minutes),
+                _ => log::warn!("Ignoring invalid ADMIN_SESSION_IDLE_MINUTES: {}", value),
+            }
+        }
+
+        if let Ok(value) = std::env::var("ADMIN_SESSION_MAX_HOURS") {
+            match value.parse::<i64>() {
+                Ok(hours) if hours > 0 => config.max_lifetime = Duration::hours(hours),
+                _ => log::warn!("Ignoring invalid ADMIN_SESSION_MAX_HOURS: {}", value),
+            }
+        }
+
+        config
+    }
+
+    /// Process-wide configuration loaded from the environment on first use
+    pub fn global() -> &'static AdminSessionConfig {
+        &ADMIN_SESSION_CONFIG
+    }
+}
+
+/// Start a session for an admin user, returning its token and absolute expiry
+pub async fn create_admin_session(
+    pool: &PgPool,
+    user_id: i32,
+    req: &HttpRequest,
+) -> Result<(String, DateTime<Utc>), sqlx::Error> {
+    let token = Uuid::new_v4().to_string();
+    let expires_at = Utc::now() + AdminSessionConfig::global().max_lifetime;
+    let ip_address = lockout::client_ip(req);
+
+    sqlx::query(
+        r#"
+        INSERT INTO admin_sessions (token_hash, admin_user_id, expires_at, ip_address)
+        VALUES ($1, $2, $3, $4)
+        "#
+    )
+    .bind(token_hash(&token))
+    .bind(user_id)
+    .bind(expires_at)
+    .bind(ip_address)
+    .execute(pool)
+    .await?;
+
+    Ok((token, expires_at))
+}
+
+/// Revoke every live session of an admin user, optionally keeping one token.
+/// Returns the number of sessions revoked.
+pub async fn revoke_user_sessions<'e, E: PgExecutor<'e>>(
+    executor: E,
+    user_id: i32,
+    keep_token: Option<&str>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE admin_sessions
+        SET revoked_at = NOW()
+        WHERE admin_user_id = $1
+          AND revoked_at IS NULL
+          AND ($2::text IS NULL OR token_hash <> $2)
+        "#
+    )
+    .bind(user_id)
+    .bind(keep_token.map(token_hash))
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Delete sessions that expired or were revoked more than a day ago
+pub async fn purge_admin_sessions(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM admin_sessions
+        WHERE expires_at < NOW() - INTERVAL '1 day'
+           OR revoked_at < NOW() - INTERVAL '1 day'
+           OR last_seen_at < NOW() - make_interval(secs => $1) - INTERVAL '1 day'
+        "#
+    )
+    .bind(AdminSessionConfig::global().idle_timeout.num_seconds() as f64)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
 }
 
 pub async fn admin_login(
     pool: web::Data<PgPool>,
+    http_req: HttpRequest,
     req: web::Json<AdminLoginRequest>,
 ) -> HttpResponse {
-    match sqlx::query_as::<_, AdminUser>(
-        "SELECT * FROM admin_user WHERE username = $1 AND password = $2"
+    let ip = lockout::client_ip(&http_req);
+    let subjects = [(LockScope::Ip, ip.as_str()), (LockScope::AdminUser, req.username.as_str())];
+
+    match lockout::locked_until(&pool, &subjects).await {
+        Ok(Some(until)) => return lockout::too_many_attempts(until),
+        Ok(None) => {}
+        Err(e) => {
+            log::error!("Failed to check login lockout: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    }
+
+    let user = match sqlx::query_as::<_, AdminUser>(
+        "SELECT * FROM admin_user WHERE username = $1"
     )
     .bind(&req.username)
-    .bind(&req.password)
     .fetch_optional(pool.as_ref())
     .await
     {
+        Ok(Some(user)) => match verify_password(&req.password, &user.password) {
+            PasswordCheck::Valid => Ok(Some(user)),
+            PasswordCheck::ValidLegacy => {
+                upgrade_legacy_password(&pool, user.id, &req.password).await;
+                Ok(Some(user))
+            }
+            PasswordCheck::Invalid => Ok(None),
+        },
+        Ok(None) => {
+            // Unknown usernames cost an Argon2 check too, so timing does not reveal which exist
+            verify_dummy_password(&req.password);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    };
+
+    match user {
         Ok(Some(user)) => {
-            let token = Uuid::new_v4().to_string();
+            // Accounts with a second factor get a challenge instead of a session
+            if two_factor::login_requires_totp(&user) {
+                return match two_factor::create_challenge(&pool, user.id).await {
+                    Ok((challenge_token, expires_at)) => HttpResponse::Ok().json(ApiResponse::success(
+                        AdminLoginChallengeResponse {
+                            challenge_token,
+                            expires_at,
+                            enrollment_required: user.totp_enabled_at.is_none(),
+                        },
+                        "Two-factor authentication required"
+                    )),
+                    Err(e) => {
+                        log::error!("Failed to create login challenge: {:?}", e);
+                        HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"))
+                    }
+                };
+            }
+
             let session = AdminSession {
                 user_id: user.id,
-                username: user.username.clone(),
-                role: user.role.clone(),
-                department: user.department,
-            };
-            
-            let mut sessions = ADMIN_SESSIONS.lock().unwrap();
-            sessions.insert(token.clone(), session);
-            
-            let user_info = AdminUserInfo {
-                id: user.id,
                 username: user.username,
                 role: user.role,
-                department: user.department,
+                departments: user.departments,
+                api_key_scopes: None,
             };
-            
-            let response = AdminLoginResponse {
-                token,
-                user: user_info,
-            };
-            
-            HttpResponse::Ok().json(ApiResponse::success(response, "Login successful"))
+            complete_login(&pool, &http_req, session, None).await
         }
         Ok(None) => {
+            if let Err(e) = lockout::record_failure(&pool, &subjects).await {
+                log::error!("Failed to record failed login: {:?}", e);
+            }
             HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid credentials"))
         }
         Err(_) => {
@@ -59,42 +213,180 @@ pub async fn admin_login(
     }
 }
 
+/// Start a session for a user who passed every login step and clear their failed logins
+pub async fn complete_login(
+    pool: &PgPool,
+    req: &HttpRequest,
+    session: AdminSession,
+    recovery_codes: Option<Vec<String>>,
+) -> HttpResponse {
+    if let Err(e) = lockout::clear(pool, LockScope::AdminUser, &session.username).await {
+        log::error!("Failed to clear failed logins of {}: {:?}", session.username, e);
+    }
+
+    let (token, expires_at) = match create_admin_session(pool, session.user_id, req).await {
+        Ok(session) => session,
+        Err(e) => {
+            log::error!("Failed to create admin session: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    };
+
+    let response = AdminLoginResponse {
+        token,
+        expires_at,
+        user: AdminUserInfo::from(session),
+        recovery_codes,
+    };
+
+    HttpResponse::Ok().json(ApiResponse::success(response, "Login successful"))
+}
+
+/// Replace a plaintext password from before hashing with its hash.
+/// Failure is only logged; the login itself already succeeded.
+async fn upgrade_legacy_password(pool: &PgPool, user_id: i32, password: &str) {
+    let hash = match hash_password(password) {
+        Ok(hash) => hash,
+        Err(e) => {
+            log::error!("Failed to hash password for admin user {}: {:?}", user_id, e);
+            return;
+        }
+    };
+
+    match sqlx::query("UPDATE admin_user SET password = $1 WHERE id = $2")
+        .bind(&hash)
+        .bind(user_id)
+        .execute(pool)
+        .await
+    {
+        Ok(_) => log::info!("Upgraded plaintext password of admin user {} to a hash", user_id),
+        Err(e) => log::error!("Failed to store password hash for admin user {}: {:?}", user_id, e),
+    }
+}
+
 pub async fn get_admin_info(req: HttpRequest) -> HttpResponse {
-    if let Some(session) = get_session_from_request(&req) {
-        let user_info = AdminUserInfo {
-            id: session.user_id,
-            username: session.username,
-            role: session.role,
-            department: session.department,
-        };
-        HttpResponse::Ok().json(ApiResponse::success(user_info, "User info retrieved"))
-    } else {
-        HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or expired token"))
+    match require_admin_auth(&req).await {
+        Ok(session) => {
+            HttpResponse::Ok().json(ApiResponse::success(AdminUserInfo::from(session), "User info retrieved"))
+        }
+        Err(response) => response,
+    }
+}
+
+pub async fn admin_logout(pool: web::Data<PgPool>, req: HttpRequest) -> HttpResponse {
+    let token = match bearer_token(&req) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Authentication required")),
+    };
+
+    match sqlx::query("UPDATE admin_sessions SET revoked_at = NOW() WHERE token_hash = $1 AND revoked_at IS NULL")
+        .bind(token_hash(token))
+        .execute(pool.as_ref())
+        .await
+    {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::<()>::success((), "Logged out")),
+        Err(e) => {
+            log::error!("Failed to revoke admin session: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to log out"))
+        }
     }
 }
 
-pub fn verify_admin_token(token: &str) -> Option<AdminSession> {
-    let sessions = ADMIN_SESSIONS.lock().unwrap();
-    sessions.get(token).cloned()
+/// Look up a live session by token, sliding its idle expiry forward
+pub async fn verify_admin_token(pool: &PgPool, token: &str) -> Result<Option<AdminSession>, sqlx::Error> {
+    sqlx::query_as::<_, AdminSession>(
+        r#"
+        UPDATE admin_sessions s
+        SET last_seen_at = NOW()
+        FROM admin_user u
+        WHERE s.token_hash = $1
+          AND u.id = s.admin_user_id
+          AND s.revoked_at IS NULL
+          AND s.expires_at > NOW()
+          AND s.last_seen_at > NOW() - make_interval(secs => $2)
+        RETURNING u.id AS user_id, u.username, u.role, u.departments
+        "#
+    )
+    .bind(token_hash(token))
+    .bind(AdminSessionConfig::global().idle_timeout.num_seconds() as f64)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Token from an `Authorization: Bearer` header
+pub fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
 }
 
-pub fn get_session_from_request(req: &HttpRequest) -> Option<AdminSession> {
-    if let Some(auth_header) = req.headers().get("Authorization") {
-        if let Ok(auth_str) = auth_header.to_str() {
-            if auth_str.starts_with("Bearer ") {
-                let token = &auth_str[7..];
-                return verify_admin_token(token);
+/// Key from the API key header
+pub fn api_key(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(api_keys::API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+}
+
+// Helper function to check authentication in handlers. Accepts an admin session token
+// or a service account's API key, which is checked first when both are sent.
+pub async fn require_admin_auth(req: &HttpRequest) -> Result<AdminSession, HttpResponse> {
+    let pool = match req.app_data::<web::Data<PgPool>>() {
+        Some(pool) => pool,
+        None => {
+            log::error!("Database pool missing from app data");
+            return Err(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")));
+        }
+    };
+
+    if let Some(key) = api_key(req) {
+        return match api_keys::verify(pool, key).await {
+            Ok(Some(key)) => Ok(AdminSession::from(key)),
+            Ok(None) => Err(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or expired API key"))),
+            Err(e) => {
+                log::error!("Failed to verify API key: {:?}", e);
+                Err(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")))
             }
+        };
+    }
+
+    let token = match bearer_token(req) {
+        Some(token) => token,
+        None => return Err(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Authentication required"))),
+    };
+
+    match verify_admin_token(pool, token).await {
+        Ok(Some(session)) => Ok(session),
+        Ok(None) => Err(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or expired token"))),
+        Err(e) => {
+            log::error!("Failed to verify admin session: {:?}", e);
+            Err(HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")))
         }
     }
-    None
 }
 
-// Helper function to check authentication in handlers
-pub fn require_admin_auth(req: &HttpRequest) -> Result<AdminSession, HttpResponse> {
-    if let Some(session) = get_session_from_request(req) {
-        Ok(session)
-    } else {
-        Err(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Authentication required")))
+/// Authenticate an admin user signed in with their password; API keys belong to no
+/// admin user, so they cannot use endpoints about the signed-in account
+pub async fn require_user_session(req: &HttpRequest) -> Result<AdminSession, HttpResponse> {
+    let session = require_admin_auth(req).await?;
+
+    if session.api_key_scopes.is_some() {
+        return Err(HttpResponse::Forbidden().json(ApiResponse::<()>::error("Not available to API keys")));
     }
-}
\ No newline at end of file
+
+    Ok(session)
+}
+
+/// Authenticate the request and check that the admin user's role grants `capability`.
+/// Handlers limited to some departments check those against `AdminSession::scope`.
+pub async fn require_capability(req: &HttpRequest, capability: Capability) -> Result<AdminSession, HttpResponse> {
+    let session = require_admin_auth(req).await?;
+
+    if !session.can(capability) {
+        return Err(HttpResponse::Forbidden().json(ApiResponse::<()>::error(&format!(
+            "Permission denied: {} required", capability.as_str()
+        ))));
+    }
+
+    Ok(session)
+}
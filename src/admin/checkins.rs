@@ -1,51 +1,43 @@
 use actix_web::{web, HttpResponse, HttpRequest};
-use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use std::collections::BTreeMap;
 
-use crate::admin::auth::require_admin_auth;
-use crate::admin::models::{CreateCheckinRequest, UpdateCheckinRequest};
+use crate::admin::auth::require_capability;
+use crate::admin::models::{CheckinMutationResponse, CreateCheckinRequest, UpdateCheckinRequest, UserSessionChanges};
+use crate::audit::{self, Change};
 use crate::models::{ApiResponse, Checkin};
+use crate::permissions::{Capability, DepartmentScope};
+use crate::sessions::{self, SessionConfig};
 
 pub async fn get_checkins(
     pool: web::Data<PgPool>,
     req: HttpRequest,
     query: web::Query<CheckinQuery>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::CheckinsRead).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
-    }
+    let filter = CheckinFilter::new(&query, &session.scope());
 
-    let mut sql = "SELECT * FROM checkins".to_string();
-    let mut conditions = Vec::new();
-    
-    if let Some(user_id) = &query.user_id {
-        conditions.push(format!("user_id = '{}'", user_id));
-    }
-    
-    if let Some(action) = &query.action {
-        conditions.push(format!("action = '{}'", action));
-    }
-    
-    if !conditions.is_empty() {
-        sql.push_str(" WHERE ");
-        sql.push_str(&conditions.join(" AND "));
-    }
-    
-    sql.push_str(" ORDER BY created_at DESC");
-    
-    if let Some(limit) = query.limit {
-        sql.push_str(&format!(" LIMIT {}", limit));
-    } else {
-        sql.push_str(" LIMIT 100"); // Default limit
-    }
-
-    match sqlx::query_as::<_, Checkin>(&sql)
-        .fetch_all(pool.as_ref())
-        .await
+    match sqlx::query_as::<_, Checkin>(
+        r#"
+        SELECT * FROM checkins
+        WHERE ($1::text IS NULL OR user_id = $1)
+          AND ($2::text IS NULL OR action = $2)
+          AND ($3::int[] IS NULL OR user_id IN (SELECT user_id FROM user_info WHERE department = ANY($3)))
+        ORDER BY created_at DESC
+        LIMIT $4
+        "#
+    )
+    .bind(&filter.user_id)
+    .bind(&filter.action)
+    .bind(&filter.departments)
+    .bind(filter.limit)
+    .fetch_all(pool.as_ref())
+    .await
     {
         Ok(checkins) => HttpResponse::Ok().json(ApiResponse::success(checkins, "Checkins retrieved")),
         Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve checkins")),
@@ -57,15 +49,11 @@ pub async fn create_checkin(
     req: HttpRequest,
     checkin_req: web::Json<CreateCheckinRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::CheckinsWrite).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
-    }
-
     // Start a transaction to ensure atomicity
     let mut tx = match pool.begin().await {
         Ok(tx) => tx,
@@ -97,145 +85,56 @@ pub async fn create_checkin(
         }
     };
 
-    // Now process the checkin into attendance_sessions using the same logic as sync endpoint
-    let date = checkin_req.created_at.date_naive();
-    
-    // Get the current max session number for this user and date
-    let mut session_number = 
-        match sqlx::query_scalar::<_, i32>(
-            "SELECT COALESCE(MAX(session_number), 0) FROM attendance_sessions 
-             WHERE user_id = $1 AND date = $2"
-        )
-        .bind(&checkin_req.user_id)
-        .bind(date)
-        .fetch_one(&mut *tx)
-        .await {
-            Ok(num) => num,
-            Err(_) => 0,
-        };
-
-    // Process the checkin action
-    if checkin_req.action == "IN" {
-        // Check if there's an incomplete session (no checkout)
-        let has_incomplete_session = sqlx::query_scalar::<_, bool>(
-            "SELECT EXISTS(SELECT 1 FROM attendance_sessions 
-             WHERE user_id = $1 AND date = $2 AND checkout_time IS NULL)"
-        )
-        .bind(&checkin_req.user_id)
-        .bind(date)
-        .fetch_one(&mut *tx)
-        .await
-        .unwrap_or(false);
-
-        if !has_incomplete_session {
-            // Start new session
-            session_number += 1;
-            let result = sqlx::query(
-                "INSERT INTO attendance_sessions 
-                 (user_id, date, session_number, checkin_time, checkin_latitude, checkin_longitude) 
-                 VALUES ($1, $2, $3, $4, $5, $6)
-                 ON CONFLICT (user_id, date, session_number) 
-                 DO UPDATE SET 
-                    checkin_time = EXCLUDED.checkin_time,
-                    checkin_latitude = EXCLUDED.checkin_latitude,
-                    checkin_longitude = EXCLUDED.checkin_longitude"
-            )
-            .bind(&checkin_req.user_id)
-            .bind(date)
-            .bind(session_number)
-            .bind(checkin_req.created_at)
-            .bind(checkin_req.latitude)
-            .bind(checkin_req.longitude)
-            .execute(&mut *tx)
-            .await;
-
-            if result.is_err() {
-                let _ = tx.rollback().await;
-                return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create session"));
-            }
-        }
-    } else if checkin_req.action == "OUT" {
-        // Try to close an existing incomplete session (including previous day for midnight crossings)
-        let update_result = sqlx::query(
-            "UPDATE attendance_sessions 
-             SET checkout_time = $1, checkout_latitude = $2, checkout_longitude = $3
-             WHERE user_id = $4 
-                AND date IN ($5, $5 - INTERVAL '1 day')
-                AND checkout_time IS NULL
-                AND checkin_time < $1 + INTERVAL '16 hours'  -- Max session duration
-             ORDER BY checkin_time DESC
-             LIMIT 1"
-        )
-        .bind(checkin_req.created_at)
-        .bind(checkin_req.latitude)
-        .bind(checkin_req.longitude)
-        .bind(&checkin_req.user_id)
-        .bind(date)
-        .execute(&mut *tx)
-        .await;
-
-        match update_result {
-            Ok(result) if result.rows_affected() == 0 => {
-                // No incomplete session found, create a checkout-only session
-                session_number += 1;
-                let result = sqlx::query(
-                    "INSERT INTO attendance_sessions 
-                     (user_id, date, session_number, checkin_time, checkout_time, checkout_latitude, checkout_longitude) 
-                     VALUES ($1, $2, $3, $4, $5, $6, $7)
-                     ON CONFLICT (user_id, date, session_number) DO NOTHING"
-                )
-                .bind(&checkin_req.user_id)
-                .bind(date)
-                .bind(session_number)
-                .bind(checkin_req.created_at) // Use checkout time as checkin for incomplete session
-                .bind(checkin_req.created_at)
-                .bind(checkin_req.latitude)
-                .bind(checkin_req.longitude)
-                .execute(&mut *tx)
-                .await;
-
-                if result.is_err() {
-                    let _ = tx.rollback().await;
-                    return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create incomplete session"));
-                }
-            }
-            Err(_) => {
-                let _ = tx.rollback().await;
-                return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update session"));
-            }
-            _ => {} // Successfully updated existing session
+    // Now process the checkin into attendance_sessions using the same engine as the sync endpoint
+    let session_changes = match rebuild_sessions_for(&mut tx, &[(&checkin.user_id, checkin.created_at)]).await {
+        Ok(changes) => changes,
+        Err(e) => {
+            log::error!("Failed to rebuild sessions for user {}: {:?}", checkin.user_id, e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to process sessions"));
         }
-    }
+    };
 
     // Commit the transaction - attendance_summary will be automatically updated by triggers
-    match tx.commit().await {
-        Ok(_) => HttpResponse::Created().json(ApiResponse::success(checkin, "Checkin created and processed into sessions")),
+    let change = Change::new("checkin_create", "checkin", checkin.id).after(&checkin);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Created().json(ApiResponse::success(
+            CheckinMutationResponse { checkin: Some(checkin), session_changes },
+            "Checkin created and processed into sessions"
+        )),
         Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to commit transaction")),
     }
 }
 
-// Note: Updating checkins is complex as it may require reprocessing attendance sessions.
-// For now, this function only updates the checkin record itself.
-// If you need to update attendance data, consider deleting and recreating the checkin,
-// or manually run the populate_sessions_from_checkins.sql script.
 pub async fn update_checkin(
     pool: web::Data<PgPool>,
     req: HttpRequest,
     path: web::Path<i32>,
     checkin_req: web::Json<UpdateCheckinRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::CheckinsWrite).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
-    }
-
     let id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Transaction failed")),
+    };
+
+    let previous = match sqlx::query_as::<_, Checkin>("SELECT * FROM checkins WHERE id = $1 FOR UPDATE")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(previous)) => previous,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Checkin not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update checkin")),
+    };
     
-    match sqlx::query_as::<_, Checkin>(
+    let checkin = match sqlx::query_as::<_, Checkin>(
         r#"
         UPDATE checkins 
         SET user_id = $1, action = $2, created_at = $3, latitude = $4, longitude = $5, is_synced = $6
@@ -250,12 +149,37 @@ pub async fn update_checkin(
     .bind(checkin_req.longitude)
     .bind(checkin_req.is_synced)
     .bind(id)
-    .fetch_optional(pool.as_ref())
+    .fetch_one(&mut *tx)
     .await
     {
-        Ok(Some(checkin)) => HttpResponse::Ok().json(ApiResponse::success(checkin, "Checkin updated")),
-        Ok(None) => HttpResponse::NotFound().json(ApiResponse::<()>::error("Checkin not found")),
-        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update checkin")),
+        Ok(checkin) => checkin,
+        Err(_) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update checkin"));
+        }
+    };
+
+    // Re-pair both where the punch was and where it is now
+    let punches = [
+        (previous.user_id.as_str(), previous.created_at),
+        (checkin.user_id.as_str(), checkin.created_at),
+    ];
+    let session_changes = match rebuild_sessions_for(&mut tx, &punches).await {
+        Ok(changes) => changes,
+        Err(e) => {
+            log::error!("Failed to rebuild sessions after updating checkin {}: {:?}", id, e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to process sessions"));
+        }
+    };
+
+    let change = Change::new("checkin_update", "checkin", id).before(&previous).after(&checkin);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success(
+            CheckinMutationResponse { checkin: Some(checkin), session_changes },
+            "Checkin updated"
+        )),
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to commit transaction")),
     }
 }
 
@@ -264,31 +188,75 @@ pub async fn delete_checkin(
     req: HttpRequest,
     path: web::Path<i32>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::CheckinsWrite).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
-    }
-
     let id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Transaction failed")),
+    };
     
-    match sqlx::query("DELETE FROM checkins WHERE id = $1")
+    let deleted = match sqlx::query_as::<_, Checkin>("DELETE FROM checkins WHERE id = $1 RETURNING *")
         .bind(id)
-        .execute(pool.as_ref())
+        .fetch_optional(&mut *tx)
         .await
     {
-        Ok(result) => {
-            if result.rows_affected() > 0 {
-                HttpResponse::Ok().json(ApiResponse::<()>::success((), "Checkin deleted"))
-            } else {
-                HttpResponse::NotFound().json(ApiResponse::<()>::error("Checkin not found"))
-            }
-        },
-        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete checkin")),
+        Ok(Some(deleted)) => deleted,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Checkin not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete checkin")),
+    };
+
+    let session_changes = match rebuild_sessions_for(&mut tx, &[(&deleted.user_id, deleted.created_at)]).await {
+        Ok(changes) => changes,
+        Err(e) => {
+            log::error!("Failed to rebuild sessions after deleting checkin {}: {:?}", id, e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to process sessions"));
+        }
+    };
+
+    let change = Change::new("checkin_delete", "checkin", id).before(&deleted);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success(
+            CheckinMutationResponse { checkin: None, session_changes },
+            "Checkin deleted"
+        )),
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to commit transaction")),
+    }
+}
+
+/// Rebuild the sessions around the given (user, punch time) pairs and report what changed.
+/// Punches of the same user are rebuilt together so overlapping spans are handled once.
+async fn rebuild_sessions_for(
+    conn: &mut PgConnection,
+    punches: &[(&str, DateTime<Utc>)],
+) -> Result<Vec<UserSessionChanges>, sqlx::Error> {
+    let mut windows: BTreeMap<&str, (DateTime<Utc>, DateTime<Utc>)> = BTreeMap::new();
+    for &(user_id, at) in punches {
+        let window = windows.entry(user_id).or_insert((at, at));
+        window.0 = window.0.min(at);
+        window.1 = window.1.max(at);
+    }
+
+    let mut changes = Vec::new();
+    for (user_id, (from, to)) in windows {
+        let rebuild = sessions::rebuild_user_sessions(conn, user_id, from, to, SessionConfig::global())
+            .await?
+            .into_changes();
+        if !rebuild.is_empty() {
+            changes.push(UserSessionChanges {
+                user_id: user_id.to_string(),
+                removed: rebuild.removed,
+                added: rebuild.added,
+            });
+        }
     }
+
+    Ok(changes)
 }
 
 #[derive(serde::Deserialize)]
@@ -296,4 +264,95 @@ pub struct CheckinQuery {
     pub user_id: Option<String>,
     pub action: Option<String>,
     pub limit: Option<i64>,
+}
+
+/// Check-ins listed when the request gives no limit, and the most it may ask for
+const DEFAULT_CHECKINS_LIMIT: i64 = 100;
+const MAX_CHECKINS_LIMIT: i64 = 1000;
+
+/// Parameters bound into the check-ins listing
+#[derive(Debug, PartialEq)]
+struct CheckinFilter {
+    user_id: Option<String>,
+    action: Option<String>,
+    /// Departments the caller is limited to, None when unrestricted
+    departments: Option<Vec<i32>>,
+    limit: i64,
+}
+
+impl CheckinFilter {
+    fn new(query: &CheckinQuery, scope: &DepartmentScope) -> Self {
+        CheckinFilter {
+            user_id: query.user_id.clone(),
+            action: query.action.clone(),
+            departments: scope.departments().map(<[i32]>::to_vec),
+            limit: query.limit.unwrap_or(DEFAULT_CHECKINS_LIMIT).clamp(1, MAX_CHECKINS_LIMIT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::models::AdminSession;
+
+    fn query(user_id: Option<&str>, limit: Option<i64>) -> CheckinQuery {
+        CheckinQuery { user_id: user_id.map(str::to_string), action: None, limit }
+    }
+
+    fn session(role: &str, departments: Vec<i32>) -> AdminSession {
+        AdminSession {
+            user_id: 1,
+            username: "manager".to_string(),
+            role: role.to_string(),
+            departments,
+            api_key_scopes: None,
+        }
+    }
+
+    #[test]
+    fn test_scoped_session_is_limited_to_its_departments() {
+        let scope = session("department", vec![2]).scope();
+
+        // A user id crafted to end the condition early is only ever compared as a value
+        let filter = CheckinFilter::new(&query(Some("x' OR true --"), None), &scope);
+        assert_eq!(filter.departments, Some(vec![2]));
+        assert_eq!(filter.user_id.as_deref(), Some("x' OR true --"));
+
+        let filter = CheckinFilter::new(&query(None, None), &scope);
+        assert_eq!(filter.departments, Some(vec![2]));
+
+        let filter = CheckinFilter::new(&query(None, None), &session("admin", vec![2]).scope());
+        assert_eq!(filter.departments, None);
+    }
+
+    #[test]
+    fn test_department_limited_api_key_is_filtered() {
+        let key = crate::api_keys::ApiKey {
+            id: 1,
+            name: "hr-sync".to_string(),
+            key_prefix: "kbk_abcd".to_string(),
+            scopes: vec!["checkins.read".to_string()],
+            departments: vec![3],
+            created_by: "admin".to_string(),
+            created_at: chrono::Utc::now(),
+            expires_at: None,
+            last_used_at: None,
+            revoked_at: None,
+        };
+        let session = AdminSession::from(key);
+        assert!(session.capabilities().contains(&Capability::CheckinsRead));
+
+        let filter = CheckinFilter::new(&query(Some("x' OR true --"), None), &session.scope());
+        assert_eq!(filter.departments, Some(vec![3]));
+    }
+
+    #[test]
+    fn test_limit_is_clamped() {
+        let scope = DepartmentScope::All;
+        assert_eq!(CheckinFilter::new(&query(None, None), &scope).limit, DEFAULT_CHECKINS_LIMIT);
+        assert_eq!(CheckinFilter::new(&query(None, Some(20)), &scope).limit, 20);
+        assert_eq!(CheckinFilter::new(&query(None, Some(i64::MAX)), &scope).limit, MAX_CHECKINS_LIMIT);
+        assert_eq!(CheckinFilter::new(&query(None, Some(-1)), &scope).limit, 1);
+    }
 }
\ No newline at end of file
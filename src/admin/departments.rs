@@ -0,0 +1,271 @@
+use actix_web::{web, HttpResponse, HttpRequest};
+use chrono::{Duration, NaiveDate};
+use sqlx::PgPool;
+
+use crate::admin::auth::require_capability;
+use crate::admin::models::{
+    DepartmentSetting, DepartmentTimeSetting, DepartmentTimeSettingRequest, DepartmentTimeSettingVersion,
+    UpdateDepartmentSettingRequest
+};
+use crate::audit::{self, Change};
+use crate::clock_skew::SkewPolicy;
+use crate::devices::DevicePolicy;
+use crate::geofence::GeofencePolicy;
+use crate::models::ApiResponse;
+use crate::permissions::Capability;
+use crate::schedule;
+use crate::sessions::AutoClosePolicy;
+
+pub async fn get_department_settings(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Err(response) = require_capability(&req, Capability::SettingsRead).await {
+        return response;
+    }
+
+    match sqlx::query_as::<_, DepartmentSetting>("SELECT * FROM department_settings ORDER BY department")
+        .fetch_all(pool.as_ref())
+        .await
+    {
+        Ok(settings) => HttpResponse::Ok().json(ApiResponse::success(settings, "Department settings retrieved")),
+        Err(e) => {
+            log::error!("Failed to retrieve department settings: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve department settings"))
+        }
+    }
+}
+
+pub async fn update_department_setting(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    setting_req: web::Json<UpdateDepartmentSettingRequest>,
+) -> HttpResponse {
+    if let Err(response) = require_capability(&req, Capability::SettingsManage).await {
+        return response;
+    }
+
+    if let Some(policy) = &setting_req.geofence_policy {
+        if GeofencePolicy::parse(policy).is_none() {
+            return HttpResponse::BadRequest().json(ApiResponse::<()>::error("geofence_policy must be 'flag' or 'reject'"));
+        }
+    }
+
+    if let Some(policy) = &setting_req.auto_close_policy {
+        if AutoClosePolicy::parse(policy).is_none() {
+            return HttpResponse::BadRequest().json(ApiResponse::<()>::error("auto_close_policy must be 'off_duty' or 'zero'"));
+        }
+    }
+
+    if let Some(policy) = &setting_req.clock_skew_policy {
+        if SkewPolicy::parse(policy).is_none() {
+            return HttpResponse::BadRequest().json(ApiResponse::<()>::error("clock_skew_policy must be 'flag' or 'correct'"));
+        }
+    }
+
+    if let Some(policy) = &setting_req.device_policy {
+        if DevicePolicy::parse(policy).is_none() {
+            return HttpResponse::BadRequest().json(ApiResponse::<()>::error("device_policy must be 'off', 'hold' or 'reject'"));
+        }
+    }
+
+    if let Err(message) = schedule::validate_rules(
+        setting_req.late_grace_minutes,
+        setting_req.early_leave_grace_minutes,
+        setting_req.major_late_minutes,
+    ) {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error(message));
+    }
+
+    let department = path.into_inner();
+
+    match sqlx::query_as::<_, DepartmentSetting>(
+        r#"
+        INSERT INTO department_settings (
+            department, geofence_policy, auto_close_policy, clock_skew_policy, device_policy,
+            late_grace_minutes, early_leave_grace_minutes, major_late_minutes
+        )
+        VALUES (
+            $1, COALESCE($2, 'flag'), COALESCE($3, 'off_duty'), COALESCE($4, 'flag'), COALESCE($5, 'off'),
+            COALESCE($6, 0), COALESCE($7, 0), COALESCE($8, 30)
+        )
+        ON CONFLICT (department)
+        DO UPDATE SET
+            geofence_policy = COALESCE($2, department_settings.geofence_policy),
+            auto_close_policy = COALESCE($3, department_settings.auto_close_policy),
+            clock_skew_policy = COALESCE($4, department_settings.clock_skew_policy),
+            device_policy = COALESCE($5, department_settings.device_policy),
+            late_grace_minutes = COALESCE($6, department_settings.late_grace_minutes),
+            early_leave_grace_minutes = COALESCE($7, department_settings.early_leave_grace_minutes),
+            major_late_minutes = COALESCE($8, department_settings.major_late_minutes),
+            updated_at = NOW()
+        RETURNING *
+        "#
+    )
+    .bind(department)
+    .bind(&setting_req.geofence_policy)
+    .bind(&setting_req.auto_close_policy)
+    .bind(&setting_req.clock_skew_policy)
+    .bind(&setting_req.device_policy)
+    .bind(setting_req.late_grace_minutes)
+    .bind(setting_req.early_leave_grace_minutes)
+    .bind(setting_req.major_late_minutes)
+    .fetch_one(pool.as_ref())
+    .await
+    {
+        Ok(setting) => HttpResponse::Ok().json(ApiResponse::success(setting, "Department setting updated")),
+        Err(e) => {
+            log::error!("Failed to update department setting: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update department setting"))
+        }
+    }
+}
+
+/// A department's default duty times over time, including changes scheduled for later dates
+pub async fn get_department_time_settings(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> HttpResponse {
+    if let Err(response) = require_capability(&req, Capability::SettingsRead).await {
+        return response;
+    }
+
+    let department = path.into_inner();
+
+    match sqlx::query_as::<_, DepartmentTimeSetting>(
+        "SELECT * FROM department_time_settings WHERE department = $1 ORDER BY effective_from"
+    )
+    .bind(department)
+    .fetch_all(pool.as_ref())
+    .await
+    {
+        Ok(settings) => {
+            let effective_to: Vec<Option<NaiveDate>> = settings
+                .iter()
+                .skip(1)
+                .map(|next| Some(next.effective_from - Duration::days(1)))
+                .chain(std::iter::once(None))
+                .collect();
+            let versions: Vec<DepartmentTimeSettingVersion> = settings
+                .into_iter()
+                .zip(effective_to)
+                .map(|(setting, effective_to)| DepartmentTimeSettingVersion { setting, effective_to })
+                .collect();
+            HttpResponse::Ok().json(ApiResponse::success(versions, "Department time settings retrieved"))
+        }
+        Err(e) => {
+            log::error!("Failed to retrieve department time settings: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve department time settings"))
+        }
+    }
+}
+
+/// Set a department's default duty times from a date; every user of the department without
+/// a setting of their own follows them
+pub async fn set_department_time_setting(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    setting_req: web::Json<DepartmentTimeSettingRequest>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let department = path.into_inner();
+    let effective_from = setting_req.effective_from.unwrap_or_else(schedule::local_today);
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Transaction failed")),
+    };
+
+    let previous = match sqlx::query_as::<_, DepartmentTimeSetting>(
+        "SELECT * FROM department_time_settings WHERE department = $1 AND effective_from = $2 FOR UPDATE"
+    )
+    .bind(department)
+    .bind(effective_from)
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(previous) => previous,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to save department time setting")),
+    };
+
+    let setting = match sqlx::query_as::<_, DepartmentTimeSetting>(
+        r#"
+        INSERT INTO department_time_settings (department, effective_from, on_duty_time, off_duty_time)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (department, effective_from)
+        DO UPDATE SET
+            on_duty_time = EXCLUDED.on_duty_time,
+            off_duty_time = EXCLUDED.off_duty_time,
+            updated_at = NOW()
+        RETURNING *
+        "#
+    )
+    .bind(department)
+    .bind(effective_from)
+    .bind(setting_req.on_duty_time)
+    .bind(setting_req.off_duty_time)
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(setting) => setting,
+        Err(e) => {
+            log::error!("Failed to save department time setting: {:?}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to save department time setting"));
+        }
+    };
+
+    let mut change = Change::new("department_time_setting_update", "department_time_setting", department).after(&setting);
+    if let Some(previous) = &previous {
+        change = change.before(previous);
+    }
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success(setting, "Department time setting saved")),
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to commit transaction")),
+    }
+}
+
+/// Remove the department setting starting on one date, e.g. to cancel a scheduled change
+pub async fn delete_department_time_setting(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<(i32, NaiveDate)>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let (department, effective_from) = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Transaction failed")),
+    };
+
+    let deleted = match sqlx::query_as::<_, DepartmentTimeSetting>(
+        "DELETE FROM department_time_settings WHERE department = $1 AND effective_from = $2 RETURNING *"
+    )
+    .bind(department)
+    .bind(effective_from)
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(deleted)) => deleted,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Department time setting not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete department time setting")),
+    };
+
+    let change = Change::new("department_time_setting_delete", "department_time_setting", department).before(&deleted);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::<()>::success((), "Department time setting deleted")),
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to commit transaction")),
+    }
+}
@@ -0,0 +1,197 @@
+use actix_web::{web, HttpResponse, HttpRequest};
+use sqlx::PgPool;
+
+use crate::admin::auth::require_capability;
+use crate::admin::models::{AdminSession, ApproveDeviceResponse};
+use crate::audit;
+use crate::devices::{self, DeviceStatus};
+use crate::models::ApiResponse;
+use crate::permissions::Capability;
+use crate::sessions::{self, SessionConfig};
+
+async fn load_user_id(pool: &PgPool, id: i32) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar::<_, String>("SELECT user_id FROM user_info WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Like `load_user_id`, but users outside the session's department scope are not found
+async fn load_scoped_user_id(pool: &PgPool, id: i32, session: &AdminSession) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar::<_, String>(
+        "SELECT user_id FROM user_info WHERE id = $1 AND ($2::int[] IS NULL OR department = ANY($2))"
+    )
+    .bind(id)
+    .bind(session.scope().departments())
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn get_user_devices(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::UsersRead).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let user_id = match load_scoped_user_id(&pool, path.into_inner(), &session).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    };
+
+    match devices::user_devices(pool.as_ref(), &user_id).await {
+        Ok(devices) => HttpResponse::Ok().json(ApiResponse::success(devices, "Devices retrieved")),
+        Err(e) => {
+            log::error!("Failed to retrieve devices of user {}: {:?}", user_id, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve devices"))
+        }
+    }
+}
+
+/// Approve a device and let the punches held for it count towards sessions
+pub async fn approve_device(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<(i32, i32)>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::UsersManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let (id, device) = path.into_inner();
+    let user_id = match load_user_id(&pool, id).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    };
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to approve device"));
+        }
+    };
+
+    let device = match devices::review(&mut *tx, &user_id, device, DeviceStatus::Approved, &session.username).await {
+        Ok(Some(device)) => device,
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(ApiResponse::<()>::error("Device not found"));
+        }
+        Err(e) => {
+            log::error!("Failed to approve device of user {}: {:?}", user_id, e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to approve device"));
+        }
+    };
+
+    let released = match devices::release_held_punches(&mut *tx, &user_id, &device.device_id).await {
+        Ok(released) => released,
+        Err(e) => {
+            log::error!("Failed to release held punches of user {}: {:?}", user_id, e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to approve device"));
+        }
+    };
+
+    // Pair the released punches into sessions
+    if let (Some(&from), Some(&to)) = (released.iter().min(), released.iter().max()) {
+        if let Err(e) = sessions::rebuild_user_sessions(&mut tx, &user_id, from, to, SessionConfig::global()).await {
+            log::error!("Failed to rebuild sessions of user {}: {:?}", user_id, e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to approve device"));
+        }
+    }
+
+    let target = format!("{}:{}", user_id, device.device_id);
+    let details = format!("{} held punches released", released.len());
+    if let Err(e) = audit::record(&mut *tx, &session.username, "device_approve", Some(&target), Some(&details)).await {
+        log::error!("Failed to audit approval of device {}: {:?}", target, e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to approve device"));
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            log::info!("Admin {} approved device {} ({})", session.username, target, details);
+            HttpResponse::Ok().json(ApiResponse::success(
+                ApproveDeviceResponse { device, released_punches: released.len() },
+                "Device approved"
+            ))
+        }
+        Err(e) => {
+            log::error!("Failed to commit approval of device {}: {:?}", target, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to approve device"))
+        }
+    }
+}
+
+/// Revoke a device and sign it out. Punches it already synced are kept.
+pub async fn revoke_device(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<(i32, i32)>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::UsersManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let (id, device) = path.into_inner();
+    let user_id = match load_user_id(&pool, id).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    };
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke device"));
+        }
+    };
+
+    let device = match devices::review(&mut *tx, &user_id, device, DeviceStatus::Revoked, &session.username).await {
+        Ok(Some(device)) => device,
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(ApiResponse::<()>::error("Device not found"));
+        }
+        Err(e) => {
+            log::error!("Failed to revoke device of user {}: {:?}", user_id, e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke device"));
+        }
+    };
+
+    if let Err(e) = devices::delete_device_tokens(&mut *tx, &user_id, &device.device_id).await {
+        log::error!("Failed to sign out device of user {}: {:?}", user_id, e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke device"));
+    }
+
+    let target = format!("{}:{}", user_id, device.device_id);
+    if let Err(e) = audit::record(&mut *tx, &session.username, "device_revoke", Some(&target), None).await {
+        log::error!("Failed to audit revocation of device {}: {:?}", target, e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke device"));
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            log::info!("Admin {} revoked device {}", session.username, target);
+            HttpResponse::Ok().json(ApiResponse::success(device, "Device revoked"))
+        }
+        Err(e) => {
+            log::error!("Failed to commit revocation of device {}: {:?}", target, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to revoke device"))
+        }
+    }
+}
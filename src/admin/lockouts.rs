@@ -0,0 +1,77 @@
+use actix_web::{web, HttpResponse, HttpRequest};
+use sqlx::PgPool;
+
+use crate::admin::auth::require_capability;
+use crate::admin::models::UnlockRequest;
+use crate::audit;
+use crate::lockout;
+use crate::models::ApiResponse;
+use crate::permissions::Capability;
+
+pub async fn get_lockouts(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Err(response) = require_capability(&req, Capability::AdminUsersManage).await {
+        return response;
+    }
+
+    match lockout::active_lockouts(pool.as_ref()).await {
+        Ok(lockouts) => HttpResponse::Ok().json(ApiResponse::success(lockouts, "Lockouts retrieved")),
+        Err(e) => {
+            log::error!("Failed to retrieve lockouts: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve lockouts"))
+        }
+    }
+}
+
+pub async fn unlock(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    unlock_req: web::Json<UnlockRequest>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::AdminUsersManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let target = format!("{}:{}", unlock_req.scope.as_str(), unlock_req.subject);
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to unlock"));
+        }
+    };
+
+    match lockout::clear(&mut *tx, unlock_req.scope, &unlock_req.subject).await {
+        Ok(true) => {}
+        Ok(false) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::NotFound().json(ApiResponse::<()>::error("No failed logins recorded for this subject"));
+        }
+        Err(e) => {
+            log::error!("Failed to unlock {}: {:?}", target, e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to unlock"));
+        }
+    }
+
+    if let Err(e) = audit::record(&mut *tx, &session.username, "login_unlock", Some(&target), None).await {
+        log::error!("Failed to audit unlock of {}: {:?}", target, e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to unlock"));
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            log::info!("Admin {} unlocked {}", session.username, target);
+            HttpResponse::Ok().json(ApiResponse::<()>::success((), "Unlocked"))
+        }
+        Err(e) => {
+            log::error!("Failed to commit unlock of {}: {:?}", target, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to unlock"))
+        }
+    }
+}
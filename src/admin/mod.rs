@@ -5,15 +5,30 @@ pub mod users;
 pub mod checkins;
 pub mod stats;
 pub mod admin_users;
-pub mod sync;
 pub mod time_settings;
+pub mod departments;
+pub mod sessions;
+pub mod anomalies;
+pub mod lockouts;
+pub mod devices;
+pub mod two_factor;
+pub mod audit_log;
+pub mod api_keys;
+pub mod shifts;
 
 use actix_web::web;
 
 pub fn admin_routes() -> actix_web::Scope {
     web::scope("/admin")
         .route("/login", web::post().to(auth::admin_login))
+        .route("/login/enroll", web::post().to(two_factor::enroll_during_login))
+        .route("/login/verify", web::post().to(two_factor::verify_login))
+        .route("/logout", web::post().to(auth::admin_logout))
         .route("/me", web::get().to(auth::get_admin_info))
+        .route("/me/totp/enroll", web::post().to(two_factor::enroll))
+        .route("/me/totp/confirm", web::post().to(two_factor::confirm))
+        .route("/me/totp/recovery-codes", web::post().to(two_factor::regenerate_recovery_codes))
+        .route("/me/totp", web::delete().to(two_factor::disable))
         .service(
             web::scope("")
                 .service(
@@ -33,6 +48,10 @@ pub fn admin_routes() -> actix_web::Scope {
                         .route("", web::post().to(users::create_user))
                         .route("/{id}", web::put().to(users::update_user))
                         .route("/{id}", web::delete().to(users::delete_user))
+                        .route("/{id}/passkey/rotate", web::post().to(users::rotate_passkey))
+                        .route("/{id}/devices", web::get().to(devices::get_user_devices))
+                        .route("/{id}/devices/{device}/approve", web::post().to(devices::approve_device))
+                        .route("/{id}/devices/{device}/revoke", web::post().to(devices::revoke_device))
                 )
                 .service(
                     web::scope("/checkins")
@@ -55,17 +74,65 @@ pub fn admin_routes() -> actix_web::Scope {
                         .route("/{id}", web::put().to(admin_users::update_admin_user))
                         .route("/{id}", web::delete().to(admin_users::delete_admin_user))
                         .route("/{id}/password", web::put().to(admin_users::reset_admin_password))
+                        .route("/{id}/revoke-sessions", web::post().to(admin_users::revoke_admin_sessions))
+                        .route("/{id}/totp/reset", web::post().to(two_factor::reset_admin_totp))
                 )
                 .service(
                     web::scope("/time-settings")
                         .route("/users", web::get().to(time_settings::get_users_with_time_settings))
                         .route("/batch", web::post().to(time_settings::batch_update_time_settings))
                         .route("/{user_id}", web::delete().to(time_settings::delete_time_setting))
+                        .route("/{user_id}/history", web::get().to(time_settings::get_time_setting_history))
+                        .route("/{user_id}/lateness", web::get().to(time_settings::get_lateness_rules))
+                        .route("/{user_id}/lateness", web::put().to(time_settings::set_lateness_rules))
+                        .route("/{user_id}/{effective_from}", web::delete().to(time_settings::delete_time_setting_version))
                 )
                 .service(
-                    web::scope("/sync")
-                        .route("/time-settings", web::post().to(sync::manual_sync_time_settings))
-                        .route("/status", web::get().to(sync::get_sync_status))
+                    web::scope("/shifts")
+                        .route("/templates", web::get().to(shifts::get_shift_templates))
+                        .route("/templates", web::post().to(shifts::create_shift_template))
+                        .route("/templates/{id}", web::put().to(shifts::update_shift_template))
+                        .route("/templates/{id}", web::delete().to(shifts::delete_shift_template))
+                        .route("/roster", web::get().to(shifts::get_roster))
+                        .route("/assignments", web::put().to(shifts::set_shift_assignments))
+                        .route("/assignments/{user_id}/{date}", web::delete().to(shifts::delete_shift_assignment))
+                        .route("/rotations", web::get().to(shifts::get_shift_rotations))
+                        .route("/rotations", web::post().to(shifts::create_shift_rotation))
+                        .route("/rotations/{id}", web::delete().to(shifts::delete_shift_rotation))
+                )
+                .service(
+                    web::scope("/sessions")
+                        .route("/rebuild", web::post().to(sessions::rebuild_sessions))
+                        .route("/auto-closed", web::get().to(sessions::get_auto_closed_sessions))
+                )
+                .service(
+                    web::scope("/anomalies")
+                        .route("", web::get().to(anomalies::get_anomalies))
+                        .route("/{id}/resolve", web::post().to(anomalies::resolve_anomaly))
+                        .route("/{id}/dismiss", web::post().to(anomalies::dismiss_anomaly))
+                )
+                .service(
+                    web::scope("/lockouts")
+                        .route("", web::get().to(lockouts::get_lockouts))
+                        .route("/unlock", web::post().to(lockouts::unlock))
+                )
+                .service(
+                    web::scope("/api-keys")
+                        .route("", web::get().to(api_keys::get_api_keys))
+                        .route("", web::post().to(api_keys::create_api_key))
+                        .route("/{id}/revoke", web::post().to(api_keys::revoke_api_key))
+                )
+                .service(
+                    web::scope("/audit")
+                        .route("", web::get().to(audit_log::get_audit_log))
+                )
+                .service(
+                    web::scope("/departments")
+                        .route("/settings", web::get().to(departments::get_department_settings))
+                        .route("/{department}/settings", web::put().to(departments::update_department_setting))
+                        .route("/{department}/time-settings", web::get().to(departments::get_department_time_settings))
+                        .route("/{department}/time-settings", web::post().to(departments::set_department_time_setting))
+                        .route("/{department}/time-settings/{effective_from}", web::delete().to(departments::delete_department_time_setting))
                 )
         )
 }
\ No newline at end of file
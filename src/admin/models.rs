@@ -1,15 +1,26 @@
+use actix_web::HttpRequest;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+use crate::api_keys::ApiKey;
+use crate::audit::Actor;
+use crate::permissions::{Capability, DepartmentScope, Role};
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct AdminUser {
     pub id: i32,
     pub username: String,
+    /// Argon2 PHC string, or plaintext for accounts not yet upgraded on login
+    #[serde(skip_serializing)]
     pub password: String,
     pub role: String,
     pub department: Option<i32>,
     pub created_at: DateTime<Utc>,
+    /// Departments the user's role is limited to; empty means all
+    pub departments: Vec<i32>,
+    /// When TOTP two-factor login was confirmed; None while not enrolled
+    pub totp_enabled_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -18,18 +29,74 @@ pub struct AdminLoginRequest {
     pub password: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
 pub struct AdminLoginResponse {
     pub token: String,
+    pub expires_at: DateTime<Utc>,
     pub user: AdminUserInfo,
+    /// Recovery codes, only when the login completed a TOTP enrolment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+/// Returned by the password step of a login that needs a second factor
+#[derive(Debug, Serialize)]
+pub struct AdminLoginChallengeResponse {
+    pub challenge_token: String,
+    pub expires_at: DateTime<Utc>,
+    /// The role requires TOTP but the user has not enrolled; enrol with the challenge first
+    pub enrollment_required: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginChallengeRequest {
+    pub challenge_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyLoginRequest {
+    pub challenge_token: String,
+    /// Code from the authenticator app, or an unused recovery code
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollmentResponse {
+    /// Base32 secret for entering into an authenticator app by hand
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
 pub struct AdminUserInfo {
     pub id: i32,
     pub username: String,
     pub role: String,
-    pub department: Option<i32>,
+    pub departments: Vec<i32>,
+    pub capabilities: Vec<&'static str>,
+}
+
+impl From<AdminSession> for AdminUserInfo {
+    fn from(session: AdminSession) -> Self {
+        let capabilities = session.capabilities().iter().map(Capability::as_str).collect();
+        AdminUserInfo {
+            id: session.user_id,
+            username: session.username,
+            role: session.role,
+            departments: session.departments,
+            capabilities,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -65,6 +132,20 @@ pub struct UpdateUserRequest {
     pub user_name: Option<String>,
     pub department: i32,
     pub department_name: Option<String>,
+    /// New passkey; omit to keep the current one
+    pub passkey: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotatePasskeyRequest {
+    /// When the new passkey stops working; omit for no expiry
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RotatePasskeyResponse {
+    pub user: crate::models::UserInfo,
+    /// The new passkey in clear text. It is not stored and cannot be retrieved again.
     pub passkey: String,
 }
 
@@ -118,15 +199,32 @@ pub struct AdminUserResponse {
     pub username: String,
     pub role: String,
     pub department: Option<i32>,
+    pub departments: Vec<i32>,
+    pub totp_enabled: bool,
     pub created_at: DateTime<Utc>,
 }
 
+impl From<AdminUser> for AdminUserResponse {
+    fn from(user: AdminUser) -> Self {
+        AdminUserResponse {
+            id: user.id,
+            username: user.username,
+            role: user.role,
+            department: user.department,
+            departments: user.departments,
+            totp_enabled: user.totp_enabled_at.is_some(),
+            created_at: user.created_at,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateAdminUserRequest {
     pub username: String,
     pub password: String,
-    pub role: String,
-    pub department: Option<i32>,
+    pub role: String, // "admin", "department", "auditor" or "payroll"
+    pub department: Option<i32>, // Single department, kept for older clients
+    pub departments: Option<Vec<i32>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -135,6 +233,7 @@ pub struct UpdateAdminUserRequest {
     pub password: Option<String>,
     pub role: String,
     pub department: Option<i32>,
+    pub departments: Option<Vec<i32>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -176,14 +275,24 @@ pub struct UserDetailRecord {
     pub last_checkout: Option<DateTime<Utc>>,
     pub total_work_minutes: Option<i32>,
     pub total_sessions: Option<i32>,
+    pub auto_closed_sessions: i32,
     pub is_late: bool,
+    /// Minutes after the shift start, 0 unless late
+    pub late_minutes: i64,
+    pub late_tier: Option<crate::schedule::LateTier>,
     pub is_early_leave: bool,
+    /// Minutes before the shift end, 0 unless leaving early
+    pub early_leave_minutes: i64,
+    /// Shift scheduled that day, None on a day off
+    pub shift: Option<crate::schedule::Shift>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct UserTimeSetting {
     pub id: i32,
     pub user_id: String,
+    /// First day the times apply; they last until the user's next setting takes effect
+    pub effective_from: chrono::NaiveDate,
     pub on_duty_time: chrono::NaiveTime,
     pub off_duty_time: chrono::NaiveTime,
     pub created_at: DateTime<Utc>,
@@ -195,8 +304,22 @@ pub struct CreateUserTimeSettingRequest {
     pub user_id: String,
     pub on_duty_time: String, // Format: "HH:MM:SS"
     pub off_duty_time: String, // Format: "HH:MM:SS"
+    /// Date the times take effect, today when absent; later dates schedule a change
+    pub effective_from: Option<chrono::NaiveDate>,
+    /// Replaces the weekday hours of the setting when present; an empty list clears them.
+    /// A new setting without them keeps the weekday hours of the one before.
+    pub weekdays: Option<Vec<WeekdayHoursRequest>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WeekdayHoursRequest {
+    pub weekday: i16, // 1 = Monday ... 7 = Sunday
+    /// Both times, or neither for a non-working day
+    pub on_duty_time: Option<String>,
+    pub off_duty_time: Option<String>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateUserTimeSettingRequest {
     pub on_duty_time: String, // Format: "HH:MM:SS"
@@ -214,14 +337,304 @@ pub struct UserWithTimeSetting {
     pub user_name: Option<String>,
     pub department: i32,
     pub department_name: Option<String>,
-    pub on_duty_time: Option<chrono::NaiveTime>,
-    pub off_duty_time: Option<chrono::NaiveTime>,
+    /// Hours in effect today, the user's own or inherited
+    pub on_duty_time: chrono::NaiveTime,
+    pub off_duty_time: chrono::NaiveTime,
+    /// `time_setting` when the user overrides the hours, else `department` or `default`
+    pub source: crate::schedule::ShiftSource,
+    /// Start of the user's own setting in effect today
+    pub effective_from: Option<chrono::NaiveDate>,
+    pub weekdays: Vec<crate::schedule::WeekdayHours>,
 }
 
-#[derive(Debug, Clone)]
+/// One of a user's dated time settings, as listed in their history
+#[derive(Debug, Serialize)]
+pub struct TimeSettingVersion {
+    #[serde(flatten)]
+    pub setting: UserTimeSetting,
+    /// Last day the setting applies, open-ended for the latest one
+    pub effective_to: Option<chrono::NaiveDate>,
+    pub weekdays: Vec<crate::schedule::WeekdayHours>,
+}
+
+/// A user's own lateness thresholds; absent values follow their department
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow)]
+pub struct LatenessOverrides {
+    pub late_grace_minutes: Option<i32>,
+    pub early_leave_grace_minutes: Option<i32>,
+    pub major_late_minutes: Option<i32>,
+}
+
+impl LatenessOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.late_grace_minutes.is_none() && self.early_leave_grace_minutes.is_none() && self.major_late_minutes.is_none()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserLatenessRules {
+    pub user_id: String,
+    pub overrides: LatenessOverrides,
+    /// Thresholds the user's days are judged by
+    pub effective: crate::schedule::LatenessRules,
+}
+
+#[derive(Debug, Clone, FromRow)]
 pub struct AdminSession {
+    /// 0 for API keys, which belong to no admin user
     pub user_id: i32,
     pub username: String,
     pub role: String,
-    pub department: Option<i32>,
-}
\ No newline at end of file
+    pub departments: Vec<i32>,
+    /// Capabilities of the API key the request was made with, None for admin users
+    #[sqlx(skip)]
+    pub api_key_scopes: Option<Vec<Capability>>,
+}
+
+/// Role reported for requests made with an API key
+pub const API_KEY_ROLE: &str = "api_key";
+
+impl From<ApiKey> for AdminSession {
+    fn from(key: ApiKey) -> Self {
+        AdminSession {
+            user_id: 0,
+            // The prefix tells apart keys of the same service account, e.g. in the audit log
+            username: format!("{} ({})", key.name, key.key_prefix),
+            role: API_KEY_ROLE.to_string(),
+            api_key_scopes: Some(key.capabilities()),
+            departments: key.departments,
+        }
+    }
+}
+
+impl AdminSession {
+    /// Capabilities of the session's role or API key; none for a role this server does not know
+    pub fn capabilities(&self) -> &[Capability] {
+        match &self.api_key_scopes {
+            Some(scopes) => scopes,
+            None => Role::parse(&self.role).map_or(&[], |role| role.capabilities()),
+        }
+    }
+
+    pub fn can(&self, capability: Capability) -> bool {
+        self.capabilities().contains(&capability)
+    }
+
+    /// Departments whose employees the session may see
+    pub fn scope(&self) -> DepartmentScope {
+        if self.api_key_scopes.is_some() {
+            return DepartmentScope::for_departments(&self.departments);
+        }
+        match Role::parse(&self.role) {
+            Some(role) => DepartmentScope::for_role(role, &self.departments),
+            None => DepartmentScope::Only(Vec::new()),
+        }
+    }
+
+    /// The session's user as the actor of audited changes made by `req`
+    pub fn actor(&self, req: &HttpRequest) -> Actor {
+        Actor::from_request(&self.username, req)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevokeSessionsResponse {
+    pub revoked: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct DepartmentSetting {
+    pub department: i32,
+    pub geofence_policy: String,
+    pub updated_at: DateTime<Utc>,
+    pub auto_close_policy: String,
+    pub clock_skew_policy: String,
+    pub device_policy: String,
+    pub late_grace_minutes: i32,
+    pub early_leave_grace_minutes: i32,
+    pub major_late_minutes: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateDepartmentSettingRequest {
+    pub geofence_policy: Option<String>, // "flag" or "reject"
+    pub auto_close_policy: Option<String>, // "off_duty" or "zero"
+    pub clock_skew_policy: Option<String>, // "flag" or "correct"
+    pub device_policy: Option<String>, // "off", "hold" or "reject"
+    pub late_grace_minutes: Option<i32>,
+    pub early_leave_grace_minutes: Option<i32>,
+    pub major_late_minutes: Option<i32>, // lateness from which the major tier applies
+}
+
+/// Default duty times of a department from `effective_from`, for its users without their own
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct DepartmentTimeSetting {
+    pub id: i32,
+    pub department: i32,
+    pub effective_from: chrono::NaiveDate,
+    pub on_duty_time: chrono::NaiveTime,
+    pub off_duty_time: chrono::NaiveTime,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DepartmentTimeSettingVersion {
+    #[serde(flatten)]
+    pub setting: DepartmentTimeSetting,
+    /// Last day the setting applies, open-ended for the latest one
+    pub effective_to: Option<chrono::NaiveDate>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DepartmentTimeSettingRequest {
+    pub on_duty_time: chrono::NaiveTime,
+    pub off_duty_time: chrono::NaiveTime,
+    /// Date the times take effect, today when absent; later dates schedule a change
+    pub effective_from: Option<chrono::NaiveDate>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RebuildSessionsRequest {
+    pub user_ids: Option<Vec<String>>,
+    pub departments: Option<Vec<i32>>,
+    pub start_date: chrono::NaiveDate,
+    pub end_date: chrono::NaiveDate,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserSessionChanges {
+    pub user_id: String,
+    pub removed: Vec<crate::models::AttendanceSession>,
+    pub added: Vec<crate::models::AttendanceSession>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RebuildSessionsResponse {
+    pub dry_run: bool,
+    pub users_processed: usize,
+    pub sessions_removed: usize,
+    pub sessions_added: usize,
+    pub changes: Vec<UserSessionChanges>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckinMutationResponse {
+    pub checkin: Option<crate::models::Checkin>,
+    pub session_changes: Vec<UserSessionChanges>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct PunchAnomaly {
+    pub id: i32,
+    pub user_id: String,
+    pub anomaly_type: String,
+    pub checkin_id: i32,
+    pub related_checkin_id: Option<i32>,
+    pub occurred_at: DateTime<Utc>,
+    pub details: Option<String>,
+    pub status: String, // "open", "resolved" or "dismissed"
+    pub review_note: Option<String>,
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnomalyQuery {
+    pub status: Option<String>,
+    pub user_id: Option<String>,
+    pub anomaly_type: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReviewAnomalyRequest {
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AutoClosedSessionsQuery {
+    pub user_id: Option<String>,
+    pub start_date: Option<chrono::NaiveDate>,
+    pub end_date: Option<chrono::NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApproveDeviceResponse {
+    pub device: crate::devices::UserDevice,
+    /// Punches held for the device that now count
+    pub released_punches: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnlockRequest {
+    pub scope: crate::lockout::LockScope,
+    /// IP address, admin username or employee user_id, depending on the scope
+    pub subject: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    /// Service account the key is for, e.g. "hr-sync"
+    pub name: String,
+    /// Capabilities of the key, e.g. ["stats.read", "stats.export"]; read capabilities only
+    pub scopes: Vec<String>,
+    /// Departments whose data the key can read; all when empty
+    pub departments: Option<Vec<i32>>,
+    /// Never expires when absent
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKeyResponse {
+    /// The key itself, shown only once
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShiftTemplateRequest {
+    pub name: String,
+    pub start_time: chrono::NaiveTime,
+    /// At or before `start_time` for a shift that ends the next day
+    pub end_time: chrono::NaiveTime,
+    pub break_minutes: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShiftAssignmentEntry {
+    pub user_id: String,
+    pub date: chrono::NaiveDate,
+    /// None schedules a day off
+    pub template_id: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShiftAssignmentsRequest {
+    pub assignments: Vec<ShiftAssignmentEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateShiftRotationRequest {
+    pub user_id: String,
+    pub starts_on: chrono::NaiveDate,
+    pub ends_on: Option<chrono::NaiveDate>,
+    /// One entry per day of the cycle; null for a day off
+    pub template_ids: Vec<Option<i32>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RosterQuery {
+    pub user_id: String,
+    pub from: chrono::NaiveDate,
+    /// Inclusive
+    pub to: chrono::NaiveDate,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShiftRotationQuery {
+    pub user_id: String,
+}
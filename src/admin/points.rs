@@ -1,21 +1,18 @@
 use actix_web::{web, HttpResponse, HttpRequest};
 use sqlx::PgPool;
 
-use crate::admin::auth::require_admin_auth;
+use crate::admin::auth::require_capability;
 use crate::admin::models::{CreatePointRequest, UpdatePointRequest};
+use crate::audit::{self, Change};
 use crate::models::{ApiResponse, CheckinPoint, CheckoutPoint};
+use crate::permissions::Capability;
 
 pub async fn get_checkin_points(
     pool: web::Data<PgPool>,
     req: HttpRequest,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
-        Ok(session) => session,
-        Err(response) => return response,
-    };
-
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
+    if let Err(response) = require_capability(&req, Capability::SettingsRead).await {
+        return response;
     }
 
     match sqlx::query_as::<_, CheckinPoint>("SELECT * FROM checkin_points ORDER BY id")
@@ -32,16 +29,17 @@ pub async fn create_checkin_point(
     req: HttpRequest,
     point_req: web::Json<CreatePointRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
-    }
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create checkin point")),
+    };
 
-    match sqlx::query_as::<_, CheckinPoint>(
+    let point = match sqlx::query_as::<_, CheckinPoint>(
         r#"
         INSERT INTO checkin_points (latitude, longitude, radius, location_name, allowed_department)
         VALUES ($1, $2, $3, $4, $5)
@@ -53,10 +51,16 @@ pub async fn create_checkin_point(
     .bind(point_req.radius)
     .bind(&point_req.location_name)
     .bind(&point_req.allowed_department)
-    .fetch_one(pool.as_ref())
+    .fetch_one(&mut *tx)
     .await
     {
-        Ok(point) => HttpResponse::Created().json(ApiResponse::success(point, "Checkin point created")),
+        Ok(point) => point,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create checkin point")),
+    };
+
+    let change = Change::new("checkin_point_create", "checkin_point", point.id).after(&point);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Created().json(ApiResponse::success(point, "Checkin point created")),
         Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create checkin point")),
     }
 }
@@ -67,18 +71,29 @@ pub async fn update_checkin_point(
     path: web::Path<i32>,
     point_req: web::Json<UpdatePointRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
-    }
-
     let id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update checkin point")),
+    };
+
+    let previous = match sqlx::query_as::<_, CheckinPoint>("SELECT * FROM checkin_points WHERE id = $1 FOR UPDATE")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(previous)) => previous,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Checkin point not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update checkin point")),
+    };
     
-    match sqlx::query_as::<_, CheckinPoint>(
+    let point = match sqlx::query_as::<_, CheckinPoint>(
         r#"
         UPDATE checkin_points 
         SET latitude = $1, longitude = $2, radius = $3, location_name = $4, allowed_department = $5
@@ -92,11 +107,16 @@ pub async fn update_checkin_point(
     .bind(&point_req.location_name)
     .bind(&point_req.allowed_department)
     .bind(id)
-    .fetch_optional(pool.as_ref())
+    .fetch_one(&mut *tx)
     .await
     {
-        Ok(Some(point)) => HttpResponse::Ok().json(ApiResponse::success(point, "Checkin point updated")),
-        Ok(None) => HttpResponse::NotFound().json(ApiResponse::<()>::error("Checkin point not found")),
+        Ok(point) => point,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update checkin point")),
+    };
+
+    let change = Change::new("checkin_point_update", "checkin_point", id).before(&previous).after(&point);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success(point, "Checkin point updated")),
         Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update checkin point")),
     }
 }
@@ -106,29 +126,31 @@ pub async fn delete_checkin_point(
     req: HttpRequest,
     path: web::Path<i32>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
-    }
-
     let id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete checkin point")),
+    };
     
-    match sqlx::query("DELETE FROM checkin_points WHERE id = $1")
+    let deleted = match sqlx::query_as::<_, CheckinPoint>("DELETE FROM checkin_points WHERE id = $1 RETURNING *")
         .bind(id)
-        .execute(pool.as_ref())
+        .fetch_optional(&mut *tx)
         .await
     {
-        Ok(result) => {
-            if result.rows_affected() > 0 {
-                HttpResponse::Ok().json(ApiResponse::<()>::success((), "Checkin point deleted"))
-            } else {
-                HttpResponse::NotFound().json(ApiResponse::<()>::error("Checkin point not found"))
-            }
-        },
+        Ok(Some(deleted)) => deleted,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Checkin point not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete checkin point")),
+    };
+
+    let change = Change::new("checkin_point_delete", "checkin_point", id).before(&deleted);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::<()>::success((), "Checkin point deleted")),
         Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete checkin point")),
     }
 }
@@ -137,13 +159,8 @@ pub async fn get_checkout_points(
     pool: web::Data<PgPool>,
     req: HttpRequest,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
-        Ok(session) => session,
-        Err(response) => return response,
-    };
-
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
+    if let Err(response) = require_capability(&req, Capability::SettingsRead).await {
+        return response;
     }
 
     match sqlx::query_as::<_, CheckoutPoint>("SELECT * FROM checkout_points ORDER BY id")
@@ -160,16 +177,17 @@ pub async fn create_checkout_point(
     req: HttpRequest,
     point_req: web::Json<CreatePointRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
-    }
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create checkout point")),
+    };
 
-    match sqlx::query_as::<_, CheckoutPoint>(
+    let point = match sqlx::query_as::<_, CheckoutPoint>(
         r#"
         INSERT INTO checkout_points (latitude, longitude, radius, location_name, allowed_department)
         VALUES ($1, $2, $3, $4, $5)
@@ -181,10 +199,16 @@ pub async fn create_checkout_point(
     .bind(point_req.radius)
     .bind(&point_req.location_name)
     .bind(&point_req.allowed_department)
-    .fetch_one(pool.as_ref())
+    .fetch_one(&mut *tx)
     .await
     {
-        Ok(point) => HttpResponse::Created().json(ApiResponse::success(point, "Checkout point created")),
+        Ok(point) => point,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create checkout point")),
+    };
+
+    let change = Change::new("checkout_point_create", "checkout_point", point.id).after(&point);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Created().json(ApiResponse::success(point, "Checkout point created")),
         Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create checkout point")),
     }
 }
@@ -195,18 +219,29 @@ pub async fn update_checkout_point(
     path: web::Path<i32>,
     point_req: web::Json<UpdatePointRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
-    }
-
     let id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update checkout point")),
+    };
+
+    let previous = match sqlx::query_as::<_, CheckoutPoint>("SELECT * FROM checkout_points WHERE id = $1 FOR UPDATE")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(previous)) => previous,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Checkout point not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update checkout point")),
+    };
     
-    match sqlx::query_as::<_, CheckoutPoint>(
+    let point = match sqlx::query_as::<_, CheckoutPoint>(
         r#"
         UPDATE checkout_points 
         SET latitude = $1, longitude = $2, radius = $3, location_name = $4, allowed_department = $5
@@ -220,11 +255,16 @@ pub async fn update_checkout_point(
     .bind(&point_req.location_name)
     .bind(&point_req.allowed_department)
     .bind(id)
-    .fetch_optional(pool.as_ref())
+    .fetch_one(&mut *tx)
     .await
     {
-        Ok(Some(point)) => HttpResponse::Ok().json(ApiResponse::success(point, "Checkout point updated")),
-        Ok(None) => HttpResponse::NotFound().json(ApiResponse::<()>::error("Checkout point not found")),
+        Ok(point) => point,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update checkout point")),
+    };
+
+    let change = Change::new("checkout_point_update", "checkout_point", id).before(&previous).after(&point);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success(point, "Checkout point updated")),
         Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update checkout point")),
     }
 }
@@ -234,29 +274,31 @@ pub async fn delete_checkout_point(
     req: HttpRequest,
     path: web::Path<i32>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
-    }
-
     let id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete checkout point")),
+    };
     
-    match sqlx::query("DELETE FROM checkout_points WHERE id = $1")
+    let deleted = match sqlx::query_as::<_, CheckoutPoint>("DELETE FROM checkout_points WHERE id = $1 RETURNING *")
         .bind(id)
-        .execute(pool.as_ref())
+        .fetch_optional(&mut *tx)
         .await
     {
-        Ok(result) => {
-            if result.rows_affected() > 0 {
-                HttpResponse::Ok().json(ApiResponse::<()>::success((), "Checkout point deleted"))
-            } else {
-                HttpResponse::NotFound().json(ApiResponse::<()>::error("Checkout point not found"))
-            }
-        },
+        Ok(Some(deleted)) => deleted,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Checkout point not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete checkout point")),
+    };
+
+    let change = Change::new("checkout_point_delete", "checkout_point", id).before(&deleted);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::<()>::success((), "Checkout point deleted")),
         Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete checkout point")),
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,157 @@
+use actix_web::{web, HttpResponse, HttpRequest};
+use chrono::{Duration, NaiveTime, TimeZone};
+use sqlx::PgPool;
+
+use crate::admin::auth::require_capability;
+use crate::admin::models::{AutoClosedSessionsQuery, RebuildSessionsRequest, RebuildSessionsResponse, UserSessionChanges};
+use crate::models::{ApiResponse, AttendanceSession};
+use crate::permissions::Capability;
+use crate::sessions::{self, SessionConfig};
+
+/// Longest range a single rebuild may cover
+const MAX_REBUILD_DAYS: i64 = 366;
+
+/// Regenerate attendance_sessions (and through the triggers attendance_summary) from checkins.
+/// With `dry_run` the work is done inside a transaction that is rolled back, so the
+/// response shows the diff without changing anything.
+pub async fn rebuild_sessions(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    rebuild_req: web::Json<RebuildSessionsRequest>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SessionsManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    if rebuild_req.end_date < rebuild_req.start_date {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error("end_date must not be before start_date"));
+    }
+
+    if (rebuild_req.end_date - rebuild_req.start_date).num_days() >= MAX_REBUILD_DAYS {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error(&format!("Date range cannot exceed {} days", MAX_REBUILD_DAYS)));
+    }
+
+    let user_ids = match sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT user_id FROM user_info
+        WHERE ($1::text[] IS NULL OR user_id = ANY($1))
+          AND ($2::int[] IS NULL OR department = ANY($2))
+          AND ($3::int[] IS NULL OR department = ANY($3))
+        ORDER BY user_id
+        "#
+    )
+    .bind(&rebuild_req.user_ids)
+    .bind(&rebuild_req.departments)
+    .bind(session.scope().departments())
+    .fetch_all(pool.as_ref())
+    .await
+    {
+        Ok(user_ids) => user_ids,
+        Err(e) => {
+            log::error!("Failed to select users for session rebuild: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to select users"));
+        }
+    };
+
+    // Translate the local date range into the timestamps it covers
+    let config = SessionConfig::global();
+    let from = config.utc_offset
+        .from_local_datetime(&rebuild_req.start_date.and_time(NaiveTime::MIN))
+        .unwrap()
+        .to_utc();
+    let to = config.utc_offset
+        .from_local_datetime(&rebuild_req.end_date.and_time(NaiveTime::MIN))
+        .unwrap()
+        .to_utc() + Duration::days(1) - Duration::seconds(1);
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Transaction failed")),
+    };
+
+    let mut changes = Vec::new();
+    for user_id in &user_ids {
+        match sessions::rebuild_user_sessions(&mut tx, user_id, from, to, config).await {
+            Ok(rebuild) => {
+                let rebuild = rebuild.into_changes();
+                if !rebuild.is_empty() {
+                    changes.push(UserSessionChanges {
+                        user_id: user_id.clone(),
+                        removed: rebuild.removed,
+                        added: rebuild.added,
+                    });
+                }
+            }
+            Err(e) => {
+                log::error!("Failed to rebuild sessions for user {}: {:?}", user_id, e);
+                let _ = tx.rollback().await;
+                return HttpResponse::InternalServerError().json(ApiResponse::<()>::error(&format!("Failed to rebuild sessions for user {}", user_id)));
+            }
+        }
+    }
+
+    let result = if rebuild_req.dry_run {
+        tx.rollback().await
+    } else {
+        tx.commit().await
+    };
+
+    if let Err(e) = result {
+        log::error!("Failed to finish session rebuild: {:?}", e);
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to finish session rebuild"));
+    }
+
+    let response = RebuildSessionsResponse {
+        dry_run: rebuild_req.dry_run,
+        users_processed: user_ids.len(),
+        sessions_removed: changes.iter().map(|c| c.removed.len()).sum(),
+        sessions_added: changes.iter().map(|c| c.added.len()).sum(),
+        changes,
+    };
+
+    let message = if rebuild_req.dry_run {
+        "Session rebuild preview generated"
+    } else {
+        "Sessions rebuilt"
+    };
+
+    HttpResponse::Ok().json(ApiResponse::success(response, message))
+}
+
+/// Sessions closed by the scheduled job rather than an OUT punch, for admin review
+pub async fn get_auto_closed_sessions(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    query: web::Query<AutoClosedSessionsQuery>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SessionsRead).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    match sqlx::query_as::<_, AttendanceSession>(
+        r#"
+        SELECT * FROM attendance_sessions
+        WHERE auto_closed
+          AND ($1::text IS NULL OR user_id = $1)
+          AND ($2::date IS NULL OR date >= $2)
+          AND ($3::date IS NULL OR date <= $3)
+          AND ($4::int[] IS NULL OR user_id IN (SELECT user_id FROM user_info WHERE department = ANY($4)))
+        ORDER BY date DESC, user_id, session_number
+        "#
+    )
+    .bind(&query.user_id)
+    .bind(query.start_date)
+    .bind(query.end_date)
+    .bind(session.scope().departments())
+    .fetch_all(pool.as_ref())
+    .await
+    {
+        Ok(sessions) => HttpResponse::Ok().json(ApiResponse::success(sessions, "Auto-closed sessions retrieved")),
+        Err(e) => {
+            log::error!("Failed to retrieve auto-closed sessions: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve auto-closed sessions"))
+        }
+    }
+}
@@ -0,0 +1,552 @@
+use actix_web::{web, HttpResponse, HttpRequest};
+use chrono::Duration;
+use sqlx::{PgConnection, PgPool};
+
+use crate::admin::auth::require_capability;
+use crate::admin::models::{
+    AdminSession, CreateShiftRotationRequest, RosterQuery, ShiftAssignmentsRequest, ShiftRotationQuery,
+    ShiftTemplateRequest
+};
+use crate::audit::{self, Change};
+use crate::models::ApiResponse;
+use crate::permissions::Capability;
+use crate::schedule::{self, ShiftAssignment, ShiftRotation, ShiftTemplate};
+
+/// Longest span of days the roster can be read for at once
+const MAX_ROSTER_DAYS: i64 = 366;
+/// Longest rotation cycle, in days
+const MAX_ROTATION_DAYS: usize = 366;
+
+/// Whether an employee exists and is in the session's department scope
+async fn user_in_scope(pool: &PgPool, user_id: &str, session: &AdminSession) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM user_info WHERE user_id = $1 AND ($2::int[] IS NULL OR department = ANY($2)))"
+    )
+    .bind(user_id)
+    .bind(session.scope().departments())
+    .fetch_one(pool)
+    .await
+}
+
+/// First of `ids` that is not a shift template
+async fn unknown_template(conn: &mut PgConnection, ids: &[i32]) -> Result<Option<i32>, sqlx::Error> {
+    let known = sqlx::query_scalar::<_, i32>("SELECT id FROM shift_templates WHERE id = ANY($1)")
+        .bind(ids)
+        .fetch_all(&mut *conn)
+        .await?;
+
+    Ok(ids.iter().copied().find(|id| !known.contains(id)))
+}
+
+/// Check a template request, returning its break allowance
+#[allow(clippy::result_large_err)]
+fn validate_template(template_req: &ShiftTemplateRequest) -> Result<i32, HttpResponse> {
+    if template_req.name.trim().is_empty() {
+        return Err(HttpResponse::BadRequest().json(ApiResponse::<()>::error("name is required")));
+    }
+    if template_req.start_time == template_req.end_time {
+        return Err(HttpResponse::BadRequest().json(ApiResponse::<()>::error("start_time and end_time must differ")));
+    }
+
+    let break_minutes = template_req.break_minutes.unwrap_or(0);
+    let shift = schedule::Shift {
+        start_time: template_req.start_time,
+        end_time: template_req.end_time,
+        break_minutes,
+        ..schedule::default_shift()
+    };
+    if break_minutes < 0 || shift.scheduled_minutes() <= 0 {
+        return Err(HttpResponse::BadRequest().json(ApiResponse::<()>::error(
+            "break_minutes must be at least 0 and shorter than the shift"
+        )));
+    }
+
+    Ok(break_minutes)
+}
+
+fn conflict_or_error(e: sqlx::Error, message: &str) -> HttpResponse {
+    if e.to_string().contains("duplicate") || e.to_string().contains("unique") {
+        HttpResponse::Conflict().json(ApiResponse::<()>::error("A shift template with that name already exists"))
+    } else {
+        log::error!("{}: {:?}", message, e);
+        HttpResponse::InternalServerError().json(ApiResponse::<()>::error(message))
+    }
+}
+
+pub async fn get_shift_templates(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Err(response) = require_capability(&req, Capability::SettingsRead).await {
+        return response;
+    }
+
+    match sqlx::query_as::<_, ShiftTemplate>("SELECT * FROM shift_templates ORDER BY start_time, name")
+        .fetch_all(pool.as_ref())
+        .await
+    {
+        Ok(templates) => HttpResponse::Ok().json(ApiResponse::success(templates, "Shift templates retrieved")),
+        Err(e) => {
+            log::error!("Failed to retrieve shift templates: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve shift templates"))
+        }
+    }
+}
+
+pub async fn create_shift_template(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    template_req: web::Json<ShiftTemplateRequest>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let break_minutes = match validate_template(&template_req) {
+        Ok(break_minutes) => break_minutes,
+        Err(response) => return response,
+    };
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Transaction failed")),
+    };
+
+    let template = match sqlx::query_as::<_, ShiftTemplate>(
+        r#"
+        INSERT INTO shift_templates (name, start_time, end_time, break_minutes)
+        VALUES ($1, $2, $3, $4)
+        RETURNING *
+        "#
+    )
+    .bind(template_req.name.trim())
+    .bind(template_req.start_time)
+    .bind(template_req.end_time)
+    .bind(break_minutes)
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(template) => template,
+        Err(e) => {
+            let _ = tx.rollback().await;
+            return conflict_or_error(e, "Failed to create shift template");
+        }
+    };
+
+    let change = Change::new("shift_template_create", "shift_template", template.id).after(&template);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Created().json(ApiResponse::success(template, "Shift template created")),
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to commit transaction")),
+    }
+}
+
+pub async fn update_shift_template(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    template_req: web::Json<ShiftTemplateRequest>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let id = path.into_inner();
+
+    let break_minutes = match validate_template(&template_req) {
+        Ok(break_minutes) => break_minutes,
+        Err(response) => return response,
+    };
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Transaction failed")),
+    };
+
+    let previous = match sqlx::query_as::<_, ShiftTemplate>("SELECT * FROM shift_templates WHERE id = $1 FOR UPDATE")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(previous)) => previous,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Shift template not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update shift template")),
+    };
+
+    // Changing a template reschedules every day it is assigned to, past days included
+    let template = match sqlx::query_as::<_, ShiftTemplate>(
+        r#"
+        UPDATE shift_templates
+        SET name = $1, start_time = $2, end_time = $3, break_minutes = $4, updated_at = NOW()
+        WHERE id = $5
+        RETURNING *
+        "#
+    )
+    .bind(template_req.name.trim())
+    .bind(template_req.start_time)
+    .bind(template_req.end_time)
+    .bind(break_minutes)
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(template) => template,
+        Err(e) => {
+            let _ = tx.rollback().await;
+            return conflict_or_error(e, "Failed to update shift template");
+        }
+    };
+
+    let change = Change::new("shift_template_update", "shift_template", id).before(&previous).after(&template);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success(template, "Shift template updated")),
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to commit transaction")),
+    }
+}
+
+pub async fn delete_shift_template(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Transaction failed")),
+    };
+
+    // Rosters keep referring to a template, so one in use cannot go
+    let in_use = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM shift_assignments WHERE template_id = $1)
+            OR EXISTS(SELECT 1 FROM shift_rotations WHERE $1 = ANY(template_ids))
+        "#
+    )
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await;
+
+    match in_use {
+        Ok(false) => {}
+        Ok(true) => {
+            return HttpResponse::Conflict().json(ApiResponse::<()>::error(
+                "Shift template is used by the roster; reassign those days first"
+            ));
+        }
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete shift template")),
+    }
+
+    let deleted = match sqlx::query_as::<_, ShiftTemplate>("DELETE FROM shift_templates WHERE id = $1 RETURNING *")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(deleted)) => deleted,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Shift template not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete shift template")),
+    };
+
+    let change = Change::new("shift_template_delete", "shift_template", id).before(&deleted);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::<()>::success((), "Shift template deleted")),
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to commit transaction")),
+    }
+}
+
+/// The shift scheduled each day for a user, and where it comes from
+pub async fn get_roster(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    query: web::Query<RosterQuery>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsRead).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let days = (query.to - query.from).num_days() + 1;
+    if !(1..=MAX_ROSTER_DAYS).contains(&days) {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error(&format!(
+            "to must be on or after from and at most {} days later", MAX_ROSTER_DAYS
+        )));
+    }
+
+    match user_in_scope(&pool, &query.user_id, &session).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    }
+
+    let end = query.to + Duration::days(1);
+    match schedule::load_roster(&pool, &query.user_id, query.from, end).await {
+        Ok(roster) => HttpResponse::Ok().json(ApiResponse::success(roster.days(query.from, end), "Roster retrieved")),
+        Err(e) => {
+            log::error!("Failed to load roster of {}: {:?}", query.user_id, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve roster"))
+        }
+    }
+}
+
+/// Assign templates, or days off, to users on single dates, replacing what was assigned before
+pub async fn set_shift_assignments(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    body: web::Json<ShiftAssignmentsRequest>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+    let actor = session.actor(&req);
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Transaction failed")),
+    };
+
+    let template_ids: Vec<i32> = body.assignments.iter().filter_map(|a| a.template_id).collect();
+    match unknown_template(&mut tx, &template_ids).await {
+        Ok(None) => {}
+        Ok(Some(id)) => {
+            return HttpResponse::BadRequest().json(ApiResponse::<()>::error(&format!("Unknown shift template {}", id)));
+        }
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    }
+
+    let mut assignments = Vec::with_capacity(body.assignments.len());
+    for entry in &body.assignments {
+        let previous = sqlx::query_as::<_, ShiftAssignment>(
+            "SELECT * FROM shift_assignments WHERE user_id = $1 AND date = $2 FOR UPDATE"
+        )
+        .bind(&entry.user_id)
+        .bind(entry.date)
+        .fetch_optional(&mut *tx)
+        .await;
+
+        let assignment = match previous {
+            Ok(previous) => sqlx::query_as::<_, ShiftAssignment>(
+                r#"
+                INSERT INTO shift_assignments (user_id, date, template_id)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (user_id, date) DO UPDATE SET template_id = EXCLUDED.template_id
+                RETURNING *
+                "#
+            )
+            .bind(&entry.user_id)
+            .bind(entry.date)
+            .bind(entry.template_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map(|assignment| (previous, assignment)),
+            Err(e) => Err(e),
+        };
+
+        let (previous, assignment) = match assignment {
+            Ok(assignment) => assignment,
+            Err(e) => {
+                log::error!("Failed to assign shift to {} on {}: {:?}", entry.user_id, entry.date, e);
+                let _ = tx.rollback().await;
+                return if e.to_string().contains("foreign key") {
+                    HttpResponse::BadRequest().json(ApiResponse::<()>::error(&format!("Unknown user {}", entry.user_id)))
+                } else {
+                    HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to save shift assignments"))
+                };
+            }
+        };
+
+        let target = format!("{}:{}", assignment.user_id, assignment.date);
+        let mut change = Change::new("shift_assignment_set", "shift_assignment", target).after(&assignment);
+        if let Some(previous) = &previous {
+            change = change.before(previous);
+        }
+        if let Err(e) = audit::record_change(&mut *tx, &actor, &change).await {
+            log::error!("Failed to audit shift assignment: {:?}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to save shift assignments"));
+        }
+
+        assignments.push(assignment);
+    }
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success(assignments, "Shift assignments saved")),
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to commit transaction")),
+    }
+}
+
+/// Remove the assignment of a date, leaving it to the user's rotation or duty times
+pub async fn delete_shift_assignment(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<(String, chrono::NaiveDate)>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let (user_id, date) = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Transaction failed")),
+    };
+
+    let deleted = match sqlx::query_as::<_, ShiftAssignment>(
+        "DELETE FROM shift_assignments WHERE user_id = $1 AND date = $2 RETURNING *"
+    )
+    .bind(&user_id)
+    .bind(date)
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(deleted)) => deleted,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Shift assignment not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete shift assignment")),
+    };
+
+    let change = Change::new("shift_assignment_delete", "shift_assignment", format!("{}:{}", user_id, date)).before(&deleted);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::<()>::success((), "Shift assignment deleted")),
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to commit transaction")),
+    }
+}
+
+pub async fn get_shift_rotations(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    query: web::Query<ShiftRotationQuery>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsRead).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    match user_in_scope(&pool, &query.user_id, &session).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    }
+
+    match sqlx::query_as::<_, ShiftRotation>(
+        "SELECT * FROM shift_rotations WHERE user_id = $1 ORDER BY starts_on DESC, id DESC"
+    )
+    .bind(&query.user_id)
+    .fetch_all(pool.as_ref())
+    .await
+    {
+        Ok(rotations) => HttpResponse::Ok().json(ApiResponse::success(rotations, "Shift rotations retrieved")),
+        Err(e) => {
+            log::error!("Failed to retrieve shift rotations: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve shift rotations"))
+        }
+    }
+}
+
+/// Start a recurring pattern for a user. Where rotations overlap, the one starting last applies.
+pub async fn create_shift_rotation(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    rotation_req: web::Json<CreateShiftRotationRequest>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    if rotation_req.template_ids.is_empty() || rotation_req.template_ids.len() > MAX_ROTATION_DAYS {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error(&format!(
+            "template_ids must have 1 to {} entries", MAX_ROTATION_DAYS
+        )));
+    }
+    if rotation_req.ends_on.is_some_and(|ends_on| ends_on < rotation_req.starts_on) {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error("ends_on must not be before starts_on"));
+    }
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Transaction failed")),
+    };
+
+    let template_ids: Vec<i32> = rotation_req.template_ids.iter().flatten().copied().collect();
+    match unknown_template(&mut tx, &template_ids).await {
+        Ok(None) => {}
+        Ok(Some(id)) => {
+            return HttpResponse::BadRequest().json(ApiResponse::<()>::error(&format!("Unknown shift template {}", id)));
+        }
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    }
+
+    let rotation = match sqlx::query_as::<_, ShiftRotation>(
+        r#"
+        INSERT INTO shift_rotations (user_id, starts_on, ends_on, template_ids)
+        VALUES ($1, $2, $3, $4)
+        RETURNING *
+        "#
+    )
+    .bind(&rotation_req.user_id)
+    .bind(rotation_req.starts_on)
+    .bind(rotation_req.ends_on)
+    .bind(&rotation_req.template_ids)
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(rotation) => rotation,
+        Err(e) => {
+            log::error!("Failed to create shift rotation: {:?}", e);
+            let _ = tx.rollback().await;
+            return if e.to_string().contains("foreign key") {
+                HttpResponse::BadRequest().json(ApiResponse::<()>::error(&format!("Unknown user {}", rotation_req.user_id)))
+            } else {
+                HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create shift rotation"))
+            };
+        }
+    };
+
+    let change = Change::new("shift_rotation_create", "shift_rotation", rotation.id).after(&rotation);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Created().json(ApiResponse::success(rotation, "Shift rotation created")),
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to commit transaction")),
+    }
+}
+
+pub async fn delete_shift_rotation(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Transaction failed")),
+    };
+
+    let deleted = match sqlx::query_as::<_, ShiftRotation>("DELETE FROM shift_rotations WHERE id = $1 RETURNING *")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(deleted)) => deleted,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Shift rotation not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete shift rotation")),
+    };
+
+    let change = Change::new("shift_rotation_delete", "shift_rotation", id).before(&deleted);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::<()>::success((), "Shift rotation deleted")),
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to commit transaction")),
+    }
+}
@@ -2,26 +2,28 @@ use actix_web::{web, HttpResponse, HttpRequest};
 use sqlx::PgPool;
 use chrono::Datelike;
 
-use crate::admin::auth::require_admin_auth;
+use crate::admin::auth::require_capability;
 use crate::admin::models::{
     DepartmentStatsResponse, DepartmentStat, UserAttendanceStat,
     FilteredDepartmentStatsRequest, UserDetailRequest, UserDetailResponse, UserDetailRecord
 };
 use crate::models::ApiResponse;
+use crate::permissions::Capability;
+use crate::schedule;
+use crate::sessions::SessionConfig;
 use crate::timezone_config::TimezoneConfig;
 
 pub async fn get_department_stats(
     pool: web::Data<PgPool>,
     req: HttpRequest,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::StatsRead).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    let departments_query = if session.role == "admin" {
-        // Admin can see all departments
-        r#"
+    // Users limited to some departments only see those
+    let departments_query = r#"
         SELECT 
             ui.department,
             ui.department_name,
@@ -30,36 +32,15 @@ pub async fn get_department_stats(
             CAST(COALESCE(AVG(ats.total_work_minutes), 0) / 60.0 AS FLOAT8) as avg_work_hours
         FROM user_info ui
         LEFT JOIN attendance_summary ats ON ui.user_id = ats.user_id
+        WHERE $1::int[] IS NULL OR ui.department = ANY($1)
         GROUP BY ui.department, ui.department_name
         ORDER BY ui.department
-        "#
-    } else {
-        // Department users can only see their own department
-        r#"
-        SELECT 
-            ui.department,
-            ui.department_name,
-            COUNT(DISTINCT ui.user_id) as user_count,
-            COUNT(DISTINCT ats.date) as total_attendance_days,
-            CAST(COALESCE(AVG(ats.total_work_minutes), 0) / 60.0 AS FLOAT8) as avg_work_hours
-        FROM user_info ui
-        LEFT JOIN attendance_summary ats ON ui.user_id = ats.user_id
-        WHERE ui.department = $1
-        GROUP BY ui.department, ui.department_name
-        ORDER BY ui.department
-        "#
-    };
+    "#;
 
-    let departments_result = if session.role == "admin" {
-        sqlx::query_as::<_, (i32, Option<String>, i64, i64, f64)>(departments_query)
-            .fetch_all(pool.as_ref())
-            .await
-    } else {
-        sqlx::query_as::<_, (i32, Option<String>, i64, i64, f64)>(departments_query)
-            .bind(session.department.unwrap_or(0))
-            .fetch_all(pool.as_ref())
-            .await
-    };
+    let departments_result = sqlx::query_as::<_, (i32, Option<String>, i64, i64, f64)>(departments_query)
+        .bind(session.scope().departments())
+        .fetch_all(pool.as_ref())
+        .await;
 
     let departments = match departments_result {
         Ok(rows) => rows,
@@ -130,30 +111,15 @@ pub async fn export_attendance_csv(
     req: HttpRequest,
     _query: web::Query<ExportQuery>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::StatsExport).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    let export_query = if session.role == "admin" {
-        // Admin can export all departments
-        r#"
-        SELECT 
-            ats.user_id,
-            ats.date,
-            ats.first_checkin_time,
-            ats.last_checkout_time,
-            ats.total_work_minutes,
-            ats.total_sessions,
-            ui.department,
-            ui.department_name
-        FROM attendance_summary ats
-        JOIN user_info ui ON ats.user_id = ui.user_id
-        ORDER BY ats.date DESC, ats.user_id
-        "#
-    } else {
-        // Department users can only export their department
-        r#"
+    let scope = session.scope();
+
+    // Users limited to some departments can only export those
+    let export_query = r#"
         SELECT 
             ats.user_id,
             ats.date,
@@ -161,25 +127,19 @@ pub async fn export_attendance_csv(
             ats.last_checkout_time,
             ats.total_work_minutes,
             ats.total_sessions,
+            ats.auto_closed_sessions,
             ui.department,
             ui.department_name
         FROM attendance_summary ats
         JOIN user_info ui ON ats.user_id = ui.user_id
-        WHERE ui.department = $1
+        WHERE $1::int[] IS NULL OR ui.department = ANY($1)
         ORDER BY ats.date DESC, ats.user_id
-        "#
-    };
+    "#;
 
-    let records_result = if session.role == "admin" {
-        sqlx::query_as::<_, (String, chrono::NaiveDate, Option<chrono::DateTime<chrono::Utc>>, Option<chrono::DateTime<chrono::Utc>>, i32, i32, i32, Option<String>)>(export_query)
-            .fetch_all(pool.as_ref())
-            .await
-    } else {
-        sqlx::query_as::<_, (String, chrono::NaiveDate, Option<chrono::DateTime<chrono::Utc>>, Option<chrono::DateTime<chrono::Utc>>, i32, i32, i32, Option<String>)>(export_query)
-            .bind(session.department.unwrap_or(0))
-            .fetch_all(pool.as_ref())
-            .await
-    };
+    let records_result = sqlx::query_as::<_, (String, chrono::NaiveDate, Option<chrono::DateTime<chrono::Utc>>, Option<chrono::DateTime<chrono::Utc>>, i32, i32, i32, i32, Option<String>)>(export_query)
+        .bind(scope.departments())
+        .fetch_all(pool.as_ref())
+        .await;
 
     let records = match records_result {
         Ok(rows) => rows,
@@ -190,9 +150,9 @@ pub async fn export_attendance_csv(
     };
 
     // Generate CSV content
-    let mut csv_content = String::from("User ID,Date,First Checkin,Last Checkout,Work Minutes,Work Hours,Sessions,Department,Department Name\n");
+    let mut csv_content = String::from("User ID,Date,First Checkin,Last Checkout,Work Minutes,Work Hours,Sessions,Auto-closed Sessions,Department,Department Name\n");
     
-    for (user_id, date, first_checkin, last_checkout, work_minutes, sessions, department, department_name) in records {
+    for (user_id, date, first_checkin, last_checkout, work_minutes, sessions, auto_closed_sessions, department, department_name) in records {
         // Use configured timezone for local display  
         let timezone_config = TimezoneConfig::local();
         
@@ -208,19 +168,14 @@ pub async fn export_attendance_csv(
         let dept_name = department_name.unwrap_or_else(|| "".to_string());
         
         csv_content.push_str(&format!(
-            "{},{},{},{},{},{:.2},{},{},{}\n",
-            user_id, date, first_checkin_str, last_checkout_str, work_minutes, work_hours, sessions, department, dept_name
+            "{},{},{},{},{},{:.2},{},{},{},{}\n",
+            user_id, date, first_checkin_str, last_checkout_str, work_minutes, work_hours, sessions, auto_closed_sessions, department, dept_name
         ));
     }
 
-    let filename = if let Some(dept) = session.department {
-        if session.role == "department" {
-            format!("attendance_department_{}.csv", dept)
-        } else {
-            "attendance_export.csv".to_string()
-        }
-    } else {
-        "attendance_export.csv".to_string()
+    let filename = match scope.departments() {
+        Some([dept]) => format!("attendance_department_{}.csv", dept),
+        _ => "attendance_export.csv".to_string(),
     };
 
     HttpResponse::Ok()
@@ -241,7 +196,7 @@ pub async fn get_filtered_department_stats(
     req: HttpRequest,
     query: web::Query<FilteredDepartmentStatsRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::StatsRead).await {
         Ok(session) => session,
         Err(response) => return response,
     };
@@ -278,13 +233,10 @@ pub async fn get_filtered_department_stats(
     // Build department filter conditions
     let mut dept_conditions = Vec::new();
 
-    // Role-based department filtering
-    if session.role == "department" {
-        if let Some(dept) = session.department {
-            dept_conditions.push(format!("ui.department = {}", dept));
-        }
-    } else if let Some(dept) = query.department {
-        dept_conditions.push(format!("ui.department = {}", dept));
+    // Requested department, narrowed to the departments the user may see
+    if let Some(departments) = session.scope().filter(query.department) {
+        let list = departments.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(",");
+        dept_conditions.push(format!("ui.department = ANY('{{{}}}'::int[])", list));
     }
 
     // User name filter
@@ -398,7 +350,7 @@ pub async fn get_user_detail(
     req: HttpRequest,
     query: web::Query<UserDetailRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::StatsRead).await {
         Ok(session) => session,
         Err(response) => return response,
     };
@@ -422,14 +374,8 @@ pub async fn get_user_detail(
     };
 
     // Permission check
-    if session.role == "department" {
-        if let Some(session_dept) = session.department {
-            if session_dept != user_department {
-                return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Access denied"));
-            }
-        } else {
-            return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Access denied"));
-        }
+    if !session.scope().contains(user_department) {
+        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Access denied"));
     }
 
     // Calculate date range for the selected month
@@ -442,7 +388,17 @@ pub async fn get_user_detail(
         chrono::NaiveDate::from_ymd_opt(query.year, query.month + 1, 1).unwrap()
     };
 
-    // Get detailed attendance records for the user and month with custom time settings
+    // Lateness is judged against the shift scheduled each day
+    let roster = match schedule::load_roster(&pool, &query.user_id, start_date, end_date).await {
+        Ok(roster) => roster,
+        Err(e) => {
+            log::error!("Failed to load roster of {}: {:?}", query.user_id, e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve user records"));
+        }
+    };
+    let utc_offset = SessionConfig::global().utc_offset;
+
+    // Get detailed attendance records for the user and month
     let records_query = r#"
         SELECT 
             ats.date,
@@ -450,23 +406,13 @@ pub async fn get_user_detail(
             ats.last_checkout_time,
             ats.total_work_minutes,
             ats.total_sessions,
-            CASE 
-                WHEN ats.first_checkin_time IS NOT NULL AND 
-                     ats.first_checkin_time::time > COALESCE(uts.on_duty_time, '07:30:00'::time) THEN true
-                ELSE false
-            END as is_late,
-            CASE 
-                WHEN ats.last_checkout_time IS NOT NULL AND 
-                     ats.last_checkout_time::time < COALESCE(uts.off_duty_time, '17:00:00'::time) THEN true
-                ELSE false
-            END as is_early_leave
+            ats.auto_closed_sessions
         FROM attendance_summary ats
-        LEFT JOIN user_time_settings uts ON ats.user_id = uts.user_id
         WHERE ats.user_id = $1 AND ats.date >= $2 AND ats.date < $3
         ORDER BY ats.date ASC
     "#;
 
-    let records_result = sqlx::query_as::<_, (chrono::NaiveDate, Option<chrono::DateTime<chrono::Utc>>, Option<chrono::DateTime<chrono::Utc>>, Option<i32>, Option<i32>, bool, bool)>(records_query)
+    let records_result = sqlx::query_as::<_, (chrono::NaiveDate, Option<chrono::DateTime<chrono::Utc>>, Option<chrono::DateTime<chrono::Utc>>, Option<i32>, Option<i32>, i32)>(records_query)
         .bind(&query.user_id)
         .bind(start_date)
         .bind(end_date)
@@ -474,15 +420,22 @@ pub async fn get_user_detail(
         .await;
 
     let records = match records_result {
-        Ok(rows) => rows.into_iter().map(|(date, first_checkin, last_checkout, total_work_minutes, total_sessions, is_late, is_early_leave)| {
+        Ok(rows) => rows.into_iter().map(|(date, first_checkin, last_checkout, total_work_minutes, total_sessions, auto_closed_sessions)| {
+            let day = roster.day(date);
+            let evaluation = schedule::evaluate(&day, first_checkin, last_checkout, utc_offset, &roster.rules);
             UserDetailRecord {
                 date,
                 first_checkin,
                 last_checkout,
                 total_work_minutes,
                 total_sessions,
-                is_late,
-                is_early_leave,
+                auto_closed_sessions,
+                is_late: evaluation.is_late,
+                late_minutes: evaluation.late_minutes,
+                late_tier: evaluation.late_tier,
+                is_early_leave: evaluation.is_early_leave,
+                early_leave_minutes: evaluation.early_leave_minutes,
+                shift: day.shift,
             }
         }).collect::<Vec<_>>(),
         Err(e) => {
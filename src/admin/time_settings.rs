@@ -1,76 +1,148 @@
 use actix_web::{web, HttpResponse, HttpRequest};
-use sqlx::PgPool;
-use chrono::NaiveTime;
+use sqlx::{PgConnection, PgPool};
+use chrono::{Duration, NaiveDate, NaiveTime};
+use std::collections::HashMap;
 
-use crate::admin::auth::require_admin_auth;
+use crate::admin::auth::require_capability;
 use crate::admin::models::{
-    BatchUpdateTimeSettingsRequest, UserWithTimeSetting
+    BatchUpdateTimeSettingsRequest, LatenessOverrides, TimeSettingVersion, UserLatenessRules, UserTimeSetting,
+    UserWithTimeSetting, WeekdayHoursRequest
 };
+use crate::audit::{self, Actor, Change};
 use crate::models::ApiResponse;
+use crate::permissions::Capability;
+use crate::schedule::{self, ShiftSource, WeekdayHours};
+
+/// A user with their own time setting and their department's default in effect today, if any
+type UserTimeSettingRow = (
+    String, Option<String>, i32, Option<String>,
+    Option<NaiveTime>, Option<NaiveTime>, Option<NaiveDate>,
+    Option<NaiveTime>, Option<NaiveTime>,
+);
 
 pub async fn get_users_with_time_settings(
     pool: web::Data<PgPool>,
     req: HttpRequest,
     query: web::Query<serde_json::Value>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::SettingsRead).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    // Only admin can access time settings
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Access denied"));
-    }
-
     let department_filter = query.get("department")
         .and_then(|v| v.as_str())
         .and_then(|s| s.parse::<i32>().ok());
 
-    let result = if let Some(dept) = department_filter {
-        sqlx::query_as::<_, (String, Option<String>, i32, Option<String>, Option<NaiveTime>, Option<NaiveTime>)>(r#"
+    let today = schedule::local_today();
+    let result = if let Some(departments) = session.scope().filter(department_filter) {
+        sqlx::query_as::<_, UserTimeSettingRow>(r#"
             SELECT 
                 ui.user_id,
                 ui.user_name,
                 ui.department,
                 ui.department_name,
                 uts.on_duty_time,
-                uts.off_duty_time
+                uts.off_duty_time,
+                uts.effective_from,
+                dts.on_duty_time,
+                dts.off_duty_time
             FROM user_info ui
-            LEFT JOIN user_time_settings uts ON ui.user_id = uts.user_id
-            WHERE ui.department = $1
+            LEFT JOIN LATERAL (
+                SELECT * FROM user_time_settings
+                WHERE user_id = ui.user_id AND effective_from <= $2
+                ORDER BY effective_from DESC
+                LIMIT 1
+            ) uts ON true
+            LEFT JOIN LATERAL (
+                SELECT * FROM department_time_settings
+                WHERE department = ui.department AND effective_from <= $2
+                ORDER BY effective_from DESC
+                LIMIT 1
+            ) dts ON true
+            WHERE ui.department = ANY($1)
             ORDER BY ui.department, ui.user_id
         "#)
-        .bind(dept)
+        .bind(&departments)
+        .bind(today)
         .fetch_all(pool.as_ref())
         .await
     } else {
-        sqlx::query_as::<_, (String, Option<String>, i32, Option<String>, Option<NaiveTime>, Option<NaiveTime>)>(r#"
+        sqlx::query_as::<_, UserTimeSettingRow>(r#"
             SELECT 
                 ui.user_id,
                 ui.user_name,
                 ui.department,
                 ui.department_name,
                 uts.on_duty_time,
-                uts.off_duty_time
+                uts.off_duty_time,
+                uts.effective_from,
+                dts.on_duty_time,
+                dts.off_duty_time
             FROM user_info ui
-            LEFT JOIN user_time_settings uts ON ui.user_id = uts.user_id
+            LEFT JOIN LATERAL (
+                SELECT * FROM user_time_settings
+                WHERE user_id = ui.user_id AND effective_from <= $1
+                ORDER BY effective_from DESC
+                LIMIT 1
+            ) uts ON true
+            LEFT JOIN LATERAL (
+                SELECT * FROM department_time_settings
+                WHERE department = ui.department AND effective_from <= $1
+                ORDER BY effective_from DESC
+                LIMIT 1
+            ) dts ON true
             ORDER BY ui.department, ui.user_id
         "#)
+        .bind(today)
         .fetch_all(pool.as_ref())
         .await
     };
 
-    match result {
-        Ok(rows) => {
-            let users: Vec<UserWithTimeSetting> = rows.into_iter().map(|(user_id, user_name, department, department_name, on_duty_time, off_duty_time)| {
+    let rows = match result {
+        Ok(rows) => rows,
+        Err(e) => {
+            log::error!("Failed to retrieve users with time settings: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve users"));
+        }
+    };
+
+    let user_ids: Vec<&str> = rows.iter().map(|row| row.0.as_str()).collect();
+    let weekday_hours = sqlx::query_as::<_, WeekdayHours>(
+        "SELECT * FROM user_weekday_hours WHERE user_id = ANY($1) ORDER BY user_id, weekday"
+    )
+    .bind(&user_ids)
+    .fetch_all(pool.as_ref())
+    .await;
+
+    match weekday_hours {
+        Ok(weekday_hours) => {
+            let mut weekdays_by_setting: HashMap<(String, NaiveDate), Vec<WeekdayHours>> = HashMap::new();
+            for hours in weekday_hours {
+                weekdays_by_setting.entry((hours.user_id.clone(), hours.effective_from)).or_default().push(hours);
+            }
+
+            let users: Vec<UserWithTimeSetting> = rows.into_iter().map(|row| {
+                let (user_id, user_name, department, department_name, own_on_duty, own_off_duty, effective_from, department_on_duty, department_off_duty) = row;
+                let default_shift = schedule::default_shift();
+                let (source, on_duty_time, off_duty_time) = match (own_on_duty.zip(own_off_duty), department_on_duty.zip(department_off_duty)) {
+                    (Some((on_duty, off_duty)), _) => (ShiftSource::TimeSetting, on_duty, off_duty),
+                    (None, Some((on_duty, off_duty))) => (ShiftSource::Department, on_duty, off_duty),
+                    (None, None) => (ShiftSource::Default, default_shift.start_time, default_shift.end_time),
+                };
+
                 UserWithTimeSetting {
+                    weekdays: effective_from
+                        .and_then(|effective_from| weekdays_by_setting.remove(&(user_id.clone(), effective_from)))
+                        .unwrap_or_default(),
                     user_id,
                     user_name,
                     department,
                     department_name,
                     on_duty_time,
                     off_duty_time,
+                    source,
+                    effective_from,
                 }
             }).collect();
             
@@ -88,15 +160,11 @@ pub async fn batch_update_time_settings(
     req: HttpRequest,
     body: web::Json<BatchUpdateTimeSettingsRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
-
-    // Only admin can modify time settings
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Access denied"));
-    }
+    let actor = session.actor(&req);
 
     let mut transaction = match pool.begin().await {
         Ok(tx) => tx,
@@ -124,21 +192,25 @@ pub async fn batch_update_time_settings(
             }
         };
 
-        // UPSERT time setting
-        let upsert_result = sqlx::query(r#"
-            INSERT INTO user_time_settings (user_id, on_duty_time, off_duty_time)
-            VALUES ($1, $2, $3)
-            ON CONFLICT (user_id) 
-            DO UPDATE SET 
-                on_duty_time = EXCLUDED.on_duty_time,
-                off_duty_time = EXCLUDED.off_duty_time,
-                updated_at = NOW()
-        "#)
-        .bind(&setting.user_id)
-        .bind(on_duty_time)
-        .bind(off_duty_time)
-        .execute(&mut *transaction)
-        .await;
+        let weekdays = match setting.weekdays.as_deref().map(parse_weekday_hours).transpose() {
+            Ok(weekdays) => weekdays,
+            Err(message) => {
+                return HttpResponse::BadRequest().json(ApiResponse::<()>::error(&format!("Invalid weekday hours for user {}: {}", setting.user_id, message)));
+            }
+        };
+
+        // Changes apply from today unless dated, so earlier months keep their hours
+        let effective_from = setting.effective_from.unwrap_or_else(schedule::local_today);
+
+        let upsert_result = save_time_setting(
+            &mut transaction,
+            &setting.user_id,
+            effective_from,
+            on_duty_time,
+            off_duty_time,
+            weekdays.as_deref(),
+            &actor,
+        ).await;
 
         match upsert_result {
             Ok(_) => updated_count += 1,
@@ -166,39 +238,472 @@ pub async fn batch_update_time_settings(
     }
 }
 
-pub async fn delete_time_setting(
+/// Save a user's duty times from `effective_from`, replacing the setting starting that day
+/// if there is one, and set its weekday hours. A new setting without weekday hours takes
+/// those of the setting it follows.
+#[allow(clippy::too_many_arguments)]
+async fn save_time_setting(
+    conn: &mut PgConnection,
+    user_id: &str,
+    effective_from: NaiveDate,
+    on_duty_time: NaiveTime,
+    off_duty_time: NaiveTime,
+    weekdays: Option<&[ParsedWeekday]>,
+    actor: &Actor,
+) -> Result<(), sqlx::Error> {
+    let previous = sqlx::query_as::<_, UserTimeSetting>(
+        "SELECT * FROM user_time_settings WHERE user_id = $1 AND effective_from = $2 FOR UPDATE"
+    )
+    .bind(user_id)
+    .bind(effective_from)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let setting = sqlx::query_as::<_, UserTimeSetting>(r#"
+        INSERT INTO user_time_settings (user_id, effective_from, on_duty_time, off_duty_time)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id, effective_from) 
+        DO UPDATE SET 
+            on_duty_time = EXCLUDED.on_duty_time,
+            off_duty_time = EXCLUDED.off_duty_time,
+            updated_at = NOW()
+        RETURNING *
+    "#)
+    .bind(user_id)
+    .bind(effective_from)
+    .bind(on_duty_time)
+    .bind(off_duty_time)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let mut change = Change::new("time_setting_update", "time_setting", user_id).after(&setting);
+    if let Some(previous) = &previous {
+        change = change.before(previous);
+    }
+    audit::record_change(&mut *conn, actor, &change).await?;
+
+    match weekdays {
+        Some(weekdays) => replace_weekday_hours(conn, user_id, effective_from, weekdays, actor).await,
+        None if previous.is_none() => {
+            let inherited: Vec<ParsedWeekday> = sqlx::query_as::<_, WeekdayHours>(r#"
+                SELECT * FROM user_weekday_hours
+                WHERE user_id = $1 AND effective_from = (
+                    SELECT MAX(effective_from) FROM user_time_settings WHERE user_id = $1 AND effective_from < $2
+                )
+            "#)
+            .bind(user_id)
+            .bind(effective_from)
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .map(|hours| (hours.weekday, hours.on_duty_time.zip(hours.off_duty_time)))
+            .collect();
+            replace_weekday_hours(conn, user_id, effective_from, &inherited, actor).await
+        }
+        None => Ok(()),
+    }
+}
+
+/// An ISO weekday with its on and off duty times, or None for a day off
+type ParsedWeekday = (i16, Option<(NaiveTime, NaiveTime)>);
+
+/// Check weekday hours, returning each weekday with its times or None for a day off
+fn parse_weekday_hours(weekdays: &[WeekdayHoursRequest]) -> Result<Vec<ParsedWeekday>, String> {
+    let parse = |time: &str| NaiveTime::parse_from_str(time, "%H:%M:%S").map_err(|_| format!("invalid time {}", time));
+
+    let mut parsed: Vec<ParsedWeekday> = Vec::with_capacity(weekdays.len());
+    for hours in weekdays {
+        if !(1..=7).contains(&hours.weekday) {
+            return Err(format!("weekday must be 1 (Monday) to 7 (Sunday), got {}", hours.weekday));
+        }
+        if parsed.iter().any(|(weekday, _)| *weekday == hours.weekday) {
+            return Err(format!("weekday {} is listed twice", hours.weekday));
+        }
+
+        let times = match (&hours.on_duty_time, &hours.off_duty_time) {
+            (Some(on_duty_time), Some(off_duty_time)) => Some((parse(on_duty_time)?, parse(off_duty_time)?)),
+            (None, None) => None,
+            _ => return Err(format!("weekday {} needs both on_duty_time and off_duty_time, or neither", hours.weekday)),
+        };
+        parsed.push((hours.weekday, times));
+    }
+    Ok(parsed)
+}
+
+/// Replace the weekday hours of one of a user's settings, auditing them before and after
+async fn replace_weekday_hours(
+    conn: &mut PgConnection,
+    user_id: &str,
+    effective_from: NaiveDate,
+    weekdays: &[ParsedWeekday],
+    actor: &Actor,
+) -> Result<(), sqlx::Error> {
+    let previous = sqlx::query_as::<_, WeekdayHours>(
+        "DELETE FROM user_weekday_hours WHERE user_id = $1 AND effective_from = $2 RETURNING *"
+    )
+    .bind(user_id)
+    .bind(effective_from)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut current = Vec::with_capacity(weekdays.len());
+    for (weekday, times) in weekdays {
+        let hours = sqlx::query_as::<_, WeekdayHours>(r#"
+            INSERT INTO user_weekday_hours (user_id, effective_from, weekday, on_duty_time, off_duty_time)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+        "#)
+        .bind(user_id)
+        .bind(effective_from)
+        .bind(weekday)
+        .bind(times.map(|(on_duty_time, _)| on_duty_time))
+        .bind(times.map(|(_, off_duty_time)| off_duty_time))
+        .fetch_one(&mut *conn)
+        .await?;
+        current.push(hours);
+    }
+    current.sort_by_key(|hours| hours.weekday);
+
+    let unchanged = previous.len() == current.len() && previous.iter().all(|before| {
+        current.iter().any(|after| {
+            (after.weekday, after.on_duty_time, after.off_duty_time) == (before.weekday, before.on_duty_time, before.off_duty_time)
+        })
+    });
+    if unchanged {
+        return Ok(());
+    }
+
+    let change = Change::new("time_setting_weekdays_update", "time_setting", user_id)
+        .before(&previous)
+        .after(&current);
+    audit::record_change(&mut *conn, actor, &change).await
+}
+
+/// Every setting of a user, oldest first, with the day each one ends
+async fn load_time_setting_versions(conn: &mut PgConnection, user_id: &str) -> Result<Vec<TimeSettingVersion>, sqlx::Error> {
+    let settings = sqlx::query_as::<_, UserTimeSetting>(
+        "SELECT * FROM user_time_settings WHERE user_id = $1 ORDER BY effective_from"
+    )
+    .bind(user_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut weekdays_by_setting: HashMap<NaiveDate, Vec<WeekdayHours>> = HashMap::new();
+    for hours in sqlx::query_as::<_, WeekdayHours>(
+        "SELECT * FROM user_weekday_hours WHERE user_id = $1 ORDER BY effective_from, weekday"
+    )
+    .bind(user_id)
+    .fetch_all(&mut *conn)
+    .await?
+    {
+        weekdays_by_setting.entry(hours.effective_from).or_default().push(hours);
+    }
+
+    let effective_to: Vec<Option<NaiveDate>> = settings
+        .iter()
+        .skip(1)
+        .map(|next| Some(next.effective_from - Duration::days(1)))
+        .chain(std::iter::once(None))
+        .collect();
+
+    Ok(settings
+        .into_iter()
+        .zip(effective_to)
+        .map(|(setting, effective_to)| TimeSettingVersion {
+            weekdays: weekdays_by_setting.remove(&setting.effective_from).unwrap_or_default(),
+            setting,
+            effective_to,
+        })
+        .collect())
+}
+
+/// Whether the user exists in one of `departments`, or at all when unrestricted
+async fn user_in_scope(conn: &mut PgConnection, user_id: &str, departments: Option<&[i32]>) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM user_info WHERE user_id = $1 AND ($2::int[] IS NULL OR department = ANY($2)))"
+    )
+    .bind(user_id)
+    .bind(departments)
+    .fetch_one(conn)
+    .await
+}
+
+/// A user's settings over time, including changes scheduled for later dates
+pub async fn get_time_setting_history(
     pool: web::Data<PgPool>,
     req: HttpRequest,
     path: web::Path<String>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::SettingsRead).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    // Only admin can delete time settings
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Access denied"));
+    let user_id = path.into_inner();
+
+    let mut conn = match pool.acquire().await {
+        Ok(conn) => conn,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    };
+
+    match user_in_scope(&mut conn, &user_id, session.scope().departments()).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    }
+
+    match load_time_setting_versions(&mut conn, &user_id).await {
+        Ok(versions) => HttpResponse::Ok().json(ApiResponse::success(versions, "Time setting history retrieved")),
+        Err(e) => {
+            log::error!("Failed to retrieve time setting history of {}: {:?}", user_id, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve time setting history"))
+        }
     }
+}
+
+/// Remove every setting of a user, leaving them on the default hours
+pub async fn delete_time_setting(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
 
     let user_id = path.into_inner();
 
-    let result = sqlx::query("DELETE FROM user_time_settings WHERE user_id = $1")
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete time setting"));
+        }
+    };
+
+    let versions = match load_time_setting_versions(&mut tx, &user_id).await {
+        Ok(versions) if versions.is_empty() => {
+            return HttpResponse::NotFound().json(ApiResponse::<()>::error("Time setting not found"));
+        }
+        Ok(versions) => versions,
+        Err(e) => {
+            log::error!("Failed to delete time setting: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete time setting"));
+        }
+    };
+
+    // Weekday hours are removed with their settings
+    if let Err(e) = sqlx::query("DELETE FROM user_time_settings WHERE user_id = $1")
         .bind(&user_id)
-        .execute(pool.as_ref())
-        .await;
-
-    match result {
-        Ok(rows) => {
-            if rows.rows_affected() > 0 {
-                HttpResponse::Ok().json(ApiResponse::success((), "Time setting deleted successfully"))
-            } else {
-                HttpResponse::NotFound().json(ApiResponse::<()>::error("Time setting not found"))
-            }
+        .execute(&mut *tx)
+        .await
+    {
+        log::error!("Failed to delete time setting: {:?}", e);
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete time setting"));
+    }
+
+    let change = Change::new("time_setting_delete", "time_setting", &user_id).before(&versions);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success((), "Time setting deleted successfully")),
+        Err(e) => {
+            log::error!("Failed to commit time setting deletion: {:?}", e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete time setting"))
+        }
+    }
+}
+
+/// Remove the setting starting on one date, e.g. to cancel a scheduled change. The
+/// setting before it then applies until the next one.
+pub async fn delete_time_setting_version(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<(String, NaiveDate)>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let (user_id, effective_from) = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete time setting"));
         }
+    };
+
+    let version = match load_time_setting_versions(&mut tx, &user_id).await {
+        Ok(versions) => versions.into_iter().find(|version| version.setting.effective_from == effective_from),
         Err(e) => {
             log::error!("Failed to delete time setting: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete time setting"));
+        }
+    };
+    let version = match version {
+        Some(version) => version,
+        None => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Time setting not found")),
+    };
+
+    if let Err(e) = sqlx::query("DELETE FROM user_time_settings WHERE id = $1")
+        .bind(version.setting.id)
+        .execute(&mut *tx)
+        .await
+    {
+        log::error!("Failed to delete time setting: {:?}", e);
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete time setting"));
+    }
+
+    let change = Change::new("time_setting_delete", "time_setting", &user_id).before(&version);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success((), "Time setting deleted successfully")),
+        Err(e) => {
+            log::error!("Failed to commit time setting deletion: {:?}", e);
             HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete time setting"))
         }
     }
 }
+
+async fn load_lateness_rules(conn: &mut PgConnection, user_id: &str) -> Result<UserLatenessRules, sqlx::Error> {
+    let overrides = sqlx::query_as::<_, LatenessOverrides>(
+        "SELECT late_grace_minutes, early_leave_grace_minutes, major_late_minutes FROM user_lateness_rules WHERE user_id = $1"
+    )
+    .bind(user_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .unwrap_or_default();
+
+    Ok(UserLatenessRules {
+        user_id: user_id.to_string(),
+        overrides,
+        effective: schedule::load_rules(&mut *conn, user_id).await?,
+    })
+}
+
+/// A user's lateness thresholds, their own and the ones applied after their department's
+pub async fn get_lateness_rules(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsRead).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let user_id = path.into_inner();
+
+    let mut conn = match pool.acquire().await {
+        Ok(conn) => conn,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    };
+
+    match user_in_scope(&mut conn, &user_id, session.scope().departments()).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    }
+
+    match load_lateness_rules(&mut conn, &user_id).await {
+        Ok(rules) => HttpResponse::Ok().json(ApiResponse::success(rules, "Lateness rules retrieved")),
+        Err(e) => {
+            log::error!("Failed to retrieve lateness rules of {}: {:?}", user_id, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve lateness rules"))
+        }
+    }
+}
+
+/// Replace a user's own lateness thresholds. Absent values follow the department, so an
+/// empty request returns the user to their department's thresholds.
+pub async fn set_lateness_rules(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<String>,
+    overrides: web::Json<LatenessOverrides>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::SettingsManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    if let Err(message) = schedule::validate_rules(
+        overrides.late_grace_minutes,
+        overrides.early_leave_grace_minutes,
+        overrides.major_late_minutes,
+    ) {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error(message));
+    }
+
+    let user_id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update lateness rules"));
+        }
+    };
+
+    match user_in_scope(&mut tx, &user_id, session.scope().departments()).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    }
+
+    let before = match load_lateness_rules(&mut tx, &user_id).await {
+        Ok(rules) => rules,
+        Err(e) => {
+            log::error!("Failed to update lateness rules of {}: {:?}", user_id, e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update lateness rules"));
+        }
+    };
+
+    let result = if overrides.is_empty() {
+        sqlx::query("DELETE FROM user_lateness_rules WHERE user_id = $1")
+            .bind(&user_id)
+            .execute(&mut *tx)
+            .await
+    } else {
+        sqlx::query(
+            r#"
+            INSERT INTO user_lateness_rules (user_id, late_grace_minutes, early_leave_grace_minutes, major_late_minutes)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id)
+            DO UPDATE SET
+                late_grace_minutes = EXCLUDED.late_grace_minutes,
+                early_leave_grace_minutes = EXCLUDED.early_leave_grace_minutes,
+                major_late_minutes = EXCLUDED.major_late_minutes,
+                updated_at = NOW()
+            "#
+        )
+        .bind(&user_id)
+        .bind(overrides.late_grace_minutes)
+        .bind(overrides.early_leave_grace_minutes)
+        .bind(overrides.major_late_minutes)
+        .execute(&mut *tx)
+        .await
+    };
+
+    let after = match result {
+        Ok(_) => load_lateness_rules(&mut tx, &user_id).await,
+        Err(e) => Err(e),
+    };
+    let after = match after {
+        Ok(rules) => rules,
+        Err(e) => {
+            log::error!("Failed to update lateness rules of {}: {:?}", user_id, e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update lateness rules"));
+        }
+    };
+
+    let change = Change::new("lateness_rules_update", "lateness_rules", &user_id).before(&before).after(&after);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success(after, "Lateness rules updated")),
+        Err(e) => {
+            log::error!("Failed to commit lateness rules of {}: {:?}", user_id, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update lateness rules"))
+        }
+    }
+}
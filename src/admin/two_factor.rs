@@ -0,0 +1,716 @@
+use actix_web::{web, HttpResponse, HttpRequest};
+use chrono::{DateTime, Duration, Utc};
+use lazy_static::lazy_static;
+use sqlx::{FromRow, PgConnection, PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::admin::auth::{bearer_token, complete_login, require_capability, require_user_session, revoke_user_sessions};
+use crate::admin::models::{
+    AdminSession, AdminUser, LoginChallengeRequest, RecoveryCodesResponse, TotpCodeRequest,
+    TotpEnrollmentResponse, VerifyLoginRequest,
+};
+use crate::audit;
+use crate::lockout::{self, LockScope};
+use crate::models::ApiResponse;
+use crate::password::token_hash;
+use crate::permissions::{Capability, Role};
+use crate::totp;
+
+/// Default time between the password step of a login and its second factor
+pub const DEFAULT_LOGIN_CHALLENGE_SECONDS: i64 = 300;
+/// Wrong codes allowed on one challenge before it is discarded
+pub const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+/// Default issuer shown in authenticator apps
+pub const DEFAULT_TOTP_ISSUER: &str = "KBK Attendance";
+
+lazy_static! {
+    static ref TWO_FACTOR_CONFIG: TwoFactorConfig = TwoFactorConfig::from_env();
+}
+
+/// Which roles must use TOTP and how long login challenges live
+#[derive(Debug, Clone)]
+pub struct TwoFactorConfig {
+    pub required_roles: Vec<Role>,
+    pub challenge_ttl: Duration,
+    pub issuer: String,
+}
+
+impl Default for TwoFactorConfig {
+    fn default() -> Self {
+        Self {
+            required_roles: Vec::new(),
+            challenge_ttl: Duration::seconds(DEFAULT_LOGIN_CHALLENGE_SECONDS),
+            issuer: DEFAULT_TOTP_ISSUER.to_string(),
+        }
+    }
+}
+
+impl TwoFactorConfig {
+    /// Read ADMIN_TOTP_REQUIRED_ROLES (comma-separated), ADMIN_LOGIN_CHALLENGE_SECONDS and
+    /// ADMIN_TOTP_ISSUER, falling back to the defaults
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+
+        if let Ok(value) = std::env::var("ADMIN_TOTP_REQUIRED_ROLES") {
+            for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                match Role::parse(name) {
+                    Some(role) => config.required_roles.push(role),
+                    None => log::warn!("Ignoring invalid role in ADMIN_TOTP_REQUIRED_ROLES: {}", name),
+                }
+            }
+        }
+
+        if let Ok(value) = std::env::var("ADMIN_LOGIN_CHALLENGE_SECONDS") {
+            match value.parse::<i64>() {
+                Ok(seconds) if seconds > 0 => config.challenge_ttl = Duration::seconds(seconds),
+                _ => log::warn!("Ignoring invalid ADMIN_LOGIN_CHALLENGE_SECONDS: {}", value),
+            }
+        }
+
+        if let Ok(value) = std::env::var("ADMIN_TOTP_ISSUER") {
+            if !value.trim().is_empty() {
+                config.issuer = value.trim().to_string();
+            }
+        }
+
+        config
+    }
+
+    /// Process-wide configuration loaded from the environment on first use
+    pub fn global() -> &'static TwoFactorConfig {
+        &TWO_FACTOR_CONFIG
+    }
+
+    pub fn role_requires_totp(&self, role: &str) -> bool {
+        Role::parse(role).is_some_and(|role| self.required_roles.contains(&role))
+    }
+}
+
+/// Whether the password step of a login must be followed by a TOTP code
+pub fn login_requires_totp(user: &AdminUser) -> bool {
+    user.totp_enabled_at.is_some() || TwoFactorConfig::global().role_requires_totp(&user.role)
+}
+
+/// Admin user with their TOTP state
+#[derive(Debug, FromRow)]
+struct TotpUser {
+    id: i32,
+    username: String,
+    role: String,
+    departments: Vec<i32>,
+    totp_secret: Option<String>,
+    totp_enabled_at: Option<DateTime<Utc>>,
+    totp_last_step: Option<i64>,
+}
+
+impl TotpUser {
+    fn secret(&self) -> Option<Vec<u8>> {
+        self.totp_secret.as_deref().and_then(totp::base32_decode)
+    }
+
+    fn into_session(self) -> AdminSession {
+        AdminSession {
+            user_id: self.id,
+            username: self.username,
+            role: self.role,
+            departments: self.departments,
+            api_key_scopes: None,
+        }
+    }
+}
+
+/// How a submitted code was accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SecondFactor {
+    /// Authenticator code of this time step
+    Totp(i64),
+    RecoveryCode,
+    Invalid,
+}
+
+/// Start the second step of a login, returning the challenge token and its expiry
+pub async fn create_challenge(pool: &PgPool, user_id: i32) -> Result<(String, DateTime<Utc>), sqlx::Error> {
+    let token = Uuid::new_v4().to_string();
+    let expires_at = Utc::now() + TwoFactorConfig::global().challenge_ttl;
+
+    sqlx::query(
+        r#"
+        INSERT INTO admin_login_challenges (token_hash, admin_user_id, expires_at)
+        VALUES ($1, $2, $3)
+        "#
+    )
+    .bind(token_hash(&token))
+    .bind(user_id)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok((token, expires_at))
+}
+
+/// Delete login challenges that have expired
+pub async fn purge_login_challenges(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM admin_login_challenges WHERE expires_at < NOW()")
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// User behind a live challenge, locking the challenge row
+async fn challenge_user(conn: &mut PgConnection, token: &str) -> Result<Option<TotpUser>, sqlx::Error> {
+    sqlx::query_as::<_, TotpUser>(
+        r#"
+        SELECT u.id, u.username, u.role, u.departments, u.totp_secret, u.totp_enabled_at, u.totp_last_step
+        FROM admin_login_challenges c
+        JOIN admin_user u ON u.id = c.admin_user_id
+        WHERE c.token_hash = $1 AND c.expires_at > NOW()
+        FOR UPDATE OF c
+        "#
+    )
+    .bind(token_hash(token))
+    .fetch_optional(conn)
+    .await
+}
+
+async fn load_totp_user<'e, E: PgExecutor<'e>>(executor: E, id: i32) -> Result<Option<TotpUser>, sqlx::Error> {
+    sqlx::query_as::<_, TotpUser>(
+        r#"
+        SELECT id, username, role, departments, totp_secret, totp_enabled_at, totp_last_step
+        FROM admin_user WHERE id = $1
+        "#
+    )
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Check a code against the user's authenticator, or once enrolled against their unused
+/// recovery codes. An accepted code is used up.
+async fn check_code(conn: &mut PgConnection, user: &TotpUser, code: &str) -> Result<SecondFactor, sqlx::Error> {
+    let secret = match user.secret() {
+        Some(secret) => secret,
+        None => return Ok(SecondFactor::Invalid),
+    };
+
+    if let Some(step) = totp::verify(&secret, code, Utc::now().timestamp(), user.totp_last_step) {
+        sqlx::query("UPDATE admin_user SET totp_last_step = $2 WHERE id = $1")
+            .bind(user.id)
+            .bind(step)
+            .execute(&mut *conn)
+            .await?;
+        return Ok(SecondFactor::Totp(step));
+    }
+
+    if user.totp_enabled_at.is_none() {
+        return Ok(SecondFactor::Invalid);
+    }
+
+    let used = sqlx::query(
+        r#"
+        UPDATE admin_recovery_codes SET used_at = NOW()
+        WHERE id = (
+            SELECT id FROM admin_recovery_codes
+            WHERE admin_user_id = $1 AND code_hash = $2 AND used_at IS NULL
+            LIMIT 1
+        )
+        "#
+    )
+    .bind(user.id)
+    .bind(token_hash(&totp::normalize_recovery_code(code)))
+    .execute(&mut *conn)
+    .await?;
+
+    if used.rows_affected() > 0 {
+        audit::record(&mut *conn, &user.username, "totp_recovery_code_used", Some(&user.username), None).await?;
+        Ok(SecondFactor::RecoveryCode)
+    } else {
+        Ok(SecondFactor::Invalid)
+    }
+}
+
+/// Replace the user's recovery codes with new ones, returned in plaintext once
+async fn replace_recovery_codes(conn: &mut PgConnection, user_id: i32) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query("DELETE FROM admin_recovery_codes WHERE admin_user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    let codes = totp::generate_recovery_codes();
+    let hashes: Vec<String> = codes.iter().map(|code| token_hash(&totp::normalize_recovery_code(code))).collect();
+
+    sqlx::query(
+        r#"
+        INSERT INTO admin_recovery_codes (admin_user_id, code_hash)
+        SELECT $1, UNNEST($2::varchar[])
+        "#
+    )
+    .bind(user_id)
+    .bind(&hashes)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(codes)
+}
+
+/// Confirm a pending enrolment after its first valid code, returning the recovery codes
+async fn enable_totp(conn: &mut PgConnection, user: &TotpUser) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query("UPDATE admin_user SET totp_enabled_at = NOW() WHERE id = $1")
+        .bind(user.id)
+        .execute(&mut *conn)
+        .await?;
+
+    let codes = replace_recovery_codes(conn, user.id).await?;
+    audit::record(&mut *conn, &user.username, "totp_enroll", Some(&user.username), None).await?;
+    Ok(codes)
+}
+
+/// Store a fresh secret pending confirmation, replacing any earlier pending one
+async fn start_enrollment<'e, E: PgExecutor<'e>>(executor: E, user: &TotpUser) -> Result<TotpEnrollmentResponse, sqlx::Error> {
+    let secret = totp::generate_secret();
+    let encoded = totp::base32_encode(&secret);
+
+    sqlx::query("UPDATE admin_user SET totp_secret = $2, totp_last_step = NULL WHERE id = $1 AND totp_enabled_at IS NULL")
+        .bind(user.id)
+        .bind(&encoded)
+        .execute(executor)
+        .await?;
+
+    Ok(TotpEnrollmentResponse {
+        otpauth_uri: totp::provisioning_uri(&TwoFactorConfig::global().issuer, &user.username, &secret),
+        secret: encoded,
+    })
+}
+
+fn invalid_challenge() -> HttpResponse {
+    HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or expired challenge"))
+}
+
+fn already_enabled() -> HttpResponse {
+    HttpResponse::Conflict().json(ApiResponse::<()>::error("Two-factor authentication is already enabled"))
+}
+
+/// Enrol an authenticator during a login whose role requires TOTP
+pub async fn enroll_during_login(
+    pool: web::Data<PgPool>,
+    req: web::Json<LoginChallengeRequest>,
+) -> HttpResponse {
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    };
+
+    let user = match challenge_user(&mut tx, &req.challenge_token).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return invalid_challenge(),
+        Err(e) => {
+            log::error!("Failed to look up login challenge: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    };
+
+    if user.totp_enabled_at.is_some() {
+        return already_enabled();
+    }
+
+    let enrollment = match start_enrollment(&mut *tx, &user).await {
+        Ok(enrollment) => enrollment,
+        Err(e) => {
+            log::error!("Failed to start TOTP enrolment of {}: {:?}", user.username, e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to start enrolment"));
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success(enrollment, "Scan the code, then verify the login with it")),
+        Err(e) => {
+            log::error!("Failed to commit TOTP enrolment of {}: {:?}", user.username, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to start enrolment"))
+        }
+    }
+}
+
+/// Second step of a login: trade a challenge and a code for a session
+pub async fn verify_login(
+    pool: web::Data<PgPool>,
+    http_req: HttpRequest,
+    req: web::Json<VerifyLoginRequest>,
+) -> HttpResponse {
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    };
+
+    let user = match challenge_user(&mut tx, &req.challenge_token).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return invalid_challenge(),
+        Err(e) => {
+            log::error!("Failed to look up login challenge: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    };
+
+    let ip = lockout::client_ip(&http_req);
+    let subjects = [(LockScope::Ip, ip.as_str()), (LockScope::AdminUser, user.username.as_str())];
+
+    match lockout::locked_until(&pool, &subjects).await {
+        Ok(Some(until)) => return lockout::too_many_attempts(until),
+        Ok(None) => {}
+        Err(e) => {
+            log::error!("Failed to check login lockout: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    }
+
+    if user.totp_secret.is_none() {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error(
+            "Two-factor enrolment required; enrol an authenticator first"
+        ));
+    }
+
+    let factor = match check_code(&mut tx, &user, &req.code).await {
+        Ok(factor) => factor,
+        Err(e) => {
+            log::error!("Failed to check second factor of {}: {:?}", user.username, e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    };
+
+    if factor == SecondFactor::Invalid {
+        // Count the attempt on the challenge, discarding it after too many
+        let result = sqlx::query(
+            r#"
+            WITH counted AS (
+                UPDATE admin_login_challenges SET attempts = attempts + 1
+                WHERE token_hash = $1
+                RETURNING token_hash, attempts
+            )
+            DELETE FROM admin_login_challenges c
+            USING counted
+            WHERE c.token_hash = counted.token_hash AND counted.attempts >= $2
+            "#
+        )
+        .bind(token_hash(&req.challenge_token))
+        .bind(MAX_CHALLENGE_ATTEMPTS)
+        .execute(&mut *tx)
+        .await;
+
+        if let Err(e) = result {
+            log::error!("Failed to count login challenge attempt: {:?}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+        if let Err(e) = tx.commit().await {
+            log::error!("Failed to commit login challenge attempt: {:?}", e);
+        }
+        if let Err(e) = lockout::record_failure(&pool, &subjects).await {
+            log::error!("Failed to record failed login: {:?}", e);
+        }
+        return HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid code"));
+    }
+
+    // The first code from a new authenticator confirms the enrolment
+    let recovery_codes = if user.totp_enabled_at.is_none() {
+        match enable_totp(&mut tx, &user).await {
+            Ok(codes) => Some(codes),
+            Err(e) => {
+                log::error!("Failed to enable TOTP of {}: {:?}", user.username, e);
+                let _ = tx.rollback().await;
+                return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+            }
+        }
+    } else {
+        None
+    };
+
+    if let Err(e) = sqlx::query("DELETE FROM admin_login_challenges WHERE token_hash = $1")
+        .bind(token_hash(&req.challenge_token))
+        .execute(&mut *tx)
+        .await
+    {
+        log::error!("Failed to consume login challenge: {:?}", e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+    }
+
+    if let Err(e) = tx.commit().await {
+        log::error!("Failed to commit second factor of {}: {:?}", user.username, e);
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+    }
+
+    complete_login(&pool, &http_req, user.into_session(), recovery_codes).await
+}
+
+/// Start enrolling an authenticator for the signed-in admin user
+pub async fn enroll(pool: web::Data<PgPool>, req: HttpRequest) -> HttpResponse {
+    let session = match require_user_session(&req).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let user = match load_totp_user(pool.as_ref(), session.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Admin user not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    };
+
+    if user.totp_enabled_at.is_some() {
+        return already_enabled();
+    }
+
+    match start_enrollment(pool.as_ref(), &user).await {
+        Ok(enrollment) => HttpResponse::Ok().json(ApiResponse::success(enrollment, "Scan the code, then confirm it")),
+        Err(e) => {
+            log::error!("Failed to start TOTP enrolment of {}: {:?}", user.username, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to start enrolment"))
+        }
+    }
+}
+
+fn database_error() -> HttpResponse {
+    HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"))
+}
+
+/// Open a transaction for the signed-in user once `code` checks out. `enrolled` says
+/// whether TOTP must already be enabled or still be pending confirmation.
+async fn begin_with_code<'a>(
+    pool: &'a PgPool,
+    session: &AdminSession,
+    code: &str,
+    enrolled: bool,
+) -> Result<(Transaction<'a, Postgres>, TotpUser), HttpResponse> {
+    let mut tx = pool.begin().await.map_err(|_| database_error())?;
+
+    let user = match load_totp_user(&mut *tx, session.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(HttpResponse::NotFound().json(ApiResponse::<()>::error("Admin user not found"))),
+        Err(_) => return Err(database_error()),
+    };
+
+    match (enrolled, user.totp_enabled_at.is_some()) {
+        (true, false) => {
+            return Err(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Two-factor authentication is not enabled")));
+        }
+        (false, true) => return Err(already_enabled()),
+        (false, false) if user.totp_secret.is_none() => {
+            return Err(HttpResponse::BadRequest().json(ApiResponse::<()>::error("No enrolment pending")));
+        }
+        _ => {}
+    }
+
+    match check_code(&mut tx, &user, code).await {
+        Ok(SecondFactor::Invalid) => {
+            let _ = tx.rollback().await;
+            Err(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Invalid code")))
+        }
+        Ok(_) => Ok((tx, user)),
+        Err(e) => {
+            log::error!("Failed to check second factor of {}: {:?}", user.username, e);
+            let _ = tx.rollback().await;
+            Err(database_error())
+        }
+    }
+}
+
+/// Confirm an enrolment with the first code from the authenticator
+pub async fn confirm(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    body: web::Json<TotpCodeRequest>,
+) -> HttpResponse {
+    let session = match require_user_session(&req).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let (mut tx, user) = match begin_with_code(&pool, &session, &body.code, false).await {
+        Ok(verified) => verified,
+        Err(response) => return response,
+    };
+
+    let recovery_codes = match enable_totp(&mut tx, &user).await {
+        Ok(codes) => codes,
+        Err(e) => {
+            log::error!("Failed to enable TOTP of {}: {:?}", user.username, e);
+            let _ = tx.rollback().await;
+            return database_error();
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success(
+            RecoveryCodesResponse { recovery_codes },
+            "Two-factor authentication enabled"
+        )),
+        Err(_) => database_error(),
+    }
+}
+
+/// Replace the recovery codes of the signed-in user
+pub async fn regenerate_recovery_codes(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    body: web::Json<TotpCodeRequest>,
+) -> HttpResponse {
+    let session = match require_user_session(&req).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let (mut tx, user) = match begin_with_code(&pool, &session, &body.code, true).await {
+        Ok(verified) => verified,
+        Err(response) => return response,
+    };
+
+    let result = async {
+        let codes = replace_recovery_codes(&mut tx, user.id).await?;
+        audit::record(&mut *tx, &user.username, "totp_recovery_codes_regenerate", Some(&user.username), None).await?;
+        Ok::<_, sqlx::Error>(codes)
+    }
+    .await;
+
+    let recovery_codes = match result {
+        Ok(codes) => codes,
+        Err(e) => {
+            log::error!("Failed to replace recovery codes of {}: {:?}", user.username, e);
+            let _ = tx.rollback().await;
+            return database_error();
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::success(
+            RecoveryCodesResponse { recovery_codes },
+            "Recovery codes replaced"
+        )),
+        Err(_) => database_error(),
+    }
+}
+
+/// Turn two-factor login off for the signed-in user, unless their role requires it
+pub async fn disable(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    body: web::Json<TotpCodeRequest>,
+) -> HttpResponse {
+    let session = match require_user_session(&req).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    if TwoFactorConfig::global().role_requires_totp(&session.role) {
+        return HttpResponse::Forbidden().json(ApiResponse::<()>::error(
+            "Two-factor authentication is required for your role"
+        ));
+    }
+
+    let (mut tx, user) = match begin_with_code(&pool, &session, &body.code, true).await {
+        Ok(verified) => verified,
+        Err(response) => return response,
+    };
+
+    let result = async {
+        clear_totp(&mut tx, user.id).await?;
+        audit::record(&mut *tx, &user.username, "totp_disable", Some(&user.username), None).await
+    }
+    .await;
+
+    if let Err(e) = result {
+        log::error!("Failed to disable TOTP of {}: {:?}", user.username, e);
+        let _ = tx.rollback().await;
+        return database_error();
+    }
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::<()>::success((), "Two-factor authentication disabled")),
+        Err(_) => database_error(),
+    }
+}
+
+/// Remove the authenticator and recovery codes of an admin user
+async fn clear_totp(conn: &mut PgConnection, user_id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE admin_user SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL WHERE id = $1"
+    )
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("DELETE FROM admin_recovery_codes WHERE admin_user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Reset another admin user's two-factor login, e.g. after a lost phone, and sign them out.
+/// If their role requires TOTP they enrol again on the next login.
+pub async fn reset_admin_totp(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::AdminUsersManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    let id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to reset two-factor authentication"));
+        }
+    };
+
+    let username = match sqlx::query_scalar::<_, String>("SELECT username FROM admin_user WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(username)) => username,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Admin user not found")),
+        Err(e) => {
+            log::error!("Failed to look up admin user: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to reset two-factor authentication"));
+        }
+    };
+
+    // An admin resetting their own second factor keeps the session they are using
+    let keep_token = if id == session.user_id { bearer_token(&req) } else { None };
+
+    let result = async {
+        clear_totp(&mut tx, id).await?;
+        revoke_user_sessions(&mut *tx, id, keep_token).await?;
+        audit::record(&mut *tx, &session.username, "totp_reset", Some(&username), None).await
+    }
+    .await;
+
+    if let Err(e) = result {
+        log::error!("Failed to reset two-factor authentication of {}: {:?}", username, e);
+        let _ = tx.rollback().await;
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to reset two-factor authentication"));
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            log::info!("Admin {} reset two-factor authentication of {}", session.username, username);
+            HttpResponse::Ok().json(ApiResponse::<()>::success((), "Two-factor authentication reset"))
+        }
+        Err(e) => {
+            log::error!("Failed to commit two-factor reset of {}: {:?}", username, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to reset two-factor authentication"))
+        }
+    }
+}
@@ -1,24 +1,78 @@
 use actix_web::{web, HttpResponse, HttpRequest};
-use sqlx::PgPool;
+use chrono::Utc;
+use sqlx::{PgConnection, PgPool};
 
-use crate::admin::auth::require_admin_auth;
-use crate::admin::models::{CreateUserRequest, UpdateUserRequest};
+use crate::admin::auth::require_capability;
+use crate::admin::models::{CreateUserRequest, RotatePasskeyRequest, RotatePasskeyResponse, UpdateUserRequest};
+use crate::audit::{self, Change};
+use crate::auth;
 use crate::models::{ApiResponse, UserInfo};
+use crate::passkey::{generate_passkey, hash_passkey};
+use crate::permissions::Capability;
+
+/// Whether another user already logs in with this passkey hash
+async fn passkey_in_use(conn: &mut PgConnection, passkey_hash: &str, except_id: Option<i32>) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM user_info WHERE passkey_hash = $1 AND ($2::int IS NULL OR id <> $2))"
+    )
+    .bind(passkey_hash)
+    .bind(except_id)
+    .fetch_one(conn)
+    .await
+}
+
+/// Response for a unique violation on user_info: either the user_id or the passkey is taken
+fn unique_violation_response(db_err: &dyn sqlx::error::DatabaseError) -> HttpResponse {
+    let message = match db_err.constraint() {
+        Some("idx_user_info_passkey_hash_unique") => "Passkey already in use",
+        _ => "User ID already exists",
+    };
+    HttpResponse::BadRequest().json(ApiResponse::<()>::error(message))
+}
+
+/// Store a new passkey hash and end every device login made with the old passkey
+async fn replace_passkey(
+    conn: &mut PgConnection,
+    id: i32,
+    passkey_hash: &str,
+    expires_at: Option<chrono::DateTime<Utc>>,
+) -> Result<Option<UserInfo>, sqlx::Error> {
+    let user = sqlx::query_as::<_, UserInfo>(
+        r#"
+        UPDATE user_info
+        SET passkey_hash = $1, passkey = NULL, passkey_version = passkey_version + 1,
+            passkey_rotated_at = NOW(), passkey_expires_at = $2
+        WHERE id = $3
+        RETURNING *
+        "#
+    )
+    .bind(passkey_hash)
+    .bind(expires_at)
+    .bind(id)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    // Access tokens carry the passkey version and stop working on their own
+    if let Some(user) = &user {
+        auth::delete_refresh_tokens(&mut *conn, &user.user_id).await?;
+    }
+
+    Ok(user)
+}
 
 pub async fn get_users(
     pool: web::Data<PgPool>,
     req: HttpRequest,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::UsersRead).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
-    }
-
-    match sqlx::query_as::<_, UserInfo>("SELECT * FROM user_info ORDER BY id")
+    match sqlx::query_as::<_, UserInfo>(
+        "SELECT * FROM user_info WHERE $1::int[] IS NULL OR department = ANY($1) ORDER BY id"
+    )
+        .bind(session.scope().departments())
         .fetch_all(pool.as_ref())
         .await
     {
@@ -32,15 +86,11 @@ pub async fn create_user(
     req: HttpRequest,
     user_req: web::Json<CreateUserRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::UsersManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
-    }
-
     let mut transaction = match pool.begin().await {
         Ok(tx) => tx,
         Err(e) => {
@@ -49,10 +99,24 @@ pub async fn create_user(
         }
     };
 
+    let passkey_hash = hash_passkey(&user_req.passkey);
+    match passkey_in_use(&mut transaction, &passkey_hash, None).await {
+        Ok(false) => {}
+        Ok(true) => {
+            let _ = transaction.rollback().await;
+            return HttpResponse::BadRequest().json(ApiResponse::<()>::error("Passkey already in use"));
+        }
+        Err(e) => {
+            log::error!("Failed to check passkey: {:?}", e);
+            let _ = transaction.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    }
+
     // Create user
     let user_result = sqlx::query_as::<_, UserInfo>(
         r#"
-        INSERT INTO user_info (user_id, user_name, department, department_name, passkey)
+        INSERT INTO user_info (user_id, user_name, department, department_name, passkey_hash)
         VALUES ($1, $2, $3, $4, $5)
         RETURNING *
         "#
@@ -61,42 +125,25 @@ pub async fn create_user(
     .bind(&user_req.user_name)
     .bind(user_req.department)
     .bind(&user_req.department_name)
-    .bind(&user_req.passkey)
+    .bind(&passkey_hash)
     .fetch_one(&mut *transaction)
     .await;
 
     match user_result {
         Ok(user) => {
-            // Create default time settings for the new user
-            let time_settings_result = sqlx::query(
-                "INSERT INTO user_time_settings (user_id, on_duty_time, off_duty_time) VALUES ($1, '07:30:00', '17:00:00')"
-            )
-            .bind(&user_req.user_id)
-            .execute(&mut *transaction)
-            .await;
-
-            match time_settings_result {
-                Ok(_) => {
-                    if let Err(e) = transaction.commit().await {
-                        log::error!("Failed to commit transaction: {:?}", e);
-                        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to save user"));
-                    }
-                    HttpResponse::Created().json(ApiResponse::success(user, "User created successfully"))
-                }
-                Err(e) => {
-                    log::error!("Failed to create time settings for user: {:?}", e);
-                    if let Err(rollback_err) = transaction.rollback().await {
-                        log::error!("Failed to rollback transaction: {:?}", rollback_err);
-                    }
-                    HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create user time settings"))
-                }
+            // New users follow their department's default hours until given their own
+            let change = Change::new("user_create", "user", user.id).after(&user);
+            if let Err(e) = audit::commit_change(transaction, &session.actor(&req), &change).await {
+                log::error!("Failed to commit transaction: {:?}", e);
+                return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to save user"));
             }
+            HttpResponse::Created().json(ApiResponse::success(user, "User created successfully"))
         }
         Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
             if let Err(rollback_err) = transaction.rollback().await {
                 log::error!("Failed to rollback transaction: {:?}", rollback_err);
             }
-            HttpResponse::BadRequest().json(ApiResponse::<()>::error("User ID already exists"))
+            unique_violation_response(db_err.as_ref())
         }
         Err(e) => {
             log::error!("Failed to create user: {:?}", e);
@@ -114,22 +161,84 @@ pub async fn update_user(
     path: web::Path<i32>,
     user_req: web::Json<UpdateUserRequest>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::UsersManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
+    let id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    };
+
+    match update_user_in(&mut tx, id, &user_req).await {
+        Ok(Ok((previous, user))) => match audit::commit_change(
+            tx,
+            &session.actor(&req),
+            &Change::new("user_update", "user", id).before(&previous).after(&user),
+        ).await {
+            Ok(_) => HttpResponse::Ok().json(ApiResponse::success(user, "User updated")),
+            Err(e) => {
+                log::error!("Failed to commit user update: {:?}", e);
+                HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update user"))
+            }
+        },
+        Ok(Err(response)) => {
+            let _ = tx.rollback().await;
+            response
+        }
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            let _ = tx.rollback().await;
+            unique_violation_response(db_err.as_ref())
+        }
+        Err(e) => {
+            log::error!("Failed to update user: {:?}", e);
+            let _ = tx.rollback().await;
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update user"))
+        }
     }
+}
 
-    let id = path.into_inner();
-    
-    match sqlx::query_as::<_, UserInfo>(
+/// Apply a user update, changing the passkey only when a new one is given.
+/// Returns the user before and after the update.
+async fn update_user_in(
+    conn: &mut PgConnection,
+    id: i32,
+    user_req: &UpdateUserRequest,
+) -> Result<Result<(UserInfo, UserInfo), HttpResponse>, sqlx::Error> {
+    let previous = sqlx::query_as::<_, UserInfo>("SELECT * FROM user_info WHERE id = $1 FOR UPDATE")
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return Ok(Err(HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found")))),
+    };
+
+    // Refresh tokens reference the user_id, and a renamed user should sign in again anyway
+    if previous.user_id != user_req.user_id {
+        auth::delete_refresh_tokens(&mut *conn, &previous.user_id).await?;
+    }
+
+    if let Some(passkey) = &user_req.passkey {
+        let passkey_hash = hash_passkey(passkey);
+        if passkey_in_use(&mut *conn, &passkey_hash, Some(id)).await? {
+            return Ok(Err(HttpResponse::BadRequest().json(ApiResponse::<()>::error("Passkey already in use"))));
+        }
+        replace_passkey(&mut *conn, id, &passkey_hash, None).await?;
+    }
+
+    let user = sqlx::query_as::<_, UserInfo>(
         r#"
         UPDATE user_info 
-        SET user_id = $1, user_name = $2, department = $3, department_name = $4, passkey = $5
-        WHERE id = $6
+        SET user_id = $1, user_name = $2, department = $3, department_name = $4
+        WHERE id = $5
         RETURNING *
         "#
     )
@@ -137,17 +246,64 @@ pub async fn update_user(
     .bind(&user_req.user_name)
     .bind(user_req.department)
     .bind(&user_req.department_name)
-    .bind(&user_req.passkey)
     .bind(id)
-    .fetch_optional(pool.as_ref())
-    .await
-    {
-        Ok(Some(user)) => HttpResponse::Ok().json(ApiResponse::success(user, "User updated")),
-        Ok(None) => HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found")),
-        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
-            HttpResponse::BadRequest().json(ApiResponse::<()>::error("User ID already exists"))
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(Ok((previous, user)))
+}
+
+/// Replace a user's passkey with a generated one, returned once in the response
+pub async fn rotate_passkey(
+    pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    rotate_req: web::Json<RotatePasskeyRequest>,
+) -> HttpResponse {
+    let session = match require_capability(&req, Capability::UsersManage).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    if rotate_req.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error("expires_at must be in the future"));
+    }
+
+    let id = path.into_inner();
+    let passkey = generate_passkey();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("Failed to start transaction: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    };
+
+    match replace_passkey(&mut tx, id, &hash_passkey(&passkey), rotate_req.expires_at).await {
+        Ok(Some(user)) => match audit::commit_change(
+            tx,
+            &session.actor(&req),
+            &Change::new("user_passkey_rotate", "user", id).after(&user),
+        ).await {
+            Ok(_) => {
+                log::info!("Admin {} rotated the passkey of user {}", session.username, user.user_id);
+                HttpResponse::Ok().json(ApiResponse::success(RotatePasskeyResponse { user, passkey }, "Passkey rotated"))
+            }
+            Err(e) => {
+                log::error!("Failed to commit passkey rotation: {:?}", e);
+                HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to rotate passkey"))
+            }
         },
-        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to update user")),
+        Ok(None) => {
+            let _ = tx.rollback().await;
+            HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found"))
+        }
+        Err(e) => {
+            log::error!("Failed to rotate passkey: {:?}", e);
+            let _ = tx.rollback().await;
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to rotate passkey"))
+        }
     }
 }
 
@@ -156,29 +312,31 @@ pub async fn delete_user(
     req: HttpRequest,
     path: web::Path<i32>,
 ) -> HttpResponse {
-    let session = match require_admin_auth(&req) {
+    let session = match require_capability(&req, Capability::UsersManage).await {
         Ok(session) => session,
         Err(response) => return response,
     };
 
-    if session.role != "admin" {
-        return HttpResponse::Forbidden().json(ApiResponse::<()>::error("Admin access required"));
-    }
-
     let id = path.into_inner();
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete user")),
+    };
     
-    match sqlx::query("DELETE FROM user_info WHERE id = $1")
+    let deleted = match sqlx::query_as::<_, UserInfo>("DELETE FROM user_info WHERE id = $1 RETURNING *")
         .bind(id)
-        .execute(pool.as_ref())
+        .fetch_optional(&mut *tx)
         .await
     {
-        Ok(result) => {
-            if result.rows_affected() > 0 {
-                HttpResponse::Ok().json(ApiResponse::<()>::success((), "User deleted"))
-            } else {
-                HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found"))
-            }
-        },
+        Ok(Some(deleted)) => deleted,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("User not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete user")),
+    };
+
+    let change = Change::new("user_delete", "user", id).before(&deleted);
+    match audit::commit_change(tx, &session.actor(&req), &change).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::<()>::success((), "User deleted")),
         Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to delete user")),
     }
 }
\ No newline at end of file
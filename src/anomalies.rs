@@ -0,0 +1,318 @@
+// Punch anomaly detection, recorded for admin review
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgConnection;
+
+use crate::sessions::{PairingResult, Punch, SessionConfig};
+
+/// Punches this far ahead of the server clock are considered future-dated
+pub const FUTURE_TOLERANCE_MINUTES: i64 = 5;
+
+/// IN/OUT pairs shorter than this are considered accidental
+pub const MIN_SESSION_SECONDS: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyKind {
+    /// A second IN while a session was already open
+    DuplicateIn,
+    /// An OUT with no open IN before it
+    OrphanOut,
+    /// A punch dated ahead of the server clock
+    FutureTimestamp,
+    /// An IN and OUT further apart than the max shift
+    OverlongSession,
+    /// An IN and OUT less than a minute apart
+    ShortSession,
+    /// A punch from a device whose clock was off by more than the allowed skew
+    ClockSkew,
+}
+
+impl AnomalyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnomalyKind::DuplicateIn => "duplicate_in",
+            AnomalyKind::OrphanOut => "orphan_out",
+            AnomalyKind::FutureTimestamp => "future_timestamp",
+            AnomalyKind::OverlongSession => "overlong_session",
+            AnomalyKind::ShortSession => "short_session",
+            AnomalyKind::ClockSkew => "clock_skew",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "duplicate_in" => Some(AnomalyKind::DuplicateIn),
+            "orphan_out" => Some(AnomalyKind::OrphanOut),
+            "future_timestamp" => Some(AnomalyKind::FutureTimestamp),
+            "overlong_session" => Some(AnomalyKind::OverlongSession),
+            "short_session" => Some(AnomalyKind::ShortSession),
+            "clock_skew" => Some(AnomalyKind::ClockSkew),
+            _ => None,
+        }
+    }
+}
+
+/// An anomaly found in a user's punches. `checkin_id` is the offending punch,
+/// `related_checkin_id` the punch it was judged against, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectedAnomaly {
+    pub kind: AnomalyKind,
+    pub checkin_id: i32,
+    pub related_checkin_id: Option<i32>,
+    pub occurred_at: DateTime<Utc>,
+    pub details: String,
+}
+
+/// Find anomalies in a set of punches and the pairing produced from them
+pub fn detect(punches: &[Punch], pairing: &PairingResult, now: DateTime<Utc>, config: &SessionConfig) -> Vec<DetectedAnomaly> {
+    let mut found = Vec::new();
+
+    // Judged against the arrival time so that later rebuilds find the same anomaly;
+    // `now` only stands in for older rows that did not record it
+    for punch in punches {
+        let received_at = punch.received_at.unwrap_or(now);
+        if punch.at > received_at + Duration::minutes(FUTURE_TOLERANCE_MINUTES) {
+            found.push(DetectedAnomaly {
+                kind: AnomalyKind::FutureTimestamp,
+                checkin_id: punch.id,
+                related_checkin_id: None,
+                occurred_at: punch.at,
+                details: format!(
+                    "Punch was {} minutes ahead of the server clock when received",
+                    (punch.at - received_at).num_minutes()
+                ),
+            });
+        }
+    }
+
+    for punch in punches {
+        if let Some(skew) = punch.flagged_skew_seconds {
+            found.push(DetectedAnomaly {
+                kind: AnomalyKind::ClockSkew,
+                checkin_id: punch.id,
+                related_checkin_id: None,
+                occurred_at: punch.at,
+                details: format!(
+                    "Device clock was {} seconds {} the server",
+                    skew.abs(),
+                    if skew >= 0 { "behind" } else { "ahead of" }
+                ),
+            });
+        }
+    }
+
+    for (ignored, open) in &pairing.ignored {
+        found.push(DetectedAnomaly {
+            kind: AnomalyKind::DuplicateIn,
+            checkin_id: ignored.id,
+            related_checkin_id: Some(open.id),
+            occurred_at: ignored.at,
+            details: format!("IN received while the session opened at {} was still open", open.at),
+        });
+    }
+
+    for (checkin, checkout) in &pairing.overlong {
+        found.push(DetectedAnomaly {
+            kind: AnomalyKind::OverlongSession,
+            checkin_id: checkout.id,
+            related_checkin_id: Some(checkin.id),
+            occurred_at: checkout.at,
+            details: format!(
+                "OUT is {} hours after its IN, beyond the {} hour max shift",
+                (checkout.at - checkin.at).num_hours(),
+                config.max_shift.num_hours()
+            ),
+        });
+    }
+
+    for session in &pairing.sessions {
+        match (&session.checkin, &session.checkout) {
+            (None, Some(checkout)) if !pairing.overlong.iter().any(|(_, out)| out.id == checkout.id) => {
+                found.push(DetectedAnomaly {
+                    kind: AnomalyKind::OrphanOut,
+                    checkin_id: checkout.id,
+                    related_checkin_id: None,
+                    occurred_at: checkout.at,
+                    details: "OUT without a preceding IN".to_string(),
+                });
+            }
+            (Some(checkin), Some(checkout)) if (checkout.at - checkin.at).num_seconds() < MIN_SESSION_SECONDS => {
+                found.push(DetectedAnomaly {
+                    kind: AnomalyKind::ShortSession,
+                    checkin_id: checkout.id,
+                    related_checkin_id: Some(checkin.id),
+                    occurred_at: checkout.at,
+                    details: format!("OUT only {} seconds after its IN", (checkout.at - checkin.at).num_seconds()),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    found.sort_by_key(|a| (a.occurred_at, a.checkin_id));
+    found
+}
+
+/// Replace the open anomalies of a user's punches in [from, to] with a fresh detection.
+/// Anomalies an admin already resolved or dismissed are kept and not raised again.
+pub async fn record_user_anomalies(
+    conn: &mut PgConnection,
+    user_id: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    checkin_ids: &[i32],
+    anomalies: &[DetectedAnomaly],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "DELETE FROM punch_anomalies
+         WHERE status = 'open'
+           AND (checkin_id = ANY($1) OR (user_id = $2 AND occurred_at BETWEEN $3 AND $4))"
+    )
+    .bind(checkin_ids)
+    .bind(user_id)
+    .bind(from)
+    .bind(to)
+    .execute(&mut *conn)
+    .await?;
+
+    let kinds: Vec<&str> = anomalies.iter().map(|a| a.kind.as_str()).collect();
+    let punch_ids: Vec<i32> = anomalies.iter().map(|a| a.checkin_id).collect();
+    let related_ids: Vec<Option<i32>> = anomalies.iter().map(|a| a.related_checkin_id).collect();
+    let occurred: Vec<DateTime<Utc>> = anomalies.iter().map(|a| a.occurred_at).collect();
+    let details: Vec<&str> = anomalies.iter().map(|a| a.details.as_str()).collect();
+
+    sqlx::query(
+        "INSERT INTO punch_anomalies
+         (user_id, anomaly_type, checkin_id, related_checkin_id, occurred_at, details)
+         SELECT $1, t.*
+         FROM UNNEST($2::varchar[], $3::int[], $4::int[], $5::timestamptz[], $6::text[]) AS t
+         ON CONFLICT (anomaly_type, checkin_id) DO NOTHING"
+    )
+    .bind(user_id)
+    .bind(&kinds)
+    .bind(&punch_ids)
+    .bind(&related_ids)
+    .bind(&occurred)
+    .bind(&details)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sessions::{pair_punches, PunchAction, ShiftDateRule, DEFAULT_MAX_SHIFT_HOURS};
+    use chrono::{FixedOffset, TimeZone};
+    use PunchAction::{In, Out};
+
+    fn config() -> SessionConfig {
+        SessionConfig {
+            max_shift: Duration::hours(DEFAULT_MAX_SHIFT_HOURS),
+            date_rule: ShiftDateRule::CheckinDate,
+            utc_offset: FixedOffset::east_opt(0).unwrap(),
+            auto_close_after: Duration::hours(DEFAULT_MAX_SHIFT_HOURS),
+        }
+    }
+
+    fn at(day: u32, hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 12, day, hour, minute, second).unwrap()
+    }
+
+    fn punches(list: &[(PunchAction, DateTime<Utc>)]) -> Vec<Punch> {
+        list.iter()
+            .enumerate()
+            .map(|(i, &(action, at))| Punch {
+                id: i as i32 + 1,
+                action,
+                at,
+                latitude: None,
+                longitude: None,
+                flagged_skew_seconds: None,
+                received_at: None,
+            })
+            .collect()
+    }
+
+    fn kinds(list: &[(PunchAction, DateTime<Utc>)], now: DateTime<Utc>) -> Vec<(AnomalyKind, i32)> {
+        let punches = punches(list);
+        let pairing = pair_punches(&punches, &config());
+        detect(&punches, &pairing, now, &config())
+            .into_iter()
+            .map(|a| (a.kind, a.checkin_id))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_day_has_no_anomalies() {
+        let list = [(In, at(4, 8, 0, 0)), (Out, at(4, 17, 0, 0))];
+        assert!(kinds(&list, at(10, 0, 0, 0)).is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_in_and_orphan_out() {
+        let list = [(In, at(4, 8, 0, 0)), (In, at(4, 8, 5, 0)), (Out, at(4, 17, 0, 0)), (Out, at(4, 18, 0, 0))];
+        assert_eq!(
+            kinds(&list, at(10, 0, 0, 0)),
+            vec![(AnomalyKind::DuplicateIn, 2), (AnomalyKind::OrphanOut, 4)]
+        );
+    }
+
+    #[test]
+    fn test_overlong_is_not_reported_as_orphan() {
+        let list = [(In, at(4, 8, 0, 0)), (Out, at(5, 8, 0, 0))];
+        assert_eq!(kinds(&list, at(10, 0, 0, 0)), vec![(AnomalyKind::OverlongSession, 2)]);
+    }
+
+    #[test]
+    fn test_short_session() {
+        let list = [(In, at(4, 8, 0, 0)), (Out, at(4, 8, 0, 30))];
+        assert_eq!(kinds(&list, at(10, 0, 0, 0)), vec![(AnomalyKind::ShortSession, 2)]);
+
+        let list = [(In, at(4, 8, 0, 0)), (Out, at(4, 8, 1, 0))];
+        assert!(kinds(&list, at(10, 0, 0, 0)).is_empty());
+    }
+
+    #[test]
+    fn test_future_timestamp_allows_tolerance() {
+        let list = [(In, at(4, 8, 3, 0)), (Out, at(4, 17, 0, 0))];
+        assert_eq!(kinds(&list, at(4, 8, 0, 0)), vec![(AnomalyKind::FutureTimestamp, 2)]);
+    }
+
+    #[test]
+    fn test_future_timestamp_survives_later_rebuilds() {
+        // Received at 08:00 dated 09:00; by the time of a rebuild on the 10th it is in the past
+        let mut list = punches(&[(In, at(4, 9, 0, 0)), (Out, at(4, 17, 0, 0))]);
+        list[0].received_at = Some(at(4, 8, 0, 0));
+        list[1].received_at = Some(at(4, 17, 0, 5));
+        let pairing = pair_punches(&list, &config());
+        let found = detect(&list, &pairing, at(10, 0, 0, 0), &config());
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].kind, found[0].checkin_id), (AnomalyKind::FutureTimestamp, 1));
+    }
+
+    #[test]
+    fn test_flagged_clock_skew() {
+        let mut list = punches(&[(In, at(4, 8, 0, 0)), (Out, at(4, 17, 0, 0))]);
+        list[0].flagged_skew_seconds = Some(7200);
+        let pairing = pair_punches(&list, &config());
+        let found = detect(&list, &pairing, at(10, 0, 0, 0), &config());
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].kind, found[0].checkin_id), (AnomalyKind::ClockSkew, 1));
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        for kind in [
+            AnomalyKind::DuplicateIn,
+            AnomalyKind::OrphanOut,
+            AnomalyKind::FutureTimestamp,
+            AnomalyKind::OverlongSession,
+            AnomalyKind::ShortSession,
+            AnomalyKind::ClockSkew,
+        ] {
+            assert_eq!(AnomalyKind::parse(kind.as_str()), Some(kind));
+        }
+        assert_eq!(AnomalyKind::parse("late"), None);
+    }
+}
@@ -0,0 +1,185 @@
+// API keys of service accounts, for integrations that read attendance data without an admin login
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgExecutor, PgPool};
+
+use crate::password::token_hash;
+use crate::permissions::Capability;
+
+/// Header carrying an API key, kept apart from the `Authorization` header of admin sessions
+pub const API_KEY_HEADER: &str = "X-API-Key";
+/// Prefix of every key, so leaked keys are easy to recognise in logs and code
+pub const KEY_PREFIX: &str = "kbk_";
+/// Random bytes in a key
+const KEY_BYTES: usize = 32;
+/// Characters of a key kept in plain text so admins can tell keys apart
+const DISPLAY_PREFIX_LENGTH: usize = 12;
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct ApiKey {
+    pub id: i32,
+    /// Service account the key belongs to, e.g. "hr-sync"
+    pub name: String,
+    pub key_prefix: String,
+    pub scopes: Vec<String>,
+    pub departments: Vec<i32>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    /// Capabilities granted by the key's scopes; scopes this server does not know grant nothing
+    pub fn capabilities(&self) -> Vec<Capability> {
+        self.scopes
+            .iter()
+            .filter_map(|scope| Capability::parse(scope))
+            .filter(Capability::grantable_to_api_keys)
+            .collect()
+    }
+}
+
+/// Random key, shown to the admin once
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; KEY_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", KEY_PREFIX, hex::encode(bytes))
+}
+
+/// Start of a key that is stored in plain text
+pub fn display_prefix(key: &str) -> String {
+    key.chars().take(DISPLAY_PREFIX_LENGTH).collect()
+}
+
+/// Check requested scopes, returning them deduplicated or the first one a key cannot have
+pub fn parse_scopes(scopes: &[String]) -> Result<Vec<String>, String> {
+    let mut parsed: Vec<String> = Vec::with_capacity(scopes.len());
+    for scope in scopes {
+        match Capability::parse(scope) {
+            Some(capability) if capability.grantable_to_api_keys() => {
+                if !parsed.contains(scope) {
+                    parsed.push(scope.clone());
+                }
+            }
+            _ => return Err(scope.clone()),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Look up a live key and note that it was used
+pub async fn verify(pool: &PgPool, key: &str) -> Result<Option<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(
+        r#"
+        UPDATE admin_api_keys
+        SET last_used_at = NOW()
+        WHERE key_hash = $1
+          AND revoked_at IS NULL
+          AND (expires_at IS NULL OR expires_at > NOW())
+        RETURNING id, name, key_prefix, scopes, departments, created_by, created_at, expires_at, last_used_at, revoked_at
+        "#
+    )
+    .bind(token_hash(key))
+    .fetch_optional(pool)
+    .await
+}
+
+/// Store a new key, returning it without its hash
+#[allow(clippy::too_many_arguments)]
+pub async fn create<'e, E: PgExecutor<'e>>(
+    executor: E,
+    name: &str,
+    key: &str,
+    scopes: &[String],
+    departments: &[i32],
+    expires_at: Option<DateTime<Utc>>,
+    created_by: &str,
+) -> Result<ApiKey, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(
+        r#"
+        INSERT INTO admin_api_keys (name, key_prefix, key_hash, scopes, departments, expires_at, created_by)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, name, key_prefix, scopes, departments, created_by, created_at, expires_at, last_used_at, revoked_at
+        "#
+    )
+    .bind(name)
+    .bind(display_prefix(key))
+    .bind(token_hash(key))
+    .bind(scopes)
+    .bind(departments)
+    .bind(expires_at)
+    .bind(created_by)
+    .fetch_one(executor)
+    .await
+}
+
+/// Every key, newest first, including revoked and expired ones
+pub async fn list(pool: &PgPool) -> Result<Vec<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(
+        r#"
+        SELECT id, name, key_prefix, scopes, departments, created_by, created_at, expires_at, last_used_at, revoked_at
+        FROM admin_api_keys
+        ORDER BY created_at DESC, id DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Revoke a key, returning it if it exists and was still active
+pub async fn revoke<'e, E: PgExecutor<'e>>(executor: E, id: i32) -> Result<Option<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(
+        r#"
+        UPDATE admin_api_keys
+        SET revoked_at = NOW()
+        WHERE id = $1 AND revoked_at IS NULL
+        RETURNING id, name, key_prefix, scopes, departments, created_by, created_at, expires_at, last_used_at, revoked_at
+        "#
+    )
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_keys() {
+        let key = generate_key();
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_eq!(key.len(), KEY_PREFIX.len() + KEY_BYTES * 2);
+        assert_ne!(key, generate_key());
+        assert_eq!(display_prefix(&key), key[..DISPLAY_PREFIX_LENGTH]);
+    }
+
+    #[test]
+    fn test_parse_scopes() {
+        let scopes = vec!["stats.read".to_string(), "stats.export".to_string(), "stats.read".to_string()];
+        assert_eq!(parse_scopes(&scopes), Ok(vec!["stats.read".to_string(), "stats.export".to_string()]));
+
+        assert_eq!(parse_scopes(&["users.manage".to_string()]), Err("users.manage".to_string()));
+        assert_eq!(parse_scopes(&["everything".to_string()]), Err("everything".to_string()));
+    }
+
+    #[test]
+    fn test_capabilities_skip_unknown_scopes() {
+        let key = ApiKey {
+            id: 1,
+            name: "hr-sync".to_string(),
+            key_prefix: "kbk_00000000".to_string(),
+            scopes: vec!["stats.export".to_string(), "retired.scope".to_string(), "checkins.write".to_string()],
+            departments: Vec::new(),
+            created_by: "admin".to_string(),
+            created_at: Utc::now(),
+            expires_at: None,
+            last_used_at: None,
+            revoked_at: None,
+        };
+        assert_eq!(key.capabilities(), vec![Capability::StatsExport]);
+    }
+}
@@ -0,0 +1,240 @@
+// Audit trail of security-relevant actions and admin changes to data
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{FromRow, PgExecutor, PgPool, Postgres, Transaction};
+
+use crate::lockout;
+
+/// Actor recorded for actions the server takes on its own, e.g. a lockout
+pub const SYSTEM_ACTOR: &str = "system";
+
+/// Default entries per page of the audit log
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+/// Largest page of the audit log that can be requested
+pub const MAX_PAGE_SIZE: i64 = 500;
+
+/// Who made a change and from where
+#[derive(Debug, Clone)]
+pub struct Actor {
+    pub name: String,
+    pub ip_address: String,
+}
+
+impl Actor {
+    pub fn from_request(name: &str, req: &HttpRequest) -> Self {
+        Actor {
+            name: name.to_string(),
+            ip_address: lockout::client_ip(req),
+        }
+    }
+}
+
+/// A record created, updated or deleted by an admin. Records are stored as the API
+/// serializes them, so fields it hides such as password hashes stay out of the log.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub action: &'static str,
+    pub entity: &'static str,
+    pub entity_id: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+impl Change {
+    pub fn new(action: &'static str, entity: &'static str, entity_id: impl ToString) -> Self {
+        Change {
+            action,
+            entity,
+            entity_id: entity_id.to_string(),
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn before<T: Serialize>(mut self, record: &T) -> Self {
+        self.before = serde_json::to_value(record).ok();
+        self
+    }
+
+    pub fn after<T: Serialize>(mut self, record: &T) -> Self {
+        self.after = serde_json::to_value(record).ok();
+        self
+    }
+}
+
+/// Append an entry to the audit log
+pub async fn record<'e, E: PgExecutor<'e>>(
+    executor: E,
+    actor: &str,
+    action: &str,
+    target: Option<&str>,
+    details: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO audit_log (actor, action, target, details)
+        VALUES ($1, $2, $3, $4)
+        "#
+    )
+    .bind(actor)
+    .bind(action)
+    .bind(target)
+    .bind(details)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Append a data change to the audit log. Call it in the transaction making the change
+/// so the change and its entry are committed together.
+pub async fn record_change<'e, E: PgExecutor<'e>>(
+    executor: E,
+    actor: &Actor,
+    change: &Change,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO audit_log (actor, action, entity, target, before_data, after_data, ip_address)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#
+    )
+    .bind(&actor.name)
+    .bind(change.action)
+    .bind(change.entity)
+    .bind(&change.entity_id)
+    .bind(&change.before)
+    .bind(&change.after)
+    .bind(&actor.ip_address)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Record a change in the transaction that made it, then commit both
+pub async fn commit_change(mut tx: Transaction<'_, Postgres>, actor: &Actor, change: &Change) -> Result<(), sqlx::Error> {
+    record_change(&mut *tx, actor, change).await?;
+    tx.commit().await
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub actor: String,
+    pub action: String,
+    pub entity: Option<String>,
+    pub target: Option<String>,
+    pub details: Option<String>,
+    pub before_data: Option<Value>,
+    pub after_data: Option<Value>,
+    pub ip_address: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub entity: Option<String>,
+    pub target: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// 1-based
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+impl AuditQuery {
+    /// Page number and size, clamped to what can be served
+    pub fn page(&self) -> (i64, i64) {
+        let page = self.page.unwrap_or(1).max(1);
+        let per_page = self.per_page.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        (page, per_page)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+}
+
+/// Entries matching the query, newest first
+pub async fn search(pool: &PgPool, query: &AuditQuery) -> Result<AuditPage, sqlx::Error> {
+    let (page, per_page) = query.page();
+    let filter = r#"
+        WHERE ($1::text IS NULL OR actor = $1)
+          AND ($2::text IS NULL OR action = $2)
+          AND ($3::text IS NULL OR entity = $3)
+          AND ($4::text IS NULL OR target = $4)
+          AND ($5::timestamptz IS NULL OR occurred_at >= $5)
+          AND ($6::timestamptz IS NULL OR occurred_at < $6)
+    "#;
+
+    let total = sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM audit_log {}", filter))
+        .bind(&query.actor)
+        .bind(&query.action)
+        .bind(&query.entity)
+        .bind(&query.target)
+        .bind(query.from)
+        .bind(query.to)
+        .fetch_one(pool)
+        .await?;
+
+    let entries = sqlx::query_as::<_, AuditEntry>(&format!(
+        "SELECT * FROM audit_log {} ORDER BY occurred_at DESC, id DESC LIMIT $7 OFFSET $8",
+        filter
+    ))
+    .bind(&query.actor)
+    .bind(&query.action)
+    .bind(&query.entity)
+    .bind(&query.target)
+    .bind(query.from)
+    .bind(query.to)
+    .bind(per_page)
+    .bind((page - 1) * per_page)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(AuditPage { entries, page, per_page, total })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Account {
+        name: &'static str,
+        #[serde(skip_serializing)]
+        #[allow(dead_code)]
+        secret: &'static str,
+    }
+
+    #[test]
+    fn test_change_serializes_records() {
+        let before = Account { name: "old", secret: "hash" };
+        let after = Account { name: "new", secret: "hash" };
+        let change = Change::new("update", "account", 7).before(&before).after(&after);
+
+        assert_eq!(change.entity_id, "7");
+        assert_eq!(change.before, Some(serde_json::json!({ "name": "old" })));
+        assert_eq!(change.after, Some(serde_json::json!({ "name": "new" })));
+    }
+
+    #[test]
+    fn test_query_page_is_clamped() {
+        assert_eq!(AuditQuery::default().page(), (1, DEFAULT_PAGE_SIZE));
+
+        let query = AuditQuery { page: Some(0), per_page: Some(10_000), ..Default::default() };
+        assert_eq!(query.page(), (1, MAX_PAGE_SIZE));
+
+        let query = AuditQuery { page: Some(3), per_page: Some(0), ..Default::default() };
+        assert_eq!(query.page(), (3, 1));
+    }
+}
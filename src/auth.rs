@@ -1,29 +1,432 @@
-use crate::models::UserInfo;
-use sqlx::PgPool;
+// Employee authentication: passkey login issuing signed access tokens and refresh tokens
+use actix_web::dev::Payload;
+use actix_web::error::InternalError;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{PgExecutor, PgPool};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use uuid::Uuid;
 
+use crate::models::{ApiResponse, IssuedTokens, UserInfo};
+use crate::passkey::hash_passkey;
+use crate::password::token_hash;
+
+/// Default lifetime of an access token
+pub const DEFAULT_ACCESS_TOKEN_MINUTES: i64 = 60;
+/// Default lifetime of a refresh token
+pub const DEFAULT_REFRESH_TOKEN_DAYS: i64 = 30;
+/// Shortest signing secret accepted from the environment
+const MIN_SECRET_BYTES: usize = 32;
+
+/// Header of every access token; only HS256 is issued or accepted
+const TOKEN_HEADER: &str = r#"{"alg":"HS256","typ":"JWT"}"#;
+
+lazy_static! {
+    static ref TOKEN_CONFIG: TokenConfig = TokenConfig::from_env();
+}
+
+/// Signing key and lifetimes of employee tokens
+#[derive(Clone)]
+pub struct TokenConfig {
+    pub secret: Vec<u8>,
+    pub access_ttl: Duration,
+    pub refresh_ttl: Duration,
+}
+
+impl fmt::Debug for TokenConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenConfig")
+            .field("access_ttl", &self.access_ttl)
+            .field("refresh_ttl", &self.refresh_ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TokenConfig {
+    /// Read EMPLOYEE_TOKEN_SECRET, EMPLOYEE_ACCESS_TOKEN_MINUTES and EMPLOYEE_REFRESH_TOKEN_DAYS.
+    /// Without a usable secret a random one is generated, so tokens end with the process.
+    pub fn from_env() -> Self {
+        let secret = match std::env::var("EMPLOYEE_TOKEN_SECRET") {
+            Ok(value) if value.len() >= MIN_SECRET_BYTES => value.into_bytes(),
+            Ok(_) => {
+                log::warn!("Ignoring invalid EMPLOYEE_TOKEN_SECRET: must be at least {} bytes", MIN_SECRET_BYTES);
+                random_secret()
+            }
+            Err(_) => {
+                log::warn!("EMPLOYEE_TOKEN_SECRET not set; employee tokens will not survive a restart");
+                random_secret()
+            }
+        };
+
+        let mut config = Self {
+            secret,
+            access_ttl: Duration::minutes(DEFAULT_ACCESS_TOKEN_MINUTES),
+            refresh_ttl: Duration::days(DEFAULT_REFRESH_TOKEN_DAYS),
+        };
+
+        if let Ok(value) = std::env::var("EMPLOYEE_ACCESS_TOKEN_MINUTES") {
+            match value.parse::<i64>() {
+                Ok(minutes) if minutes > 0 => config.access_ttl = Duration::minutes(minutes),
+                _ => log::warn!("Ignoring invalid EMPLOYEE_ACCESS_TOKEN_MINUTES: {}", value),
+            }
+        }
+
+        if let Ok(value) = std::env::var("EMPLOYEE_REFRESH_TOKEN_DAYS") {
+            match value.parse::<i64>() {
+                Ok(days) if days > 0 => config.refresh_ttl = Duration::days(days),
+                _ => log::warn!("Ignoring invalid EMPLOYEE_REFRESH_TOKEN_DAYS: {}", value),
+            }
+        }
+
+        config
+    }
+
+    /// Process-wide configuration loaded from the environment on first use
+    pub fn global() -> &'static TokenConfig {
+        &TOKEN_CONFIG
+    }
+}
+
+fn random_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; MIN_SECRET_BYTES];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// Payload of an access token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccessClaims {
+    /// Employee user_id
+    pub sub: String,
+    /// Passkey version the token was issued under; rotating the passkey bumps it
+    pub pkv: i32,
+    /// Device the employee signed in on, if the app sent one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub did: Option<String>,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+/// Why an access token was not accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    Missing,
+    Malformed,
+    BadSignature,
+    Expired,
+    /// Issued under a passkey that was since rotated or expired, or for a deleted user
+    Revoked,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            TokenError::Missing => "missing bearer token",
+            TokenError::Malformed => "malformed token",
+            TokenError::BadSignature => "invalid token signature",
+            TokenError::Expired => "token expired",
+            TokenError::Revoked => "token revoked",
+        };
+        f.write_str(message)
+    }
+}
+
+fn signature(signing_input: &str, secret: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(signing_input.as_bytes());
+    mac
+}
+
+/// Encode and sign claims as a compact HS256 JWT
+pub fn sign_access_token(claims: &AccessClaims, secret: &[u8]) -> String {
+    let header = URL_SAFE_NO_PAD.encode(TOKEN_HEADER);
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).expect("claims serialize"));
+    let signing_input = format!("{}.{}", header, payload);
+    let sig = URL_SAFE_NO_PAD.encode(signature(&signing_input, secret).finalize().into_bytes());
+    format!("{}.{}", signing_input, sig)
+}
+
+/// Check the signature and expiry of an access token, returning its claims
+pub fn verify_access_token(token: &str, secret: &[u8], now: DateTime<Utc>) -> Result<AccessClaims, TokenError> {
+    let mut parts = token.split('.');
+    let (header, payload, sig) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(header), Some(payload), Some(sig), None) => (header, payload, sig),
+        _ => return Err(TokenError::Malformed),
+    };
+
+    let header_json = URL_SAFE_NO_PAD.decode(header).map_err(|_| TokenError::Malformed)?;
+    if header_json != TOKEN_HEADER.as_bytes() {
+        return Err(TokenError::Malformed);
+    }
+
+    let sig = URL_SAFE_NO_PAD.decode(sig).map_err(|_| TokenError::Malformed)?;
+    signature(&token[..header.len() + 1 + payload.len()], secret)
+        .verify_slice(&sig)
+        .map_err(|_| TokenError::BadSignature)?;
+
+    let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| TokenError::Malformed)?;
+    let claims: AccessClaims = serde_json::from_slice(&payload).map_err(|_| TokenError::Malformed)?;
+
+    if claims.exp <= now.timestamp() {
+        return Err(TokenError::Expired);
+    }
+
+    Ok(claims)
+}
+
+/// Sign an access token and store a new refresh token for an employee on a device
+pub async fn issue_tokens<'e, E: PgExecutor<'e>>(
+    executor: E,
+    user: &UserInfo,
+    device_id: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<IssuedTokens, sqlx::Error> {
+    let config = TokenConfig::global();
+    let user_id = user.user_id.as_str();
+
+    let access_expires_at = now + config.access_ttl;
+    let claims = AccessClaims {
+        sub: user_id.to_string(),
+        pkv: user.passkey_version,
+        did: device_id.map(str::to_string),
+        iat: now.timestamp(),
+        exp: access_expires_at.timestamp(),
+    };
+
+    let refresh_token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let refresh_expires_at = now + config.refresh_ttl;
+
+    sqlx::query(
+        r#"
+        INSERT INTO employee_refresh_tokens (token_hash, user_id, device_id, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#
+    )
+    .bind(token_hash(&refresh_token))
+    .bind(user_id)
+    .bind(device_id)
+    .bind(refresh_expires_at)
+    .execute(executor)
+    .await?;
+
+    Ok(IssuedTokens {
+        access_token: sign_access_token(&claims, &config.secret),
+        access_expires_at,
+        refresh_token,
+        refresh_expires_at,
+        token_type: "Bearer",
+    })
+}
+
+/// Revoke a live refresh token, returning the employee and device it belonged to
+pub async fn consume_refresh_token<'e, E: PgExecutor<'e>>(
+    executor: E,
+    refresh_token: &str,
+) -> Result<Option<(String, Option<String>)>, sqlx::Error> {
+    sqlx::query_as::<_, (String, Option<String>)>(
+        r#"
+        UPDATE employee_refresh_tokens
+        SET revoked_at = NOW()
+        WHERE token_hash = $1 AND revoked_at IS NULL AND expires_at > NOW()
+        RETURNING user_id, device_id
+        "#
+    )
+    .bind(token_hash(refresh_token))
+    .fetch_optional(executor)
+    .await
+}
+
+/// Drop every refresh token of an employee, e.g. after the passkey changed.
+/// Tokens are deleted rather than revoked so the user_id can change afterwards.
+pub async fn delete_refresh_tokens<'e, E: PgExecutor<'e>>(executor: E, user_id: &str) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM employee_refresh_tokens WHERE user_id = $1")
+        .bind(user_id)
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Delete refresh tokens that expired or were revoked more than a day ago
+pub async fn purge_refresh_tokens(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM employee_refresh_tokens
+        WHERE expires_at < NOW() - INTERVAL '1 day'
+           OR revoked_at < NOW() - INTERVAL '1 day'
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Employee identified by a valid access token in the `Authorization: Bearer` header.
+/// The token must have been issued under the user's current, unexpired passkey.
+#[derive(Debug, Clone)]
+pub struct EmployeeAuth {
+    pub user_id: String,
+    pub department: i32,
+    /// Device named at sign-in, checked against the user's approved devices on sync
+    pub device_id: Option<String>,
+}
+
+fn unauthorized(e: TokenError) -> actix_web::Error {
+    let response = HttpResponse::Unauthorized().json(ApiResponse::<()>::error(match e {
+        TokenError::Missing => "Authentication required",
+        _ => "Invalid or expired token",
+    }));
+    InternalError::from_response(e, response).into()
+}
+
+impl FromRequest for EmployeeAuth {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let claims = req
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(TokenError::Missing)
+            .and_then(|token| verify_access_token(token, &TokenConfig::global().secret, Utc::now()));
+        let pool = req.app_data::<web::Data<PgPool>>().cloned();
+
+        Box::pin(async move {
+            let claims = claims.map_err(unauthorized)?;
+            let pool = pool.ok_or_else(|| actix_web::error::ErrorInternalServerError("Database pool missing"))?;
+
+            let current = sqlx::query_as::<_, (i32, i32)>(
+                r#"
+                SELECT department, passkey_version FROM user_info
+                WHERE user_id = $1 AND (passkey_expires_at IS NULL OR passkey_expires_at > NOW())
+                "#
+            )
+            .bind(&claims.sub)
+            .fetch_optional(pool.as_ref())
+            .await;
+
+            match current {
+                Ok(Some((department, version))) if version == claims.pkv => {
+                    Ok(EmployeeAuth { user_id: claims.sub, department, device_id: claims.did })
+                }
+                Ok(_) => Err(unauthorized(TokenError::Revoked)),
+                Err(e) => {
+                    log::error!("Failed to check token of user {}: {:?}", claims.sub, e);
+                    let response = HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+                    Err(InternalError::from_response(e, response).into())
+                }
+            }
+        })
+    }
+}
+
+/// Look up the employee a passkey belongs to. Passkeys inserted in plaintext since
+/// startup, e.g. by SQL scripts, are hashed on first use.
 pub async fn verify_passkey(pool: &PgPool, passkey: &str) -> Result<Option<UserInfo>, sqlx::Error> {
+    let hash = hash_passkey(passkey);
+
     let user = sqlx::query_as::<_, UserInfo>(
-        "SELECT * FROM user_info WHERE passkey = $1"
+        "SELECT * FROM user_info WHERE passkey_hash = $1"
     )
-    .bind(passkey)
+    .bind(&hash)
     .fetch_optional(pool)
     .await?;
-    
-    Ok(user)
-}
 
-pub async fn verify_user_passkey(
-    pool: &PgPool,
-    user_id: &str,
-    passkey: &str,
-) -> Result<bool, sqlx::Error> {
-    let result: (i64,) = sqlx::query_as(
-        "SELECT COUNT(*) FROM user_info WHERE user_id = $1 AND passkey = $2"
+    if user.is_some() {
+        return Ok(user);
+    }
+
+    sqlx::query_as::<_, UserInfo>(
+        r#"
+        UPDATE user_info SET passkey_hash = $1, passkey = NULL
+        WHERE id = (SELECT id FROM user_info WHERE passkey = $2 LIMIT 1)
+        RETURNING *
+        "#
     )
-    .bind(user_id)
+    .bind(&hash)
     .bind(passkey)
-    .fetch_one(pool)
-    .await?;
-    
-    Ok(result.0 > 0)
-}
\ No newline at end of file
+    .fetch_optional(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const SECRET: &[u8] = b"test-secret-that-is-at-least-32-bytes";
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 12, 4, hour, 0, 0).unwrap()
+    }
+
+    fn claims() -> AccessClaims {
+        AccessClaims { sub: "EMP001".to_string(), pkv: 1, did: None, iat: at(8).timestamp(), exp: at(9).timestamp() }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let token = sign_access_token(&claims(), SECRET);
+        assert_eq!(token.split('.').count(), 3);
+        assert_eq!(verify_access_token(&token, SECRET, at(8)), Ok(claims()));
+    }
+
+    #[test]
+    fn test_device_claim_round_trip() {
+        let with_device = AccessClaims { did: Some("phone-1".to_string()), ..claims() };
+        let token = sign_access_token(&with_device, SECRET);
+        assert_eq!(verify_access_token(&token, SECRET, at(8)), Ok(with_device));
+    }
+
+    #[test]
+    fn test_expired_token() {
+        let token = sign_access_token(&claims(), SECRET);
+        assert_eq!(verify_access_token(&token, SECRET, at(9)), Err(TokenError::Expired));
+    }
+
+    #[test]
+    fn test_wrong_secret() {
+        let token = sign_access_token(&claims(), SECRET);
+        assert_eq!(
+            verify_access_token(&token, b"another-secret-that-is-32-bytes-long", at(8)),
+            Err(TokenError::BadSignature)
+        );
+    }
+
+    #[test]
+    fn test_tampered_payload() {
+        let token = sign_access_token(&claims(), SECRET);
+        let forged = AccessClaims { sub: "EMP002".to_string(), ..claims() };
+        let forged_payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+        let parts: Vec<&str> = token.split('.').collect();
+        let tampered = format!("{}.{}.{}", parts[0], forged_payload, parts[2]);
+        assert_eq!(verify_access_token(&tampered, SECRET, at(8)), Err(TokenError::BadSignature));
+    }
+
+    #[test]
+    fn test_malformed_tokens() {
+        assert_eq!(verify_access_token("", SECRET, at(8)), Err(TokenError::Malformed));
+        assert_eq!(verify_access_token("a.b", SECRET, at(8)), Err(TokenError::Malformed));
+        assert_eq!(verify_access_token("a.b.c.d", SECRET, at(8)), Err(TokenError::Malformed));
+
+        // Unsigned tokens are never accepted
+        let none_header = URL_SAFE_NO_PAD.encode(r#"{"alg":"none","typ":"JWT"}"#);
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims()).unwrap());
+        assert_eq!(
+            verify_access_token(&format!("{}.{}.", none_header, payload), SECRET, at(8)),
+            Err(TokenError::Malformed)
+        );
+    }
+}
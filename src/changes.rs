@@ -0,0 +1,141 @@
+// Cursor-based delta sync of check-ins and tombstones for employee devices
+use sqlx::PgPool;
+
+use crate::models::{ChangesResponse, Checkin, CheckinTombstone};
+
+/// Where a page of changes ends: the `limit` lowest sequence values across both sources
+/// are sent, each source having been fetched with one row more than `limit`. Returns the
+/// cursor to continue from and whether more changes follow.
+pub fn page_end(checkin_seqs: &[i64], tombstone_seqs: &[i64], cursor: i64, limit: i64) -> (i64, bool) {
+    let mut sequences: Vec<i64> = checkin_seqs.iter().chain(tombstone_seqs).copied().collect();
+    sequences.sort_unstable();
+    let has_more = sequences.len() as i64 > limit;
+    sequences.truncate(limit as usize);
+    (sequences.last().copied().unwrap_or(cursor), has_more)
+}
+
+/// Changes of a user's check-ins after `cursor`, at most `limit` of them. Sequence values
+/// are taken under the user's lock, so no change below one seen here can commit later.
+/// Both sources are read from one snapshot.
+pub async fn load_page(pool: &PgPool, user_id: &str, cursor: i64, limit: i64) -> Result<ChangesResponse, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+
+    // Fetch one extra row from each source to know whether another page follows
+    let checkins = sqlx::query_as::<_, Checkin>(
+        r#"
+        SELECT * FROM checkins
+        WHERE user_id = $1 AND sync_seq > $2
+        ORDER BY sync_seq ASC LIMIT $3
+        "#
+    )
+    .bind(user_id)
+    .bind(cursor)
+    .bind(limit + 1)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let tombstones = sqlx::query_as::<_, CheckinTombstone>(
+        r#"
+        SELECT checkin_id, client_id, sync_seq, deleted_at FROM checkin_tombstones
+        WHERE user_id = $1 AND sync_seq > $2
+        ORDER BY sync_seq ASC LIMIT $3
+        "#
+    )
+    .bind(user_id)
+    .bind(cursor)
+    .bind(limit + 1)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let checkin_seqs: Vec<i64> = checkins.iter().map(|c| c.sync_seq).collect();
+    let tombstone_seqs: Vec<i64> = tombstones.iter().map(|t| t.sync_seq).collect();
+    let (next_cursor, has_more) = page_end(&checkin_seqs, &tombstone_seqs, cursor, limit);
+
+    Ok(ChangesResponse {
+        checkins: checkins.into_iter().filter(|c| c.sync_seq <= next_cursor).collect(),
+        deleted: tombstones.into_iter().filter(|t| t.sync_seq <= next_cursor).collect(),
+        next_cursor,
+        has_more,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_interleaves_sources() {
+        // Limit 3: each source was fetched with up to 4 rows
+        assert_eq!(page_end(&[11, 14, 15, 17], &[12, 13], 10, 3), (13, true));
+        assert_eq!(page_end(&[14, 15], &[], 13, 3), (15, false));
+    }
+
+    #[test]
+    fn test_page_exactly_full_has_no_more() {
+        assert_eq!(page_end(&[1, 2], &[3], 0, 3), (3, false));
+        assert_eq!(page_end(&[1, 2], &[3, 4], 0, 3), (3, true));
+    }
+
+    #[test]
+    fn test_empty_page_keeps_cursor() {
+        assert_eq!(page_end(&[], &[], 42, 500), (42, false));
+    }
+
+    async fn insert_punch(conn: &mut sqlx::PgConnection, user_id: &str) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(
+            "INSERT INTO checkins (user_id, action, created_at, is_synced) VALUES ($1, 'IN', NOW(), 1) RETURNING sync_seq"
+        )
+        .bind(user_id)
+        .fetch_one(conn)
+        .await
+    }
+
+    /// Runs against the database in TEST_DATABASE_URL, after the server has created the schema
+    #[tokio::test]
+    async fn test_out_of_order_commits_are_not_skipped() {
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else { return };
+        let pool = PgPool::connect(&url).await.unwrap();
+        let user_id = format!("sync-order-{}", uuid::Uuid::new_v4().simple());
+        sqlx::query("INSERT INTO user_info (user_id, user_name) VALUES ($1, 'Sync order test')")
+            .bind(&user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // A takes its transaction id first, B then writes the first change
+        let mut a = pool.begin().await.unwrap();
+        sqlx::query("SELECT pg_current_xact_id()").execute(&mut *a).await.unwrap();
+        let mut b = pool.begin().await.unwrap();
+        let b_seq = insert_punch(&mut b, &user_id).await.unwrap();
+
+        // A's change waits for B instead of taking a later value and committing first
+        let a_user = user_id.clone();
+        let a_write = tokio::spawn(async move {
+            let seq = insert_punch(&mut a, &a_user).await?;
+            a.commit().await?;
+            Ok::<_, sqlx::Error>(seq)
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        assert!(!a_write.is_finished());
+        let page = load_page(&pool, &user_id, 0, 10).await.unwrap();
+        assert!(page.checkins.is_empty());
+        assert_eq!(page.next_cursor, 0);
+
+        b.commit().await.unwrap();
+        let a_seq = a_write.await.unwrap().unwrap();
+        assert!(a_seq > b_seq);
+
+        let page = load_page(&pool, &user_id, 0, 10).await.unwrap();
+        let seqs: Vec<i64> = page.checkins.iter().map(|c| c.sync_seq).collect();
+        assert_eq!(seqs, vec![b_seq, a_seq]);
+
+        sqlx::query("DELETE FROM checkins WHERE user_id = $1").bind(&user_id).execute(&pool).await.unwrap();
+        sqlx::query("DELETE FROM checkin_tombstones WHERE user_id = $1").bind(&user_id).execute(&pool).await.unwrap();
+        sqlx::query("DELETE FROM user_info WHERE user_id = $1").bind(&user_id).execute(&pool).await.unwrap();
+    }
+}
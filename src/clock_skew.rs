@@ -0,0 +1,206 @@
+// Device clock skew estimation for synced punches
+use chrono::{DateTime, Duration, Utc};
+use lazy_static::lazy_static;
+use sqlx::{PgConnection, PgPool};
+
+/// Default skew tolerated before a punch is corrected or flagged
+pub const DEFAULT_MAX_CLOCK_SKEW_SECONDS: i64 = 300;
+
+lazy_static! {
+    static ref MAX_CLOCK_SKEW: Duration = max_skew_from_env();
+}
+
+/// How punches from a device with a skewed clock are handled, set per department
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkewPolicy {
+    /// Keep the device timestamp but mark the punch for review
+    Flag,
+    /// Shift the timestamp by the estimated skew, keeping the original
+    Correct,
+}
+
+impl SkewPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "flag" => Some(SkewPolicy::Flag),
+            "correct" => Some(SkewPolicy::Correct),
+            _ => None,
+        }
+    }
+}
+
+/// Read MAX_CLOCK_SKEW_SECONDS, falling back to the default
+fn max_skew_from_env() -> Duration {
+    match std::env::var("MAX_CLOCK_SKEW_SECONDS") {
+        Ok(value) => match value.parse::<i64>() {
+            Ok(seconds) if seconds >= 0 => Duration::seconds(seconds),
+            _ => {
+                log::warn!("Ignoring invalid MAX_CLOCK_SKEW_SECONDS: {}", value);
+                Duration::seconds(DEFAULT_MAX_CLOCK_SKEW_SECONDS)
+            }
+        },
+        Err(_) => Duration::seconds(DEFAULT_MAX_CLOCK_SKEW_SECONDS),
+    }
+}
+
+/// Process-wide skew threshold loaded from the environment on first use
+pub fn max_skew() -> Duration {
+    *MAX_CLOCK_SKEW
+}
+
+/// How far the server clock is ahead of the device clock.
+/// Positive when the device is behind, e.g. because it was set back to backdate punches.
+pub fn estimate_skew(received_at: DateTime<Utc>, device_time: DateTime<Utc>) -> Duration {
+    received_at - device_time
+}
+
+/// Timestamp to store for one punch, and how it was derived
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdjustedTime {
+    pub created_at: DateTime<Utc>,
+    /// Timestamp sent by the device, kept when it was corrected
+    pub device_created_at: Option<DateTime<Utc>>,
+    pub skew_seconds: Option<i64>,
+    pub flagged: bool,
+}
+
+/// Apply the device skew to a punch timestamp. Skew within `max_skew` is treated as
+/// normal clock drift and leaves the timestamp as sent.
+pub fn adjust(
+    created_at: DateTime<Utc>,
+    skew: Option<Duration>,
+    policy: SkewPolicy,
+    max_skew: Duration,
+) -> AdjustedTime {
+    let unchanged = AdjustedTime {
+        created_at,
+        device_created_at: None,
+        skew_seconds: skew.map(|s| s.num_seconds()),
+        flagged: false,
+    };
+
+    match skew {
+        Some(skew) if skew.num_seconds().abs() > max_skew.num_seconds() => match policy {
+            SkewPolicy::Flag => AdjustedTime { flagged: true, ..unchanged },
+            SkewPolicy::Correct => AdjustedTime {
+                created_at: created_at + skew,
+                device_created_at: Some(created_at),
+                ..unchanged
+            },
+        },
+        _ => unchanged,
+    }
+}
+
+/// Skew of the device sending this batch. A batch carrying the device clock gives a
+/// fresh sample, which is stored; otherwise the last estimate for the device is used.
+pub async fn device_skew(
+    conn: &mut PgConnection,
+    user_id: &str,
+    device_id: &str,
+    received_at: DateTime<Utc>,
+    device_time: Option<DateTime<Utc>>,
+) -> Result<Option<Duration>, sqlx::Error> {
+    match device_time {
+        Some(device_time) => {
+            let skew = estimate_skew(received_at, device_time);
+            sqlx::query(
+                r#"
+                INSERT INTO device_clocks (user_id, device_id, skew_seconds, last_device_time, last_received_at)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (user_id, device_id)
+                DO UPDATE SET
+                    skew_seconds = EXCLUDED.skew_seconds,
+                    last_device_time = EXCLUDED.last_device_time,
+                    last_received_at = EXCLUDED.last_received_at
+                "#
+            )
+            .bind(user_id)
+            .bind(device_id)
+            .bind(skew.num_seconds())
+            .bind(device_time)
+            .bind(received_at)
+            .execute(&mut *conn)
+            .await?;
+
+            Ok(Some(skew))
+        }
+        None => {
+            let skew_seconds = sqlx::query_scalar::<_, i64>(
+                "SELECT skew_seconds FROM device_clocks WHERE user_id = $1 AND device_id = $2"
+            )
+            .bind(user_id)
+            .bind(device_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+            Ok(skew_seconds.map(Duration::seconds))
+        }
+    }
+}
+
+/// Load the clock skew policy of a department, defaulting to flagging
+pub async fn load_department_policy(pool: &PgPool, department: i32) -> Result<SkewPolicy, sqlx::Error> {
+    let policy = sqlx::query_scalar::<_, String>(
+        "SELECT clock_skew_policy FROM department_settings WHERE department = $1"
+    )
+    .bind(department)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(policy
+        .and_then(|p| SkewPolicy::parse(&p))
+        .unwrap_or(SkewPolicy::Flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 12, 4, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_backdated_device_has_positive_skew() {
+        // Device clock set back two hours
+        assert_eq!(estimate_skew(at(10, 0), at(8, 0)), Duration::hours(2));
+        assert_eq!(estimate_skew(at(10, 0), at(10, 30)), Duration::minutes(-30));
+    }
+
+    #[test]
+    fn test_small_skew_is_ignored() {
+        let adjusted = adjust(at(8, 0), Some(Duration::seconds(90)), SkewPolicy::Correct, Duration::minutes(5));
+        assert_eq!(adjusted.created_at, at(8, 0));
+        assert_eq!(adjusted.device_created_at, None);
+        assert_eq!(adjusted.skew_seconds, Some(90));
+        assert!(!adjusted.flagged);
+    }
+
+    #[test]
+    fn test_large_skew_is_corrected() {
+        let adjusted = adjust(at(8, 0), Some(Duration::hours(2)), SkewPolicy::Correct, Duration::minutes(5));
+        assert_eq!(adjusted.created_at, at(10, 0));
+        assert_eq!(adjusted.device_created_at, Some(at(8, 0)));
+        assert!(!adjusted.flagged);
+
+        let adjusted = adjust(at(8, 0), Some(Duration::minutes(-30)), SkewPolicy::Correct, Duration::minutes(5));
+        assert_eq!(adjusted.created_at, at(7, 30));
+    }
+
+    #[test]
+    fn test_large_skew_is_flagged() {
+        let adjusted = adjust(at(8, 0), Some(Duration::hours(2)), SkewPolicy::Flag, Duration::minutes(5));
+        assert_eq!(adjusted.created_at, at(8, 0));
+        assert_eq!(adjusted.device_created_at, None);
+        assert_eq!(adjusted.skew_seconds, Some(7200));
+        assert!(adjusted.flagged);
+    }
+
+    #[test]
+    fn test_unknown_skew_keeps_timestamp() {
+        let adjusted = adjust(at(8, 0), None, SkewPolicy::Correct, Duration::minutes(5));
+        assert_eq!(adjusted, AdjustedTime { created_at: at(8, 0), device_created_at: None, skew_seconds: None, flagged: false });
+    }
+}
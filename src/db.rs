@@ -31,9 +31,24 @@ async fn initialize_database(pool: &PgPool) -> Result<(), sqlx::Error> {
     // Create or update other tables
     create_base_tables(pool).await?;
     
+    // Track checkin changes for delta sync
+    create_checkin_change_tracking(pool).await?;
+    
     // Create indexes
     create_indexes(pool).await?;
     
+    // An OUT without an IN is an anomaly, not a session
+    let removed = crate::sessions::remove_orphan_out_sessions(pool).await?;
+    if removed > 0 {
+        log::info!("Removed {} sessions stored for an OUT without an IN", removed);
+    }
+    
+    // Replace plaintext employee passkeys with keyed hashes
+    let hashed = crate::passkey::hash_plaintext_passkeys(pool).await?;
+    if hashed > 0 {
+        log::info!("Hashed {} plaintext employee passkeys", hashed);
+    }
+    
     // Insert sample data if needed
     insert_sample_data(pool).await?;
     
@@ -182,6 +197,26 @@ async fn create_session_schema(pool: &PgPool) -> Result<(), sqlx::Error> {
 }
 
 async fn create_triggers_and_functions(pool: &PgPool) -> Result<(), sqlx::Error> {
+    // Columns for sessions closed by the scheduled job, used by the summary function below
+    sqlx::query(
+        r#"
+        ALTER TABLE attendance_sessions
+            ADD COLUMN IF NOT EXISTS auto_closed BOOLEAN NOT NULL DEFAULT false,
+            ADD COLUMN IF NOT EXISTS auto_closed_at TIMESTAMP WITH TIME ZONE
+        "#
+    )
+    .execute(pool)
+    .await?;
+    
+    sqlx::query(
+        r#"
+        ALTER TABLE attendance_summary
+            ADD COLUMN IF NOT EXISTS auto_closed_sessions INTEGER NOT NULL DEFAULT 0
+        "#
+    )
+    .execute(pool)
+    .await?;
+    
     // Create function to update duration when checkout_time is set
     sqlx::query(
         r#"
@@ -234,6 +269,7 @@ async fn create_triggers_and_functions(pool: &PgPool) -> Result<(), sqlx::Error>
             v_total_minutes INTEGER;
             v_total_sessions INTEGER;
             v_is_complete BOOLEAN;
+            v_auto_closed INTEGER;
         BEGIN
             -- Calculate summary statistics for the day
             SELECT 
@@ -241,13 +277,15 @@ async fn create_triggers_and_functions(pool: &PgPool) -> Result<(), sqlx::Error>
                 MAX(checkout_time),
                 COALESCE(SUM(duration_minutes), 0),
                 COUNT(*),
-                BOOL_AND(is_complete)
+                BOOL_AND(is_complete),
+                COUNT(*) FILTER (WHERE auto_closed)
             INTO 
                 v_first_checkin,
                 v_last_checkout,
                 v_total_minutes,
                 v_total_sessions,
-                v_is_complete
+                v_is_complete,
+                v_auto_closed
             FROM attendance_sessions
             WHERE user_id = COALESCE(NEW.user_id, OLD.user_id)
                 AND date = COALESCE(NEW.date, OLD.date);
@@ -263,6 +301,7 @@ async fn create_triggers_and_functions(pool: &PgPool) -> Result<(), sqlx::Error>
                 total_work_minutes, 
                 total_sessions, 
                 is_complete,
+                auto_closed_sessions,
                 updated_at
             ) VALUES (
                 COALESCE(NEW.user_id, OLD.user_id),
@@ -274,6 +313,7 @@ async fn create_triggers_and_functions(pool: &PgPool) -> Result<(), sqlx::Error>
                 v_total_minutes,
                 v_total_sessions,
                 v_is_complete,
+                v_auto_closed,
                 NOW()
             )
             ON CONFLICT (user_id, date) DO UPDATE SET
@@ -284,6 +324,7 @@ async fn create_triggers_and_functions(pool: &PgPool) -> Result<(), sqlx::Error>
                 total_work_minutes = EXCLUDED.total_work_minutes,
                 total_sessions = EXCLUDED.total_sessions,
                 is_complete = EXCLUDED.is_complete,
+                auto_closed_sessions = EXCLUDED.auto_closed_sessions,
                 updated_at = NOW();
 
             RETURN NEW;
@@ -432,7 +473,7 @@ async fn create_base_tables(pool: &PgPool) -> Result<(), sqlx::Error> {
             id SERIAL PRIMARY KEY,
             username VARCHAR(255) UNIQUE NOT NULL,
             password VARCHAR(255) NOT NULL,
-            role VARCHAR(50) NOT NULL CHECK (role IN ('admin', 'department')),
+            role VARCHAR(50) NOT NULL CHECK (role IN ('admin', 'department', 'auditor', 'payroll')),
             department INTEGER,
             created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
         )
@@ -441,6 +482,204 @@ async fn create_base_tables(pool: &PgPool) -> Result<(), sqlx::Error> {
     .execute(pool)
     .await?;
 
+    // Roles beyond admin and department manager
+    sqlx::query(
+        r#"
+        ALTER TABLE admin_user
+            DROP CONSTRAINT IF EXISTS admin_user_role_check,
+            ADD CONSTRAINT admin_user_role_check CHECK (role IN ('admin', 'department', 'auditor', 'payroll'))
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Departments a non-admin user is limited to; department stays in sync as the first one
+    sqlx::query("ALTER TABLE admin_user ADD COLUMN IF NOT EXISTS departments INTEGER[] NOT NULL DEFAULT '{}'")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE admin_user SET departments = ARRAY[department]
+        WHERE department IS NOT NULL AND cardinality(departments) = 0
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Admin login sessions; only a SHA-256 digest of each token is stored
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS admin_sessions (
+            token_hash VARCHAR(64) PRIMARY KEY,
+            admin_user_id INTEGER NOT NULL REFERENCES admin_user(id) ON DELETE CASCADE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            revoked_at TIMESTAMP WITH TIME ZONE,
+            ip_address VARCHAR(255)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // TOTP two-factor login: a secret pending confirmation has no totp_enabled_at yet,
+    // and the last step used keeps a code from being replayed
+    sqlx::query(
+        r#"
+        ALTER TABLE admin_user
+            ADD COLUMN IF NOT EXISTS totp_secret VARCHAR(64),
+            ADD COLUMN IF NOT EXISTS totp_enabled_at TIMESTAMP WITH TIME ZONE,
+            ADD COLUMN IF NOT EXISTS totp_last_step BIGINT
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Single-use recovery codes, stored as SHA-256 digests
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS admin_recovery_codes (
+            id SERIAL PRIMARY KEY,
+            admin_user_id INTEGER NOT NULL REFERENCES admin_user(id) ON DELETE CASCADE,
+            code_hash VARCHAR(64) NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            used_at TIMESTAMP WITH TIME ZONE
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Short-lived challenges between the password and the second factor of a login
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS admin_login_challenges (
+            token_hash VARCHAR(64) PRIMARY KEY,
+            admin_user_id INTEGER NOT NULL REFERENCES admin_user(id) ON DELETE CASCADE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // API keys of service accounts; only a digest of each key is stored
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS admin_api_keys (
+            id SERIAL PRIMARY KEY,
+            name VARCHAR(100) NOT NULL,
+            key_prefix VARCHAR(16) NOT NULL,
+            key_hash VARCHAR(64) NOT NULL UNIQUE,
+            scopes TEXT[] NOT NULL DEFAULT '{}',
+            departments INTEGER[] NOT NULL DEFAULT '{}',
+            created_by VARCHAR(255) NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            expires_at TIMESTAMP WITH TIME ZONE,
+            last_used_at TIMESTAMP WITH TIME ZONE,
+            revoked_at TIMESTAMP WITH TIME ZONE
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Failed logins per IP and account, for brute-force lockout
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS login_attempts (
+            scope VARCHAR(20) NOT NULL CHECK (scope IN ('ip', 'admin_user', 'employee')),
+            subject VARCHAR(255) NOT NULL,
+            failures INTEGER NOT NULL DEFAULT 0,
+            last_failure_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            locked_until TIMESTAMP WITH TIME ZONE,
+            PRIMARY KEY (scope, subject)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Audit trail of security-relevant actions
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS audit_log (
+            id BIGSERIAL PRIMARY KEY,
+            occurred_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            actor VARCHAR(255) NOT NULL,
+            action VARCHAR(64) NOT NULL,
+            target VARCHAR(255),
+            details TEXT
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Data changes record the kind of record changed (target is its id), the record
+    // before and after the change as the API returns it, and where the request came from
+    sqlx::query(
+        r#"
+        ALTER TABLE audit_log
+            ADD COLUMN IF NOT EXISTS entity VARCHAR(64),
+            ADD COLUMN IF NOT EXISTS before_data JSONB,
+            ADD COLUMN IF NOT EXISTS after_data JSONB,
+            ADD COLUMN IF NOT EXISTS ip_address VARCHAR(255)
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // The audit log is append-only: entries cannot be changed or removed
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION reject_audit_log_change()
+        RETURNS TRIGGER AS $$
+        BEGIN
+            RAISE EXCEPTION 'audit_log is append-only';
+        END;
+        $$ LANGUAGE plpgsql
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("DROP TRIGGER IF EXISTS audit_log_append_only ON audit_log")
+        .execute(pool)
+        .await
+        .ok();
+
+    sqlx::query(
+        r#"
+        CREATE TRIGGER audit_log_append_only
+            BEFORE UPDATE OR DELETE ON audit_log
+            FOR EACH ROW
+            EXECUTE FUNCTION reject_audit_log_change()
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("DROP TRIGGER IF EXISTS audit_log_no_truncate ON audit_log")
+        .execute(pool)
+        .await
+        .ok();
+
+    sqlx::query(
+        r#"
+        CREATE TRIGGER audit_log_no_truncate
+            BEFORE TRUNCATE ON audit_log
+            FOR EACH STATEMENT
+            EXECUTE FUNCTION reject_audit_log_change()
+        "#
+    )
+    .execute(pool)
+    .await?;
+
     // Create checkins table
     sqlx::query(
         r#"
@@ -458,6 +697,159 @@ async fn create_base_tables(pool: &PgPool) -> Result<(), sqlx::Error> {
     .execute(pool)
     .await?;
 
+    // Add geofence validation results to checkins
+    sqlx::query(
+        r#"
+        ALTER TABLE checkins
+            ADD COLUMN IF NOT EXISTS within_geofence BOOLEAN,
+            ADD COLUMN IF NOT EXISTS distance_meters DOUBLE PRECISION,
+            ADD COLUMN IF NOT EXISTS matched_point_id INTEGER
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Add client-generated punch id so retried syncs are idempotent
+    sqlx::query("ALTER TABLE checkins ADD COLUMN IF NOT EXISTS client_id UUID")
+        .execute(pool)
+        .await?;
+
+    // Add server receive time and device clock skew handling to checkins
+    sqlx::query(
+        r#"
+        ALTER TABLE checkins
+            ADD COLUMN IF NOT EXISTS received_at TIMESTAMP WITH TIME ZONE,
+            ADD COLUMN IF NOT EXISTS device_created_at TIMESTAMP WITH TIME ZONE,
+            ADD COLUMN IF NOT EXISTS clock_skew_seconds BIGINT,
+            ADD COLUMN IF NOT EXISTS clock_skew_flagged BOOLEAN NOT NULL DEFAULT false
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Add the sending device and device approval hold to checkins
+    sqlx::query(
+        r#"
+        ALTER TABLE checkins
+            ADD COLUMN IF NOT EXISTS device_id VARCHAR(255),
+            ADD COLUMN IF NOT EXISTS held BOOLEAN NOT NULL DEFAULT false
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Existing rows keep NULL, their receive time is unknown
+    sqlx::query("ALTER TABLE checkins ALTER COLUMN received_at SET DEFAULT NOW()")
+        .execute(pool)
+        .await?;
+
+    // Create department_settings table for per-department policies
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS department_settings (
+            department INTEGER PRIMARY KEY,
+            geofence_policy VARCHAR(20) NOT NULL DEFAULT 'flag' CHECK (geofence_policy IN ('flag', 'reject')),
+            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // How forgotten sessions are closed: at the user's off-duty time or with zero minutes
+    sqlx::query(
+        r#"
+        ALTER TABLE department_settings
+            ADD COLUMN IF NOT EXISTS auto_close_policy VARCHAR(20) NOT NULL DEFAULT 'off_duty'
+                CHECK (auto_close_policy IN ('off_duty', 'zero'))
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // How punches from devices with a skewed clock are handled: flagged for review or corrected
+    sqlx::query(
+        r#"
+        ALTER TABLE department_settings
+            ADD COLUMN IF NOT EXISTS clock_skew_policy VARCHAR(20) NOT NULL DEFAULT 'flag'
+                CHECK (clock_skew_policy IN ('flag', 'correct'))
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Whether punches from devices an employee has no approval for are held or rejected
+    sqlx::query(
+        r#"
+        ALTER TABLE department_settings
+            ADD COLUMN IF NOT EXISTS device_policy VARCHAR(20) NOT NULL DEFAULT 'off'
+                CHECK (device_policy IN ('off', 'hold', 'reject'))
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Minutes of tolerance before a checkin counts as late or a checkout as leaving early,
+    // and how late a checkin has to be for the major tier
+    sqlx::query(
+        r#"
+        ALTER TABLE department_settings
+            ADD COLUMN IF NOT EXISTS late_grace_minutes INTEGER NOT NULL DEFAULT 0
+                CHECK (late_grace_minutes >= 0),
+            ADD COLUMN IF NOT EXISTS early_leave_grace_minutes INTEGER NOT NULL DEFAULT 0
+                CHECK (early_leave_grace_minutes >= 0),
+            ADD COLUMN IF NOT EXISTS major_late_minutes INTEGER NOT NULL DEFAULT 30
+                CHECK (major_late_minutes > 0)
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Create device_clocks table with the latest skew estimate per device
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS device_clocks (
+            user_id VARCHAR(255) NOT NULL,
+            device_id VARCHAR(255) NOT NULL DEFAULT '',
+            skew_seconds BIGINT NOT NULL,
+            last_device_time TIMESTAMP WITH TIME ZONE NOT NULL,
+            last_received_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            PRIMARY KEY (user_id, device_id)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Create sync_uploads tables for resumable chunked uploads of large backlogs
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS sync_uploads (
+            id UUID PRIMARY KEY,
+            user_id VARCHAR(255) NOT NULL,
+            device_id VARCHAR(255),
+            total_chunks INTEGER NOT NULL CHECK (total_chunks > 0),
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS sync_upload_chunks (
+            upload_id UUID NOT NULL REFERENCES sync_uploads(id) ON DELETE CASCADE,
+            chunk_index INTEGER NOT NULL,
+            punches INTEGER NOT NULL,
+            received_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (upload_id, chunk_index)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
     // Create checkin_points table
     sqlx::query(
         r#"
@@ -499,19 +891,495 @@ async fn create_base_tables(pool: &PgPool) -> Result<(), sqlx::Error> {
             user_name VARCHAR(255),
             department INTEGER DEFAULT 99,
             department_name VARCHAR(100),
-            passkey VARCHAR(255) NOT NULL
+            passkey VARCHAR(255),
+            passkey_hash VARCHAR(64),
+            passkey_version INTEGER NOT NULL DEFAULT 1,
+            passkey_rotated_at TIMESTAMP WITH TIME ZONE,
+            passkey_expires_at TIMESTAMP WITH TIME ZONE
         )
         "#
     )
     .execute(pool)
     .await?;
-    
-    Ok(())
-}
 
-async fn create_indexes(pool: &PgPool) -> Result<(), sqlx::Error> {
-    // Indexes for checkins table
-    sqlx::query("CREATE INDEX IF NOT EXISTS idx_checkins_user_id ON checkins(user_id)")
+    // Passkeys are stored as keyed hashes; the plaintext column is emptied on startup
+    sqlx::query(
+        r#"
+        ALTER TABLE user_info
+            ALTER COLUMN passkey DROP NOT NULL,
+            ADD COLUMN IF NOT EXISTS passkey_hash VARCHAR(64),
+            ADD COLUMN IF NOT EXISTS passkey_version INTEGER NOT NULL DEFAULT 1,
+            ADD COLUMN IF NOT EXISTS passkey_rotated_at TIMESTAMP WITH TIME ZONE,
+            ADD COLUMN IF NOT EXISTS passkey_expires_at TIMESTAMP WITH TIME ZONE
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Employee refresh tokens; only a SHA-256 digest of each token is stored
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS employee_refresh_tokens (
+            token_hash VARCHAR(64) PRIMARY KEY,
+            user_id VARCHAR(255) NOT NULL REFERENCES user_info(user_id) ON DELETE CASCADE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            revoked_at TIMESTAMP WITH TIME ZONE
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Refresh tokens remember the device they were issued to, so revoking a device signs it out
+    sqlx::query("ALTER TABLE employee_refresh_tokens ADD COLUMN IF NOT EXISTS device_id VARCHAR(255)")
+        .execute(pool)
+        .await?;
+
+    // Create user_devices table binding employees to the devices they punch from
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_devices (
+            id SERIAL PRIMARY KEY,
+            user_id VARCHAR(255) NOT NULL REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+            device_id VARCHAR(255) NOT NULL,
+            status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('approved', 'pending', 'revoked')),
+            registered_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            reviewed_by VARCHAR(255),
+            reviewed_at TIMESTAMP WITH TIME ZONE,
+            CONSTRAINT unique_user_device UNIQUE(user_id, device_id)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    
+    // Duty times per user, one row for each date a change takes effect
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_time_settings (
+            id SERIAL PRIMARY KEY,
+            user_id VARCHAR(50) NOT NULL REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+            effective_from DATE NOT NULL DEFAULT '1970-01-01',
+            on_duty_time TIME NOT NULL DEFAULT '07:30:00',
+            off_duty_time TIME NOT NULL DEFAULT '17:00:00',
+            created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+            updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Settings from before they were dated apply to all of the user's history
+    sqlx::query("ALTER TABLE user_time_settings ADD COLUMN IF NOT EXISTS effective_from DATE NOT NULL DEFAULT '1970-01-01'")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE user_time_settings DROP CONSTRAINT IF EXISTS unique_user_time_settings")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_user_time_settings_effective ON user_time_settings(user_id, effective_from)")
+        .execute(pool)
+        .await?;
+
+    // Settings follow a user whose user_id is changed; older databases named the key after
+    // the original migration
+    sqlx::query(
+        r#"
+        ALTER TABLE user_time_settings
+            DROP CONSTRAINT IF EXISTS fk_user_time_settings_user_id,
+            DROP CONSTRAINT IF EXISTS user_time_settings_user_id_fkey,
+            ADD CONSTRAINT user_time_settings_user_id_fkey FOREIGN KEY (user_id)
+                REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE
+        "#
+    )
+    .execute(pool)
+    .await?;
+    
+    // Working hours by day of the week for each dated setting; rows without times are days off
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_weekday_hours (
+            user_id VARCHAR(255) NOT NULL REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+            effective_from DATE NOT NULL DEFAULT '1970-01-01',
+            weekday SMALLINT NOT NULL CHECK (weekday BETWEEN 1 AND 7),
+            on_duty_time TIME,
+            off_duty_time TIME,
+            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (user_id, effective_from, weekday),
+            CHECK ((on_duty_time IS NULL) = (off_duty_time IS NULL))
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("ALTER TABLE user_weekday_hours ADD COLUMN IF NOT EXISTS effective_from DATE NOT NULL DEFAULT '1970-01-01'")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM user_weekday_hours uwh
+        WHERE NOT EXISTS (
+            SELECT 1 FROM user_time_settings uts
+            WHERE uts.user_id = uwh.user_id AND uts.effective_from = uwh.effective_from
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        ALTER TABLE user_weekday_hours
+            DROP CONSTRAINT IF EXISTS user_weekday_hours_pkey,
+            ADD PRIMARY KEY (user_id, effective_from, weekday),
+            DROP CONSTRAINT IF EXISTS user_weekday_hours_setting_fkey,
+            ADD CONSTRAINT user_weekday_hours_setting_fkey FOREIGN KEY (user_id, effective_from)
+                REFERENCES user_time_settings(user_id, effective_from) ON DELETE CASCADE ON UPDATE CASCADE
+        "#
+    )
+    .execute(pool)
+    .await?;
+    
+    // Default duty times of departments, dated like user settings, for users without their own
+    let department_defaults_are_new = sqlx::query_scalar::<_, bool>("SELECT to_regclass('department_time_settings') IS NULL")
+        .fetch_one(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS department_time_settings (
+            id SERIAL PRIMARY KEY,
+            department INTEGER NOT NULL,
+            effective_from DATE NOT NULL DEFAULT '1970-01-01',
+            on_duty_time TIME NOT NULL,
+            off_duty_time TIME NOT NULL,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            CONSTRAINT unique_department_time_setting UNIQUE (department, effective_from)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Default rows the time settings sync used to create for every user would hide the
+    // department defaults, so those users inherit from their department from now on
+    if department_defaults_are_new {
+        sqlx::query(
+            r#"
+            DELETE FROM user_time_settings uts
+            WHERE effective_from = '1970-01-01'
+              AND on_duty_time = '07:30:00'
+              AND off_duty_time = '17:00:00'
+              AND NOT EXISTS (
+                  SELECT 1 FROM user_weekday_hours uwh
+                  WHERE uwh.user_id = uts.user_id AND uwh.effective_from = uts.effective_from
+              )
+            "#
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    // Per-user lateness thresholds; empty columns follow the user's department
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_lateness_rules (
+            user_id VARCHAR(255) PRIMARY KEY REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+            late_grace_minutes INTEGER CHECK (late_grace_minutes >= 0),
+            early_leave_grace_minutes INTEGER CHECK (early_leave_grace_minutes >= 0),
+            major_late_minutes INTEGER CHECK (major_late_minutes > 0),
+            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Overrides follow a user whose user_id is changed
+    sqlx::query(
+        r#"
+        ALTER TABLE user_lateness_rules
+            ALTER COLUMN user_id TYPE VARCHAR(255),
+            DROP CONSTRAINT IF EXISTS user_lateness_rules_user_id_fkey,
+            ADD CONSTRAINT user_lateness_rules_user_id_fkey FOREIGN KEY (user_id)
+                REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE
+        "#
+    )
+    .execute(pool)
+    .await?;
+    
+    // Shift templates and the roster assigning them to employees by date or rotation
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS shift_templates (
+            id SERIAL PRIMARY KEY,
+            name VARCHAR(100) NOT NULL UNIQUE,
+            start_time TIME NOT NULL,
+            end_time TIME NOT NULL CHECK (end_time <> start_time),
+            crosses_midnight BOOLEAN GENERATED ALWAYS AS (end_time < start_time) STORED,
+            break_minutes INTEGER NOT NULL DEFAULT 0 CHECK (break_minutes >= 0),
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS shift_assignments (
+            id SERIAL PRIMARY KEY,
+            user_id VARCHAR(255) NOT NULL REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+            date DATE NOT NULL,
+            template_id INTEGER REFERENCES shift_templates(id),
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            CONSTRAINT unique_shift_assignment UNIQUE(user_id, date)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS shift_rotations (
+            id SERIAL PRIMARY KEY,
+            user_id VARCHAR(255) NOT NULL REFERENCES user_info(user_id) ON DELETE CASCADE ON UPDATE CASCADE,
+            starts_on DATE NOT NULL,
+            ends_on DATE CHECK (ends_on >= starts_on),
+            template_ids INTEGER[] NOT NULL CHECK (cardinality(template_ids) > 0),
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    
+    // Create punch_anomalies table for the admin review queue
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS punch_anomalies (
+            id SERIAL PRIMARY KEY,
+            user_id VARCHAR(255) NOT NULL,
+            anomaly_type VARCHAR(40) NOT NULL CHECK (anomaly_type IN
+                ('duplicate_in', 'orphan_out', 'future_timestamp', 'overlong_session', 'short_session', 'clock_skew')),
+            checkin_id INTEGER NOT NULL REFERENCES checkins(id) ON DELETE CASCADE,
+            related_checkin_id INTEGER REFERENCES checkins(id) ON DELETE SET NULL,
+            occurred_at TIMESTAMP WITH TIME ZONE NOT NULL,
+            details TEXT,
+            status VARCHAR(20) NOT NULL DEFAULT 'open' CHECK (status IN ('open', 'resolved', 'dismissed')),
+            review_note TEXT,
+            reviewed_by VARCHAR(255),
+            reviewed_at TIMESTAMP WITH TIME ZONE,
+            created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+            CONSTRAINT unique_anomaly_per_punch UNIQUE(anomaly_type, checkin_id)
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+    
+    // Allow anomaly types added after the table was first created
+    sqlx::query(
+        r#"
+        ALTER TABLE punch_anomalies
+            DROP CONSTRAINT IF EXISTS punch_anomalies_anomaly_type_check,
+            ADD CONSTRAINT punch_anomalies_anomaly_type_check CHECK (anomaly_type IN
+                ('duplicate_in', 'orphan_out', 'future_timestamp', 'overlong_session', 'short_session', 'clock_skew'))
+        "#
+    )
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
+async fn create_checkin_change_tracking(pool: &PgPool) -> Result<(), sqlx::Error> {
+    // Monotonic sequence shared by checkin changes and tombstones
+    sqlx::query("CREATE SEQUENCE IF NOT EXISTS checkins_sync_seq")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        ALTER TABLE checkins
+            ADD COLUMN IF NOT EXISTS sync_seq BIGINT,
+            ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Backfill rows created before change tracking existed
+    sqlx::query("UPDATE checkins SET sync_seq = nextval('checkins_sync_seq') WHERE sync_seq IS NULL")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        ALTER TABLE checkins
+            ALTER COLUMN sync_seq SET DEFAULT nextval('checkins_sync_seq'),
+            ALTER COLUMN sync_seq SET NOT NULL
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Create checkin_tombstones table for deleted or reassigned checkins
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS checkin_tombstones (
+            id SERIAL PRIMARY KEY,
+            checkin_id INTEGER NOT NULL,
+            user_id VARCHAR(255) NOT NULL,
+            client_id UUID,
+            sync_seq BIGINT NOT NULL DEFAULT nextval('checkins_sync_seq'),
+            deleted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+        )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Replaced by taking sequence values under the user's lock
+    sqlx::query("ALTER TABLE checkins DROP COLUMN IF EXISTS sync_xid")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE checkin_tombstones DROP COLUMN IF EXISTS sync_xid")
+        .execute(pool)
+        .await?;
+
+    // Sequence values become visible on commit, not when taken. Taking them under the
+    // user's transaction lock makes a user's changes commit in sequence order, so a device
+    // that has seen a change never misses a lower one committed later.
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION take_checkin_sync_seq()
+        RETURNS TRIGGER AS $$
+        BEGIN
+            PERFORM pg_advisory_xact_lock(hashtext(NEW.user_id));
+            NEW.sync_seq := nextval('checkins_sync_seq');
+            RETURN NEW;
+        END;
+        $$ LANGUAGE plpgsql
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("DROP TRIGGER IF EXISTS take_sync_seq_trigger ON checkins")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TRIGGER take_sync_seq_trigger
+            BEFORE INSERT ON checkins
+            FOR EACH ROW
+            EXECUTE FUNCTION take_checkin_sync_seq()
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("DROP TRIGGER IF EXISTS take_sync_seq_trigger ON checkin_tombstones")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TRIGGER take_sync_seq_trigger
+            BEFORE INSERT ON checkin_tombstones
+            FOR EACH ROW
+            EXECUTE FUNCTION take_checkin_sync_seq()
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Bump the sequence whenever a checkin is edited
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION bump_checkin_sync_seq()
+        RETURNS TRIGGER AS $$
+        BEGIN
+            PERFORM pg_advisory_xact_lock(hashtext(NEW.user_id));
+            NEW.sync_seq := nextval('checkins_sync_seq');
+            NEW.updated_at := NOW();
+            RETURN NEW;
+        END;
+        $$ LANGUAGE plpgsql
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("DROP TRIGGER IF EXISTS bump_checkin_sync_seq_trigger ON checkins")
+        .execute(pool)
+        .await
+        .ok();
+
+    sqlx::query(
+        r#"
+        CREATE TRIGGER bump_checkin_sync_seq_trigger
+            BEFORE UPDATE ON checkins
+            FOR EACH ROW
+            WHEN (OLD.* IS DISTINCT FROM NEW.*)
+            EXECUTE FUNCTION bump_checkin_sync_seq()
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    // Leave a tombstone for the owning user when a checkin is deleted or moved to another user
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION record_checkin_tombstone()
+        RETURNS TRIGGER AS $$
+        BEGIN
+            IF TG_OP = 'DELETE' OR OLD.user_id IS DISTINCT FROM NEW.user_id THEN
+                INSERT INTO checkin_tombstones (checkin_id, user_id, client_id)
+                VALUES (OLD.id, OLD.user_id, OLD.client_id);
+            END IF;
+            RETURN NULL;
+        END;
+        $$ LANGUAGE plpgsql
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("DROP TRIGGER IF EXISTS record_checkin_tombstone_trigger ON checkins")
+        .execute(pool)
+        .await
+        .ok();
+
+    sqlx::query(
+        r#"
+        CREATE TRIGGER record_checkin_tombstone_trigger
+            AFTER UPDATE OR DELETE ON checkins
+            FOR EACH ROW
+            EXECUTE FUNCTION record_checkin_tombstone()
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn create_indexes(pool: &PgPool) -> Result<(), sqlx::Error> {
+    // Indexes for checkins table
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_checkins_user_id ON checkins(user_id)")
         .execute(pool)
         .await?;
     
@@ -519,6 +1387,70 @@ async fn create_indexes(pool: &PgPool) -> Result<(), sqlx::Error> {
         .execute(pool)
         .await?;
     
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_checkins_within_geofence ON checkins(within_geofence)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_checkins_user_client_id ON checkins(user_id, client_id)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_checkins_user_sync_seq ON checkins(user_id, sync_seq)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_checkin_tombstones_user_sync_seq ON checkin_tombstones(user_id, sync_seq)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_admin_sessions_user ON admin_sessions(admin_user_id)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_employee_refresh_tokens_user ON employee_refresh_tokens(user_id)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_admin_recovery_codes_user ON admin_recovery_codes(admin_user_id)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_admin_login_challenges_expires ON admin_login_challenges(expires_at)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_checkins_held ON checkins(user_id, device_id) WHERE held")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_shift_rotations_user ON shift_rotations(user_id, starts_on)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_login_attempts_locked ON login_attempts(locked_until)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_log_occurred_at ON audit_log(occurred_at)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_log_entity ON audit_log(entity, target)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_log_actor ON audit_log(actor, occurred_at)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_punch_anomalies_status ON punch_anomalies(status, occurred_at)")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_punch_anomalies_user ON punch_anomalies(user_id, occurred_at)")
+        .execute(pool)
+        .await?;
+    
     // Indexes for attendance_sessions table
     sqlx::query("CREATE INDEX IF NOT EXISTS idx_attendance_sessions_user_date ON attendance_sessions(user_id, date)")
         .execute(pool)
@@ -532,6 +1464,10 @@ async fn create_indexes(pool: &PgPool) -> Result<(), sqlx::Error> {
         .execute(pool)
         .await?;
     
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_attendance_sessions_open ON attendance_sessions(checkin_time) WHERE checkout_time IS NULL")
+        .execute(pool)
+        .await?;
+    
     // Indexes for attendance_summary table
     sqlx::query("CREATE INDEX IF NOT EXISTS idx_attendance_summary_user_id ON attendance_summary(user_id)")
         .execute(pool)
@@ -546,7 +1482,25 @@ async fn create_indexes(pool: &PgPool) -> Result<(), sqlx::Error> {
         .await?;
     
     // Index for user_info table
-    sqlx::query("CREATE INDEX IF NOT EXISTS idx_user_info_passkey ON user_info(passkey)")
+    sqlx::query("DROP INDEX IF EXISTS idx_user_info_passkey")
+        .execute(pool)
+        .await?;
+    
+    // A passkey identifies the employee, so no two may share one
+    let shared = sqlx::query_scalar::<_, String>(
+        "SELECT string_agg(user_id, ', ') FROM user_info WHERE passkey_hash IS NOT NULL GROUP BY passkey_hash HAVING COUNT(*) > 1"
+    )
+    .fetch_all(pool)
+    .await?;
+    for users in &shared {
+        log::error!("Users {} share a passkey; rotate it for all but one of them", users);
+    }
+
+    sqlx::query("DROP INDEX IF EXISTS idx_user_info_passkey_hash")
+        .execute(pool)
+        .await?;
+    
+    sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_user_info_passkey_hash_unique ON user_info(passkey_hash)")
         .execute(pool)
         .await?;
     
@@ -606,37 +1560,61 @@ async fn insert_sample_data(pool: &PgPool) -> Result<(), sqlx::Error> {
     if admin_count.0 == 0 {
         log::info!("No admin users found, inserting sample admin users...");
         
-        // Insert sample admin users
+        // Sample admin users, hashed the same way as accounts created through the API
+        let sample_admins: [(&str, &str, &str, Option<i32>); 24] = [
+            ("admin", "admin123", "admin", None),
+            ("Office", "Office123", "department", Some(1)),
+            ("Mining", "Mining123", "department", Some(2)),
+            ("CA", "CA123", "department", Some(3)),
+            ("HR", "HR123", "department", Some(4)),
+            ("Warehouse", "Warehouse123", "department", Some(5)),
+            ("Lab", "Lab123", "department", Some(6)),
+            ("Logistics", "Logistics123", "department", Some(7)),
+            ("Training", "Training123", "department", Some(8)),
+            ("Technic", "Technic123", "department", Some(9)),
+            ("Hydro", "Hydro123", "department", Some(10)),
+            ("Washing", "Washing123", "department", Some(11)),
+            ("Instrument", "Instrument123", "department", Some(12)),
+            ("Mobile", "Mobile123", "department", Some(13)),
+            ("Dispatch", "Dispatch123", "department", Some(14)),
+            ("Beneficiation", "Beneficiation123", "department", Some(15)),
+            ("Enterprise", "Enterprise123", "department", Some(16)),
+            ("Fixed", "Fixed123", "department", Some(17)),
+            ("HSE", "HSE123", "department", Some(18)),
+            ("OfficeCamp", "OfficeCamp123", "department", Some(18)),
+            ("Equipment", "Equipment123", "department", Some(20)),
+            ("Finance", "Finance123", "department", Some(21)),
+            ("Medic", "Medic123", "department", Some(22)),
+            ("Standby", "Standby123", "department", Some(99)),
+        ];
+
+        let mut usernames = Vec::with_capacity(sample_admins.len());
+        let mut hashes = Vec::with_capacity(sample_admins.len());
+        let mut roles = Vec::with_capacity(sample_admins.len());
+        let mut departments = Vec::with_capacity(sample_admins.len());
+        for (username, password, role, department) in sample_admins {
+            let hash = crate::password::hash_password(password)
+                .map_err(|e| sqlx::Error::Protocol(format!("Failed to hash sample admin password: {}", e)))?;
+            usernames.push(username);
+            hashes.push(hash);
+            roles.push(role);
+            departments.push(department);
+        }
+
         sqlx::query(
             r#"
-            INSERT INTO admin_user (username, password, role, department) VALUES
-            ('admin', 'admin123', 'admin', NULL),
-            ('Office', 'Office123', 'department', 1),
-            ('Mining', 'Mining123', 'department', 2),
-            ('CA', 'CA123', 'department', 3),
-            ('HR', 'HR123', 'department', 4),
-            ('Warehouse', 'Warehouse123', 'department', 5),
-            ('Lab', 'Lab123', 'department', 6),
-            ('Logistics', 'Logistics123', 'department', 7),
-            ('Training', 'Training123', 'department', 8),
-            ('Technic', 'Technic123', 'department', 9),
-            ('Hydro', 'Hydro123', 'department', 10),
-            ('Washing', 'Washing123', 'department', 11),
-            ('Instrument', 'Instrument123', 'department', 12),
-            ('Mobile', 'Mobile123', 'department', 13),
-            ('Dispatch', 'Dispatch123', 'department', 14),
-            ('Beneficiation', 'Beneficiation123', 'department', 15),
-            ('Enterprise', 'Enterprise123', 'department', 16),
-            ('Fixed', 'Fixed123', 'department', 17),
-            ('HSE', 'HSE123', 'department', 18),
-            ('OfficeCamp', 'OfficeCamp123', 'department', 18),
-            ('Equipment', 'Equipment123', 'department', 20),
-            ('Finance', 'Finance123', 'department', 21),
-            ('Medic', 'Medic123', 'department', 22),
-            ('Standby', 'Standby123', 'department', 99)
+            INSERT INTO admin_user (username, password, role, department, departments)
+            SELECT username, password, role, department,
+                   CASE WHEN department IS NULL THEN '{}'::int[] ELSE ARRAY[department] END
+            FROM UNNEST($1::varchar[], $2::varchar[], $3::varchar[], $4::int[])
+                AS t(username, password, role, department)
             ON CONFLICT (username) DO NOTHING
             "#
         )
+        .bind(&usernames)
+        .bind(&hashes)
+        .bind(&roles)
+        .bind(&departments)
         .execute(pool)
         .await?;
 
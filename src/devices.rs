@@ -0,0 +1,236 @@
+// Device binding for employee accounts
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor, PgPool};
+
+/// Longest device identifier accepted from the app
+pub const MAX_DEVICE_ID_LENGTH: usize = 255;
+
+/// What happens to punches from a device the employee has no approval for, set per department
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DevicePolicy {
+    /// Devices are not checked
+    Off,
+    /// Punches are stored but do not count until an admin approves the device
+    Hold,
+    /// Punches are refused
+    Reject,
+}
+
+impl DevicePolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(DevicePolicy::Off),
+            "hold" => Some(DevicePolicy::Hold),
+            "reject" => Some(DevicePolicy::Reject),
+            _ => None,
+        }
+    }
+}
+
+/// Approval state of a registered device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceStatus {
+    Approved,
+    Pending,
+    Revoked,
+}
+
+impl DeviceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceStatus::Approved => "approved",
+            DeviceStatus::Pending => "pending",
+            DeviceStatus::Revoked => "revoked",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "approved" => Some(DeviceStatus::Approved),
+            "pending" => Some(DeviceStatus::Pending),
+            "revoked" => Some(DeviceStatus::Revoked),
+            _ => None,
+        }
+    }
+}
+
+/// How a batch of punches is stored given the sending device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PunchDisposition {
+    Accept,
+    Hold,
+    Reject,
+}
+
+/// Decide what to do with punches from a device. `status` is None for a device that
+/// was never registered, including apps that send no device identifier at all.
+pub fn disposition(policy: DevicePolicy, status: Option<DeviceStatus>) -> PunchDisposition {
+    match (policy, status) {
+        (DevicePolicy::Off, _) | (_, Some(DeviceStatus::Approved)) => PunchDisposition::Accept,
+        (DevicePolicy::Hold, _) => PunchDisposition::Hold,
+        (DevicePolicy::Reject, _) => PunchDisposition::Reject,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct UserDevice {
+    pub id: i32,
+    pub user_id: String,
+    pub device_id: String,
+    pub status: String,
+    pub registered_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+}
+
+/// Record a device the employee signed in from. The first device of an account is
+/// approved right away; later ones wait for an admin.
+pub async fn register<'e, E: PgExecutor<'e>>(
+    executor: E,
+    user_id: &str,
+    device_id: &str,
+) -> Result<DeviceStatus, sqlx::Error> {
+    let status = sqlx::query_scalar::<_, String>(
+        r#"
+        INSERT INTO user_devices (user_id, device_id, status)
+        VALUES ($1, $2, CASE
+            WHEN EXISTS (SELECT 1 FROM user_devices WHERE user_id = $1) THEN 'pending'
+            ELSE 'approved'
+        END)
+        ON CONFLICT (user_id, device_id) DO UPDATE SET last_seen_at = NOW()
+        RETURNING status
+        "#
+    )
+    .bind(user_id)
+    .bind(device_id)
+    .fetch_one(executor)
+    .await?;
+
+    Ok(DeviceStatus::parse(&status).unwrap_or(DeviceStatus::Pending))
+}
+
+/// Status of a device for an employee, None if it was never registered
+pub async fn device_status<'e, E: PgExecutor<'e>>(
+    executor: E,
+    user_id: &str,
+    device_id: &str,
+) -> Result<Option<DeviceStatus>, sqlx::Error> {
+    let status = sqlx::query_scalar::<_, String>(
+        "SELECT status FROM user_devices WHERE user_id = $1 AND device_id = $2"
+    )
+    .bind(user_id)
+    .bind(device_id)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(status.and_then(|s| DeviceStatus::parse(&s)))
+}
+
+/// Load the device policy of a department, defaulting to no device checks
+pub async fn load_department_policy(pool: &PgPool, department: i32) -> Result<DevicePolicy, sqlx::Error> {
+    let policy = sqlx::query_scalar::<_, String>(
+        "SELECT device_policy FROM department_settings WHERE department = $1"
+    )
+    .bind(department)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(policy
+        .and_then(|p| DevicePolicy::parse(&p))
+        .unwrap_or(DevicePolicy::Off))
+}
+
+/// Devices registered to an employee, oldest first
+pub async fn user_devices(pool: &PgPool, user_id: &str) -> Result<Vec<UserDevice>, sqlx::Error> {
+    sqlx::query_as::<_, UserDevice>(
+        "SELECT * FROM user_devices WHERE user_id = $1 ORDER BY registered_at, id"
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Approve or revoke one of an employee's devices, returning it if it exists
+pub async fn review<'e, E: PgExecutor<'e>>(
+    executor: E,
+    user_id: &str,
+    id: i32,
+    status: DeviceStatus,
+    reviewer: &str,
+) -> Result<Option<UserDevice>, sqlx::Error> {
+    sqlx::query_as::<_, UserDevice>(
+        r#"
+        UPDATE user_devices
+        SET status = $3, reviewed_by = $4, reviewed_at = NOW()
+        WHERE user_id = $1 AND id = $2
+        RETURNING *
+        "#
+    )
+    .bind(user_id)
+    .bind(id)
+    .bind(status.as_str())
+    .bind(reviewer)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Let the punches held for a device count, returning their times
+pub async fn release_held_punches<'e, E: PgExecutor<'e>>(
+    executor: E,
+    user_id: &str,
+    device_id: &str,
+) -> Result<Vec<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar::<_, DateTime<Utc>>(
+        r#"
+        UPDATE checkins SET held = false
+        WHERE user_id = $1 AND device_id = $2 AND held
+        RETURNING created_at
+        "#
+    )
+    .bind(user_id)
+    .bind(device_id)
+    .fetch_all(executor)
+    .await
+}
+
+/// Sign a device out by dropping its refresh tokens; its access tokens run out on their own
+pub async fn delete_device_tokens<'e, E: PgExecutor<'e>>(
+    executor: E,
+    user_id: &str,
+    device_id: &str,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM employee_refresh_tokens WHERE user_id = $1 AND device_id = $2")
+        .bind(user_id)
+        .bind(device_id)
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_off_accepts_everything() {
+        assert_eq!(disposition(DevicePolicy::Off, None), PunchDisposition::Accept);
+        assert_eq!(disposition(DevicePolicy::Off, Some(DeviceStatus::Revoked)), PunchDisposition::Accept);
+    }
+
+    #[test]
+    fn test_approved_device_is_accepted() {
+        assert_eq!(disposition(DevicePolicy::Hold, Some(DeviceStatus::Approved)), PunchDisposition::Accept);
+        assert_eq!(disposition(DevicePolicy::Reject, Some(DeviceStatus::Approved)), PunchDisposition::Accept);
+    }
+
+    #[test]
+    fn test_unapproved_devices_follow_policy() {
+        for status in [None, Some(DeviceStatus::Pending), Some(DeviceStatus::Revoked)] {
+            assert_eq!(disposition(DevicePolicy::Hold, status), PunchDisposition::Hold);
+            assert_eq!(disposition(DevicePolicy::Reject, status), PunchDisposition::Reject);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+// Geofence validation for synced check-ins
+use sqlx::{FromRow, PgPool};
+
+/// Mean Earth radius in meters, used by the haversine formula
+const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+
+/// How out-of-range punches are handled for a department
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeofencePolicy {
+    /// Store the punch but mark it as outside the geofence
+    Flag,
+    /// Refuse to store the punch
+    Reject,
+}
+
+impl GeofencePolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "flag" => Some(GeofencePolicy::Flag),
+            "reject" => Some(GeofencePolicy::Reject),
+            _ => None,
+        }
+    }
+}
+
+/// A checkin or checkout point reduced to what the distance check needs
+#[derive(Debug, Clone, FromRow)]
+pub struct GeofencePoint {
+    pub id: i32,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius: f64,
+}
+
+/// Outcome of checking one punch against the user's allowed points
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeofenceResult {
+    /// None when the department has no points for the action, so nothing was checked
+    pub within_geofence: Option<bool>,
+    /// Distance to the matched point, or to the nearest point when outside
+    pub distance_meters: Option<f64>,
+    /// Point whose radius contains the punch, if any
+    pub matched_point_id: Option<i32>,
+}
+
+/// What happens to a punch after the geofence check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeofenceOutcome {
+    Accept,
+    /// Stored, but marked as outside the geofence
+    Flag,
+    Reject,
+}
+
+impl GeofenceResult {
+    /// Punches outside every point are flagged, or refused under `Reject`. Punches without a
+    /// location are only flagged: the phone may have had no fix, and refusing would lose them.
+    pub fn outcome(&self, policy: GeofencePolicy, has_location: bool) -> GeofenceOutcome {
+        match self.within_geofence {
+            None | Some(true) => GeofenceOutcome::Accept,
+            Some(false) if has_location && policy == GeofencePolicy::Reject => GeofenceOutcome::Reject,
+            Some(false) => GeofenceOutcome::Flag,
+        }
+    }
+}
+
+/// Great-circle distance between two coordinates in meters
+pub fn haversine_distance_meters(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_METERS * a.sqrt().asin()
+}
+
+/// Check a punch location against a set of points. Without any point there is no
+/// geofence to check; punches without coordinates are treated as outside.
+pub fn evaluate(latitude: Option<f64>, longitude: Option<f64>, points: &[GeofencePoint]) -> GeofenceResult {
+    if points.is_empty() {
+        return GeofenceResult {
+            within_geofence: None,
+            distance_meters: None,
+            matched_point_id: None,
+        };
+    }
+
+    let (lat, lon) = match (latitude, longitude) {
+        (Some(lat), Some(lon)) => (lat, lon),
+        _ => {
+            return GeofenceResult {
+                within_geofence: Some(false),
+                distance_meters: None,
+                matched_point_id: None,
+            }
+        }
+    };
+
+    let mut nearest: Option<(f64, &GeofencePoint)> = None;
+    let mut matched: Option<(f64, &GeofencePoint)> = None;
+
+    for point in points {
+        let distance = haversine_distance_meters(lat, lon, point.latitude, point.longitude);
+
+        if nearest.is_none_or(|(d, _)| distance < d) {
+            nearest = Some((distance, point));
+        }
+        if distance <= point.radius && matched.is_none_or(|(d, _)| distance < d) {
+            matched = Some((distance, point));
+        }
+    }
+
+    match (matched, nearest) {
+        (Some((distance, point)), _) => GeofenceResult {
+            within_geofence: Some(true),
+            distance_meters: Some(distance),
+            matched_point_id: Some(point.id),
+        },
+        (None, nearest) => GeofenceResult {
+            within_geofence: Some(false),
+            distance_meters: nearest.map(|(distance, _)| distance),
+            matched_point_id: None,
+        },
+    }
+}
+
+/// Load the checkin and checkout points a department may punch at
+pub async fn load_department_points(
+    pool: &PgPool,
+    department: i32,
+) -> Result<(Vec<GeofencePoint>, Vec<GeofencePoint>), sqlx::Error> {
+    let checkin_points = sqlx::query_as::<_, GeofencePoint>(
+        "SELECT id, latitude, longitude, radius FROM checkin_points 
+         WHERE $1 = ANY(allowed_department) OR 0 = ANY(allowed_department)"
+    )
+    .bind(department)
+    .fetch_all(pool)
+    .await?;
+
+    let checkout_points = sqlx::query_as::<_, GeofencePoint>(
+        "SELECT id, latitude, longitude, radius FROM checkout_points 
+         WHERE $1 = ANY(allowed_department) OR 0 = ANY(allowed_department)"
+    )
+    .bind(department)
+    .fetch_all(pool)
+    .await?;
+
+    Ok((checkin_points, checkout_points))
+}
+
+/// Load the geofence policy of a department, defaulting to flagging
+pub async fn load_department_policy(pool: &PgPool, department: i32) -> Result<GeofencePolicy, sqlx::Error> {
+    let policy = sqlx::query_scalar::<_, String>(
+        "SELECT geofence_policy FROM department_settings WHERE department = $1"
+    )
+    .bind(department)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(policy
+        .and_then(|p| GeofencePolicy::parse(&p))
+        .unwrap_or(GeofencePolicy::Flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(id: i32, latitude: f64, longitude: f64, radius: f64) -> GeofencePoint {
+        GeofencePoint { id, latitude, longitude, radius }
+    }
+
+    #[test]
+    fn test_haversine_distance() {
+        // One degree of latitude is roughly 111.2 km
+        let distance = haversine_distance_meters(39.0, 116.0, 40.0, 116.0);
+        assert!((distance - 111_195.0).abs() < 100.0);
+        assert_eq!(haversine_distance_meters(39.9042, 116.4074, 39.9042, 116.4074), 0.0);
+    }
+
+    #[test]
+    fn test_inside_picks_closest_matching_point() {
+        let points = vec![
+            point(1, 39.9042, 116.4074, 500.0),
+            point(2, 39.9043, 116.4074, 100.0),
+        ];
+        let result = evaluate(Some(39.9043), Some(116.4074), &points);
+        assert_eq!(result.within_geofence, Some(true));
+        assert_eq!(result.matched_point_id, Some(2));
+    }
+
+    #[test]
+    fn test_outside_reports_nearest_distance() {
+        let points = vec![point(1, 39.9042, 116.4074, 100.0)];
+        let result = evaluate(Some(39.9142), Some(116.4074), &points);
+        assert_eq!(result.within_geofence, Some(false));
+        assert_eq!(result.matched_point_id, None);
+        assert!(result.distance_meters.unwrap() > 1000.0);
+    }
+
+    #[test]
+    fn test_missing_location_is_flagged_not_rejected() {
+        let points = vec![point(1, 39.9042, 116.4074, 100.0)];
+        let result = evaluate(None, Some(116.4074), &points);
+        assert_eq!(result.within_geofence, Some(false));
+        assert_eq!(result.distance_meters, None);
+        assert_eq!(result.outcome(GeofencePolicy::Reject, false), GeofenceOutcome::Flag);
+        assert_eq!(result.outcome(GeofencePolicy::Flag, false), GeofenceOutcome::Flag);
+    }
+
+    #[test]
+    fn test_department_without_points_is_not_checked() {
+        let result = evaluate(Some(39.9042), Some(116.4074), &[]);
+        assert_eq!(result.within_geofence, None);
+        assert_eq!(result.outcome(GeofencePolicy::Reject, true), GeofenceOutcome::Accept);
+        assert_eq!(evaluate(None, None, &[]).outcome(GeofencePolicy::Reject, false), GeofenceOutcome::Accept);
+    }
+
+    #[test]
+    fn test_outcome_under_policies() {
+        let points = vec![point(1, 39.9042, 116.4074, 100.0)];
+        let outside = evaluate(Some(39.9142), Some(116.4074), &points);
+        assert_eq!(outside.outcome(GeofencePolicy::Reject, true), GeofenceOutcome::Reject);
+        assert_eq!(outside.outcome(GeofencePolicy::Flag, true), GeofenceOutcome::Flag);
+        let inside = evaluate(Some(39.9042), Some(116.4074), &points);
+        assert_eq!(inside.outcome(GeofencePolicy::Reject, true), GeofenceOutcome::Accept);
+    }
+}
@@ -1,28 +1,180 @@
-use actix_web::{web, HttpResponse};
-use chrono::NaiveDate;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{NaiveDate, Utc};
 use sqlx::PgPool;
+use uuid::Uuid;
 
-use crate::auth::{verify_passkey, verify_user_passkey};
+use crate::auth::{self, verify_passkey, EmployeeAuth};
+use crate::changes;
+use crate::devices;
+use crate::ingest;
+use crate::lockout::{self, LockScope};
 use crate::models::*;
+use crate::schedule;
+use crate::sessions::SessionConfig;
 
+/// Exchange a passkey for an access token and a refresh token. Failed attempts are
+/// throttled per IP and per employee.
 pub async fn verify_auth(
     pool: web::Data<PgPool>,
+    http_req: HttpRequest,
     req: web::Json<AuthRequest>,
 ) -> HttpResponse {
-    match verify_passkey(&pool, &req.passkey).await {
-        Ok(Some(user)) => {
-            let response = UserInfoResponse {
-                user_id: user.user_id,
-                user_name: user.user_name,
-                department: user.department,
-                department_name: user.department_name,
+    let ip = lockout::client_ip(&http_req);
+    let subjects = [(LockScope::Ip, ip.as_str()), (LockScope::Employee, req.user_id.as_str())];
+
+    match lockout::locked_until(&pool, &subjects).await {
+        Ok(Some(until)) => return lockout::too_many_attempts(until),
+        Ok(None) => {}
+        Err(e) => {
+            log::error!("Failed to check login lockout: {:?}", e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    }
+
+    // The passkey must belong to the user it was sent for
+    let user = match verify_passkey(&pool, &req.passkey).await {
+        Ok(Some(user)) if user.user_id == req.user_id => user,
+        Ok(_) => {
+            if let Err(e) = lockout::record_failure(&pool, &subjects).await {
+                log::error!("Failed to record failed login: {:?}", e);
+            }
+            return HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid passkey"));
+        }
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    };
+
+    if let Err(e) = lockout::clear(pool.as_ref(), LockScope::Employee, &user.user_id).await {
+        log::error!("Failed to clear failed logins of {}: {:?}", user.user_id, e);
+    }
+
+    if user.passkey_expired(Utc::now()) {
+        return HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Passkey expired"));
+    }
+
+    let device_id = req.device_id.as_deref().map(str::trim).filter(|device| !device.is_empty());
+    if device_id.is_some_and(|device| device.len() > devices::MAX_DEVICE_ID_LENGTH) {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error("device_id is too long"));
+    }
+
+    // The first device an employee signs in on is approved; later ones wait for an admin
+    let device_status = match device_id {
+        Some(device) => match devices::register(pool.as_ref(), &user.user_id, device).await {
+            Ok(status) => Some(status),
+            Err(e) => {
+                log::error!("Failed to register device of user {}: {:?}", user.user_id, e);
+                return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+            }
+        },
+        None => None,
+    };
+
+    match auth::issue_tokens(pool.as_ref(), &user, device_id, Utc::now()).await {
+        Ok(tokens) => {
+            let response = AuthTokenResponse {
+                user: UserInfoResponse {
+                    user_id: user.user_id,
+                    user_name: user.user_name,
+                    department: user.department,
+                    department_name: user.department_name,
+                },
+                tokens,
+                device_status: device_status.map(|status| status.as_str()),
             };
             HttpResponse::Ok().json(ApiResponse::success(response, "Authentication successful"))
         }
+        Err(e) => {
+            log::error!("Failed to issue tokens for user {}: {:?}", user.user_id, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"))
+        }
+    }
+}
+
+/// Trade a refresh token for a new token pair; the old refresh token stops working
+pub async fn refresh_auth(
+    pool: web::Data<PgPool>,
+    req: web::Json<RefreshTokenRequest>,
+) -> HttpResponse {
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    };
+
+    let (user_id, device_id) = match auth::consume_refresh_token(&mut *tx, &req.refresh_token).await {
+        Ok(Some(token)) => token,
         Ok(None) => {
-            HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid passkey"))
+            let _ = tx.rollback().await;
+            return HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or expired refresh token"));
+        }
+        Err(e) => {
+            log::error!("Failed to consume refresh token: {:?}", e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    };
+
+    let user = match sqlx::query_as::<_, UserInfo>("SELECT * FROM user_info WHERE user_id = $1")
+        .bind(&user_id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(user)) if !user.passkey_expired(Utc::now()) => user,
+        Ok(_) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid or expired refresh token"));
         }
         Err(_) => {
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    };
+
+    let device_status = match &device_id {
+        Some(device) => match devices::device_status(&mut *tx, &user.user_id, device).await {
+            Ok(status) => status,
+            Err(_) => {
+                let _ = tx.rollback().await;
+                return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+            }
+        },
+        None => None,
+    };
+
+    let tokens = match auth::issue_tokens(&mut *tx, &user, device_id.as_deref(), Utc::now()).await {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            log::error!("Failed to issue tokens for user {}: {:?}", user.user_id, e);
+            let _ = tx.rollback().await;
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+        }
+    };
+
+    if let Err(e) = tx.commit().await {
+        log::error!("Failed to commit token refresh: {:?}", e);
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"));
+    }
+
+    let response = AuthTokenResponse {
+        user: UserInfoResponse {
+            user_id: user.user_id,
+            user_name: user.user_name,
+            department: user.department,
+            department_name: user.department_name,
+        },
+        tokens,
+        device_status: device_status.map(|status| status.as_str()),
+    };
+    HttpResponse::Ok().json(ApiResponse::success(response, "Token refreshed"))
+}
+
+/// Revoke a refresh token when the employee signs out of the app
+pub async fn logout_auth(
+    pool: web::Data<PgPool>,
+    req: web::Json<RefreshTokenRequest>,
+) -> HttpResponse {
+    match auth::consume_refresh_token(pool.as_ref(), &req.refresh_token).await {
+        Ok(_) => HttpResponse::Ok().json(ApiResponse::<()>::success((), "Logged out")),
+        Err(e) => {
+            log::error!("Failed to revoke refresh token: {:?}", e);
             HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error"))
         }
     }
@@ -30,18 +182,12 @@ pub async fn verify_auth(
 
 pub async fn get_checkin_points(
     pool: web::Data<PgPool>,
-    req: web::Json<AuthRequest>,
+    auth: EmployeeAuth,
 ) -> HttpResponse {
-    let user = match verify_passkey(&pool, &req.passkey).await {
-        Ok(Some(user)) => user,
-        Ok(None) => return HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid passkey")),
-        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
-    };
-
     match sqlx::query_as::<_, CheckinPoint>(
         "SELECT * FROM checkin_points WHERE $1 = ANY(allowed_department) OR 0 = ANY(allowed_department)"
     )
-    .bind(user.department)
+    .bind(auth.department)
     .fetch_all(pool.as_ref())
     .await
     {
@@ -52,18 +198,12 @@ pub async fn get_checkin_points(
 
 pub async fn get_checkout_points(
     pool: web::Data<PgPool>,
-    req: web::Json<AuthRequest>,
+    auth: EmployeeAuth,
 ) -> HttpResponse {
-    let user = match verify_passkey(&pool, &req.passkey).await {
-        Ok(Some(user)) => user,
-        Ok(None) => return HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid passkey")),
-        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
-    };
-
     match sqlx::query_as::<_, CheckoutPoint>(
         "SELECT * FROM checkout_points WHERE $1 = ANY(allowed_department) OR 0 = ANY(allowed_department)"
     )
-    .bind(user.department)
+    .bind(auth.department)
     .fetch_all(pool.as_ref())
     .await
     {
@@ -74,235 +214,197 @@ pub async fn get_checkout_points(
 
 pub async fn sync_checkins(
     pool: web::Data<PgPool>,
+    auth: EmployeeAuth,
     req: web::Json<SyncRequest>,
 ) -> HttpResponse {
-    if !verify_user_passkey(&pool, &req.user_id, &req.passkey).await.unwrap_or(false) {
-        return HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid credentials"));
+    // Large offline backlogs go through the resumable chunked upload instead
+    let max_batch_size = ingest::max_batch_size();
+    if req.checkins.len() > max_batch_size {
+        return HttpResponse::PayloadTooLarge().json(ApiResponse::<()>::error(&format!(
+            "Batch of {} punches exceeds the maximum of {}; use /checkin/upload/start to upload in chunks",
+            req.checkins.len(), max_batch_size
+        )));
     }
 
-    let mut tx = match pool.begin().await {
-        Ok(tx) => tx,
-        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Transaction failed")),
-    };
+    match ingest::ingest_punches(
+        &pool, &auth.user_id, auth.device_id.as_deref(), req.device_time, &req.checkins
+    ).await {
+        Ok(response) => HttpResponse::Ok().json(ApiResponse::success(response, "Checkins synced successfully")),
+        Err(e) => {
+            log::error!("Failed to sync checkins for user {}: {:?}", auth.user_id, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to sync checkins"))
+        }
+    }
+}
 
-    // First, insert all checkins to the checkins table
-    for checkin in &req.checkins {
-        let result = sqlx::query(
-            "INSERT INTO checkins (user_id, action, created_at, latitude, longitude, is_synced) 
-             VALUES ($1, $2, $3, $4, $5, 1)"
-        )
-        .bind(&req.user_id)
-        .bind(&checkin.action)
-        .bind(&checkin.created_at)
-        .bind(checkin.latitude)
-        .bind(checkin.longitude)
-        .execute(&mut *tx)
-        .await;
-
-        if result.is_err() {
-            let _ = tx.rollback().await;
-            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to sync checkins"));
+/// Start a chunked upload for a backlog larger than one sync batch
+pub async fn start_upload(
+    pool: web::Data<PgPool>,
+    auth: EmployeeAuth,
+    req: web::Json<StartUploadRequest>,
+) -> HttpResponse {
+    if let Err(message) = ingest::check_total_chunks(req.total_chunks, ingest::max_upload_chunks()) {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error(&message));
+    }
+
+    match sqlx::query_scalar::<_, Uuid>(
+        "INSERT INTO sync_uploads (id, user_id, device_id, total_chunks)
+         VALUES ($1, $2, $3, $4)
+         RETURNING id"
+    )
+    .bind(Uuid::new_v4())
+    .bind(&auth.user_id)
+    .bind(&auth.device_id)
+    .bind(req.total_chunks)
+    .fetch_one(pool.as_ref())
+    .await
+    {
+        Ok(upload_id) => HttpResponse::Ok().json(ApiResponse::success(
+            StartUploadResponse { upload_id, max_batch_size: ingest::max_batch_size() },
+            "Upload started"
+        )),
+        Err(e) => {
+            log::error!("Failed to start upload for user {}: {:?}", auth.user_id, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to start upload"))
         }
     }
+}
 
-    // Group checkins by date and process sessions
-    use std::collections::HashMap;
-    let mut checkins_by_date: HashMap<NaiveDate, Vec<&CheckinData>> = HashMap::new();
-    
-    for checkin in &req.checkins {
-        let date = checkin.created_at.date_naive();
-        checkins_by_date.entry(date).or_insert_with(Vec::new).push(checkin);
+/// Ingest one chunk of a chunked upload. Every chunk is committed on its own, so an
+/// interrupted upload resumes with the chunks that are missing from the upload status.
+pub async fn upload_chunk(
+    pool: web::Data<PgPool>,
+    auth: EmployeeAuth,
+    req: web::Json<UploadChunkRequest>,
+) -> HttpResponse {
+    let max_batch_size = ingest::max_batch_size();
+    if req.checkins.len() > max_batch_size {
+        return HttpResponse::PayloadTooLarge().json(ApiResponse::<()>::error(&format!(
+            "Chunk of {} punches exceeds the maximum of {}", req.checkins.len(), max_batch_size
+        )));
     }
 
-    // Process each day's checkins to create sessions
-    for (date, day_checkins) in checkins_by_date {
-        // Sort checkins by time
-        let mut sorted_checkins = day_checkins.clone();
-        sorted_checkins.sort_by_key(|c| c.created_at);
-
-        // Track open session
-        let mut open_session: Option<&CheckinData> = None;
-        let mut session_number = 
-            match sqlx::query_scalar::<_, i32>(
-                "SELECT COALESCE(MAX(session_number), 0) FROM attendance_sessions 
-                 WHERE user_id = $1 AND date = $2"
-            )
-            .bind(&req.user_id)
-            .bind(date)
-            .fetch_one(&mut *tx)
-            .await {
-                Ok(num) => num,
-                Err(_) => 0,
-            };
+    let upload = match load_upload(&pool, req.upload_id, &auth.user_id).await {
+        Ok(Some(upload)) => upload,
+        Ok(None) => return HttpResponse::NotFound().json(ApiResponse::<()>::error("Upload not found")),
+        Err(_) => return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Database error")),
+    };
 
-        // Check for incomplete session from previous day (midnight crossing fix)
-        let _has_previous_incomplete = sqlx::query_scalar::<_, bool>(
-            "SELECT EXISTS(SELECT 1 FROM attendance_sessions 
-             WHERE user_id = $1 AND date = $2 - INTERVAL '1 day' AND checkout_time IS NULL
-               AND checkin_time > $2 - INTERVAL '1 day' + INTERVAL '16 hours')"
-        )
-        .bind(&req.user_id)
-        .bind(date)
-        .fetch_one(&mut *tx)
-        .await
-        .unwrap_or(false);
-
-        for checkin in sorted_checkins {
-            if checkin.action == "IN" {
-                if open_session.is_none() {
-                    // Start new session
-                    session_number += 1;
-                    let result = sqlx::query(
-                        "INSERT INTO attendance_sessions 
-                         (user_id, date, session_number, checkin_time, checkin_latitude, checkin_longitude) 
-                         VALUES ($1, $2, $3, $4, $5, $6)
-                         ON CONFLICT (user_id, date, session_number) 
-                         DO UPDATE SET 
-                            checkin_time = EXCLUDED.checkin_time,
-                            checkin_latitude = EXCLUDED.checkin_latitude,
-                            checkin_longitude = EXCLUDED.checkin_longitude"
-                    )
-                    .bind(&req.user_id)
-                    .bind(date)
-                    .bind(session_number)
-                    .bind(checkin.created_at)
-                    .bind(checkin.latitude)
-                    .bind(checkin.longitude)
-                    .execute(&mut *tx)
-                    .await;
-
-                    if result.is_err() {
-                        let _ = tx.rollback().await;
-                        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create session"));
-                    }
-                    open_session = Some(checkin);
-                }
-                // If there's already an open session, ignore this IN (or log warning)
-            } else if checkin.action == "OUT" {
-                if open_session.is_some() {
-                    // Close the current session
-                    let result = sqlx::query(
-                        "UPDATE attendance_sessions 
-                         SET checkout_time = $1, checkout_latitude = $2, checkout_longitude = $3
-                         WHERE user_id = $4 AND date = $5 AND session_number = $6"
-                    )
-                    .bind(checkin.created_at)
-                    .bind(checkin.latitude)
-                    .bind(checkin.longitude)
-                    .bind(&req.user_id)
-                    .bind(date)
-                    .bind(session_number)
-                    .execute(&mut *tx)
-                    .await;
-
-                    if result.is_err() {
-                        let _ = tx.rollback().await;
-                        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to close session"));
-                    }
-                    open_session = None;
-                } else {
-                    // OUT without IN - check for incomplete session from previous day first (midnight crossing)
-                    let prev_day_updated = sqlx::query(
-                        "UPDATE attendance_sessions 
-                         SET checkout_time = $1, checkout_latitude = $2, checkout_longitude = $3
-                         WHERE user_id = $4 AND date = $5 - INTERVAL '1 day' AND checkout_time IS NULL
-                           AND checkin_time < $1 AND checkin_time > $1 - INTERVAL '16 hours'
-                         ORDER BY checkin_time DESC LIMIT 1"
-                    )
-                    .bind(checkin.created_at)
-                    .bind(checkin.latitude)
-                    .bind(checkin.longitude)
-                    .bind(&req.user_id)
-                    .bind(date)
-                    .execute(&mut *tx)
-                    .await;
-
-                    // If no previous day session was updated, create checkout-only session
-                    if prev_day_updated.map(|r| r.rows_affected()).unwrap_or(0) == 0 {
-                        session_number += 1;
-                        let result = sqlx::query(
-                            "INSERT INTO attendance_sessions 
-                             (user_id, date, session_number, checkin_time, checkout_time, checkout_latitude, checkout_longitude) 
-                             VALUES ($1, $2, $3, $4, $5, $6, $7)
-                             ON CONFLICT (user_id, date, session_number) DO NOTHING"
-                        )
-                        .bind(&req.user_id)
-                        .bind(date)
-                        .bind(session_number)
-                        .bind(checkin.created_at) // Use checkout time as checkin for incomplete session
-                        .bind(checkin.created_at)
-                        .bind(checkin.latitude)
-                        .bind(checkin.longitude)
-                        .execute(&mut *tx)
-                        .await;
-
-                        if result.is_err() {
-                            let _ = tx.rollback().await;
-                            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to create incomplete session"));
-                        }
-                    }
-                }
-            }
+    if req.chunk_index < 0 || req.chunk_index >= upload.total_chunks {
+        return HttpResponse::BadRequest().json(ApiResponse::<()>::error(&format!(
+            "chunk_index must be between 0 and {}", upload.total_chunks - 1
+        )));
+    }
+
+    // Chunks sent again after a lost response only produce duplicates
+    let sync = match ingest::ingest_punches(
+        &pool, &auth.user_id, auth.device_id.as_deref(), req.device_time, &req.checkins
+    ).await {
+        Ok(sync) => sync,
+        Err(e) => {
+            log::error!("Failed to ingest chunk {} of upload {}: {:?}", req.chunk_index, req.upload_id, e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to sync checkins"));
         }
+    };
+
+    if let Err(e) = sqlx::query(
+        "INSERT INTO sync_upload_chunks (upload_id, chunk_index, punches)
+         VALUES ($1, $2, $3)
+         ON CONFLICT (upload_id, chunk_index) DO NOTHING"
+    )
+    .bind(req.upload_id)
+    .bind(req.chunk_index)
+    .bind(req.checkins.len() as i32)
+    .execute(pool.as_ref())
+    .await
+    {
+        log::error!("Failed to record chunk {} of upload {}: {:?}", req.chunk_index, req.upload_id, e);
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to record chunk"));
     }
 
-    match tx.commit().await {
-        Ok(_) => HttpResponse::Ok().json(ApiResponse::success(
-            req.checkins.len(),
-            "Checkins synced successfully"
+    match upload_status(&pool, req.upload_id, &auth.user_id).await {
+        Ok(Some(upload)) => HttpResponse::Ok().json(ApiResponse::success(
+            UploadChunkResponse { sync, upload },
+            "Chunk synced successfully"
         )),
-        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to commit transaction")),
+        Ok(None) => HttpResponse::NotFound().json(ApiResponse::<()>::error("Upload not found")),
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to load upload status")),
     }
 }
 
-pub async fn check_count(
+/// Report which chunks of an upload have been stored, so a client can resume it
+pub async fn get_upload_status(
     pool: web::Data<PgPool>,
-    req: web::Json<CountRequest>,
+    auth: EmployeeAuth,
+    req: web::Json<UploadStatusRequest>,
 ) -> HttpResponse {
-    if !verify_user_passkey(&pool, &req.user_id, &req.passkey).await.unwrap_or(false) {
-        return HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid credentials"));
+    match upload_status(&pool, req.upload_id, &auth.user_id).await {
+        Ok(Some(upload)) => HttpResponse::Ok().json(ApiResponse::success(upload, "Upload status retrieved")),
+        Ok(None) => HttpResponse::NotFound().json(ApiResponse::<()>::error("Upload not found")),
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to load upload status")),
     }
+}
 
-    let result = sqlx::query_as::<_, (i64,)>(
-        "SELECT COUNT(*) FROM checkins WHERE user_id = $1"
-    )
-    .bind(&req.user_id)
-    .fetch_one(pool.as_ref())
-    .await;
+/// Uploads announcing more chunks than the current limit, such as ones started before
+/// it was lowered, are treated as not found
+async fn load_upload(pool: &PgPool, upload_id: Uuid, user_id: &str) -> Result<Option<SyncUpload>, sqlx::Error> {
+    sqlx::query_as::<_, SyncUpload>("SELECT * FROM sync_uploads WHERE id = $1 AND user_id = $2 AND total_chunks <= $3")
+        .bind(upload_id)
+        .bind(user_id)
+        .bind(ingest::max_upload_chunks())
+        .fetch_optional(pool)
+        .await
+}
 
-    match result {
-        Ok(record) => {
-            let server_count = record.0;
-            let action = if server_count == req.local_count {
-                "none"
-            } else if server_count < req.local_count {
-                "sync"
-            } else {
-                "full_sync"
-            };
+async fn upload_status(pool: &PgPool, upload_id: Uuid, user_id: &str) -> Result<Option<UploadStatusResponse>, sqlx::Error> {
+    let upload = match load_upload(pool, upload_id, user_id).await? {
+        Some(upload) => upload,
+        None => return Ok(None),
+    };
 
-            let response = CountResponse {
-                action: action.to_string(),
-                server_count,
-            };
-            HttpResponse::Ok().json(ApiResponse::success(response, "Count check completed"))
+    let received_chunks = sqlx::query_scalar::<_, i32>(
+        "SELECT chunk_index FROM sync_upload_chunks WHERE upload_id = $1 ORDER BY chunk_index"
+    )
+    .bind(upload_id)
+    .fetch_all(pool)
+    .await?;
+
+    let missing_chunks = ingest::missing_chunks(upload.total_chunks, &received_chunks);
+
+    Ok(Some(UploadStatusResponse {
+        upload_id,
+        total_chunks: upload.total_chunks,
+        complete: missing_chunks.is_empty(),
+        received_chunks,
+        missing_chunks,
+    }))
+}
+
+pub async fn get_checkin_changes(
+    pool: web::Data<PgPool>,
+    auth: EmployeeAuth,
+    req: web::Json<ChangesRequest>,
+) -> HttpResponse {
+    let cursor = req.cursor.unwrap_or(0);
+    let limit = req.limit.unwrap_or(500).clamp(1, 1000);
+
+    match changes::load_page(&pool, &auth.user_id, cursor, limit).await {
+        Ok(response) => HttpResponse::Ok().json(ApiResponse::success(response, "Checkin changes retrieved")),
+        Err(e) => {
+            log::error!("Failed to retrieve checkin changes of {}: {:?}", auth.user_id, e);
+            HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve checkin changes"))
         }
-        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to check count")),
     }
 }
 
 pub async fn full_sync(
     pool: web::Data<PgPool>,
-    req: web::Json<FullSyncRequest>,
+    auth: EmployeeAuth,
 ) -> HttpResponse {
-    if !verify_user_passkey(&pool, &req.user_id, &req.passkey).await.unwrap_or(false) {
-        return HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid credentials"));
-    }
-
     match sqlx::query_as::<_, Checkin>(
         "SELECT * FROM checkins WHERE user_id = $1 ORDER BY created_at ASC"
     )
-    .bind(&req.user_id)
+    .bind(&auth.user_id)
     .fetch_all(pool.as_ref())
     .await
     {
@@ -313,18 +415,28 @@ pub async fn full_sync(
 
 pub async fn get_monthly_stats(
     pool: web::Data<PgPool>,
+    auth: EmployeeAuth,
     req: web::Json<MonthlyStatsRequest>,
 ) -> HttpResponse {
-    if !verify_user_passkey(&pool, &req.user_id, &req.passkey).await.unwrap_or(false) {
-        return HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid credentials"));
-    }
+    let (start_date, end_date) = match schedule::month_range(req.year, req.month) {
+        Some(range) => range,
+        None => {
+            return HttpResponse::BadRequest().json(ApiResponse::<()>::error(&format!(
+                "month must be between 1 and 12 and year between {} and {}",
+                schedule::STATS_YEARS.start(), schedule::STATS_YEARS.end()
+            )));
+        }
+    };
 
-    let start_date = NaiveDate::from_ymd_opt(req.year, req.month, 1).unwrap();
-    let end_date = if req.month == 12 {
-        NaiveDate::from_ymd_opt(req.year + 1, 1, 1).unwrap()
-    } else {
-        NaiveDate::from_ymd_opt(req.year, req.month + 1, 1).unwrap()
+    // Lateness is judged against the shift scheduled each day
+    let roster = match schedule::load_roster(&pool, &auth.user_id, start_date, end_date).await {
+        Ok(roster) => roster,
+        Err(e) => {
+            log::error!("Failed to load roster of {}: {:?}", auth.user_id, e);
+            return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to retrieve stats"));
+        }
     };
+    let utc_offset = SessionConfig::global().utc_offset;
 
     let rows = sqlx::query(
         r#"
@@ -333,24 +445,13 @@ pub async fn get_monthly_stats(
             ats.first_checkin_time as checkin_time,
             ats.last_checkout_time as checkout_time,
             ats.total_work_minutes,
-            ats.total_sessions,
-            CASE 
-                WHEN ats.first_checkin_time IS NOT NULL AND 
-                     ats.first_checkin_time::time > COALESCE(uts.on_duty_time, '07:30:00'::time) THEN true
-                ELSE false
-            END as is_late,
-            CASE 
-                WHEN ats.last_checkout_time IS NOT NULL AND 
-                     ats.last_checkout_time::time < COALESCE(uts.off_duty_time, '17:00:00'::time) THEN true
-                ELSE false
-            END as is_early_leave
+            ats.total_sessions
         FROM attendance_summary ats
-        LEFT JOIN user_time_settings uts ON ats.user_id = uts.user_id
         WHERE ats.user_id = $1 AND ats.date >= $2 AND ats.date < $3
         ORDER BY ats.date ASC
         "#
     )
-    .bind(&req.user_id)
+    .bind(&auth.user_id)
     .bind(start_date)
     .bind(end_date)
     .fetch_all(pool.as_ref())
@@ -361,12 +462,19 @@ pub async fn get_monthly_stats(
             let mut records = Vec::new();
             for row in rows {
                 use sqlx::Row;
+                let date = row.get("date");
+                let checkin_time = row.get("checkin_time");
+                let checkout_time = row.get("checkout_time");
+                let evaluation = schedule::evaluate(&roster.day(date), checkin_time, checkout_time, utc_offset, &roster.rules);
                 records.push(DailyAttendance {
-                    date: row.get("date"),
-                    checkin_time: row.get("checkin_time"),
-                    checkout_time: row.get("checkout_time"),
-                    is_late: row.get("is_late"),
-                    is_early_leave: row.get("is_early_leave"),
+                    date,
+                    checkin_time,
+                    checkout_time,
+                    is_late: evaluation.is_late,
+                    late_minutes: evaluation.late_minutes,
+                    late_tier: evaluation.late_tier,
+                    is_early_leave: evaluation.is_early_leave,
+                    early_leave_minutes: evaluation.early_leave_minutes,
                     total_work_minutes: row.get("total_work_minutes"),
                     total_sessions: row.get("total_sessions"),
                 });
@@ -380,12 +488,32 @@ pub async fn get_monthly_stats(
         Ok(records) => {
             let attendance_days = records.iter().filter(|r| r.checkin_time.is_some()).count() as i32;
             let late_count = records.iter().filter(|r| r.is_late).count() as i32;
+            let major_late_count = records.iter().filter(|r| r.late_tier == Some(schedule::LateTier::Major)).count() as i32;
+            let late_minutes = records.iter().map(|r| r.late_minutes).sum();
             let early_leave_count = records.iter().filter(|r| r.is_early_leave).count() as i32;
 
+            // Absences count only on past days the user was scheduled to work
+            let workdays: Vec<NaiveDate> = roster
+                .days(start_date, end_date.min(schedule::local_today()))
+                .into_iter()
+                .filter(|day| day.shift.is_some())
+                .map(|day| day.date)
+                .collect();
+            let absence_days = workdays
+                .iter()
+                .filter(|date| !records.iter().any(|r| r.date == **date && r.checkin_time.is_some()))
+                .count() as i32;
+
             let response = MonthlyStatsResponse {
                 attendance_days,
+                scheduled_days: workdays.len() as i32,
+                absence_days,
                 late_count,
+                minor_late_count: late_count - major_late_count,
+                major_late_count,
+                late_minutes,
                 early_leave_count,
+                lateness_rules: roster.rules,
                 details: records,
             };
 
@@ -397,19 +525,16 @@ pub async fn get_monthly_stats(
 
 pub async fn get_daily_sessions(
     pool: web::Data<PgPool>,
+    auth: EmployeeAuth,
     req: web::Json<DailySessionsRequest>,
 ) -> HttpResponse {
-    if !verify_user_passkey(&pool, &req.user_id, &req.passkey).await.unwrap_or(false) {
-        return HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Invalid credentials"));
-    }
-
     // Fetch all sessions for the given date
     let sessions = match sqlx::query_as::<_, AttendanceSession>(
         "SELECT * FROM attendance_sessions 
          WHERE user_id = $1 AND date = $2 
          ORDER BY session_number ASC"
     )
-    .bind(&req.user_id)
+    .bind(&auth.user_id)
     .bind(req.date)
     .fetch_all(pool.as_ref())
     .await {
@@ -422,7 +547,7 @@ pub async fn get_daily_sessions(
         "SELECT * FROM attendance_summary 
          WHERE user_id = $1 AND date = $2"
     )
-    .bind(&req.user_id)
+    .bind(&auth.user_id)
     .bind(req.date)
     .fetch_optional(pool.as_ref())
     .await {
@@ -431,7 +556,7 @@ pub async fn get_daily_sessions(
             // Create empty summary if none exists
             AttendanceSummary {
                 id: 0,
-                user_id: req.user_id.clone(),
+                user_id: auth.user_id.clone(),
                 date: req.date,
                 checkin_time: None,
                 checkout_time: None,
@@ -441,6 +566,7 @@ pub async fn get_daily_sessions(
                 total_break_minutes: None,
                 total_sessions: 0,
                 is_complete: false,
+                auto_closed_sessions: 0,
                 updated_at: None,
             }
         },
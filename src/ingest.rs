@@ -0,0 +1,426 @@
+// Set-based ingestion of synced punches
+use chrono::{DateTime, SubsecRound, Utc};
+use lazy_static::lazy_static;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::clock_skew::{self, AdjustedTime};
+use crate::devices::{self, PunchDisposition};
+use crate::geofence::{self, GeofenceOutcome, GeofenceResult};
+use crate::models::{CheckinData, PunchStatus, PunchSyncResult, SyncResponse};
+use crate::sessions::{self, SessionConfig};
+
+/// Default number of punches accepted in one sync request or upload chunk
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 200;
+
+/// Default number of chunks one upload may be split into
+pub const DEFAULT_MAX_UPLOAD_CHUNKS: usize = 500;
+
+lazy_static! {
+    static ref MAX_BATCH_SIZE: usize = limit_from_env("SYNC_MAX_BATCH_SIZE", DEFAULT_MAX_BATCH_SIZE);
+    static ref MAX_UPLOAD_CHUNKS: usize = limit_from_env("SYNC_MAX_UPLOAD_CHUNKS", DEFAULT_MAX_UPLOAD_CHUNKS);
+}
+
+/// Read a positive limit from the environment, falling back to the default
+fn limit_from_env(name: &str, default: usize) -> usize {
+    match std::env::var(name) {
+        Ok(value) => match value.parse::<usize>() {
+            Ok(limit) if limit > 0 => limit,
+            _ => {
+                log::warn!("Ignoring invalid {}: {}", name, value);
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+/// Process-wide batch limit loaded from the environment on first use
+pub fn max_batch_size() -> usize {
+    *MAX_BATCH_SIZE
+}
+
+/// Process-wide chunk limit for uploads, loaded from the environment on first use
+pub fn max_upload_chunks() -> i32 {
+    i32::try_from(*MAX_UPLOAD_CHUNKS).unwrap_or(i32::MAX)
+}
+
+/// Check the number of chunks a client announces for an upload
+pub fn check_total_chunks(total_chunks: i32, max_chunks: i32) -> Result<(), String> {
+    if total_chunks <= 0 || total_chunks > max_chunks {
+        return Err(format!("total_chunks must be between 1 and {}", max_chunks));
+    }
+    Ok(())
+}
+
+/// Chunks of an upload not stored yet; `received` is sorted
+pub fn missing_chunks(total_chunks: i32, received: &[i32]) -> Vec<i32> {
+    (0..total_chunks)
+        .filter(|index| received.binary_search(index).is_err())
+        .collect()
+}
+
+/// What identifies a punch sent without a client_id: its action and the device's time,
+/// at the microsecond precision the database keeps
+fn untagged_key(checkin: &CheckinData) -> (String, DateTime<Utc>) {
+    (checkin.action.clone(), checkin.created_at.trunc_subsecs(6))
+}
+
+/// Which punches go to the insert. Those with a client_id all do, and the unique index
+/// skips replays; one without is left out when a punch with the same action and device
+/// time was stored before or comes earlier in the batch.
+fn punches_to_insert(checkins: &[&CheckinData], stored_untagged: &HashSet<(String, DateTime<Utc>)>) -> Vec<bool> {
+    let mut seen = HashSet::new();
+    checkins
+        .iter()
+        .map(|checkin| match checkin.client_id {
+            Some(_) => true,
+            None => {
+                let key = untagged_key(checkin);
+                !stored_untagged.contains(&key) && seen.insert(key)
+            }
+        })
+        .collect()
+}
+
+/// Which punches the batch stored. One with a client_id was stored when the insert returned
+/// its id and no earlier punch in the batch carried the same id.
+fn stored_punches(checkins: &[&CheckinData], to_insert: &[bool], inserted_client_ids: &HashSet<Uuid>) -> Vec<bool> {
+    let mut seen = HashSet::new();
+    checkins
+        .iter()
+        .zip(to_insert)
+        .map(|(checkin, &to_insert)| match checkin.client_id {
+            Some(client_id) => inserted_client_ids.contains(&client_id) && seen.insert(client_id),
+            None => to_insert,
+        })
+        .collect()
+}
+
+/// Per-punch outcome of a punch that passed validation
+fn punch_result(client_id: Option<Uuid>, stored: bool, held: bool) -> PunchSyncResult {
+    match (stored, held) {
+        (true, true) => PunchSyncResult {
+            client_id,
+            status: PunchStatus::Held,
+            message: Some("Held until the device is approved".to_string()),
+        },
+        (true, false) => PunchSyncResult { client_id, status: PunchStatus::Inserted, message: None },
+        (false, _) => PunchSyncResult {
+            client_id,
+            status: PunchStatus::Duplicate,
+            message: Some("Punch already synced".to_string()),
+        },
+    }
+}
+
+/// A punch that passed validation, with what will be stored for it
+struct ValidatedPunch<'a> {
+    index: usize,
+    checkin: &'a CheckinData,
+    time: AdjustedTime,
+    geofence: GeofenceResult,
+}
+
+/// Validate, insert and pair a batch of punches for one user in a single transaction.
+/// Punches go in with one INSERT and sessions are rebuilt once over the batch's time span.
+/// `bound_device` is the device from the access token. Its clock skew is tracked, and
+/// unless it is approved, the department's device policy decides whether the batch is held
+/// or rejected.
+pub async fn ingest_punches(
+    pool: &PgPool,
+    user_id: &str,
+    bound_device: Option<&str>,
+    device_time: Option<DateTime<Utc>>,
+    checkins: &[CheckinData],
+) -> Result<SyncResponse, sqlx::Error> {
+    let department = sqlx::query_scalar::<_, i32>("SELECT department FROM user_info WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+    let (checkin_points, checkout_points) = geofence::load_department_points(pool, department).await?;
+    let policy = geofence::load_department_policy(pool, department).await?;
+    let skew_policy = clock_skew::load_department_policy(pool, department).await?;
+    let device_policy = devices::load_department_policy(pool, department).await?;
+
+    let device_status = match bound_device {
+        Some(device) => devices::device_status(pool, user_id, device).await?,
+        None => None,
+    };
+    let disposition = devices::disposition(device_policy, device_status);
+
+    if disposition == PunchDisposition::Reject {
+        log::warn!(
+            "Rejected {} punches for user {}: device {:?} is not approved",
+            checkins.len(), user_id, bound_device
+        );
+        return Ok(SyncResponse {
+            synced: 0,
+            duplicates: 0,
+            flagged: 0,
+            rejected: checkins.len(),
+            held: 0,
+            results: checkins
+                .iter()
+                .map(|checkin| PunchSyncResult {
+                    client_id: checkin.client_id,
+                    status: PunchStatus::Rejected,
+                    message: Some("Device not approved".to_string()),
+                })
+                .collect(),
+        });
+    }
+    let held = disposition == PunchDisposition::Hold;
+
+    // Trusted server time for the whole batch
+    let received_at = Utc::now();
+
+    let mut tx = pool.begin().await?;
+
+    // One batch per user at a time, so a replay without client_ids cannot pass the
+    // duplicate check while the first copy is still being inserted
+    sessions::lock_user_sessions(&mut tx, user_id).await?;
+
+    let skew = clock_skew::device_skew(&mut tx, user_id, bound_device.unwrap_or(""), received_at, device_time).await?;
+
+    let mut results: Vec<Option<PunchSyncResult>> = Vec::with_capacity(checkins.len());
+    let mut valid = Vec::with_capacity(checkins.len());
+    let mut rejected = 0;
+
+    // Validate every punch against the geofence and the device clock before touching the table
+    for (index, checkin) in checkins.iter().enumerate() {
+        let time = clock_skew::adjust(checkin.created_at, skew, skew_policy, clock_skew::max_skew());
+        if time.flagged {
+            log::warn!(
+                "Flagged {} punch for user {} at {}: device clock off by {:?} s",
+                checkin.action, user_id, checkin.created_at, time.skew_seconds
+            );
+        }
+
+        let points = if checkin.action == "IN" { &checkin_points } else { &checkout_points };
+        let geofence = geofence::evaluate(checkin.latitude, checkin.longitude, points);
+
+        let has_location = checkin.latitude.is_some() && checkin.longitude.is_some();
+        if geofence.outcome(policy, has_location) == GeofenceOutcome::Reject {
+            log::warn!(
+                "Rejected {} punch for user {} at {}: outside geofence (distance {:?} m)",
+                checkin.action, user_id, time.created_at, geofence.distance_meters
+            );
+            rejected += 1;
+            results.push(Some(PunchSyncResult {
+                client_id: checkin.client_id,
+                status: PunchStatus::Rejected,
+                message: Some("Outside allowed geofence".to_string()),
+            }));
+            continue;
+        }
+
+        results.push(None);
+        valid.push(ValidatedPunch { index, checkin, time, geofence });
+    }
+
+    // Punches sent without a client_id are recognised by action and device time
+    let untagged_times: Vec<DateTime<Utc>> = valid
+        .iter()
+        .filter(|p| p.checkin.client_id.is_none())
+        .map(|p| untagged_key(p.checkin).1)
+        .collect();
+    let stored_untagged: HashSet<(String, DateTime<Utc>)> = if untagged_times.is_empty() {
+        HashSet::new()
+    } else {
+        sqlx::query_as::<_, (String, DateTime<Utc>)>(
+            r#"
+            SELECT action, COALESCE(device_created_at, created_at) FROM checkins
+            WHERE user_id = $1 AND client_id IS NULL
+              AND COALESCE(device_created_at, created_at) = ANY($2)
+            "#
+        )
+        .bind(user_id)
+        .bind(&untagged_times)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect()
+    };
+
+    let checkins: Vec<&CheckinData> = valid.iter().map(|p| p.checkin).collect();
+    let to_insert = punches_to_insert(&checkins, &stored_untagged);
+    let inserting: Vec<&ValidatedPunch> = valid
+        .iter()
+        .zip(&to_insert)
+        .filter(|(_, &insert)| insert)
+        .map(|(p, _)| p)
+        .collect();
+
+    // Punches whose client_id was already stored, or repeated within the batch, are skipped
+    let inserted_client_ids: HashSet<Uuid> = sqlx::query_scalar::<_, Option<Uuid>>(
+        r#"
+        INSERT INTO checkins (user_id, action, created_at, latitude, longitude, is_synced,
+                              within_geofence, distance_meters, matched_point_id, client_id,
+                              received_at, device_created_at, clock_skew_seconds, clock_skew_flagged,
+                              device_id, held)
+        SELECT $1, t.action, t.created_at, t.latitude, t.longitude, 1,
+               t.within_geofence, t.distance_meters, t.matched_point_id, t.client_id,
+               $2, t.device_created_at, t.clock_skew_seconds, t.clock_skew_flagged,
+               $14, $15
+        FROM UNNEST($3::varchar[], $4::timestamptz[], $5::float8[], $6::float8[], $7::bool[],
+                    $8::float8[], $9::int[], $10::uuid[], $11::timestamptz[], $12::int8[], $13::bool[])
+             WITH ORDINALITY AS t(action, created_at, latitude, longitude, within_geofence,
+                                  distance_meters, matched_point_id, client_id, device_created_at,
+                                  clock_skew_seconds, clock_skew_flagged, ord)
+        ORDER BY t.ord
+        ON CONFLICT (user_id, client_id) DO NOTHING
+        RETURNING client_id
+        "#
+    )
+    .bind(user_id)
+    .bind(received_at)
+    .bind(inserting.iter().map(|p| p.checkin.action.as_str()).collect::<Vec<_>>())
+    .bind(inserting.iter().map(|p| p.time.created_at).collect::<Vec<_>>())
+    .bind(inserting.iter().map(|p| p.checkin.latitude).collect::<Vec<_>>())
+    .bind(inserting.iter().map(|p| p.checkin.longitude).collect::<Vec<_>>())
+    .bind(inserting.iter().map(|p| p.geofence.within_geofence).collect::<Vec<_>>())
+    .bind(inserting.iter().map(|p| p.geofence.distance_meters).collect::<Vec<_>>())
+    .bind(inserting.iter().map(|p| p.geofence.matched_point_id).collect::<Vec<_>>())
+    .bind(inserting.iter().map(|p| p.checkin.client_id).collect::<Vec<_>>())
+    .bind(inserting.iter().map(|p| p.time.device_created_at).collect::<Vec<_>>())
+    .bind(inserting.iter().map(|p| p.time.skew_seconds).collect::<Vec<_>>())
+    .bind(inserting.iter().map(|p| p.time.flagged).collect::<Vec<_>>())
+    .bind(bound_device)
+    .bind(held)
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let stored = stored_punches(&checkins, &to_insert, &inserted_client_ids);
+
+    let mut accepted: Vec<DateTime<Utc>> = Vec::new();
+    let mut duplicates = 0;
+    let mut flagged = 0;
+    let mut held_count = 0;
+
+    for (punch, &stored) in valid.iter().zip(&stored) {
+        if !stored {
+            duplicates += 1;
+        } else if held {
+            held_count += 1;
+        } else {
+            if punch.geofence.within_geofence == Some(false) {
+                flagged += 1;
+            }
+            accepted.push(punch.time.created_at);
+        }
+        results[punch.index] = Some(punch_result(punch.checkin.client_id, stored, held));
+    }
+
+    // Re-pair the user's punches around the new ones into sessions
+    let first = accepted.iter().min().copied();
+    let last = accepted.iter().max().copied();
+    if let (Some(first), Some(last)) = (first, last) {
+        sessions::rebuild_user_sessions(&mut tx, user_id, first, last, SessionConfig::global()).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(SyncResponse {
+        synced: accepted.len(),
+        duplicates,
+        flagged,
+        rejected,
+        held: held_count,
+        results: results.into_iter().flatten().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn punch(client_id: Option<u128>, action: &str, minute: u32) -> CheckinData {
+        CheckinData {
+            client_id: client_id.map(Uuid::from_u128),
+            action: action.to_string(),
+            created_at: Utc.with_ymd_and_hms(2024, 12, 4, 8, minute, 0).unwrap(),
+            latitude: None,
+            longitude: None,
+        }
+    }
+
+    fn statuses(checkins: &[CheckinData], stored_untagged: &[(&str, u32)], inserted_ids: &[u128], held: bool) -> Vec<PunchStatus> {
+        let checkins: Vec<&CheckinData> = checkins.iter().collect();
+        let stored_untagged = stored_untagged.iter().map(|&(action, minute)| untagged_key(&punch(None, action, minute))).collect();
+        let inserted_ids = inserted_ids.iter().copied().map(Uuid::from_u128).collect();
+        let to_insert = punches_to_insert(&checkins, &stored_untagged);
+        stored_punches(&checkins, &to_insert, &inserted_ids)
+            .into_iter()
+            .zip(&checkins)
+            .map(|(stored, checkin)| punch_result(checkin.client_id, stored, held).status)
+            .collect()
+    }
+
+    #[test]
+    fn test_replayed_client_ids_are_duplicates() {
+        let batch = [punch(Some(1), "IN", 0), punch(Some(2), "OUT", 30)];
+        assert_eq!(statuses(&batch, &[], &[1, 2], false), vec![PunchStatus::Inserted, PunchStatus::Inserted]);
+        // On replay the insert returns nothing
+        assert_eq!(statuses(&batch, &[], &[], false), vec![PunchStatus::Duplicate, PunchStatus::Duplicate]);
+    }
+
+    #[test]
+    fn test_client_id_repeated_within_batch() {
+        let batch = [punch(Some(1), "IN", 0), punch(Some(1), "IN", 0)];
+        assert_eq!(statuses(&batch, &[], &[1], false), vec![PunchStatus::Inserted, PunchStatus::Duplicate]);
+    }
+
+    #[test]
+    fn test_replayed_punches_without_client_id_are_duplicates() {
+        let batch = [punch(None, "IN", 0), punch(None, "OUT", 30)];
+        assert_eq!(statuses(&batch, &[], &[], false), vec![PunchStatus::Inserted, PunchStatus::Inserted]);
+        assert_eq!(
+            statuses(&batch, &[("IN", 0), ("OUT", 30)], &[], false),
+            vec![PunchStatus::Duplicate, PunchStatus::Duplicate]
+        );
+
+        // Same time but another action is a different punch
+        let batch = [punch(None, "IN", 0), punch(None, "IN", 0), punch(None, "OUT", 0)];
+        assert_eq!(
+            statuses(&batch, &[], &[], false),
+            vec![PunchStatus::Inserted, PunchStatus::Duplicate, PunchStatus::Inserted]
+        );
+    }
+
+    #[test]
+    fn test_untagged_key_matches_stored_precision() {
+        let mut checkin = punch(None, "IN", 0);
+        checkin.created_at += chrono::Duration::nanoseconds(1_234_567);
+        assert_eq!(untagged_key(&checkin).1.timestamp_subsec_nanos(), 1_234_000);
+    }
+
+    #[test]
+    fn test_held_punches() {
+        let batch = [punch(Some(1), "IN", 0), punch(Some(2), "OUT", 30)];
+        assert_eq!(statuses(&batch, &[], &[2], true), vec![PunchStatus::Duplicate, PunchStatus::Held]);
+    }
+
+    #[test]
+    fn test_total_chunks_bounds() {
+        assert!(check_total_chunks(1, 500).is_ok());
+        assert!(check_total_chunks(500, 500).is_ok());
+        assert!(check_total_chunks(0, 500).is_err());
+        assert!(check_total_chunks(-3, 500).is_err());
+        assert!(check_total_chunks(501, 500).is_err());
+        assert!(check_total_chunks(i32::MAX, 500).is_err());
+    }
+
+    #[test]
+    fn test_missing_chunks_for_resume() {
+        assert_eq!(missing_chunks(4, &[]), vec![0, 1, 2, 3]);
+        assert_eq!(missing_chunks(4, &[0, 2]), vec![1, 3]);
+        assert!(missing_chunks(4, &[0, 1, 2, 3]).is_empty());
+    }
+}
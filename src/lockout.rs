@@ -0,0 +1,323 @@
+// Brute-force protection for passkey and admin login
+use actix_web::{HttpRequest, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgExecutor, PgPool};
+
+use crate::audit;
+use crate::models::ApiResponse;
+
+/// Default failed logins allowed for one account before it is locked
+pub const DEFAULT_ACCOUNT_MAX_FAILURES: i32 = 5;
+/// Default failed logins allowed from one IP before it is locked; higher because
+/// several employees may share an address
+pub const DEFAULT_IP_MAX_FAILURES: i32 = 20;
+/// Default length of the first lockout, doubled with every further failure
+pub const DEFAULT_LOCKOUT_BASE_SECONDS: i64 = 60;
+/// Default longest lockout
+pub const DEFAULT_LOCKOUT_MAX_SECONDS: i64 = 3600;
+
+lazy_static! {
+    static ref LOCKOUT_CONFIG: LockoutConfig = LockoutConfig::from_env();
+}
+
+/// What a failed login is counted against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockScope {
+    Ip,
+    /// Admin account, by username
+    AdminUser,
+    /// Employee account, by user_id
+    Employee,
+}
+
+impl LockScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LockScope::Ip => "ip",
+            LockScope::AdminUser => "admin_user",
+            LockScope::Employee => "employee",
+        }
+    }
+}
+
+/// Lockout thresholds and durations
+#[derive(Debug, Clone)]
+pub struct LockoutConfig {
+    pub account_max_failures: i32,
+    pub ip_max_failures: i32,
+    pub base_lockout: Duration,
+    pub max_lockout: Duration,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            account_max_failures: DEFAULT_ACCOUNT_MAX_FAILURES,
+            ip_max_failures: DEFAULT_IP_MAX_FAILURES,
+            base_lockout: Duration::seconds(DEFAULT_LOCKOUT_BASE_SECONDS),
+            max_lockout: Duration::seconds(DEFAULT_LOCKOUT_MAX_SECONDS),
+        }
+    }
+}
+
+impl LockoutConfig {
+    /// Read LOGIN_MAX_FAILURES, LOGIN_MAX_FAILURES_PER_IP, LOGIN_LOCKOUT_BASE_SECONDS and
+    /// LOGIN_LOCKOUT_MAX_SECONDS, falling back to the defaults
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+
+        if let Ok(value) = std::env::var("LOGIN_MAX_FAILURES") {
+            match value.parse::<i32>() {
+                Ok(failures) if failures > 0 => config.account_max_failures = failures,
+                _ => log::warn!("Ignoring invalid LOGIN_MAX_FAILURES: {}", value),
+            }
+        }
+
+        if let Ok(value) = std::env::var("LOGIN_MAX_FAILURES_PER_IP") {
+            match value.parse::<i32>() {
+                Ok(failures) if failures > 0 => config.ip_max_failures = failures,
+                _ => log::warn!("Ignoring invalid LOGIN_MAX_FAILURES_PER_IP: {}", value),
+            }
+        }
+
+        if let Ok(value) = std::env::var("LOGIN_LOCKOUT_BASE_SECONDS") {
+            match value.parse::<i64>() {
+                Ok(seconds) if seconds > 0 => config.base_lockout = Duration::seconds(seconds),
+                _ => log::warn!("Ignoring invalid LOGIN_LOCKOUT_BASE_SECONDS: {}", value),
+            }
+        }
+
+        if let Ok(value) = std::env::var("LOGIN_LOCKOUT_MAX_SECONDS") {
+            match value.parse::<i64>() {
+                Ok(seconds) if seconds > 0 => config.max_lockout = Duration::seconds(seconds),
+                _ => log::warn!("Ignoring invalid LOGIN_LOCKOUT_MAX_SECONDS: {}", value),
+            }
+        }
+
+        config
+    }
+
+    /// Process-wide configuration loaded from the environment on first use
+    pub fn global() -> &'static LockoutConfig {
+        &LOCKOUT_CONFIG
+    }
+
+    fn max_failures(&self, scope: LockScope) -> i32 {
+        match scope {
+            LockScope::Ip => self.ip_max_failures,
+            LockScope::AdminUser | LockScope::Employee => self.account_max_failures,
+        }
+    }
+}
+
+/// Failed logins recorded against one IP or account
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct LoginAttempt {
+    pub scope: String,
+    pub subject: String,
+    pub failures: i32,
+    pub last_failure_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+/// Failure count and lockout after one more failed login. Failures older than the
+/// longest lockout are forgotten; past the threshold every failure doubles the lockout.
+pub fn register_failure(
+    failures: i32,
+    last_failure_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    max_failures: i32,
+    config: &LockoutConfig,
+) -> (i32, Option<DateTime<Utc>>) {
+    let failures = match last_failure_at {
+        Some(last) if now - last < config.max_lockout => failures + 1,
+        _ => 1,
+    };
+
+    if failures < max_failures {
+        return (failures, None);
+    }
+
+    let doublings = (failures - max_failures).min(30) as u32;
+    let seconds = config.base_lockout.num_seconds().saturating_mul(1i64 << doublings);
+    let lockout = Duration::seconds(seconds).min(config.max_lockout);
+
+    (failures, Some(now + lockout))
+}
+
+/// Address a login came from. The socket peer is used rather than forwarding headers,
+/// which a client could set to dodge or misdirect a lockout.
+pub fn client_ip(req: &HttpRequest) -> String {
+    req.peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Latest lockout in effect for any of the given subjects
+pub async fn locked_until(pool: &PgPool, subjects: &[(LockScope, &str)]) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+        r#"
+        SELECT MAX(a.locked_until)
+        FROM login_attempts a
+        JOIN UNNEST($1::varchar[], $2::varchar[]) AS t(scope, subject)
+          ON a.scope = t.scope AND a.subject = t.subject
+        WHERE a.locked_until > NOW()
+        "#
+    )
+    .bind(subjects.iter().map(|(scope, _)| scope.as_str()).collect::<Vec<_>>())
+    .bind(subjects.iter().map(|(_, subject)| *subject).collect::<Vec<_>>())
+    .fetch_one(pool)
+    .await
+}
+
+/// Count a failed login against each subject, locking those past their threshold.
+/// New lockouts are written to the audit log.
+pub async fn record_failure(pool: &PgPool, subjects: &[(LockScope, &str)]) -> Result<(), sqlx::Error> {
+    let config = LockoutConfig::global();
+    let now = Utc::now();
+    let mut tx = pool.begin().await?;
+
+    for &(scope, subject) in subjects {
+        sqlx::query(
+            r#"
+            INSERT INTO login_attempts (scope, subject, failures, last_failure_at)
+            VALUES ($1, $2, 0, NOW())
+            ON CONFLICT (scope, subject) DO NOTHING
+            "#
+        )
+        .bind(scope.as_str())
+        .bind(subject)
+        .execute(&mut *tx)
+        .await?;
+
+        let attempt = sqlx::query_as::<_, LoginAttempt>(
+            "SELECT * FROM login_attempts WHERE scope = $1 AND subject = $2 FOR UPDATE"
+        )
+        .bind(scope.as_str())
+        .bind(subject)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let last_failure_at = (attempt.failures > 0).then_some(attempt.last_failure_at);
+        let (failures, locked_until) =
+            register_failure(attempt.failures, last_failure_at, now, config.max_failures(scope), config);
+
+        sqlx::query(
+            r#"
+            UPDATE login_attempts
+            SET failures = $3, last_failure_at = $4, locked_until = $5
+            WHERE scope = $1 AND subject = $2
+            "#
+        )
+        .bind(scope.as_str())
+        .bind(subject)
+        .bind(failures)
+        .bind(now)
+        .bind(locked_until)
+        .execute(&mut *tx)
+        .await?;
+
+        if let Some(until) = locked_until {
+            log::warn!("Locked {} {} until {} after {} failed logins", scope.as_str(), subject, until, failures);
+            audit::record(
+                &mut *tx,
+                audit::SYSTEM_ACTOR,
+                "login_lockout",
+                Some(&format!("{}:{}", scope.as_str(), subject)),
+                Some(&format!("{} failed logins, locked until {}", failures, until.to_rfc3339())),
+            )
+            .await?;
+        }
+    }
+
+    tx.commit().await
+}
+
+/// Forget the failed logins of a subject, e.g. after a successful login
+pub async fn clear<'e, E: PgExecutor<'e>>(executor: E, scope: LockScope, subject: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM login_attempts WHERE scope = $1 AND subject = $2")
+        .bind(scope.as_str())
+        .bind(subject)
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Subjects that are locked out right now
+pub async fn active_lockouts(pool: &PgPool) -> Result<Vec<LoginAttempt>, sqlx::Error> {
+    sqlx::query_as::<_, LoginAttempt>(
+        "SELECT * FROM login_attempts WHERE locked_until > NOW() ORDER BY locked_until DESC"
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Delete attempts that no longer count towards a lockout
+pub async fn purge_login_attempts(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM login_attempts
+        WHERE (locked_until IS NULL OR locked_until < NOW())
+          AND last_failure_at < NOW() - make_interval(secs => $1)
+        "#
+    )
+    .bind(LockoutConfig::global().max_lockout.num_seconds() as f64)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// 429 response telling the client when it may try again
+pub fn too_many_attempts(locked_until: DateTime<Utc>) -> HttpResponse {
+    let retry_after = (locked_until - Utc::now()).num_seconds().max(1);
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", retry_after.to_string()))
+        .json(ApiResponse::<()>::error(&format!(
+            "Too many failed login attempts; try again in {} seconds", retry_after
+        )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 12, 4, 8, minute, second).unwrap()
+    }
+
+    fn config() -> LockoutConfig {
+        LockoutConfig {
+            account_max_failures: 3,
+            ip_max_failures: 10,
+            base_lockout: Duration::seconds(60),
+            max_lockout: Duration::seconds(600),
+        }
+    }
+
+    #[test]
+    fn test_failures_below_threshold_do_not_lock() {
+        assert_eq!(register_failure(0, None, at(0, 0), 3, &config()), (1, None));
+        assert_eq!(register_failure(1, Some(at(0, 0)), at(0, 10), 3, &config()), (2, None));
+    }
+
+    #[test]
+    fn test_lockout_doubles_and_is_capped() {
+        assert_eq!(register_failure(2, Some(at(0, 0)), at(1, 0), 3, &config()), (3, Some(at(2, 0))));
+        assert_eq!(register_failure(3, Some(at(1, 0)), at(2, 0), 3, &config()), (4, Some(at(4, 0))));
+        assert_eq!(register_failure(4, Some(at(2, 0)), at(4, 0), 3, &config()), (5, Some(at(8, 0))));
+        // 16 minutes would follow, but the cap is 10
+        assert_eq!(register_failure(6, Some(at(8, 0)), at(9, 0), 3, &config()), (7, Some(at(19, 0))));
+        assert_eq!(register_failure(60, Some(at(8, 0)), at(9, 0), 3, &config()), (61, Some(at(19, 0))));
+    }
+
+    #[test]
+    fn test_old_failures_are_forgotten() {
+        assert_eq!(register_failure(2, Some(at(0, 0)), at(10, 0), 3, &config()), (1, None));
+    }
+}
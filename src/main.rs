@@ -1,10 +1,25 @@
 mod admin;
+mod anomalies;
+mod api_keys;
+mod audit;
 mod auth;
+mod changes;
+mod clock_skew;
 mod db;
+mod devices;
+mod geofence;
 mod handlers;
+mod ingest;
+mod lockout;
 mod models;
+mod passkey;
+mod password;
+mod permissions;
+mod schedule;
+mod sessions;
 mod sync;
 mod timezone_config;
+mod totp;
 
 use actix_cors::Cors;
 use actix_web::{middleware::Logger, web, App, HttpServer, HttpResponse};
@@ -20,6 +35,11 @@ async fn main() -> std::io::Result<()> {
 
     log::info!("Starting KBK Attendance Server...");
 
+    if !passkey::hash_key_is_set() {
+        log::error!("PASSKEY_HASH_KEY is not set; refusing to start without a key to hash employee passkeys");
+        return Err(std::io::Error::other("PASSKEY_HASH_KEY is not set"));
+    }
+
     let pool = db::create_pool()
         .await
         .expect("Failed to create database pool");
@@ -29,11 +49,8 @@ async fn main() -> std::io::Result<()> {
     // Initialize sync service
     let sync_service = Arc::new(sync::SyncService::new(Arc::new(pool.clone())));
     
-    // Run startup sync to ensure all users have time settings
-    sync_service.startup_sync().await;
-    
     // Start periodic sync process
-    sync_service.clone().start_periodic_sync();
+    sync_service.start_periodic_sync();
 
     let server_port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
     let server_addr = format!("0.0.0.0:{}", server_port);
@@ -58,10 +75,15 @@ async fn main() -> std::io::Result<()> {
             .service(
                 web::scope("/api")
                     .route("/auth/verify", web::post().to(handlers::verify_auth))
+                    .route("/auth/refresh", web::post().to(handlers::refresh_auth))
+                    .route("/auth/logout", web::post().to(handlers::logout_auth))
                     .route("/points/checkin", web::post().to(handlers::get_checkin_points))
                     .route("/points/checkout", web::post().to(handlers::get_checkout_points))
                     .route("/checkin/sync", web::post().to(handlers::sync_checkins))
-                    .route("/checkin/count", web::post().to(handlers::check_count))
+                    .route("/checkin/upload/start", web::post().to(handlers::start_upload))
+                    .route("/checkin/upload/chunk", web::post().to(handlers::upload_chunk))
+                    .route("/checkin/upload/status", web::post().to(handlers::get_upload_status))
+                    .route("/checkin/changes", web::post().to(handlers::get_checkin_changes))
                     .route("/checkin/full-sync", web::post().to(handlers::full_sync))
                     .route("/stats/monthly", web::post().to(handlers::get_monthly_stats))
                     .route("/sessions/daily", web::post().to(handlers::get_daily_sessions))
@@ -1,6 +1,7 @@
 use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct Checkin {
@@ -11,8 +12,21 @@ pub struct Checkin {
     pub latitude: Option<f64>,
     pub longitude: Option<f64>,
     pub is_synced: i32,
+    pub within_geofence: Option<bool>,
+    pub distance_meters: Option<f64>,
+    pub matched_point_id: Option<i32>,
+    pub client_id: Option<Uuid>,
+    pub sync_seq: i64,
+    pub updated_at: DateTime<Utc>,
+    pub received_at: Option<DateTime<Utc>>, // Server time the punch arrived, unset for older rows
+    pub device_created_at: Option<DateTime<Utc>>, // Original device timestamp when created_at was corrected
+    pub clock_skew_seconds: Option<i64>,
+    pub clock_skew_flagged: bool,
+    pub device_id: Option<String>, // Device the punch was sent from, per the employee's token
+    pub held: bool, // Waiting for the device to be approved; not paired into sessions
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CheckinRequest {
     pub user_id: String,
@@ -25,19 +39,99 @@ pub struct CheckinRequest {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SyncRequest {
-    pub user_id: String,
-    pub passkey: String,
+    /// Device clock at the time the batch was sent
+    pub device_time: Option<DateTime<Utc>>,
     pub checkins: Vec<CheckinData>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CheckinData {
+    /// Client-generated punch id; replays with the same id are not inserted twice. Punches
+    /// from older apps without one are matched on action and time instead, which is weaker.
+    pub client_id: Option<Uuid>,
     pub action: String,
     pub created_at: DateTime<Utc>,
     pub latitude: Option<f64>,
     pub longitude: Option<f64>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncResponse {
+    pub synced: usize,
+    pub duplicates: usize,
+    pub flagged: usize,
+    pub rejected: usize,
+    /// Stored but not counted until an admin approves the sending device
+    pub held: usize,
+    pub results: Vec<PunchSyncResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StartUploadRequest {
+    pub total_chunks: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StartUploadResponse {
+    pub upload_id: Uuid,
+    /// Largest number of punches accepted per chunk
+    pub max_batch_size: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadChunkRequest {
+    pub upload_id: Uuid,
+    /// Zero-based position of this chunk in the upload
+    pub chunk_index: i32,
+    pub device_time: Option<DateTime<Utc>>,
+    pub checkins: Vec<CheckinData>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadStatusRequest {
+    pub upload_id: Uuid,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct SyncUpload {
+    pub id: Uuid,
+    pub user_id: String,
+    pub device_id: Option<String>,
+    pub total_chunks: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadStatusResponse {
+    pub upload_id: Uuid,
+    pub total_chunks: i32,
+    pub received_chunks: Vec<i32>,
+    pub missing_chunks: Vec<i32>,
+    pub complete: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadChunkResponse {
+    pub sync: SyncResponse,
+    pub upload: UploadStatusResponse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PunchStatus {
+    Inserted,
+    Duplicate,
+    Rejected,
+    Held,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PunchSyncResult {
+    pub client_id: Option<Uuid>,
+    pub status: PunchStatus,
+    pub message: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct CheckinPoint {
     pub id: i32,
@@ -65,7 +159,16 @@ pub struct UserInfo {
     pub user_name: Option<String>,
     pub department: i32,
     pub department_name: Option<String>,
-    pub passkey: String,
+    /// Bumped on every passkey change, invalidating tokens issued under the old one
+    pub passkey_version: i32,
+    pub passkey_rotated_at: Option<DateTime<Utc>>,
+    pub passkey_expires_at: Option<DateTime<Utc>>,
+}
+
+impl UserInfo {
+    pub fn passkey_expired(&self, now: DateTime<Utc>) -> bool {
+        self.passkey_expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -76,6 +179,31 @@ pub struct UserInfoResponse {
     pub department_name: Option<String>,
 }
 
+/// Tokens handed to an employee app on login or refresh
+#[derive(Debug, Serialize)]
+pub struct IssuedTokens {
+    pub access_token: String,
+    pub access_expires_at: DateTime<Utc>,
+    pub refresh_token: String,
+    pub refresh_expires_at: DateTime<Utc>,
+    pub token_type: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthTokenResponse {
+    #[serde(flatten)]
+    pub user: UserInfoResponse,
+    #[serde(flatten)]
+    pub tokens: IssuedTokens,
+    /// Approval state of the device that signed in, if it sent an identifier
+    pub device_status: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct AttendanceSession {
     pub id: i32,
@@ -92,6 +220,8 @@ pub struct AttendanceSession {
     pub checkin_location: Option<String>,
     pub checkout_location: Option<String>,
     pub is_complete: bool,
+    pub auto_closed: bool, // Closed by the scheduled job, not by an OUT punch
+    pub auto_closed_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -109,38 +239,44 @@ pub struct AttendanceSummary {
     pub total_break_minutes: Option<i32>,
     pub total_sessions: i32,
     pub is_complete: bool,
+    pub auto_closed_sessions: i32,
     pub updated_at: Option<DateTime<Utc>>,
 }
 
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthRequest {
+    /// Employee the passkey belongs to; failed attempts count against it as well as the IP
+    pub user_id: String,
     pub passkey: String,
+    /// Stable identifier of the app install; registered to the employee on first use
+    pub device_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct CountRequest {
-    pub user_id: String,
-    pub passkey: String,
-    pub local_count: i64,
+pub struct ChangesRequest {
+    pub cursor: Option<i64>, // Last sync_seq the device has seen, omit for a fresh device
+    pub limit: Option<i64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct CountResponse {
-    pub action: String, // "none", "sync", "full_sync"
-    pub server_count: i64,
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct CheckinTombstone {
+    pub checkin_id: i32,
+    pub client_id: Option<Uuid>,
+    pub sync_seq: i64,
+    pub deleted_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct FullSyncRequest {
-    pub user_id: String,
-    pub passkey: String,
+pub struct ChangesResponse {
+    pub checkins: Vec<Checkin>,
+    pub deleted: Vec<CheckinTombstone>,
+    pub next_cursor: i64,
+    pub has_more: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MonthlyStatsRequest {
-    pub user_id: String,
-    pub passkey: String,
     pub year: i32,
     pub month: u32,
 }
@@ -148,8 +284,19 @@ pub struct MonthlyStatsRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MonthlyStatsResponse {
     pub attendance_days: i32,
+    /// Days so far this month the user was scheduled to work
+    pub scheduled_days: i32,
+    /// Scheduled days without a checkin
+    pub absence_days: i32,
+    /// Late days past the grace period, of either tier
     pub late_count: i32,
+    pub minor_late_count: i32,
+    pub major_late_count: i32,
+    /// Minutes late summed over the late days
+    pub late_minutes: i64,
     pub early_leave_count: i32,
+    /// Thresholds the days were judged by
+    pub lateness_rules: crate::schedule::LatenessRules,
     pub details: Vec<DailyAttendance>,
 }
 
@@ -159,15 +306,18 @@ pub struct DailyAttendance {
     pub checkin_time: Option<DateTime<Utc>>,
     pub checkout_time: Option<DateTime<Utc>>,
     pub is_late: bool,
+    /// Minutes after the shift start, 0 unless late
+    pub late_minutes: i64,
+    pub late_tier: Option<crate::schedule::LateTier>,
     pub is_early_leave: bool,
+    /// Minutes before the shift end, 0 unless leaving early
+    pub early_leave_minutes: i64,
     pub total_work_minutes: Option<i32>,
     pub total_sessions: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DailySessionsRequest {
-    pub user_id: String,
-    pub passkey: String,
     pub date: NaiveDate,
 }
 
@@ -0,0 +1,117 @@
+// Employee passkeys: keyed hashing for lookup, generation for rotation
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use sha2::Sha256;
+use sqlx::PgPool;
+
+/// Length of generated passkeys
+pub const GENERATED_PASSKEY_LENGTH: usize = 12;
+
+/// Characters of generated passkeys, without look-alikes such as 0/O and 1/l/I
+const PASSKEY_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnpqrstuvwxyz23456789";
+
+lazy_static! {
+    static ref PASSKEY_HASH_KEY: Option<Vec<u8>> = hash_key_from_env();
+}
+
+/// Read PASSKEY_HASH_KEY. Changing it invalidates every stored passkey.
+fn hash_key_from_env() -> Option<Vec<u8>> {
+    std::env::var("PASSKEY_HASH_KEY")
+        .ok()
+        .filter(|value| !value.is_empty())
+        .map(String::into_bytes)
+}
+
+/// Whether PASSKEY_HASH_KEY is set. The server does not start without it, since short
+/// passkeys hashed with a known key can be guessed offline from a database dump.
+pub fn hash_key_is_set() -> bool {
+    PASSKEY_HASH_KEY.is_some()
+}
+
+/// Deterministic keyed hash of a passkey, so a login can be looked up by hash
+pub fn hash_passkey_with_key(passkey: &str, key: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(passkey.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Hash a passkey with the process-wide key
+pub fn hash_passkey(passkey: &str) -> String {
+    let key = PASSKEY_HASH_KEY.as_deref().expect("PASSKEY_HASH_KEY is checked at startup");
+    hash_passkey_with_key(passkey, key)
+}
+
+/// Random passkey for rotation, shown to the admin once
+pub fn generate_passkey() -> String {
+    // Skip bytes past the last whole multiple of the alphabet size so every character is equally likely
+    let limit = 256 - 256 % PASSKEY_ALPHABET.len();
+    let mut passkey = String::with_capacity(GENERATED_PASSKEY_LENGTH);
+    let mut buf = [0u8; 32];
+
+    while passkey.len() < GENERATED_PASSKEY_LENGTH {
+        OsRng.fill_bytes(&mut buf);
+        for &byte in buf.iter().filter(|&&b| (b as usize) < limit) {
+            if passkey.len() == GENERATED_PASSKEY_LENGTH {
+                break;
+            }
+            passkey.push(PASSKEY_ALPHABET[byte as usize % PASSKEY_ALPHABET.len()] as char);
+        }
+    }
+
+    passkey
+}
+
+/// Hash passkeys still stored in plaintext and clear the plaintext column.
+/// Returns the number of users converted.
+pub async fn hash_plaintext_passkeys(pool: &PgPool) -> Result<usize, sqlx::Error> {
+    let rows = sqlx::query_as::<_, (i32, String)>(
+        "SELECT id, passkey FROM user_info WHERE passkey IS NOT NULL"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    if rows.is_empty() {
+        return Ok(0);
+    }
+
+    let ids: Vec<i32> = rows.iter().map(|(id, _)| *id).collect();
+    let hashes: Vec<String> = rows.iter().map(|(_, passkey)| hash_passkey(passkey)).collect();
+
+    sqlx::query(
+        r#"
+        UPDATE user_info u
+        SET passkey_hash = t.passkey_hash, passkey = NULL
+        FROM UNNEST($1::int[], $2::varchar[]) AS t(id, passkey_hash)
+        WHERE u.id = t.id
+        "#
+    )
+    .bind(&ids)
+    .bind(&hashes)
+    .execute(pool)
+    .await?;
+
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_is_keyed_and_deterministic() {
+        let hash = hash_passkey_with_key("1234", b"key-one");
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_passkey_with_key("1234", b"key-one"));
+        assert_ne!(hash, hash_passkey_with_key("1234", b"key-two"));
+        assert_ne!(hash, hash_passkey_with_key("1235", b"key-one"));
+    }
+
+    #[test]
+    fn test_generated_passkeys() {
+        let passkey = generate_passkey();
+        assert_eq!(passkey.len(), GENERATED_PASSKEY_LENGTH);
+        assert!(passkey.bytes().all(|b| PASSKEY_ALPHABET.contains(&b)));
+        assert_ne!(passkey, generate_passkey());
+    }
+}
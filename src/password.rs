@@ -0,0 +1,102 @@
+// Credential hashing: Argon2id for admin passwords, SHA-256 digests for bearer tokens
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use sha2::{Digest, Sha256};
+
+/// Prefix of every PHC string produced by `hash_password`
+const ARGON2_PREFIX: &str = "$argon2";
+
+/// Argon2 hash of a throwaway password with the default parameters. Logins for unknown
+/// accounts verify against it so they take as long as logins for real ones.
+const DUMMY_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$X9lun2D63uX0WsC43tB6wg$SFqRy+FYQApUj6lfXHdtoXzIbB0IceveVIKmP4EcrKY";
+
+/// Outcome of checking a password against what is stored for an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordCheck {
+    Valid,
+    /// Correct, but stored in plaintext from before hashing; it should be rehashed
+    ValidLegacy,
+    Invalid,
+}
+
+/// Hash a password with a fresh random salt, returning a PHC string
+pub fn hash_password(password: &str) -> Result<String, password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+/// Verify a password against a stored hash, or against a legacy plaintext value
+pub fn verify_password(password: &str, stored: &str) -> PasswordCheck {
+    if stored.starts_with(ARGON2_PREFIX) {
+        let valid = PasswordHash::new(stored)
+            .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+            .unwrap_or(false);
+        return if valid { PasswordCheck::Valid } else { PasswordCheck::Invalid };
+    }
+
+    if constant_time_eq(password.as_bytes(), stored.as_bytes()) {
+        PasswordCheck::ValidLegacy
+    } else {
+        PasswordCheck::Invalid
+    }
+}
+
+/// Spend the same work as `verify_password` on an account that does not exist
+pub fn verify_dummy_password(password: &str) {
+    let _ = verify_password(password, DUMMY_HASH);
+}
+
+/// Digest under which a random bearer token is stored, so a leaked table cannot be replayed.
+/// Tokens are long random values, so a fast unsalted hash is enough.
+pub fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Compare two byte strings without stopping at the first difference
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_round_trip() {
+        let hash = hash_password("admin123").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert_eq!(verify_password("admin123", &hash), PasswordCheck::Valid);
+        assert_eq!(verify_password("admin124", &hash), PasswordCheck::Invalid);
+    }
+
+    #[test]
+    fn test_hashes_are_salted() {
+        assert_ne!(hash_password("admin123").unwrap(), hash_password("admin123").unwrap());
+    }
+
+    #[test]
+    fn test_legacy_plaintext() {
+        assert_eq!(verify_password("admin123", "admin123"), PasswordCheck::ValidLegacy);
+        assert_eq!(verify_password("admin12", "admin123"), PasswordCheck::Invalid);
+        assert_eq!(verify_password("", "admin123"), PasswordCheck::Invalid);
+    }
+
+    #[test]
+    fn test_dummy_hash_matches_default_cost() {
+        let dummy = PasswordHash::new(DUMMY_HASH).unwrap();
+        let fresh = hash_password("admin123").unwrap();
+        let fresh = PasswordHash::new(&fresh).unwrap();
+        assert_eq!(dummy.algorithm, fresh.algorithm);
+        assert_eq!(dummy.version, fresh.version);
+        assert_eq!(dummy.params, fresh.params);
+    }
+
+    #[test]
+    fn test_malformed_hash_is_invalid() {
+        assert_eq!(verify_password("admin123", "$argon2id$broken"), PasswordCheck::Invalid);
+    }
+}
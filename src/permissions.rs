@@ -0,0 +1,278 @@
+// Roles, capabilities and department scopes of admin users
+
+/// Something an admin user may do. Read capabilities on employee data are limited to
+/// the user's department scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    CheckinsRead,
+    CheckinsWrite,
+    UsersRead,
+    UsersManage,
+    StatsRead,
+    StatsExport,
+    SessionsRead,
+    SessionsManage,
+    AnomaliesRead,
+    AnomaliesReview,
+    /// Check-in points, time settings and department policies
+    SettingsRead,
+    SettingsManage,
+    /// Admin accounts and login lockouts
+    AdminUsersManage,
+    /// The audit log of admin changes
+    AuditRead,
+}
+
+impl Capability {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::CheckinsRead => "checkins.read",
+            Capability::CheckinsWrite => "checkins.write",
+            Capability::UsersRead => "users.read",
+            Capability::UsersManage => "users.manage",
+            Capability::StatsRead => "stats.read",
+            Capability::StatsExport => "stats.export",
+            Capability::SessionsRead => "sessions.read",
+            Capability::SessionsManage => "sessions.manage",
+            Capability::AnomaliesRead => "anomalies.read",
+            Capability::AnomaliesReview => "anomalies.review",
+            Capability::SettingsRead => "settings.read",
+            Capability::SettingsManage => "settings.manage",
+            Capability::AdminUsersManage => "admin_users.manage",
+            Capability::AuditRead => "audit.read",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        ALL_CAPABILITIES.iter().copied().find(|capability| capability.as_str() == value)
+    }
+
+    /// API keys are for integrations that pull data, so they only get read capabilities
+    pub fn grantable_to_api_keys(&self) -> bool {
+        API_KEY_CAPABILITIES.contains(self)
+    }
+}
+
+const ALL_CAPABILITIES: &[Capability] = &[
+    Capability::CheckinsRead,
+    Capability::CheckinsWrite,
+    Capability::UsersRead,
+    Capability::UsersManage,
+    Capability::StatsRead,
+    Capability::StatsExport,
+    Capability::SessionsRead,
+    Capability::SessionsManage,
+    Capability::AnomaliesRead,
+    Capability::AnomaliesReview,
+    Capability::SettingsRead,
+    Capability::SettingsManage,
+    Capability::AdminUsersManage,
+    Capability::AuditRead,
+];
+
+const DEPARTMENT_CAPABILITIES: &[Capability] = &[
+    Capability::StatsRead,
+    Capability::StatsExport,
+    Capability::AnomaliesRead,
+];
+
+const AUDITOR_CAPABILITIES: &[Capability] = &[
+    Capability::CheckinsRead,
+    Capability::UsersRead,
+    Capability::StatsRead,
+    Capability::StatsExport,
+    Capability::SessionsRead,
+    Capability::AnomaliesRead,
+    Capability::SettingsRead,
+    Capability::AuditRead,
+];
+
+const PAYROLL_CAPABILITIES: &[Capability] = &[
+    Capability::CheckinsRead,
+    Capability::StatsRead,
+    Capability::StatsExport,
+    Capability::SessionsRead,
+];
+
+const API_KEY_CAPABILITIES: &[Capability] = &[
+    Capability::CheckinsRead,
+    Capability::UsersRead,
+    Capability::StatsRead,
+    Capability::StatsExport,
+    Capability::SessionsRead,
+    Capability::AnomaliesRead,
+    Capability::SettingsRead,
+];
+
+/// Named bundle of capabilities, stored in admin_user.role
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Everything, across all departments
+    Admin,
+    /// Department manager: reads attendance of the departments in scope
+    Department,
+    /// Read-only access for audits
+    Auditor,
+    /// Attendance figures and exports for payroll
+    Payroll,
+}
+
+impl Role {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "admin" => Some(Role::Admin),
+            "department" => Some(Role::Department),
+            "auditor" => Some(Role::Auditor),
+            "payroll" => Some(Role::Payroll),
+            _ => None,
+        }
+    }
+
+    pub fn capabilities(&self) -> &'static [Capability] {
+        match self {
+            Role::Admin => ALL_CAPABILITIES,
+            Role::Department => DEPARTMENT_CAPABILITIES,
+            Role::Auditor => AUDITOR_CAPABILITIES,
+            Role::Payroll => PAYROLL_CAPABILITIES,
+        }
+    }
+
+    /// Department managers only make sense with at least one department in scope
+    pub fn requires_departments(&self) -> bool {
+        *self == Role::Department
+    }
+}
+
+/// Departments whose employee data an admin user can see
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DepartmentScope {
+    All,
+    Only(Vec<i32>),
+}
+
+impl DepartmentScope {
+    /// Admins see every department; other roles are limited to their departments,
+    /// or see all of them when none are assigned
+    pub fn for_role(role: Role, departments: &[i32]) -> Self {
+        if role == Role::Admin {
+            DepartmentScope::All
+        } else {
+            DepartmentScope::for_departments(departments)
+        }
+    }
+
+    /// Limited to the given departments, or all of them when none are given
+    pub fn for_departments(departments: &[i32]) -> Self {
+        if departments.is_empty() {
+            DepartmentScope::All
+        } else {
+            DepartmentScope::Only(departments.to_vec())
+        }
+    }
+
+    pub fn contains(&self, department: i32) -> bool {
+        match self {
+            DepartmentScope::All => true,
+            DepartmentScope::Only(departments) => departments.contains(&department),
+        }
+    }
+
+    /// Departments to filter queries by, None when unrestricted
+    pub fn departments(&self) -> Option<&[i32]> {
+        match self {
+            DepartmentScope::All => None,
+            DepartmentScope::Only(departments) => Some(departments),
+        }
+    }
+
+    /// Narrow a department filter from a request to the scope. None means no filter;
+    /// a department outside the scope yields an empty list.
+    pub fn filter(&self, requested: Option<i32>) -> Option<Vec<i32>> {
+        match (requested, self) {
+            (Some(department), scope) if scope.contains(department) => Some(vec![department]),
+            (Some(_), _) => Some(Vec::new()),
+            (None, DepartmentScope::All) => None,
+            (None, DepartmentScope::Only(departments)) => Some(departments.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles_round_trip() {
+        for name in ["admin", "department", "auditor", "payroll"] {
+            assert!(Role::parse(name).is_some());
+        }
+        assert_eq!(Role::parse("superuser"), None);
+    }
+
+    #[test]
+    fn test_capabilities_round_trip() {
+        for capability in ALL_CAPABILITIES {
+            assert_eq!(Capability::parse(capability.as_str()), Some(*capability));
+        }
+        assert_eq!(Capability::parse("stats"), None);
+    }
+
+    #[test]
+    fn test_api_keys_only_read() {
+        assert!(Capability::StatsExport.grantable_to_api_keys());
+        assert!(Capability::StatsRead.grantable_to_api_keys());
+        assert!(!Capability::CheckinsWrite.grantable_to_api_keys());
+        assert!(!Capability::AdminUsersManage.grantable_to_api_keys());
+        assert!(!Capability::AuditRead.grantable_to_api_keys());
+    }
+
+    #[test]
+    fn test_admin_has_every_capability() {
+        assert_eq!(Role::Admin.capabilities(), ALL_CAPABILITIES);
+    }
+
+    #[test]
+    fn test_other_roles_cannot_write() {
+        for role in [Role::Department, Role::Auditor, Role::Payroll] {
+            for capability in [
+                Capability::CheckinsWrite,
+                Capability::UsersManage,
+                Capability::SettingsManage,
+                Capability::SessionsManage,
+                Capability::AnomaliesReview,
+                Capability::AdminUsersManage,
+            ] {
+                assert!(!role.capabilities().contains(&capability), "{:?} has {:?}", role, capability);
+            }
+        }
+        assert!(Role::Payroll.capabilities().contains(&Capability::StatsExport));
+        assert!(!Role::Payroll.capabilities().contains(&Capability::UsersRead));
+        assert!(Role::Auditor.capabilities().contains(&Capability::AuditRead));
+        assert!(!Role::Department.capabilities().contains(&Capability::AuditRead));
+        // Department managers only had statistics before capabilities existed
+        assert!(!Role::Department.capabilities().contains(&Capability::CheckinsRead));
+        assert!(!Role::Department.capabilities().contains(&Capability::UsersRead));
+    }
+
+    #[test]
+    fn test_scope() {
+        assert_eq!(DepartmentScope::for_role(Role::Admin, &[3]), DepartmentScope::All);
+        assert_eq!(DepartmentScope::for_role(Role::Auditor, &[]), DepartmentScope::All);
+
+        let scope = DepartmentScope::for_role(Role::Department, &[2, 5]);
+        assert!(scope.contains(5));
+        assert!(!scope.contains(3));
+        assert_eq!(scope.departments(), Some(&[2, 5][..]));
+    }
+
+    #[test]
+    fn test_scope_filter() {
+        let scope = DepartmentScope::Only(vec![2, 5]);
+        assert_eq!(scope.filter(None), Some(vec![2, 5]));
+        assert_eq!(scope.filter(Some(5)), Some(vec![5]));
+        assert_eq!(scope.filter(Some(3)), Some(vec![]));
+
+        assert_eq!(DepartmentScope::All.filter(None), None);
+        assert_eq!(DepartmentScope::All.filter(Some(3)), Some(vec![3]));
+    }
+}
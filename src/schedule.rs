@@ -0,0 +1,669 @@
+// Shift templates, the roster assigning them to employees, and lateness against the scheduled shift
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::collections::HashMap;
+
+use crate::sessions::SessionConfig;
+
+/// Today's date in local time, the date time settings take effect by default
+pub fn local_today() -> NaiveDate {
+    Utc::now().with_timezone(&SessionConfig::global().utc_offset).date_naive()
+}
+
+/// Years monthly statistics can be asked for
+pub const STATS_YEARS: std::ops::RangeInclusive<i32> = 2000..=2100;
+
+/// First day of a month and of the month after it, or None for a month or year out of range
+pub fn month_range(year: i32, month: u32) -> Option<(NaiveDate, NaiveDate)> {
+    if !STATS_YEARS.contains(&year) {
+        return None;
+    }
+    let start = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let end = if month == 12 {
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)?
+    } else {
+        NaiveDate::from_ymd_opt(year, month + 1, 1)?
+    };
+    Some((start, end))
+}
+
+/// Duty times of employees with neither a roster, time settings nor department defaults
+pub fn default_shift() -> Shift {
+    Shift {
+        template_id: None,
+        name: None,
+        start_time: NaiveTime::from_hms_opt(7, 30, 0).unwrap(),
+        end_time: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+        break_minutes: 0,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ShiftTemplate {
+    pub id: i32,
+    pub name: String,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    /// Ends the next day; derived from the times by the database
+    pub crosses_midnight: bool,
+    /// Unpaid break time within the shift
+    pub break_minutes: i32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A template assigned to a user on one date; no template means a day off
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ShiftAssignment {
+    pub id: i32,
+    pub user_id: String,
+    pub date: NaiveDate,
+    pub template_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A cycle of templates repeating from `starts_on`, one entry per day; empty entries are days off
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ShiftRotation {
+    pub id: i32,
+    pub user_id: String,
+    pub starts_on: NaiveDate,
+    /// Last day of the rotation, open-ended when absent
+    pub ends_on: Option<NaiveDate>,
+    pub template_ids: Vec<Option<i32>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ShiftRotation {
+    /// Template of the rotation on `date`, None when the rotation does not cover it
+    pub fn template_on(&self, date: NaiveDate) -> Option<Option<i32>> {
+        if date < self.starts_on || self.ends_on.is_some_and(|end| date > end) || self.template_ids.is_empty() {
+            return None;
+        }
+        let day = (date - self.starts_on).num_days() as usize % self.template_ids.len();
+        Some(self.template_ids[day])
+    }
+}
+
+/// A user's hours on one day of the week, replacing their usual duty times on that day.
+/// No times means the user does not work that weekday.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct WeekdayHours {
+    pub user_id: String,
+    /// Time setting the hours belong to
+    pub effective_from: NaiveDate,
+    /// ISO weekday, 1 for Monday to 7 for Sunday
+    pub weekday: i16,
+    pub on_duty_time: Option<NaiveTime>,
+    pub off_duty_time: Option<NaiveTime>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl WeekdayHours {
+    pub fn shift(&self) -> Option<Shift> {
+        match (self.on_duty_time, self.off_duty_time) {
+            (Some(start_time), Some(end_time)) => Some(Shift { start_time, end_time, ..default_shift() }),
+            _ => None,
+        }
+    }
+}
+
+/// A user's duty times from `effective_from` until their next time setting takes effect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeSettingPeriod {
+    pub effective_from: NaiveDate,
+    pub shift: Shift,
+    /// Hours by ISO weekday; None for weekdays the user does not work
+    pub weekday_hours: HashMap<u32, Option<Shift>>,
+}
+
+impl TimeSettingPeriod {
+    pub fn new(effective_from: NaiveDate, shift: Shift) -> Self {
+        TimeSettingPeriod { effective_from, shift, weekday_hours: HashMap::new() }
+    }
+}
+
+/// Working hours expected of an employee on one day
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shift {
+    /// Template the shift comes from; None for fixed duty times
+    pub template_id: Option<i32>,
+    pub name: Option<String>,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    pub break_minutes: i32,
+}
+
+impl From<&ShiftTemplate> for Shift {
+    fn from(template: &ShiftTemplate) -> Self {
+        Shift {
+            template_id: Some(template.id),
+            name: Some(template.name.clone()),
+            start_time: template.start_time,
+            end_time: template.end_time,
+            break_minutes: template.break_minutes,
+        }
+    }
+}
+
+impl Shift {
+    pub fn crosses_midnight(&self) -> bool {
+        self.end_time <= self.start_time
+    }
+
+    /// Start and end of the shift on `date`, whose times are local to `offset`
+    pub fn bounds(&self, date: NaiveDate, offset: FixedOffset) -> (DateTime<Utc>, DateTime<Utc>) {
+        let start = offset.from_local_datetime(&date.and_time(self.start_time)).unwrap().to_utc();
+        let mut end = offset.from_local_datetime(&date.and_time(self.end_time)).unwrap().to_utc();
+        if self.crosses_midnight() {
+            end += Duration::days(1);
+        }
+        (start, end)
+    }
+
+    /// Minutes of work expected, without the break
+    pub fn scheduled_minutes(&self) -> i64 {
+        let mut length = self.end_time - self.start_time;
+        if self.crosses_midnight() {
+            length += Duration::days(1);
+        }
+        length.num_minutes() - self.break_minutes as i64
+    }
+}
+
+/// Where the schedule of a day comes from, in order of precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShiftSource {
+    Assignment,
+    Rotation,
+    /// The user's hours for that day of the week
+    Weekday,
+    /// The user's fixed on/off duty times
+    TimeSetting,
+    /// The default hours of the user's department
+    Department,
+    Default,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScheduledDay {
+    pub date: NaiveDate,
+    pub source: ShiftSource,
+    /// None on a day off
+    pub shift: Option<Shift>,
+}
+
+/// Everything that decides a user's shifts over a range of dates
+#[derive(Debug, Default)]
+pub struct Roster {
+    pub templates: HashMap<i32, ShiftTemplate>,
+    pub assignments: HashMap<NaiveDate, Option<i32>>,
+    /// The rotation that started last wins where rotations overlap
+    pub rotations: Vec<ShiftRotation>,
+    /// The user's dated duty times, each applying until the next one starts
+    pub time_settings: Vec<TimeSettingPeriod>,
+    /// Dated default hours of the user's department, for days the user has no setting of their own
+    pub department_settings: Vec<TimeSettingPeriod>,
+    /// The user's thresholds, falling back to their department's
+    pub rules: LatenessRules,
+}
+
+/// The period of `periods` in effect on `date`
+fn period_on(periods: &[TimeSettingPeriod], date: NaiveDate) -> Option<&TimeSettingPeriod> {
+    periods
+        .iter()
+        .filter(|period| period.effective_from <= date)
+        .max_by_key(|period| period.effective_from)
+}
+
+impl Roster {
+    /// The shift scheduled on `date`: an assignment for the date, else the rotation
+    /// covering it, else the user's hours for the weekday, else their fixed duty times,
+    /// else their department's default hours, else the default hours
+    pub fn day(&self, date: NaiveDate) -> ScheduledDay {
+        let template = |id: Option<i32>| id.and_then(|id| self.templates.get(&id)).map(Shift::from);
+
+        if let Some(&template_id) = self.assignments.get(&date) {
+            return ScheduledDay { date, source: ShiftSource::Assignment, shift: template(template_id) };
+        }
+
+        let rotation = self.rotations
+            .iter()
+            .filter_map(|rotation| rotation.template_on(date).map(|id| (rotation, id)))
+            .max_by_key(|(rotation, _)| (rotation.starts_on, rotation.id));
+        if let Some((_, template_id)) = rotation {
+            return ScheduledDay { date, source: ShiftSource::Rotation, shift: template(template_id) };
+        }
+
+        if let Some(setting) = period_on(&self.time_settings, date) {
+            return match setting.weekday_hours.get(&date.weekday().number_from_monday()) {
+                Some(shift) => ScheduledDay { date, source: ShiftSource::Weekday, shift: shift.clone() },
+                None => ScheduledDay { date, source: ShiftSource::TimeSetting, shift: Some(setting.shift.clone()) },
+            };
+        }
+
+        match period_on(&self.department_settings, date) {
+            Some(setting) => ScheduledDay { date, source: ShiftSource::Department, shift: Some(setting.shift.clone()) },
+            None => ScheduledDay { date, source: ShiftSource::Default, shift: Some(default_shift()) },
+        }
+    }
+
+    /// Scheduled days from `from` up to but excluding `to`
+    pub fn days(&self, from: NaiveDate, to: NaiveDate) -> Vec<ScheduledDay> {
+        from.iter_days().take_while(|date| *date < to).map(|date| self.day(date)).collect()
+    }
+}
+
+/// Load the roster of a user for dates from `from` up to but excluding `to`
+pub async fn load_roster(pool: &PgPool, user_id: &str, from: NaiveDate, to: NaiveDate) -> Result<Roster, sqlx::Error> {
+    let templates = sqlx::query_as::<_, ShiftTemplate>("SELECT * FROM shift_templates")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|template| (template.id, template))
+        .collect();
+
+    let assignments = sqlx::query_as::<_, (NaiveDate, Option<i32>)>(
+        "SELECT date, template_id FROM shift_assignments WHERE user_id = $1 AND date >= $2 AND date < $3"
+    )
+    .bind(user_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .collect();
+
+    let rotations = sqlx::query_as::<_, ShiftRotation>(
+        r#"
+        SELECT * FROM shift_rotations
+        WHERE user_id = $1 AND starts_on < $3 AND (ends_on IS NULL OR ends_on >= $2)
+        "#
+    )
+    .bind(user_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await?;
+
+    // Settings taking effect after the range cannot apply to it
+    let mut time_settings: Vec<TimeSettingPeriod> = sqlx::query_as::<_, (NaiveDate, NaiveTime, NaiveTime)>(
+        "SELECT effective_from, on_duty_time, off_duty_time FROM user_time_settings WHERE user_id = $1 AND effective_from < $2"
+    )
+    .bind(user_id)
+    .bind(to)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(effective_from, start_time, end_time)| {
+        TimeSettingPeriod::new(effective_from, Shift { start_time, end_time, ..default_shift() })
+    })
+    .collect();
+
+    let weekday_hours = sqlx::query_as::<_, WeekdayHours>(
+        "SELECT * FROM user_weekday_hours WHERE user_id = $1 AND effective_from < $2"
+    )
+    .bind(user_id)
+    .bind(to)
+    .fetch_all(pool)
+    .await?;
+    for hours in weekday_hours {
+        if let Some(setting) = time_settings.iter_mut().find(|setting| setting.effective_from == hours.effective_from) {
+            setting.weekday_hours.insert(hours.weekday as u32, hours.shift());
+        }
+    }
+
+    let department_settings = sqlx::query_as::<_, (NaiveDate, NaiveTime, NaiveTime)>(
+        r#"
+        SELECT dts.effective_from, dts.on_duty_time, dts.off_duty_time
+        FROM department_time_settings dts
+        JOIN user_info ui ON ui.department = dts.department
+        WHERE ui.user_id = $1 AND dts.effective_from < $2
+        "#
+    )
+    .bind(user_id)
+    .bind(to)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(effective_from, start_time, end_time)| {
+        TimeSettingPeriod::new(effective_from, Shift { start_time, end_time, ..default_shift() })
+    })
+    .collect();
+
+    let rules = load_rules(pool, user_id).await?;
+
+    Ok(Roster { templates, assignments, rotations, time_settings, department_settings, rules })
+}
+
+/// Tolerances applied when judging a day against its shift
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, FromRow)]
+pub struct LatenessRules {
+    /// Minutes after the shift start a checkin may come without counting as late
+    pub late_grace_minutes: i32,
+    /// Minutes before the shift end a checkout may come without counting as leaving early
+    pub early_leave_grace_minutes: i32,
+    /// Minutes late, counted from the shift start, from which lateness is major
+    pub major_late_minutes: i32,
+}
+
+impl Default for LatenessRules {
+    /// Any lateness counts, and half an hour of it is major
+    fn default() -> Self {
+        LatenessRules { late_grace_minutes: 0, early_leave_grace_minutes: 0, major_late_minutes: 30 }
+    }
+}
+
+/// Check thresholds given by an admin; absent values are left as they are
+pub fn validate_rules(
+    late_grace_minutes: Option<i32>,
+    early_leave_grace_minutes: Option<i32>,
+    major_late_minutes: Option<i32>,
+) -> Result<(), &'static str> {
+    if late_grace_minutes.is_some_and(|minutes| minutes < 0) {
+        return Err("late_grace_minutes must not be negative");
+    }
+    if early_leave_grace_minutes.is_some_and(|minutes| minutes < 0) {
+        return Err("early_leave_grace_minutes must not be negative");
+    }
+    if major_late_minutes.is_some_and(|minutes| minutes < 1) {
+        return Err("major_late_minutes must be at least 1");
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LateTier {
+    Minor,
+    Major,
+}
+
+/// Thresholds applying to a user: their own, else their department's, else the defaults
+pub async fn load_rules<'e, E: sqlx::PgExecutor<'e>>(executor: E, user_id: &str) -> Result<LatenessRules, sqlx::Error> {
+    let rules = sqlx::query_as::<_, LatenessRules>(
+        r#"
+        SELECT
+            COALESCE(ulr.late_grace_minutes, ds.late_grace_minutes, 0) AS late_grace_minutes,
+            COALESCE(ulr.early_leave_grace_minutes, ds.early_leave_grace_minutes, 0) AS early_leave_grace_minutes,
+            COALESCE(ulr.major_late_minutes, ds.major_late_minutes, 30) AS major_late_minutes
+        FROM user_info ui
+        LEFT JOIN department_settings ds ON ds.department = ui.department
+        LEFT JOIN user_lateness_rules ulr ON ulr.user_id = ui.user_id
+        WHERE ui.user_id = $1
+        "#
+    )
+    .bind(user_id)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(rules.unwrap_or_default())
+}
+
+/// How a day's attendance compares with the scheduled shift
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DayEvaluation {
+    pub is_late: bool,
+    /// Minutes from the shift start to the checkin, a started minute counting in full; 0 unless late
+    pub late_minutes: i64,
+    pub late_tier: Option<LateTier>,
+    pub is_early_leave: bool,
+    /// Minutes from the checkout to the shift end, likewise; 0 unless leaving early
+    pub early_leave_minutes: i64,
+}
+
+/// Whole minutes in a positive number of seconds, rounding a started minute up
+fn started_minutes(seconds: i64) -> i64 {
+    (seconds + 59) / 60
+}
+
+/// Compare the first checkin and last checkout of a day with its shift. A checkin within
+/// the grace period is not late and a checkout within it not early. Nothing counts as
+/// late or early on a day off.
+pub fn evaluate(
+    day: &ScheduledDay,
+    first_checkin: Option<DateTime<Utc>>,
+    last_checkout: Option<DateTime<Utc>>,
+    offset: FixedOffset,
+    rules: &LatenessRules,
+) -> DayEvaluation {
+    let shift = match &day.shift {
+        Some(shift) => shift,
+        None => return DayEvaluation::default(),
+    };
+    let (start, end) = shift.bounds(day.date, offset);
+
+    let late_seconds = first_checkin.map_or(0, |checkin| (checkin - start).num_seconds());
+    let early_seconds = last_checkout.map_or(0, |checkout| (end - checkout).num_seconds());
+    let is_late = late_seconds > i64::from(rules.late_grace_minutes) * 60;
+    let is_early_leave = early_seconds > i64::from(rules.early_leave_grace_minutes) * 60;
+    let late_minutes = if is_late { started_minutes(late_seconds) } else { 0 };
+
+    DayEvaluation {
+        is_late,
+        late_minutes,
+        late_tier: is_late.then_some(if late_minutes >= i64::from(rules.major_late_minutes) {
+            LateTier::Major
+        } else {
+            LateTier::Minor
+        }),
+        is_early_leave,
+        early_leave_minutes: if is_early_leave { started_minutes(early_seconds) } else { 0 },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, day).unwrap()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn template(id: i32, name: &str, start: NaiveTime, end: NaiveTime) -> ShiftTemplate {
+        ShiftTemplate {
+            id,
+            name: name.to_string(),
+            start_time: start,
+            end_time: end,
+            crosses_midnight: end <= start,
+            break_minutes: 30,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn rotation(id: i32, starts_on: NaiveDate, ends_on: Option<NaiveDate>, template_ids: Vec<Option<i32>>) -> ShiftRotation {
+        ShiftRotation { id, user_id: "u1".to_string(), starts_on, ends_on, template_ids, created_at: Utc::now() }
+    }
+
+    fn roster() -> Roster {
+        let mut roster = Roster::default();
+        roster.templates.insert(1, template(1, "Day", time(7, 0), time(15, 0)));
+        roster.templates.insert(2, template(2, "Night", time(22, 0), time(6, 0)));
+        roster
+    }
+
+    fn utc(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        date(day).and_time(time(hour, minute)).and_utc()
+    }
+
+    #[test]
+    fn test_rotation_cycles() {
+        let rotation = rotation(1, date(4), Some(date(20)), vec![Some(1), Some(1), Some(2), None]);
+        assert_eq!(rotation.template_on(date(3)), None);
+        assert_eq!(rotation.template_on(date(4)), Some(Some(1)));
+        assert_eq!(rotation.template_on(date(6)), Some(Some(2)));
+        assert_eq!(rotation.template_on(date(7)), Some(None));
+        assert_eq!(rotation.template_on(date(8)), Some(Some(1)));
+        assert_eq!(rotation.template_on(date(21)), None);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let mut roster = roster();
+        assert_eq!(roster.day(date(5)).source, ShiftSource::Default);
+        assert_eq!(roster.day(date(5)).shift, Some(default_shift()));
+
+        let mut setting = TimeSettingPeriod::new(date(1), Shift { start_time: time(9, 0), end_time: time(17, 0), ..default_shift() });
+        roster.time_settings.push(setting.clone());
+        assert_eq!(roster.day(date(5)).source, ShiftSource::TimeSetting);
+
+        // 2024-03-08 is a Friday, 2024-03-09 a Saturday
+        setting.weekday_hours.insert(5, Some(Shift { start_time: time(9, 0), end_time: time(13, 0), ..default_shift() }));
+        setting.weekday_hours.insert(6, None);
+        roster.time_settings = vec![setting];
+        assert_eq!(roster.day(date(8)).source, ShiftSource::Weekday);
+        assert_eq!(roster.day(date(8)).shift.unwrap().end_time, time(13, 0));
+        assert_eq!(roster.day(date(9)), ScheduledDay { date: date(9), source: ShiftSource::Weekday, shift: None });
+        assert_eq!(roster.day(date(7)).source, ShiftSource::TimeSetting);
+
+        roster.rotations.push(rotation(1, date(1), None, vec![Some(1)]));
+        roster.rotations.push(rotation(2, date(5), None, vec![Some(2)]));
+        assert_eq!(roster.day(date(4)).shift.unwrap().template_id, Some(1));
+        let day = roster.day(date(5));
+        assert_eq!(day.source, ShiftSource::Rotation);
+        assert_eq!(day.shift.unwrap().template_id, Some(2));
+
+        roster.assignments.insert(date(6), None);
+        roster.assignments.insert(date(7), Some(1));
+        assert_eq!(roster.day(date(6)), ScheduledDay { date: date(6), source: ShiftSource::Assignment, shift: None });
+        assert_eq!(roster.day(date(7)).shift.unwrap().name.as_deref(), Some("Day"));
+
+        assert_eq!(roster.days(date(4), date(8)).len(), 4);
+        assert_eq!(roster.day(date(9)).source, ShiftSource::Rotation);
+    }
+
+    #[test]
+    fn test_time_settings_apply_from_their_date() {
+        let mut roster = roster();
+        let hours = |start: u32| Shift { start_time: time(start, 0), end_time: time(17, 0), ..default_shift() };
+        let mut part_time = TimeSettingPeriod::new(date(11), hours(9));
+        part_time.weekday_hours.insert(1, None);
+        roster.time_settings.push(part_time);
+        roster.time_settings.push(TimeSettingPeriod::new(date(1), hours(8)));
+        roster.time_settings.push(TimeSettingPeriod::new(date(20), hours(10)));
+
+        assert_eq!(roster.day(date(10)).shift, Some(hours(8)));
+        assert_eq!(roster.day(date(11)).shift, None);
+        assert_eq!(roster.day(date(12)).shift, Some(hours(9)));
+        assert_eq!(roster.day(date(19)).shift, Some(hours(9)));
+        assert_eq!(roster.day(date(20)).shift, Some(hours(10)));
+
+        roster.time_settings.retain(|setting| setting.effective_from != date(1));
+        assert_eq!(roster.day(date(10)).source, ShiftSource::Default);
+
+        // Days before the user's own setting follow their department
+        roster.department_settings.push(TimeSettingPeriod::new(date(1), hours(7)));
+        roster.department_settings.push(TimeSettingPeriod::new(date(5), hours(6)));
+        assert_eq!(roster.day(date(4)).shift, Some(hours(7)));
+        let day = roster.day(date(10));
+        assert_eq!(day.source, ShiftSource::Department);
+        assert_eq!(day.shift, Some(hours(6)));
+        assert_eq!(roster.day(date(12)).shift, Some(hours(9)));
+    }
+
+    #[test]
+    fn test_shift_bounds_and_length() {
+        let utc_offset = FixedOffset::east_opt(0).unwrap();
+        let night = Shift::from(&template(2, "Night", time(22, 0), time(6, 0)));
+        assert!(night.crosses_midnight());
+        assert_eq!(night.bounds(date(5), utc_offset), (utc(5, 22, 0), utc(6, 6, 0)));
+        assert_eq!(night.scheduled_minutes(), 8 * 60 - 30);
+
+        let aest = FixedOffset::east_opt(10 * 3600).unwrap();
+        let day = Shift::from(&template(1, "Day", time(7, 0), time(15, 0)));
+        assert_eq!(day.bounds(date(5), aest), (utc(4, 21, 0), utc(5, 5, 0)));
+    }
+
+    #[test]
+    fn test_evaluate_against_scheduled_shift() {
+        let utc_offset = FixedOffset::east_opt(0).unwrap();
+        let rules = LatenessRules::default();
+        let mut roster = roster();
+        roster.assignments.insert(date(5), Some(2));
+        roster.assignments.insert(date(6), None);
+
+        // Night shift from 22:00 to 06:00 the next morning
+        let night = roster.day(date(5));
+        assert_eq!(
+            evaluate(&night, Some(utc(5, 21, 55)), Some(utc(6, 6, 5)), utc_offset, &rules),
+            DayEvaluation::default()
+        );
+        assert_eq!(
+            evaluate(&night, Some(utc(5, 22, 10)), Some(utc(6, 5, 0)), utc_offset, &rules),
+            DayEvaluation {
+                is_late: true,
+                late_minutes: 10,
+                late_tier: Some(LateTier::Minor),
+                is_early_leave: true,
+                early_leave_minutes: 60,
+            }
+        );
+
+        let off = roster.day(date(6));
+        assert_eq!(
+            evaluate(&off, Some(utc(6, 12, 0)), Some(utc(6, 13, 0)), utc_offset, &rules),
+            DayEvaluation::default()
+        );
+
+        let default_day = roster.day(date(7));
+        let evaluation = evaluate(&default_day, Some(utc(7, 7, 31)), None, utc_offset, &rules);
+        assert!(evaluation.is_late && !evaluation.is_early_leave);
+        assert_eq!(evaluation.late_minutes, 1);
+    }
+
+    #[test]
+    fn test_evaluate_grace_and_tiers() {
+        let utc_offset = FixedOffset::east_opt(0).unwrap();
+        let rules = LatenessRules { late_grace_minutes: 5, early_leave_grace_minutes: 10, major_late_minutes: 15 };
+        let day = roster().day(date(7));
+        let checkin = |hour, minute, second| Some(date(7).and_hms_opt(hour, minute, second).unwrap().and_utc());
+
+        // Within the grace periods after 07:30 and before 17:00
+        let evaluation = evaluate(&day, checkin(7, 35, 0), Some(utc(7, 16, 50)), utc_offset, &rules);
+        assert_eq!(evaluation, DayEvaluation::default());
+
+        // Past the grace period, lateness counts from the shift start
+        let evaluation = evaluate(&day, checkin(7, 35, 1), Some(utc(7, 16, 49)), utc_offset, &rules);
+        assert_eq!(evaluation.late_minutes, 6);
+        assert_eq!(evaluation.late_tier, Some(LateTier::Minor));
+        assert_eq!(evaluation.early_leave_minutes, 11);
+
+        let evaluation = evaluate(&day, checkin(7, 45, 0), None, utc_offset, &rules);
+        assert_eq!(evaluation.late_minutes, 15);
+        assert_eq!(evaluation.late_tier, Some(LateTier::Major));
+
+        // The seconds the default rules used to count are still late without a grace period
+        let evaluation = evaluate(&day, checkin(7, 30, 1), None, utc_offset, &LatenessRules::default());
+        assert_eq!((evaluation.late_minutes, evaluation.late_tier), (1, Some(LateTier::Minor)));
+    }
+
+    #[test]
+    fn test_validate_rules() {
+        assert!(validate_rules(Some(0), Some(0), Some(1)).is_ok());
+        assert!(validate_rules(None, None, None).is_ok());
+        assert!(validate_rules(Some(-1), None, None).is_err());
+        assert!(validate_rules(None, Some(-5), None).is_err());
+        assert!(validate_rules(None, None, Some(0)).is_err());
+    }
+
+    #[test]
+    fn test_month_range() {
+        assert_eq!(month_range(2024, 3), Some((date(1), NaiveDate::from_ymd_opt(2024, 4, 1).unwrap())));
+        assert_eq!(
+            month_range(2024, 12),
+            Some((NaiveDate::from_ymd_opt(2024, 12, 1).unwrap(), NaiveDate::from_ymd_opt(2025, 1, 1).unwrap()))
+        );
+        assert_eq!(month_range(2024, 0), None);
+        assert_eq!(month_range(2024, 13), None);
+        assert_eq!(month_range(i32::MAX, 12), None);
+        assert_eq!(month_range(-5, 1), None);
+    }
+}
@@ -0,0 +1,960 @@
+// Chronological session pairing for attendance punches
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveTime, Utc};
+use lazy_static::lazy_static;
+use sqlx::{PgConnection, PgPool};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::anomalies;
+use crate::models::{AttendanceSession, Checkin};
+use crate::schedule;
+use crate::timezone_config::TimezoneConfig;
+
+/// Default maximum length of a single IN/OUT pair (see DURATION_LIMITS_EXPLAINED.md)
+pub const DEFAULT_MAX_SHIFT_HOURS: i64 = 16;
+
+lazy_static! {
+    static ref SESSION_CONFIG: SessionConfig = SessionConfig::from_env();
+}
+
+/// Which calendar date a session is filed under
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShiftDateRule {
+    /// Local date of the checkin (a night shift belongs to the evening it started)
+    CheckinDate,
+    /// Local date of the checkout
+    CheckoutDate,
+    /// Work day starts at the given local time, e.g. 06:00 files a 02:00 checkin under the previous day
+    WorkdayStart(NaiveTime),
+}
+
+impl ShiftDateRule {
+    /// Parse "checkin", "checkout" or "workday_start:HH:MM"
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "checkin" => Some(ShiftDateRule::CheckinDate),
+            "checkout" => Some(ShiftDateRule::CheckoutDate),
+            _ => value
+                .strip_prefix("workday_start:")
+                .and_then(|time| NaiveTime::parse_from_str(time, "%H:%M").ok())
+                .map(ShiftDateRule::WorkdayStart),
+        }
+    }
+}
+
+/// Configuration of the session pairing engine
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// An OUT further than this from the open IN does not close it
+    pub max_shift: Duration,
+    pub date_rule: ShiftDateRule,
+    /// Offset used to turn punch timestamps into local dates
+    pub utc_offset: FixedOffset,
+    /// A session still open this long after its checkin is closed automatically
+    pub auto_close_after: Duration,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            max_shift: Duration::hours(DEFAULT_MAX_SHIFT_HOURS),
+            date_rule: ShiftDateRule::CheckinDate,
+            utc_offset: TimezoneConfig::local().local_offset,
+            auto_close_after: Duration::hours(DEFAULT_MAX_SHIFT_HOURS),
+        }
+    }
+}
+
+impl SessionConfig {
+    /// Read MAX_SHIFT_HOURS, SHIFT_DATE_RULE and AUTO_CLOSE_AFTER_HOURS, falling back to the defaults
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+
+        if let Ok(value) = std::env::var("MAX_SHIFT_HOURS") {
+            match value.parse::<i64>() {
+                Ok(hours) if hours > 0 => config.max_shift = Duration::hours(hours),
+                _ => log::warn!("Ignoring invalid MAX_SHIFT_HOURS: {}", value),
+            }
+        }
+
+        if let Ok(value) = std::env::var("SHIFT_DATE_RULE") {
+            match ShiftDateRule::parse(&value) {
+                Some(rule) => config.date_rule = rule,
+                None => log::warn!("Ignoring invalid SHIFT_DATE_RULE: {}", value),
+            }
+        }
+
+        if let Ok(value) = std::env::var("AUTO_CLOSE_AFTER_HOURS") {
+            match value.parse::<i64>() {
+                Ok(hours) if hours > 0 => config.auto_close_after = Duration::hours(hours),
+                _ => log::warn!("Ignoring invalid AUTO_CLOSE_AFTER_HOURS: {}", value),
+            }
+        }
+
+        config
+    }
+
+    /// Process-wide configuration loaded from the environment on first use
+    pub fn global() -> &'static SessionConfig {
+        &SESSION_CONFIG
+    }
+
+    fn local_date(&self, at: DateTime<Utc>) -> NaiveDate {
+        at.with_timezone(&self.utc_offset).date_naive()
+    }
+
+    /// Date a session belongs to under the configured rule
+    pub fn session_date(&self, checkin: Option<DateTime<Utc>>, checkout: Option<DateTime<Utc>>) -> NaiveDate {
+        let anchor = match self.date_rule {
+            ShiftDateRule::CheckoutDate => checkout.or(checkin),
+            _ => checkin.or(checkout),
+        }
+        .expect("a session has at least one punch");
+
+        match self.date_rule {
+            ShiftDateRule::WorkdayStart(start) => {
+                let since_midnight = start - NaiveTime::MIN;
+                (anchor.with_timezone(&self.utc_offset).naive_local() - since_midnight).date()
+            }
+            _ => self.local_date(anchor),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PunchAction {
+    In,
+    Out,
+}
+
+impl PunchAction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "IN" => Some(PunchAction::In),
+            "OUT" => Some(PunchAction::Out),
+            _ => None,
+        }
+    }
+}
+
+/// A single IN or OUT punch as seen by the pairing engine
+#[derive(Debug, Clone, PartialEq)]
+pub struct Punch {
+    pub id: i32,
+    pub action: PunchAction,
+    pub at: DateTime<Utc>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Device clock skew, when the punch was flagged for it
+    pub flagged_skew_seconds: Option<i64>,
+    /// Server time the punch arrived, unset for older rows
+    pub received_at: Option<DateTime<Utc>>,
+}
+
+impl Punch {
+    pub fn from_checkin(checkin: &Checkin) -> Option<Self> {
+        Some(Punch {
+            id: checkin.id,
+            action: PunchAction::parse(&checkin.action)?,
+            at: checkin.created_at,
+            latitude: checkin.latitude,
+            longitude: checkin.longitude,
+            flagged_skew_seconds: if checkin.clock_skew_flagged { checkin.clock_skew_seconds } else { None },
+            received_at: checkin.received_at,
+        })
+    }
+}
+
+/// A session produced by the engine. Either side may be missing:
+/// an IN that was never closed, or an OUT with no IN before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairedSession {
+    pub date: NaiveDate,
+    pub checkin: Option<Punch>,
+    pub checkout: Option<Punch>,
+}
+
+impl PairedSession {
+    /// Time the session starts at, used for ordering within a date
+    pub fn start_time(&self) -> DateTime<Utc> {
+        self.checkin
+            .as_ref()
+            .or(self.checkout.as_ref())
+            .map(|p| p.at)
+            .expect("a session has at least one punch")
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.checkin.is_some() && self.checkout.is_some()
+    }
+
+    /// Worked minutes, rounded the same way as the update_session_duration trigger
+    pub fn duration_minutes(&self) -> Option<i32> {
+        match (&self.checkin, &self.checkout) {
+            (Some(checkin), Some(checkout)) => {
+                Some(((checkout.at - checkin.at).num_seconds() as f64 / 60.0).round() as i32)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PairingResult {
+    pub sessions: Vec<PairedSession>,
+    /// Extra INs received while a session was already open, with the IN they collided with
+    pub ignored: Vec<(Punch, Punch)>,
+    /// IN/OUT pairs too far apart to form a session
+    pub overlong: Vec<(Punch, Punch)>,
+}
+
+impl PairingResult {
+    /// Sessions kept in attendance_sessions. An OUT without an IN is only recorded as an
+    /// anomaly; stored, it would start at the OUT and pass for the day's first checkin.
+    pub fn stored_sessions(&self) -> impl Iterator<Item = &PairedSession> {
+        self.sessions.iter().filter(|s| s.checkin.is_some())
+    }
+}
+
+/// Walk a user's punches in time order and pair them into sessions.
+///
+/// An OUT closes the open IN if it is no more than `max_shift` later; otherwise the IN
+/// is left incomplete and the OUT stands alone. An IN arriving while a session has been
+/// open for longer than `max_shift` starts a new session; a closer one is ignored.
+pub fn pair_punches(punches: &[Punch], config: &SessionConfig) -> PairingResult {
+    let mut sorted: Vec<&Punch> = punches.iter().collect();
+    sorted.sort_by_key(|p| (p.at, p.id));
+
+    let mut result = PairingResult::default();
+    let mut open: Option<&Punch> = None;
+
+    let push = |result: &mut PairingResult, checkin: Option<&Punch>, checkout: Option<&Punch>| {
+        result.sessions.push(PairedSession {
+            date: config.session_date(checkin.map(|p| p.at), checkout.map(|p| p.at)),
+            checkin: checkin.cloned(),
+            checkout: checkout.cloned(),
+        });
+    };
+
+    for punch in sorted {
+        match punch.action {
+            PunchAction::In => match open {
+                Some(current) if punch.at - current.at <= config.max_shift => {
+                    result.ignored.push((punch.clone(), current.clone()));
+                }
+                Some(current) => {
+                    push(&mut result, Some(current), None);
+                    open = Some(punch);
+                }
+                None => open = Some(punch),
+            },
+            PunchAction::Out => match open.take() {
+                Some(current) if punch.at - current.at <= config.max_shift => {
+                    push(&mut result, Some(current), Some(punch));
+                }
+                Some(current) => {
+                    result.overlong.push((current.clone(), punch.clone()));
+                    push(&mut result, Some(current), None);
+                    push(&mut result, None, Some(punch));
+                }
+                None => push(&mut result, None, Some(punch)),
+            },
+        }
+    }
+
+    if let Some(current) = open {
+        push(&mut result, Some(current), None);
+    }
+
+    result.sessions.sort_by_key(|s| s.start_time());
+    result
+}
+
+/// How a forgotten session is closed, set per department
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoClosePolicy {
+    /// Close at the user's off-duty time, if that falls within the max shift
+    OffDuty,
+    /// Close at the checkin time, counting zero minutes
+    Zero,
+}
+
+impl AutoClosePolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off_duty" => Some(AutoClosePolicy::OffDuty),
+            "zero" => Some(AutoClosePolicy::Zero),
+            _ => None,
+        }
+    }
+}
+
+/// Checkout time given to a session that was never closed. Under `OffDuty` this is the
+/// end of the first shift scheduled on `days` (the session's date and the day before, for
+/// night shifts) that ends after the checkin; sessions without one, e.g. on a day off, or
+/// where it would exceed the max shift, fall back to zero minutes.
+pub fn auto_close_time(
+    checkin: DateTime<Utc>,
+    days: &[schedule::ScheduledDay],
+    policy: AutoClosePolicy,
+    config: &SessionConfig,
+) -> DateTime<Utc> {
+    if policy == AutoClosePolicy::Zero {
+        return checkin;
+    }
+
+    days.iter()
+        .filter_map(|day| day.shift.as_ref().map(|shift| shift.bounds(day.date, config.utc_offset).1))
+        .filter(|end| *end > checkin)
+        .min()
+        .filter(|end| *end - checkin <= config.max_shift)
+        .unwrap_or(checkin)
+}
+
+/// Close sessions still open `auto_close_after` past their checkin, marking them auto_closed.
+/// Returns the sessions that were closed.
+pub async fn auto_close_open_sessions(
+    pool: &PgPool,
+    now: DateTime<Utc>,
+    config: &SessionConfig,
+) -> Result<Vec<AttendanceSession>, sqlx::Error> {
+    let open = sqlx::query_as::<_, (i32, String, NaiveDate, DateTime<Utc>, Option<String>)>(
+        r#"
+        SELECT s.id, s.user_id, s.date, s.checkin_time, ds.auto_close_policy
+        FROM attendance_sessions s
+        LEFT JOIN user_info ui ON ui.user_id = s.user_id
+        LEFT JOIN department_settings ds ON ds.department = ui.department
+        WHERE s.checkout_time IS NULL AND s.checkin_time < $1
+        ORDER BY s.checkin_time
+        "#
+    )
+    .bind(now - config.auto_close_after)
+    .fetch_all(pool)
+    .await?;
+
+    // The shifts a session may belong to come from the same schedule lateness is judged by
+    let mut spans: HashMap<&str, (NaiveDate, NaiveDate)> = HashMap::new();
+    for (_, user_id, date, _, _) in &open {
+        let span = spans.entry(user_id).or_insert((*date, *date));
+        *span = (span.0.min(*date), span.1.max(*date));
+    }
+    let mut rosters = HashMap::with_capacity(spans.len());
+    for (user_id, (first, last)) in spans {
+        let roster = schedule::load_roster(pool, user_id, first - Duration::days(1), last + Duration::days(1)).await?;
+        rosters.insert(user_id, roster);
+    }
+
+    let mut closed = Vec::with_capacity(open.len());
+    for (id, user_id, date, checkin_time, policy) in &open {
+        let policy = policy
+            .as_deref()
+            .and_then(AutoClosePolicy::parse)
+            .unwrap_or(AutoClosePolicy::OffDuty);
+        let roster = &rosters[user_id.as_str()];
+        let days = [roster.day(*date - Duration::days(1)), roster.day(*date)];
+        let checkout_time = auto_close_time(*checkin_time, &days, policy, config);
+
+        let mut tx = pool.begin().await?;
+        lock_user_sessions(&mut tx, user_id).await?;
+
+        // The duration trigger fills in duration_minutes and is_complete
+        let session = sqlx::query_as::<_, AttendanceSession>(
+            "UPDATE attendance_sessions
+             SET checkout_time = $2, auto_closed = true, auto_closed_at = NOW()
+             WHERE id = $1 AND checkout_time IS NULL
+             RETURNING *"
+        )
+        .bind(id)
+        .bind(checkout_time)
+        .fetch_optional(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        closed.extend(session);
+    }
+
+    Ok(closed)
+}
+
+/// Find the span of punches whose pairing can be affected by changes within [from, to].
+/// Punches separated by more than `max_shift` never pair, so the span stops at such gaps.
+async fn affected_span(
+    conn: &mut PgConnection,
+    user_id: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    config: &SessionConfig,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), sqlx::Error> {
+    let max_shift_secs = config.max_shift.num_seconds() as f64;
+
+    let span_start = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+        "SELECT MAX(created_at) FROM (
+            SELECT created_at, created_at - LAG(created_at) OVER (ORDER BY created_at) AS gap
+            FROM checkins WHERE user_id = $1 AND created_at <= $2 AND NOT held
+         ) t WHERE gap IS NULL OR gap > make_interval(secs => $3)"
+    )
+    .bind(user_id)
+    .bind(from)
+    .bind(max_shift_secs)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let span_end = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+        "SELECT MIN(created_at) FROM (
+            SELECT created_at, LEAD(created_at) OVER (ORDER BY created_at) - created_at AS gap
+            FROM checkins WHERE user_id = $1 AND created_at >= $2 AND NOT held
+         ) t WHERE gap IS NULL OR gap > make_interval(secs => $3)"
+    )
+    .bind(user_id)
+    .bind(to)
+    .bind(max_shift_secs)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok((
+        span_start.map_or(from, |start| start.min(from)),
+        span_end.map_or(to, |end| end.max(to)),
+    ))
+}
+
+/// Sessions deleted and inserted by one rebuild
+#[derive(Debug, Default)]
+pub struct SessionRebuild {
+    pub removed: Vec<AttendanceSession>,
+    pub added: Vec<AttendanceSession>,
+}
+
+impl SessionRebuild {
+    /// Drop sessions that were deleted and re-inserted with the same times,
+    /// leaving only what actually changed
+    pub fn into_changes(self) -> SessionRebuild {
+        fn key(s: &AttendanceSession) -> (NaiveDate, DateTime<Utc>, Option<DateTime<Utc>>) {
+            (s.date, s.checkin_time, s.checkout_time)
+        }
+
+        let mut added = self.added;
+        let mut removed = Vec::new();
+        for old in self.removed {
+            match added.iter().position(|new| key(new) == key(&old)) {
+                Some(index) => {
+                    added.remove(index);
+                }
+                None => removed.push(old),
+            }
+        }
+
+        SessionRebuild { removed, added }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty()
+    }
+}
+
+/// Serialize changes to a user's sessions until the caller's transaction ends. A sync, an
+/// admin edit and auto-close may otherwise rebuild the same span at once. Writes to the
+/// user's check-ins take the same lock for their sync sequence values.
+pub async fn lock_user_sessions(conn: &mut PgConnection, user_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Re-pair a user's punches around [from, to] and replace the derived attendance_sessions
+/// and open punch anomalies. attendance_summary follows through the session triggers.
+/// Punches held for device approval are left out.
+pub async fn rebuild_user_sessions(
+    conn: &mut PgConnection,
+    user_id: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    config: &SessionConfig,
+) -> Result<SessionRebuild, sqlx::Error> {
+    lock_user_sessions(conn, user_id).await?;
+    let (span_start, span_end) = affected_span(conn, user_id, from, to, config).await?;
+
+    let checkins = sqlx::query_as::<_, Checkin>(
+        "SELECT * FROM checkins
+         WHERE user_id = $1 AND created_at BETWEEN $2 AND $3 AND NOT held
+         ORDER BY created_at, id"
+    )
+    .bind(user_id)
+    .bind(span_start)
+    .bind(span_end)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let punches: Vec<Punch> = checkins.iter().filter_map(Punch::from_checkin).collect();
+    let pairing = pair_punches(&punches, config);
+
+    let checkin_ids: Vec<i32> = checkins.iter().map(|c| c.id).collect();
+    let detected = anomalies::detect(&punches, &pairing, Utc::now(), config);
+    anomalies::record_user_anomalies(conn, user_id, span_start, span_end, &checkin_ids, &detected).await?;
+
+    let removed = sqlx::query_as::<_, AttendanceSession>(
+        "DELETE FROM attendance_sessions
+         WHERE user_id = $1 AND checkin_time BETWEEN $2 AND $3
+         RETURNING *"
+    )
+    .bind(user_id)
+    .bind(span_start)
+    .bind(span_end)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    // Sessions that were auto-closed keep their checkout while they are still unclosed by a punch
+    let auto_closed: HashMap<DateTime<Utc>, &AttendanceSession> = removed.iter()
+        .filter(|s| s.auto_closed && s.checkout_time.is_some())
+        .map(|s| (s.checkin_time, s))
+        .collect();
+
+    let stored: Vec<&PairedSession> = pairing.stored_sessions().collect();
+    let count = stored.len();
+    let mut dates = Vec::with_capacity(count);
+    let mut numbers = Vec::with_capacity(count);
+    let mut checkin_times = Vec::with_capacity(count);
+    let mut checkout_times = Vec::with_capacity(count);
+    let mut durations = Vec::with_capacity(count);
+    let mut checkin_latitudes = Vec::with_capacity(count);
+    let mut checkin_longitudes = Vec::with_capacity(count);
+    let mut checkout_latitudes = Vec::with_capacity(count);
+    let mut checkout_longitudes = Vec::with_capacity(count);
+    let mut complete = Vec::with_capacity(count);
+    let mut auto_closed_flags = Vec::with_capacity(count);
+    let mut auto_closed_ats = Vec::with_capacity(count);
+
+    for (index, session) in stored.iter().enumerate() {
+        let carried = match (&session.checkin, &session.checkout) {
+            (Some(checkin), None) => auto_closed.get(&checkin.at).copied(),
+            _ => None,
+        };
+        let (checkout_time, duration_minutes) = match carried {
+            Some(closed) => (closed.checkout_time, closed.duration_minutes),
+            None => (session.checkout.as_ref().map(|p| p.at), session.duration_minutes()),
+        };
+
+        dates.push(session.date);
+        numbers.push(-1_000_000 - index as i32);
+        checkin_times.push(session.start_time());
+        checkout_times.push(checkout_time);
+        durations.push(duration_minutes);
+        checkin_latitudes.push(session.checkin.as_ref().and_then(|p| p.latitude));
+        checkin_longitudes.push(session.checkin.as_ref().and_then(|p| p.longitude));
+        checkout_latitudes.push(session.checkout.as_ref().and_then(|p| p.latitude));
+        checkout_longitudes.push(session.checkout.as_ref().and_then(|p| p.longitude));
+        complete.push(session.is_complete() || carried.is_some());
+        auto_closed_flags.push(carried.is_some());
+        auto_closed_ats.push(carried.and_then(|closed| closed.auto_closed_at));
+    }
+
+    // Insert in one statement with temporary negative numbers, then renumber each date in time order
+    let added_ids = sqlx::query_scalar::<_, i32>(
+        "INSERT INTO attendance_sessions
+         (user_id, date, session_number, checkin_time, checkout_time, duration_minutes,
+          checkin_latitude, checkin_longitude, checkout_latitude, checkout_longitude, is_complete,
+          auto_closed, auto_closed_at)
+         SELECT $1, t.*
+         FROM UNNEST($2::date[], $3::int[], $4::timestamptz[], $5::timestamptz[], $6::int[],
+                     $7::float8[], $8::float8[], $9::float8[], $10::float8[], $11::bool[],
+                     $12::bool[], $13::timestamptz[]) AS t
+         RETURNING id"
+    )
+    .bind(user_id)
+    .bind(&dates)
+    .bind(&numbers)
+    .bind(&checkin_times)
+    .bind(&checkout_times)
+    .bind(&durations)
+    .bind(&checkin_latitudes)
+    .bind(&checkin_longitudes)
+    .bind(&checkout_latitudes)
+    .bind(&checkout_longitudes)
+    .bind(&complete)
+    .bind(&auto_closed_flags)
+    .bind(&auto_closed_ats)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let dates: BTreeSet<NaiveDate> = removed.iter().map(|s| s.date)
+        .chain(stored.iter().map(|s| s.date))
+        .collect();
+    renumber_sessions(conn, user_id, &dates.into_iter().collect::<Vec<_>>()).await?;
+
+    let added = sqlx::query_as::<_, AttendanceSession>(
+        "SELECT * FROM attendance_sessions WHERE id = ANY($1) ORDER BY checkin_time"
+    )
+    .bind(&added_ids)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(SessionRebuild { removed, added })
+}
+
+/// Give the sessions of each date consecutive numbers ordered by checkin time
+async fn renumber_sessions(conn: &mut PgConnection, user_id: &str, dates: &[NaiveDate]) -> Result<(), sqlx::Error> {
+    // Two passes through negative numbers so the unique constraint never sees a clash
+    sqlx::query(
+        "UPDATE attendance_sessions s SET session_number = -r.rn
+         FROM (
+            SELECT id, ROW_NUMBER() OVER (PARTITION BY date ORDER BY checkin_time, id) AS rn
+            FROM attendance_sessions WHERE user_id = $1 AND date = ANY($2)
+         ) r
+         WHERE s.id = r.id"
+    )
+    .bind(user_id)
+    .bind(dates)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "UPDATE attendance_sessions SET session_number = -session_number
+         WHERE user_id = $1 AND date = ANY($2) AND session_number < 0"
+    )
+    .bind(user_id)
+    .bind(dates)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Remove sessions earlier versions stored for an OUT without an IN: zero minutes long,
+/// starting and ending at an OUT punch with no IN at the same time
+pub async fn remove_orphan_out_sessions(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM attendance_sessions s
+        WHERE s.checkout_time = s.checkin_time
+          AND NOT s.auto_closed
+          AND EXISTS (
+              SELECT 1 FROM checkins c
+              WHERE c.user_id = s.user_id AND c.created_at = s.checkin_time AND c.action = 'OUT'
+          )
+          AND NOT EXISTS (
+              SELECT 1 FROM checkins c
+              WHERE c.user_id = s.user_id AND c.created_at = s.checkin_time AND c.action = 'IN'
+          )
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn utc_config() -> SessionConfig {
+        SessionConfig {
+            max_shift: Duration::hours(DEFAULT_MAX_SHIFT_HOURS),
+            date_rule: ShiftDateRule::CheckinDate,
+            utc_offset: FixedOffset::east_opt(0).unwrap(),
+            auto_close_after: Duration::hours(DEFAULT_MAX_SHIFT_HOURS),
+        }
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 12, day, hour, minute, 0).unwrap()
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 12, day).unwrap()
+    }
+
+    fn punches(list: &[(PunchAction, DateTime<Utc>)]) -> Vec<Punch> {
+        list.iter()
+            .enumerate()
+            .map(|(i, (action, at))| Punch {
+                id: i as i32 + 1,
+                action: *action,
+                at: *at,
+                latitude: None,
+                longitude: None,
+                flagged_skew_seconds: None,
+                received_at: None,
+            })
+            .collect()
+    }
+
+    use PunchAction::{In, Out};
+
+    #[test]
+    fn test_normal_day_shift() {
+        let result = pair_punches(&punches(&[(In, at(4, 8, 0)), (Out, at(4, 17, 0))]), &utc_config());
+        assert_eq!(result.sessions.len(), 1);
+        assert_eq!(result.sessions[0].date, date(4));
+        assert_eq!(result.sessions[0].duration_minutes(), Some(540));
+        assert!(result.sessions[0].is_complete());
+    }
+
+    #[test]
+    fn test_night_shift_crosses_midnight() {
+        let result = pair_punches(&punches(&[(In, at(4, 23, 30)), (Out, at(5, 1, 30))]), &utc_config());
+        assert_eq!(result.sessions.len(), 1);
+        assert_eq!(result.sessions[0].date, date(4));
+        assert_eq!(result.sessions[0].duration_minutes(), Some(120));
+
+        let result = pair_punches(&punches(&[(In, at(4, 22, 30)), (Out, at(5, 6, 30))]), &utc_config());
+        assert_eq!(result.sessions.len(), 1);
+        assert_eq!(result.sessions[0].duration_minutes(), Some(480));
+    }
+
+    #[test]
+    fn test_two_minute_session_across_midnight() {
+        let result = pair_punches(&punches(&[(In, at(4, 23, 59)), (Out, at(5, 0, 1))]), &utc_config());
+        assert_eq!(result.sessions.len(), 1);
+        assert_eq!(result.sessions[0].duration_minutes(), Some(2));
+    }
+
+    #[test]
+    fn test_long_shifts_within_limit() {
+        // 20:00 -> 08:00 (12 hours) and mining 22:00 -> 12:00 (14 hours)
+        let result = pair_punches(&punches(&[(In, at(4, 20, 0)), (Out, at(5, 8, 0))]), &utc_config());
+        assert_eq!(result.sessions[0].duration_minutes(), Some(720));
+
+        let result = pair_punches(&punches(&[(In, at(4, 22, 0)), (Out, at(5, 12, 0))]), &utc_config());
+        assert_eq!(result.sessions.len(), 1);
+        assert_eq!(result.sessions[0].duration_minutes(), Some(840));
+    }
+
+    #[test]
+    fn test_checkout_beyond_limit_is_not_paired() {
+        // 08:00 -> 08:00 next day (24 hours) and a clock jump producing an 18 hour pair
+        for checkout in [at(5, 8, 0), at(5, 2, 0)] {
+            let result = pair_punches(&punches(&[(In, at(4, 8, 0)), (Out, checkout)]), &utc_config());
+            assert_eq!(result.sessions.len(), 2);
+            assert!(result.sessions[0].checkout.is_none());
+            assert!(result.sessions[1].checkin.is_none());
+            assert_eq!(result.sessions[1].date, date(5));
+            assert_eq!(result.overlong.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_orphan_out_is_not_stored() {
+        let result = pair_punches(
+            &punches(&[(Out, at(4, 7, 0)), (In, at(4, 8, 0)), (Out, at(4, 17, 0))]),
+            &utc_config(),
+        );
+        assert_eq!(result.sessions.len(), 2);
+
+        let stored: Vec<&PairedSession> = result.stored_sessions().collect();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].start_time(), at(4, 8, 0));
+    }
+
+    #[test]
+    fn test_forgotten_checkout_does_not_create_huge_session() {
+        let result = pair_punches(
+            &punches(&[(In, at(2, 8, 0)), (In, at(3, 8, 0)), (Out, at(3, 17, 0))]),
+            &utc_config(),
+        );
+        assert_eq!(result.sessions.len(), 2);
+        assert_eq!(result.sessions[0].date, date(2));
+        assert!(!result.sessions[0].is_complete());
+        assert_eq!(result.sessions[1].date, date(3));
+        assert_eq!(result.sessions[1].duration_minutes(), Some(540));
+        assert!(result.ignored.is_empty());
+    }
+
+    #[test]
+    fn test_week_long_gap_does_not_create_session() {
+        let result = pair_punches(
+            &punches(&[(In, at(2, 8, 0)), (In, at(6, 8, 0)), (Out, at(6, 17, 0))]),
+            &utc_config(),
+        );
+        assert_eq!(result.sessions.len(), 2);
+        assert!(!result.sessions[0].is_complete());
+        assert_eq!(result.sessions[1].duration_minutes(), Some(540));
+    }
+
+    #[test]
+    fn test_split_shift_makes_two_sessions() {
+        let result = pair_punches(
+            &punches(&[(In, at(4, 6, 0)), (Out, at(4, 10, 0)), (In, at(4, 18, 0)), (Out, at(4, 22, 0))]),
+            &utc_config(),
+        );
+        assert_eq!(result.sessions.len(), 2);
+        assert!(result.sessions.iter().all(|s| s.date == date(4) && s.duration_minutes() == Some(240)));
+    }
+
+    #[test]
+    fn test_multiple_checkouts_for_one_checkin() {
+        let result = pair_punches(
+            &punches(&[(In, at(4, 8, 0)), (Out, at(4, 12, 0)), (Out, at(4, 17, 0))]),
+            &utc_config(),
+        );
+        assert_eq!(result.sessions.len(), 2);
+        assert_eq!(result.sessions[0].duration_minutes(), Some(240));
+        assert!(result.sessions[1].checkin.is_none());
+    }
+
+    #[test]
+    fn test_second_in_while_open_is_ignored() {
+        let result = pair_punches(
+            &punches(&[(In, at(4, 8, 0)), (In, at(4, 8, 5)), (Out, at(4, 17, 0))]),
+            &utc_config(),
+        );
+        assert_eq!(result.sessions.len(), 1);
+        assert_eq!(result.sessions[0].checkin.as_ref().unwrap().at, at(4, 8, 0));
+        assert_eq!(result.ignored.len(), 1);
+    }
+
+    #[test]
+    fn test_unsorted_input_is_walked_in_time_order() {
+        let result = pair_punches(&punches(&[(Out, at(5, 6, 0)), (In, at(4, 22, 0))]), &utc_config());
+        assert_eq!(result.sessions.len(), 1);
+        assert!(result.sessions[0].is_complete());
+    }
+
+    #[test]
+    fn test_configurable_max_shift() {
+        let config = SessionConfig { max_shift: Duration::hours(12), ..utc_config() };
+        let result = pair_punches(&punches(&[(In, at(4, 22, 0)), (Out, at(5, 12, 0))]), &config);
+        assert_eq!(result.sessions.len(), 2);
+    }
+
+    #[test]
+    fn test_date_rules() {
+        let night = punches(&[(In, at(4, 23, 0)), (Out, at(5, 7, 0))]);
+
+        let config = SessionConfig { date_rule: ShiftDateRule::CheckoutDate, ..utc_config() };
+        assert_eq!(pair_punches(&night, &config).sessions[0].date, date(5));
+
+        let workday = ShiftDateRule::WorkdayStart(NaiveTime::from_hms_opt(6, 0, 0).unwrap());
+        let config = SessionConfig { date_rule: workday, ..utc_config() };
+        let early = punches(&[(In, at(5, 2, 0)), (Out, at(5, 9, 0))]);
+        assert_eq!(pair_punches(&early, &config).sessions[0].date, date(4));
+        assert_eq!(pair_punches(&night, &config).sessions[0].date, date(4));
+    }
+
+    #[test]
+    fn test_dates_use_local_offset() {
+        // 15:00 UTC is 01:00 the next day at UTC+10
+        let config = SessionConfig { utc_offset: FixedOffset::east_opt(10 * 3600).unwrap(), ..utc_config() };
+        let result = pair_punches(&punches(&[(In, at(4, 15, 0)), (Out, at(4, 20, 0))]), &config);
+        assert_eq!(result.sessions[0].date, date(5));
+    }
+
+    fn stored(id: i32, checkin: DateTime<Utc>, checkout: Option<DateTime<Utc>>) -> AttendanceSession {
+        AttendanceSession {
+            id,
+            user_id: "user_001".to_string(),
+            date: checkin.date_naive(),
+            session_number: 1,
+            checkin_time: checkin,
+            checkout_time: checkout,
+            duration_minutes: None,
+            checkin_latitude: None,
+            checkin_longitude: None,
+            checkout_latitude: None,
+            checkout_longitude: None,
+            checkin_location: None,
+            checkout_location: None,
+            is_complete: checkout.is_some(),
+            auto_closed: false,
+            auto_closed_at: None,
+            created_at: checkin,
+            updated_at: checkin,
+        }
+    }
+
+    #[test]
+    fn test_rebuild_changes_skip_unchanged_sessions() {
+        let rebuild = SessionRebuild {
+            removed: vec![stored(1, at(4, 8, 0), Some(at(4, 12, 0))), stored(2, at(4, 23, 0), None)],
+            added: vec![stored(3, at(4, 8, 0), Some(at(4, 12, 0))), stored(4, at(4, 23, 0), Some(at(5, 7, 0)))],
+        };
+        let changes = rebuild.into_changes();
+        assert_eq!(changes.removed.iter().map(|s| s.id).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(changes.added.iter().map(|s| s.id).collect::<Vec<_>>(), vec![4]);
+    }
+
+    fn scheduled(day: u32, start: (u32, u32), end: (u32, u32)) -> schedule::ScheduledDay {
+        schedule::ScheduledDay {
+            date: date(day),
+            source: schedule::ShiftSource::TimeSetting,
+            shift: Some(schedule::Shift {
+                start_time: NaiveTime::from_hms_opt(start.0, start.1, 0).unwrap(),
+                end_time: NaiveTime::from_hms_opt(end.0, end.1, 0).unwrap(),
+                ..schedule::default_shift()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_auto_close_at_shift_end() {
+        let days = [scheduled(3, (8, 0), (17, 0)), scheduled(4, (8, 0), (17, 0))];
+        let checkout = auto_close_time(at(4, 8, 0), &days, AutoClosePolicy::OffDuty, &utc_config());
+        assert_eq!(checkout, at(4, 17, 0));
+
+        // A night shift closes the next morning
+        let days = [scheduled(3, (22, 0), (7, 0)), scheduled(4, (22, 0), (7, 0))];
+        let checkout = auto_close_time(at(4, 22, 0), &days, AutoClosePolicy::OffDuty, &utc_config());
+        assert_eq!(checkout, at(5, 7, 0));
+
+        // Checked in late after midnight, still within the shift that started the day before
+        let checkout = auto_close_time(at(4, 0, 30), &days, AutoClosePolicy::OffDuty, &utc_config());
+        assert_eq!(checkout, at(4, 7, 0));
+    }
+
+    #[test]
+    fn test_auto_close_follows_rostered_night_shift() {
+        let night = schedule::ShiftTemplate {
+            id: 1,
+            name: "Night".to_string(),
+            start_time: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            end_time: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+            crosses_midnight: true,
+            break_minutes: 0,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let mut roster = schedule::Roster::default();
+        roster.templates.insert(1, night);
+        roster.assignments.insert(date(4), Some(1));
+        // Their own duty times, which the roster takes precedence over
+        roster.time_settings.push(schedule::TimeSettingPeriod::new(date(1), schedule::default_shift()));
+
+        let days = [roster.day(date(3)), roster.day(date(4))];
+        let checkout = auto_close_time(at(4, 22, 5), &days, AutoClosePolicy::OffDuty, &utc_config());
+        assert_eq!(checkout, at(5, 6, 0));
+    }
+
+    #[test]
+    fn test_auto_close_falls_back_to_zero_minutes() {
+        let days = [scheduled(3, (8, 0), (17, 0)), scheduled(4, (8, 0), (17, 0))];
+        assert_eq!(auto_close_time(at(4, 8, 0), &days, AutoClosePolicy::Zero, &utc_config()), at(4, 8, 0));
+
+        // Checked in after the shift ended
+        assert_eq!(auto_close_time(at(4, 18, 0), &days, AutoClosePolicy::OffDuty, &utc_config()), at(4, 18, 0));
+
+        // A day off has no shift to close at, even if the day before had one
+        let off = schedule::ScheduledDay { date: date(4), source: schedule::ShiftSource::Weekday, shift: None };
+        let days = [scheduled(3, (8, 0), (17, 0)), off];
+        assert_eq!(auto_close_time(at(4, 9, 0), &days, AutoClosePolicy::OffDuty, &utc_config()), at(4, 9, 0));
+    }
+
+    #[test]
+    fn test_auto_close_uses_local_shift_end() {
+        // 17:00 at UTC+8 is 09:00 UTC
+        let config = SessionConfig { utc_offset: FixedOffset::east_opt(8 * 3600).unwrap(), ..utc_config() };
+        let days = [scheduled(3, (8, 0), (17, 0)), scheduled(4, (8, 0), (17, 0))];
+        assert_eq!(auto_close_time(at(4, 0, 30), &days, AutoClosePolicy::OffDuty, &config), at(4, 9, 0));
+    }
+
+    #[test]
+    fn test_parse_date_rule() {
+        assert_eq!(ShiftDateRule::parse("checkin"), Some(ShiftDateRule::CheckinDate));
+        assert_eq!(ShiftDateRule::parse("checkout"), Some(ShiftDateRule::CheckoutDate));
+        assert_eq!(
+            ShiftDateRule::parse("workday_start:06:00"),
+            Some(ShiftDateRule::WorkdayStart(NaiveTime::from_hms_opt(6, 0, 0).unwrap()))
+        );
+        assert_eq!(ShiftDateRule::parse("midnight"), None);
+    }
+}
@@ -0,0 +1,255 @@
+// Time-based one-time passwords (RFC 6238) and recovery codes for admin two-factor login
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+/// Seconds each code is valid for
+pub const STEP_SECONDS: i64 = 30;
+/// Digits of each code
+pub const DIGITS: u32 = 6;
+/// Steps before and after the current one that are still accepted, for clock drift
+pub const ALLOWED_DRIFT_STEPS: i64 = 1;
+/// Bytes of generated secrets; 160 bits as RFC 4226 recommends
+pub const SECRET_LENGTH: usize = 20;
+/// Recovery codes issued on enrolment
+pub const RECOVERY_CODE_COUNT: usize = 10;
+/// Characters of a recovery code, not counting the separator
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+/// RFC 4648 base32 alphabet, the encoding authenticator apps expect for secrets
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+/// Characters of recovery codes, without look-alikes such as 0/o and 1/l
+const RECOVERY_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Random secret for a new enrolment
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SECRET_LENGTH];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// Base32 without padding
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 8).div_ceil(5));
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    encoded
+}
+
+/// Decode base32, ignoring case, spaces and padding. None if a character is not base32.
+pub fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in encoded.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = BASE32_ALPHABET.iter().position(|&a| a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(decoded)
+}
+
+/// HOTP value (RFC 4226) of a counter, truncated to `digits` decimal digits
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([hash[offset], hash[offset + 1], hash[offset + 2], hash[offset + 3]]) & 0x7fff_ffff;
+    binary % 10u32.pow(digits)
+}
+
+/// Time step a unix timestamp falls in
+pub fn step_at(unix_time: i64) -> i64 {
+    unix_time.div_euclid(STEP_SECONDS)
+}
+
+/// Code of a time step, zero-padded to `digits`
+pub fn code_for_step(secret: &[u8], step: i64, digits: u32) -> String {
+    format!("{:0width$}", hotp(secret, step as u64, digits), width = digits as usize)
+}
+
+/// Check a code against the steps around `unix_time`, returning the step it matched.
+/// Steps up to `last_used_step` are refused so a code cannot be replayed.
+pub fn verify(secret: &[u8], code: &str, unix_time: i64, last_used_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let current = step_at(unix_time);
+    (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS)
+        .filter(|&step| step >= 0 && last_used_step.is_none_or(|last| step > last))
+        .find(|&step| {
+            let expected = code_for_step(secret, step, DIGITS);
+            crate::password::constant_time_eq(expected.as_bytes(), code.as_bytes())
+        })
+}
+
+/// otpauth:// URI for enrolling a secret in an authenticator app, usually shown as a QR code
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        uri_encode(issuer),
+        uri_encode(account),
+        base32_encode(secret),
+        uri_encode(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Single-use codes for signing in without the authenticator, formatted `xxxxx-xxxxx`
+pub fn generate_recovery_codes() -> Vec<String> {
+    // Skip bytes past the last whole multiple of the alphabet size so every character is equally likely
+    let limit = 256 - 256 % RECOVERY_ALPHABET.len();
+    let mut buf = [0u8; 64];
+
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut code = String::with_capacity(RECOVERY_CODE_LENGTH);
+            while code.len() < RECOVERY_CODE_LENGTH {
+                OsRng.fill_bytes(&mut buf);
+                for &byte in buf.iter().filter(|&&b| (b as usize) < limit) {
+                    if code.len() == RECOVERY_CODE_LENGTH {
+                        break;
+                    }
+                    code.push(RECOVERY_ALPHABET[byte as usize % RECOVERY_ALPHABET.len()] as char);
+                }
+            }
+            code.insert(RECOVERY_CODE_LENGTH / 2, '-');
+            code
+        })
+        .collect()
+}
+
+/// Form of a recovery code that is hashed and compared: lowercase, without separators
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn code_at(secret: &[u8], unix_time: i64, digits: u32) -> String {
+        code_for_step(secret, step_at(unix_time), digits)
+    }
+
+    #[test]
+    fn test_rfc4226_vectors() {
+        let expected = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+        for (counter, &code) in expected.iter().enumerate() {
+            assert_eq!(hotp(RFC_SECRET, counter as u64, 6), code);
+        }
+    }
+
+    #[test]
+    fn test_rfc6238_sha1_vectors() {
+        let vectors = [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ];
+        for (time, code) in vectors {
+            assert_eq!(code_at(RFC_SECRET, time, 8), code, "at {}", time);
+        }
+    }
+
+    #[test]
+    fn test_verify_window_and_replay() {
+        let now = 1111111111;
+        let code = code_at(RFC_SECRET, now, DIGITS);
+        let step = step_at(now);
+
+        assert_eq!(verify(RFC_SECRET, &code, now, None), Some(step));
+        assert_eq!(verify(RFC_SECRET, &code, now + STEP_SECONDS, None), Some(step));
+        assert_eq!(verify(RFC_SECRET, &code, now - STEP_SECONDS, None), Some(step));
+        assert_eq!(verify(RFC_SECRET, &code, now + 2 * STEP_SECONDS, None), None);
+
+        assert_eq!(verify(RFC_SECRET, &code, now, Some(step)), None);
+        assert_eq!(verify(RFC_SECRET, &code, now, Some(step - 1)), Some(step));
+    }
+
+    #[test]
+    fn test_verify_rejects_malformed_codes() {
+        assert_eq!(verify(RFC_SECRET, "12345", 59, None), None);
+        assert_eq!(verify(RFC_SECRET, "12a456", 59, None), None);
+        assert_eq!(verify(RFC_SECRET, "", 59, None), None);
+    }
+
+    #[test]
+    fn test_base32() {
+        // RFC 4648 test vectors, without padding
+        let vectors = [("", ""), ("f", "MY"), ("fo", "MZXQ"), ("foo", "MZXW6"), ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"), ("foobar", "MZXW6YTBOI")];
+        for (plain, encoded) in vectors {
+            assert_eq!(base32_encode(plain.as_bytes()), encoded);
+            assert_eq!(base32_decode(encoded).unwrap(), plain.as_bytes());
+        }
+
+        assert_eq!(base32_decode("mzxw 6ytb oi======").unwrap(), b"foobar");
+        assert_eq!(base32_decode("MZXW1"), None);
+
+        let secret = generate_secret();
+        assert_eq!(base32_decode(&base32_encode(&secret)).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_provisioning_uri() {
+        let uri = provisioning_uri("KBK Attendance", "admin", b"foobar");
+        assert_eq!(
+            uri,
+            "otpauth://totp/KBK%20Attendance:admin?secret=MZXW6YTBOI&issuer=KBK%20Attendance&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        for code in &codes {
+            assert_eq!(code.len(), RECOVERY_CODE_LENGTH + 1);
+            assert_eq!(normalize_recovery_code(code).len(), RECOVERY_CODE_LENGTH);
+        }
+        assert_eq!(normalize_recovery_code(" ABCDE-fghjk "), "abcdefghjk");
+    }
+}
@@ -98,9 +98,9 @@ const admin = {
         const currentYear = now.getFullYear();
         const currentMonth = now.getMonth() + 1;
         
-        // Get current user info to check role
+        // Users limited to one department get no department filter
         const user = JSON.parse(localStorage.getItem('admin_user') || '{}');
-        const isAdmin = user.role === 'admin';
+        const isAdmin = !user.departments || user.departments.length !== 1;
         
         const filtersHtml = `
             <div class="stats-filters">
@@ -534,7 +534,7 @@ const admin = {
                     const sessions = record.total_sessions || 0;
                     
                     let statusBadges = [];
-                    if (record.is_late) statusBadges.push('<span class="badge badge-warning">Late</span>');
+                    if (record.is_late) statusBadges.push(`<span class="badge badge-warning">Late ${record.late_minutes}m${record.late_tier === 'major' ? ' (major)' : ''}</span>`);
                     if (record.is_early_leave) statusBadges.push('<span class="badge badge-info">Early Leave</span>');
                     const status = statusBadges.length > 0 ? statusBadges.join(' ') : '<span class="badge badge-success">Normal</span>';
                     
@@ -982,7 +982,7 @@ const admin = {
                         <th>User Name</th>
                         <th>Department</th>
                         <th>Department Name</th>
-                        <th>Passkey</th>
+                        <th>Passkey Expires</th>
                         <th>Actions</th>
                     </tr>
                 </thead>
@@ -997,9 +997,10 @@ const admin = {
                     <td>${user.user_name || 'N/A'}</td>
                     <td>${user.department}</td>
                     <td>${user.department_name || 'N/A'}</td>
-                    <td>${user.passkey}</td>
+                    <td>${user.passkey_expires_at ? new Date(user.passkey_expires_at).toLocaleString() : 'Never'}</td>
                     <td class="actions">
                         <button class="btn btn-secondary" onclick="admin.editUser(${user.id})">Edit</button>
+                        <button class="btn btn-secondary" onclick="admin.rotateUserPasskey(${user.id})">Rotate Passkey</button>
                         <button class="btn btn-danger" onclick="admin.deleteUser(${user.id})">Delete</button>
                     </td>
                 </tr>
@@ -1044,7 +1045,7 @@ const admin = {
                     
                     <div class="form-group">
                         <label for="passkey">Passkey:</label>
-                        <input type="text" id="passkey" name="passkey" value="${user ? user.passkey : ''}" required>
+                        <input type="text" id="passkey" name="passkey" ${isEdit ? 'placeholder="Leave blank to keep current passkey"' : 'required'}>
                     </div>
                     
                     <div class="form-actions">
@@ -1073,7 +1074,7 @@ const admin = {
             user_name: formData.get('user_name') || null,
             department: parseInt(formData.get('department')),
             department_name: formData.get('department_name') || null,
-            passkey: formData.get('passkey')
+            passkey: formData.get('passkey') || null
         };
         
         try {
@@ -1095,6 +1096,33 @@ const admin = {
         }
     },
     
+    async rotateUserPasskey(id) {
+        const days = prompt('Rotate passkey. Expire the new passkey after how many days? (leave blank for no expiry)');
+        if (days === null) return;
+        
+        const data = {};
+        if (days.trim() !== '') {
+            const count = parseInt(days);
+            if (isNaN(count) || count <= 0) {
+                alert('Please enter a positive number of days');
+                return;
+            }
+            data.expires_at = new Date(Date.now() + count * 24 * 60 * 60 * 1000).toISOString();
+        }
+        
+        try {
+            const response = await api.rotateUserPasskey(id, data);
+            if (response.success) {
+                alert(`New passkey for ${response.data.user.user_id}: ${response.data.passkey}\n\nIt will not be shown again. Devices signed in with the old passkey have been signed out.`);
+                this.loadUsers();
+            } else {
+                alert('Error: ' + response.message);
+            }
+        } catch (error) {
+            alert('Error rotating passkey: ' + error.message);
+        }
+    },
+    
     async editUser(id) {
         try {
             const response = await api.getUsers();
@@ -1418,7 +1446,9 @@ const admin = {
         
         adminUsers.forEach(user => {
             const createdAt = new Date(user.created_at).toLocaleString();
-            const departmentDisplay = user.department || 'N/A';
+            const departmentDisplay = user.departments && user.departments.length > 0
+                ? user.departments.join(', ')
+                : 'All';
             
             html += `
                 <tr>
@@ -1430,6 +1460,7 @@ const admin = {
                     <td class="actions">
                         <button class="btn btn-secondary" onclick="admin.editAdminUser(${user.id})">Edit</button>
                         <button class="btn btn-warning" onclick="admin.resetAdminPassword(${user.id})">Reset Password</button>
+                        ${user.totp_enabled ? `<button class="btn btn-warning" onclick="admin.resetAdminTotp(${user.id})">Reset 2FA</button>` : ''}
                         <button class="btn btn-danger" onclick="admin.deleteAdminUser(${user.id})">Delete</button>
                     </td>
                 </tr>
@@ -1467,13 +1498,15 @@ const admin = {
                         <select id="admin_role" name="role" required>
                             <option value="admin" ${user && user.role === 'admin' ? 'selected' : ''}>Admin</option>
                             <option value="department" ${user && user.role === 'department' ? 'selected' : ''}>Department</option>
+                            <option value="auditor" ${user && user.role === 'auditor' ? 'selected' : ''}>Auditor</option>
+                            <option value="payroll" ${user && user.role === 'payroll' ? 'selected' : ''}>Payroll</option>
                         </select>
                     </div>
                     
                     <div class="form-group">
                         <label for="admin_department">Department:</label>
                         <select id="admin_department" name="department">
-                            <option value="">None (Admin only)</option>
+                            <option value="">All departments</option>
                             <option value="1" ${user && user.department === 1 ? 'selected' : ''}>1 - Office</option>
                             <option value="2" ${user && user.department === 2 ? 'selected' : ''}>2 - Mining</option>
                             <option value="3" ${user && user.department === 3 ? 'selected' : ''}>3 - CA</option>
@@ -1503,9 +1536,10 @@ const admin = {
         document.getElementById('admin_role').addEventListener('change', function() {
             const deptField = document.getElementById('admin_department');
             const deptGroup = deptField.closest('.form-group');
-            if (this.value === 'department') {
+            if (this.value !== 'admin') {
+                // Auditors and payroll users may be limited to a department or see all
                 deptGroup.style.display = 'block';
-                deptField.required = true;
+                deptField.required = this.value === 'department';
             } else {
                 deptGroup.style.display = 'none';
                 deptField.required = false;
@@ -1618,6 +1652,24 @@ const admin = {
         }
     },
 
+    async resetAdminTotp(id) {
+        if (!confirm('Remove this user\'s authenticator and recovery codes and sign them out?')) {
+            return;
+        }
+        
+        try {
+            const response = await api.resetAdminTotp(id);
+            if (response.success) {
+                alert('Two-factor authentication reset');
+                this.loadAdminUsers();
+            } else {
+                alert('Error: ' + response.message);
+            }
+        } catch (error) {
+            alert('Error resetting two-factor authentication: ' + error.message);
+        }
+    },
+
     // Time Settings Management
     async loadTimeSettings() {
         const loadBtn = document.getElementById('load-time-settings');
@@ -1686,9 +1738,10 @@ const admin = {
         `;
 
         users.forEach(user => {
-            const onDutyTime = user.on_duty_time || '07:30:00';
-            const offDutyTime = user.off_duty_time || '17:00:00';
-            const hasCustomSettings = user.on_duty_time !== null;
+            const onDutyTime = user.on_duty_time;
+            const offDutyTime = user.off_duty_time;
+            const hasCustomSettings = user.source === 'time_setting';
+            const sourceLabel = hasCustomSettings ? 'Custom' : (user.source === 'department' ? 'Department' : 'Default');
             
             html += `
                 <tr data-user-id="${user.user_id}">
@@ -1710,7 +1763,7 @@ const admin = {
                     </td>
                     <td>
                         <span class="status-badge ${hasCustomSettings ? 'custom' : 'default'}">
-                            ${hasCustomSettings ? 'Custom' : 'Default'}
+                            ${sourceLabel}
                         </span>
                     </td>
                     <td>
@@ -1759,9 +1812,10 @@ const admin = {
             });
         });
 
-        // Time input change tracking
+        // Time input change tracking; only edited rows are saved so the others keep inheriting
         document.querySelectorAll('.time-input').forEach(input => {
             input.addEventListener('change', () => {
+                input.closest('tr').dataset.changed = 'true';
                 this.markAsChanged();
             });
         });
@@ -1770,26 +1824,15 @@ const admin = {
 
 
     async resetUserTimeSetting(userId) {
-        if (!confirm(`Reset time settings for user ${userId} to default (7:30-17:00)?`)) {
+        if (!confirm(`Reset time settings for user ${userId} to their department's default hours?`)) {
             return;
         }
 
         try {
             const response = await api.deleteTimeSetting(userId);
             if (response.success) {
-                // Update UI to show default times
-                const row = document.querySelector(`tr[data-user-id="${userId}"]`);
-                if (row) {
-                    row.querySelector('.on-duty-time').value = '07:30';
-                    row.querySelector('.off-duty-time').value = '17:00';
-                    const statusBadge = row.querySelector('.status-badge');
-                    statusBadge.textContent = 'Default';
-                    statusBadge.className = 'status-badge default';
-                    
-                    // Remove reset button
-                    const resetBtn = row.querySelector('.reset-btn');
-                    if (resetBtn) resetBtn.remove();
-                }
+                // Reload to show the inherited times
+                await this.loadUsersWithTimeSettings();
                 alert('Time setting reset to default successfully.');
             } else {
                 alert('Failed to reset time setting: ' + response.message);
@@ -1817,14 +1860,9 @@ const admin = {
         
         try {
             const settings = [];
-            const timeInputs = document.querySelectorAll('.time-input');
-            const processedUsers = new Set();
             
-            timeInputs.forEach(input => {
-                const userId = input.getAttribute('data-user-id');
-                if (processedUsers.has(userId)) return;
-                
-                const row = document.querySelector(`tr[data-user-id="${userId}"]`);
+            document.querySelectorAll('tr[data-changed="true"]').forEach(row => {
+                const userId = row.getAttribute('data-user-id');
                 const onDutyTime = row.querySelector('.on-duty-time').value + ':00';
                 const offDutyTime = row.querySelector('.off-duty-time').value + ':00';
                 
@@ -1833,8 +1871,6 @@ const admin = {
                     on_duty_time: onDutyTime,
                     off_duty_time: offDutyTime
                 });
-                
-                processedUsers.add(userId);
             });
             
             const response = await api.batchUpdateTimeSettings(settings);
@@ -46,6 +46,35 @@ const api = {
         return response.json();
     },
     
+    // Second login step for accounts with two-factor authentication
+    async verifyAdminLogin(challengeToken, code) {
+        const response = await fetch(`${this.baseUrl}/login/verify`, {
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json'
+            },
+            body: JSON.stringify({ challenge_token: challengeToken, code })
+        });
+        return response.json();
+    },
+    
+    async enrollAdminLogin(challengeToken) {
+        const response = await fetch(`${this.baseUrl}/login/enroll`, {
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json'
+            },
+            body: JSON.stringify({ challenge_token: challengeToken })
+        });
+        return response.json();
+    },
+    
+    async adminLogout() {
+        return this.request(`${this.baseUrl}/logout`, {
+            method: 'POST'
+        });
+    },
+    
     async getAdminInfo() {
         return this.request(`${this.baseUrl}/me`);
     },
@@ -125,6 +154,29 @@ const api = {
         });
     },
     
+    async rotateUserPasskey(id, data = {}) {
+        return this.request(`${this.baseUrl}/users/${id}/passkey/rotate`, {
+            method: 'POST',
+            body: JSON.stringify(data)
+        });
+    },
+    
+    async getUserDevices(id) {
+        return this.request(`${this.baseUrl}/users/${id}/devices`);
+    },
+    
+    async approveUserDevice(id, deviceId) {
+        return this.request(`${this.baseUrl}/users/${id}/devices/${deviceId}/approve`, {
+            method: 'POST'
+        });
+    },
+    
+    async revokeUserDevice(id, deviceId) {
+        return this.request(`${this.baseUrl}/users/${id}/devices/${deviceId}/revoke`, {
+            method: 'POST'
+        });
+    },
+    
     // Checkins
     async getCheckins(filters = {}) {
         const params = new URLSearchParams();
@@ -213,6 +265,18 @@ const api = {
             body: JSON.stringify(data)
         });
     },
+    
+    async revokeAdminSessions(id) {
+        return this.request(`${this.baseUrl}/admin-users/${id}/revoke-sessions`, {
+            method: 'POST'
+        });
+    },
+    
+    async resetAdminTotp(id) {
+        return this.request(`${this.baseUrl}/admin-users/${id}/totp/reset`, {
+            method: 'POST'
+        });
+    },
 
     // Time Settings
     async getUsersWithTimeSettings(params = {}) {
@@ -236,6 +300,201 @@ const api = {
         });
     },
 
+    async getTimeSettingHistory(userId) {
+        return this.request(`${this.baseUrl}/time-settings/${userId}/history`);
+    },
+
+    async deleteTimeSettingVersion(userId, effectiveFrom) {
+        return this.request(`${this.baseUrl}/time-settings/${userId}/${effectiveFrom}`, {
+            method: 'DELETE'
+        });
+    },
+
+    async getLatenessRules(userId) {
+        return this.request(`${this.baseUrl}/time-settings/${userId}/lateness`);
+    },
+
+    async setLatenessRules(userId, data) {
+        return this.request(`${this.baseUrl}/time-settings/${userId}/lateness`, {
+            method: 'PUT',
+            body: JSON.stringify(data)
+        });
+    },
+
+    // Shifts
+    async getShiftTemplates() {
+        return this.request(`${this.baseUrl}/shifts/templates`);
+    },
+
+    async createShiftTemplate(data) {
+        return this.request(`${this.baseUrl}/shifts/templates`, {
+            method: 'POST',
+            body: JSON.stringify(data)
+        });
+    },
+
+    async updateShiftTemplate(id, data) {
+        return this.request(`${this.baseUrl}/shifts/templates/${id}`, {
+            method: 'PUT',
+            body: JSON.stringify(data)
+        });
+    },
+
+    async deleteShiftTemplate(id) {
+        return this.request(`${this.baseUrl}/shifts/templates/${id}`, {
+            method: 'DELETE'
+        });
+    },
+
+    async getRoster(userId, from, to) {
+        const queryParams = new URLSearchParams({ user_id: userId, from, to });
+        return this.request(`${this.baseUrl}/shifts/roster?${queryParams.toString()}`);
+    },
+
+    async setShiftAssignments(assignments) {
+        return this.request(`${this.baseUrl}/shifts/assignments`, {
+            method: 'PUT',
+            body: JSON.stringify({ assignments })
+        });
+    },
+
+    async deleteShiftAssignment(userId, date) {
+        return this.request(`${this.baseUrl}/shifts/assignments/${userId}/${date}`, {
+            method: 'DELETE'
+        });
+    },
+
+    async getShiftRotations(userId) {
+        return this.request(`${this.baseUrl}/shifts/rotations?user_id=${encodeURIComponent(userId)}`);
+    },
+
+    async createShiftRotation(data) {
+        return this.request(`${this.baseUrl}/shifts/rotations`, {
+            method: 'POST',
+            body: JSON.stringify(data)
+        });
+    },
+
+    async deleteShiftRotation(id) {
+        return this.request(`${this.baseUrl}/shifts/rotations/${id}`, {
+            method: 'DELETE'
+        });
+    },
+
+    // Sessions
+    async rebuildSessions(data) {
+        return this.request(`${this.baseUrl}/sessions/rebuild`, {
+            method: 'POST',
+            body: JSON.stringify(data)
+        });
+    },
+
+    async getAutoClosedSessions(params = {}) {
+        const queryParams = new URLSearchParams();
+        if (params.user_id) queryParams.append('user_id', params.user_id);
+        if (params.start_date) queryParams.append('start_date', params.start_date);
+        if (params.end_date) queryParams.append('end_date', params.end_date);
+        
+        const url = `${this.baseUrl}/sessions/auto-closed${queryParams.toString() ? '?' + queryParams.toString() : ''}`;
+        return this.request(url);
+    },
+
+    // Anomalies
+    async getAnomalies(params = {}) {
+        const queryParams = new URLSearchParams();
+        if (params.status) queryParams.append('status', params.status);
+        if (params.user_id) queryParams.append('user_id', params.user_id);
+        if (params.anomaly_type) queryParams.append('anomaly_type', params.anomaly_type);
+        if (params.limit) queryParams.append('limit', params.limit);
+        
+        const url = `${this.baseUrl}/anomalies${queryParams.toString() ? '?' + queryParams.toString() : ''}`;
+        return this.request(url);
+    },
+
+    async resolveAnomaly(id, note) {
+        return this.request(`${this.baseUrl}/anomalies/${id}/resolve`, {
+            method: 'POST',
+            body: JSON.stringify({ note })
+        });
+    },
+
+    async dismissAnomaly(id, note) {
+        return this.request(`${this.baseUrl}/anomalies/${id}/dismiss`, {
+            method: 'POST',
+            body: JSON.stringify({ note })
+        });
+    },
+
+    // Login Lockouts
+    async getLockouts() {
+        return this.request(`${this.baseUrl}/lockouts`);
+    },
+
+    async unlockLogin(scope, subject) {
+        return this.request(`${this.baseUrl}/lockouts/unlock`, {
+            method: 'POST',
+            body: JSON.stringify({ scope, subject })
+        });
+    },
+
+    // API keys
+    async getApiKeys() {
+        return this.request(`${this.baseUrl}/api-keys`);
+    },
+
+    async createApiKey(data) {
+        return this.request(`${this.baseUrl}/api-keys`, {
+            method: 'POST',
+            body: JSON.stringify(data)
+        });
+    },
+
+    async revokeApiKey(id) {
+        return this.request(`${this.baseUrl}/api-keys/${id}/revoke`, {
+            method: 'POST'
+        });
+    },
+
+    // Audit log
+    async getAuditLog(filters = {}) {
+        const params = new URLSearchParams();
+        for (const key of ['actor', 'action', 'entity', 'target', 'from', 'to', 'page', 'per_page']) {
+            if (filters[key]) params.append(key, filters[key]);
+        }
+
+        const url = `${this.baseUrl}/audit${params.toString() ? '?' + params.toString() : ''}`;
+        return this.request(url);
+    },
+
+    // Department Settings
+    async getDepartmentSettings() {
+        return this.request(`${this.baseUrl}/departments/settings`);
+    },
+
+    async updateDepartmentSetting(department, data) {
+        return this.request(`${this.baseUrl}/departments/${department}/settings`, {
+            method: 'PUT',
+            body: JSON.stringify(data)
+        });
+    },
+
+    async getDepartmentTimeSettings(department) {
+        return this.request(`${this.baseUrl}/departments/${department}/time-settings`);
+    },
+
+    async setDepartmentTimeSetting(department, data) {
+        return this.request(`${this.baseUrl}/departments/${department}/time-settings`, {
+            method: 'POST',
+            body: JSON.stringify(data)
+        });
+    },
+
+    async deleteDepartmentTimeSetting(department, effectiveFrom) {
+        return this.request(`${this.baseUrl}/departments/${department}/time-settings/${effectiveFrom}`, {
+            method: 'DELETE'
+        });
+    },
+
 
     
     // Export
@@ -25,8 +25,19 @@ const auth = {
         return user && user.role === 'admin';
     },
     
-    // Logout
-    logout() {
+    // Check if the current user's role grants a capability, e.g. 'stats.export'
+    can(capability) {
+        const user = this.getCurrentUser();
+        return !!(user && user.capabilities && user.capabilities.includes(capability));
+    },
+    
+    // Logout, revoking the session on the server first
+    async logout() {
+        try {
+            await api.adminLogout();
+        } catch (error) {
+            console.error('Failed to revoke session:', error);
+        }
         localStorage.removeItem('admin_token');
         localStorage.removeItem('admin_user');
         window.location.href = '/ui/login.html';
@@ -416,7 +416,7 @@ const stats = {
                     const sessions = record.total_sessions || 0;
                     
                     let statusBadges = [];
-                    if (record.is_late) statusBadges.push('<span class="badge badge-warning">Late</span>');
+                    if (record.is_late) statusBadges.push(`<span class="badge badge-warning">Late ${record.late_minutes}m${record.late_tier === 'major' ? ' (major)' : ''}</span>`);
                     if (record.is_early_leave) statusBadges.push('<span class="badge badge-info">Early Leave</span>');
                     const status = statusBadges.length > 0 ? statusBadges.join(' ') : '<span class="badge badge-success">Normal</span>';
                     